@@ -0,0 +1,969 @@
+use std::{
+    collections::HashMap,
+    env, fmt, fs,
+    io::{self, BufRead, BufReader, Read},
+    mem,
+    path::PathBuf,
+    sync::{Arc, LazyLock},
+    time::Duration,
+};
+
+use axum::body::Bytes;
+use chrono::Utc;
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use futures::{stream, Stream, StreamExt};
+use reqwest::{redirect, Client, Url};
+use scylla::{
+    frame::value::CqlTimestamp, prepared_statement::PreparedStatement, query::Query,
+    transport::errors::QueryError, transport::iterator::RowIterator, Session,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use sha2::{Digest, Sha256};
+use tokio::{io::AsyncWriteExt, sync::mpsc};
+use tracing::{event, Level};
+
+use crate::{
+    cache,
+    dns::{self, PublicResolver},
+    hosts,
+    purge::{Table, TABLES},
+    state::{AppState, State},
+    tenants,
+    usage::{self, Metric},
+    utils::id::gen_id,
+};
+
+/// Identifies tenant archives, in their manifest.
+pub const FORMAT: &str = "accesscore-tenant";
+
+/// Version of the archive layout. Archives from newer versions are refused.
+pub const VERSION: u32 = 1;
+
+/// Largest archive downloaded for an import.
+pub const MAX_ARCHIVE_SIZE: usize = 256 * 1024 * 1024;
+
+/// Largest total size of the files of an archive, once decompressed.
+const MAX_UNPACKED_SIZE: u64 = 2 * 1024 * 1024 * 1024;
+
+/// How long downloading an archive may take.
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// Rows per NDJSON file, so a large table is split into several files.
+const ROWS_PER_FILE: u64 = 10_000;
+
+/// Rows read per page while exporting a table.
+const PAGE_SIZE: i32 = 1000;
+
+/// Rows written at once while importing.
+const CONCURRENCY: usize = 32;
+
+/// Rows read from an archive at once while importing.
+const BATCH_SIZE: usize = 1000;
+
+/// Largest row of an archive.
+const MAX_ROW_SIZE: u64 = 1024 * 1024;
+
+/// Largest manifest of an archive.
+const MAX_MANIFEST_SIZE: u64 = 16 * 1024 * 1024;
+
+const MANIFEST_PATH: &str = "manifest.json";
+
+/// Tables of the tenant registry, exported along with the tenant's data. Hosts are only
+/// activated on import if no other tenant holds them. Admins are platform users, so they're left
+/// out, and the admin importing an archive administrates the tenant instead.
+const REGISTRY: [Table; 4] = [
+    Table {
+        name: "tenants",
+        partition_key: &["tenant_id"],
+    },
+    Table {
+        name: "tenant_hosts",
+        partition_key: &["tenant_id"],
+    },
+    Table {
+        name: "tenant_settings",
+        partition_key: &["tenant_id"],
+    },
+    Table {
+        name: "tenant_signing_keys",
+        partition_key: &["tenant_id"],
+    },
+];
+
+/// Tables archives used to hold which aren't imported anymore.
+const DROPPED: [&str; 1] = ["tenants_by_admin_users"];
+
+/// Tables left out of archives. They hold short-lived tokens, codes and sandbox messages, whose
+/// expiry wouldn't survive the import, the record of past imports, and usage counters, which
+/// can't be inserted.
+const SKIPPED: [&str; 9] = [
+    "api_tokens",
+    "mfa_codes",
+    "email_changes",
+    "email_claims",
+    "email_rollbacks",
+    "username_reservations",
+    "tenant_imports",
+    "tenant_usage",
+    "sandbox_messages",
+];
+
+static CLIENT: LazyLock<Client> = LazyLock::new(|| {
+    Client::builder()
+        .timeout(DOWNLOAD_TIMEOUT)
+        .redirect(redirect::Policy::none())
+        .dns_resolver(Arc::new(PublicResolver))
+        .user_agent("AccessCore-Import/1")
+        .build()
+        .unwrap()
+});
+
+/// Every table in archives, in the order they're exported.
+fn tables() -> impl Iterator<Item = &'static Table> {
+    REGISTRY
+        .iter()
+        .chain(TABLES.iter().filter(|table| !SKIPPED.contains(&table.name)))
+}
+
+/// Describes an archive. It's the last file of the archive, as the checksums are only known
+/// once every other file is written.
+#[derive(Debug, Serialize, Deserialize)]
+struct Manifest {
+    format: String,
+    version: u32,
+    tenant_id: String,
+    exported_at: i64,
+    files: Vec<File>,
+}
+
+/// An NDJSON file of an archive, holding rows of a table as returned by `SELECT JSON`.
+#[derive(Debug, Serialize, Deserialize)]
+struct File {
+    path: String,
+    table: String,
+    rows: u64,
+    sha256: String,
+}
+
+/// Sends a chunk of an archive to the client.
+async fn send(sender: &mpsc::Sender<io::Result<Bytes>>, chunk: Vec<u8>) -> io::Result<()> {
+    if chunk.is_empty() {
+        return Ok(());
+    }
+
+    // The receiver is only dropped once the client is gone.
+    sender
+        .send(Ok(Bytes::from(chunk)))
+        .await
+        .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))
+}
+
+/// Writes a gzipped tarball, sending it in chunks as it's written.
+struct Writer {
+    builder: tar::Builder<GzEncoder<Vec<u8>>>,
+    sender: mpsc::Sender<io::Result<Bytes>>,
+    mtime: u64,
+}
+
+impl Writer {
+    fn new(sender: mpsc::Sender<io::Result<Bytes>>) -> Self {
+        Self {
+            builder: tar::Builder::new(GzEncoder::new(vec![], Compression::default())),
+            sender,
+            mtime: Utc::now().timestamp().max(0) as u64,
+        }
+    }
+
+    async fn append(&mut self, path: &str, contents: &[u8]) -> io::Result<()> {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(contents.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(self.mtime);
+
+        self.builder.append_data(&mut header, path, contents)?;
+
+        let chunk = std::mem::take(self.builder.get_mut().get_mut());
+        send(&self.sender, chunk).await
+    }
+
+    /// Appends the rows of a table as its `part`th file.
+    async fn append_rows(
+        &mut self,
+        table: &str,
+        part: usize,
+        rows: u64,
+        contents: &[u8],
+    ) -> io::Result<File> {
+        let path = format!("tables/{table}/{part:05}.ndjson");
+        self.append(&path, contents).await?;
+
+        Ok(File {
+            path,
+            table: table.to_string(),
+            rows,
+            sha256: format!("{:x}", Sha256::digest(contents)),
+        })
+    }
+
+    async fn finish(self) -> io::Result<()> {
+        let chunk = self.builder.into_inner()?.finish()?;
+        send(&self.sender, chunk).await
+    }
+}
+
+/// Reads every row of the tenant from a table as JSON.
+async fn rows(db: &Session, table: &Table, tenant_id: &str) -> Result<RowIterator, QueryError> {
+    let filtering = if table.partition_key == ["tenant_id"] {
+        ""
+    } else {
+        " ALLOW FILTERING"
+    };
+
+    let mut query = Query::new(format!(
+        "SELECT JSON * FROM {} WHERE tenant_id = ?{filtering}",
+        table.name
+    ));
+    query.set_page_size(PAGE_SIZE);
+
+    db.query_iter(query, (tenant_id,)).await
+}
+
+async fn write(db: &Session, tenant_id: &str, writer: &mut Writer) -> io::Result<Vec<File>> {
+    let mut files = vec![];
+
+    for table in tables() {
+        let mut rows = rows(db, table, tenant_id).await.map_err(io::Error::other)?;
+        let mut contents = vec![];
+        let mut count = 0;
+        let mut part = 0;
+
+        while let Some(row) = rows.next().await {
+            let (json,) = row
+                .map_err(io::Error::other)?
+                .into_typed::<(String,)>()
+                .map_err(io::Error::other)?;
+
+            contents.extend_from_slice(json.as_bytes());
+            contents.push(b'\n');
+            count += 1;
+
+            if count == ROWS_PER_FILE {
+                files.push(
+                    writer
+                        .append_rows(table.name, part, count, &contents)
+                        .await?,
+                );
+                contents.clear();
+                count = 0;
+                part += 1;
+            }
+        }
+
+        if count > 0 {
+            files.push(
+                writer
+                    .append_rows(table.name, part, count, &contents)
+                    .await?,
+            );
+        }
+    }
+
+    Ok(files)
+}
+
+/// Exports every row of a tenant as a gzipped tarball of NDJSON files, one or more per table,
+/// followed by a manifest with their checksums. The archive is streamed as it's read, so it
+/// isn't a snapshot of a single point in time unless the tenant is suspended first. It holds
+/// the tenant's signing keys and password hashes, so it must be stored securely.
+///
+/// The stream ends with an error if the export fails midway, so the archive is never mistaken
+/// for a complete one.
+pub fn export(state: AppState, tenant_id: String) -> impl Stream<Item = io::Result<Bytes>> {
+    let (sender, receiver) = mpsc::channel(8);
+
+    tokio::spawn(async move {
+        let state = state.read().await;
+        let mut writer = Writer::new(sender.clone());
+
+        let result = async {
+            let files = write(&state.db, &tenant_id, &mut writer).await?;
+
+            let manifest = Manifest {
+                format: FORMAT.to_string(),
+                version: VERSION,
+                tenant_id: tenant_id.clone(),
+                exported_at: Utc::now().timestamp_millis(),
+                files,
+            };
+
+            writer
+                .append(MANIFEST_PATH, &serde_json::to_vec_pretty(&manifest)?)
+                .await?;
+            writer.finish().await
+        }
+        .await;
+
+        if let Err(e) = result {
+            event!(Level::WARN, error = format!("{e}"), tenant_id);
+            sender.send(Err(e)).await.ok();
+        }
+    });
+
+    stream::unfold(receiver, |mut receiver| async move {
+        receiver.recv().await.map(|chunk| (chunk, receiver))
+    })
+}
+
+#[derive(Debug)]
+pub enum ImportError {
+    /// The archive couldn't be downloaded.
+    Download(String),
+
+    /// The archive is malformed, corrupted or from an unsupported version.
+    Invalid(String),
+    Query(QueryError),
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Download(e) => write!(f, "The archive couldn't be downloaded: {e}"),
+            Self::Invalid(e) => write!(f, "The archive is invalid: {e}"),
+            Self::Query(e) => write!(f, "The archive couldn't be written: {e}"),
+        }
+    }
+}
+
+impl From<QueryError> for ImportError {
+    fn from(e: QueryError) -> Self {
+        Self::Query(e)
+    }
+}
+
+fn invalid(e: impl fmt::Display) -> ImportError {
+    ImportError::Invalid(e.to_string())
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    Running,
+    Completed,
+
+    /// The import stopped midway. The tenant is left suspended with the rows written so far,
+    /// and must be deleted before importing it again.
+    Failed,
+}
+
+impl Status {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Running => "running",
+            Self::Completed => "completed",
+            Self::Failed => "failed",
+        }
+    }
+
+    fn parse(status: &str) -> Option<Self> {
+        match status {
+            "running" => Some(Self::Running),
+            "completed" => Some(Self::Completed),
+            "failed" => Some(Self::Failed),
+            _ => None,
+        }
+    }
+}
+
+/// The import of an archive into a tenant.
+#[derive(Debug, Serialize)]
+pub struct Import {
+    pub tenant_id: String,
+
+    /// The ID of the tenant in the archive, once it's read.
+    pub source_tenant_id: Option<String>,
+    pub status: Status,
+    pub requested_by: Option<String>,
+    pub requested_at: Option<i64>,
+    pub completed_at: Option<i64>,
+
+    /// The rows written to each table so far.
+    pub imported: Map<String, Value>,
+
+    /// Hosts of the archive left out, as other tenants hold them.
+    pub skipped_hosts: Vec<String>,
+    pub error: Option<String>,
+}
+
+type ImportRow = (
+    String,
+    Option<String>,
+    Option<String>,
+    Option<String>,
+    Option<CqlTimestamp>,
+    Option<CqlTimestamp>,
+    Option<HashMap<String, i64>>,
+    Option<Vec<String>>,
+    Option<String>,
+);
+
+const COLUMNS: &str = "tenant_id, source_tenant_id, status, requested_by, requested_at, completed_at, imported, skipped_hosts, error";
+
+fn from_row(
+    (
+        tenant_id,
+        source_tenant_id,
+        status,
+        requested_by,
+        requested_at,
+        completed_at,
+        imported,
+        skipped_hosts,
+        error,
+    ): ImportRow,
+) -> Option<Import> {
+    Some(Import {
+        tenant_id,
+        source_tenant_id,
+        status: Status::parse(status.as_deref()?)?,
+        requested_by,
+        requested_at: requested_at.map(|CqlTimestamp(t)| t),
+        completed_at: completed_at.map(|CqlTimestamp(t)| t),
+        imported: imported
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(table, count)| (table, json!(count)))
+            .collect(),
+        skipped_hosts: skipped_hosts.unwrap_or_default(),
+        error,
+    })
+}
+
+pub async fn get_import(db: &Session, tenant_id: &str) -> Result<Option<Import>, QueryError> {
+    let result = db
+        .query_unpaged(
+            format!("SELECT {COLUMNS} FROM tenant_imports WHERE tenant_id = ?"),
+            (tenant_id,),
+        )
+        .await?;
+
+    Ok(result
+        .maybe_first_row_typed::<ImportRow>()
+        .ok()
+        .flatten()
+        .and_then(from_row))
+}
+
+/// Reserves the tenant ID for an import, creating the tenant suspended until the import
+/// completes, with the admin requesting it as its admin. Returns `None` if the ID is taken.
+pub async fn start_import(
+    state: &State,
+    tenant_id: &str,
+    requested_by: &str,
+) -> Result<Option<Import>, QueryError> {
+    let now = Utc::now().timestamp_millis();
+
+    let result = state
+        .db
+        .query_unpaged(
+            "INSERT INTO tenants (tenant_id, name, is_suspended, suspended_at, created_at, updated_at) VALUES (?, ?, true, ?, ?, ?) IF NOT EXISTS",
+            (tenant_id, tenant_id, CqlTimestamp(now), CqlTimestamp(now), CqlTimestamp(now)),
+        )
+        .await?;
+
+    let applied = result
+        .first_row()
+        .ok()
+        .and_then(|row| row.columns.into_iter().next().flatten())
+        .and_then(|applied| applied.as_boolean())
+        .unwrap_or(false);
+
+    // Lookups of the ID may have been cached as missing.
+    cache::TENANTS.invalidate(&state.redis, tenant_id).await;
+
+    if !applied {
+        return Ok(None);
+    }
+
+    state
+        .db
+        .query_unpaged(
+            "
+                INSERT INTO tenant_imports (
+                    tenant_id, source_tenant_id, status, requested_by, requested_at, completed_at, imported, skipped_hosts, error
+                ) VALUES (
+                    ?, null, ?, ?, ?, null, null, null, null
+                )
+            ",
+            (
+                tenant_id,
+                Status::Running.as_str(),
+                requested_by,
+                CqlTimestamp(now),
+            ),
+        )
+        .await?;
+
+    tenants::add_admin(&state.db, tenant_id, requested_by).await?;
+
+    Ok(Some(Import {
+        tenant_id: tenant_id.to_string(),
+        source_tenant_id: None,
+        status: Status::Running,
+        requested_by: Some(requested_by.to_string()),
+        requested_at: Some(now),
+        completed_at: None,
+        imported: Map::new(),
+        skipped_hosts: vec![],
+        error: None,
+    }))
+}
+
+/// An archive downloaded to a temporary file, removed once dropped.
+struct Spool {
+    path: PathBuf,
+}
+
+impl Spool {
+    fn new() -> Self {
+        Self {
+            path: env::temp_dir().join(format!("accesscore-import-{}.tar.gz", gen_id(None))),
+        }
+    }
+
+    fn open(&self) -> Result<tar::Archive<GzDecoder<BufReader<fs::File>>>, ImportError> {
+        let file = fs::File::open(&self.path).map_err(invalid)?;
+        Ok(tar::Archive::new(GzDecoder::new(BufReader::new(file))))
+    }
+}
+
+impl Drop for Spool {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Checks whether an archive can be downloaded from a URL: over HTTP or HTTPS, from a host that
+/// could be public. Names are only checked to resolve to public addresses when connecting.
+pub fn is_valid_url(url: &str) -> bool {
+    Url::parse(url).is_ok_and(|url| {
+        ["http", "https"].contains(&url.scheme()) && url.host_str().is_some_and(dns::is_public_host)
+    })
+}
+
+async fn download(url: &str) -> Result<Spool, ImportError> {
+    let download = |e: reqwest::Error| ImportError::Download(e.to_string());
+    let write = |e: io::Error| ImportError::Download(e.to_string());
+
+    if !is_valid_url(url) {
+        return Err(ImportError::Download(
+            "The archive isn't on a public host.".to_string(),
+        ));
+    }
+
+    let mut response = CLIENT
+        .get(url)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(download)?;
+
+    let spool = Spool::new();
+    let mut file = tokio::fs::File::create(&spool.path).await.map_err(write)?;
+    let mut size = 0;
+
+    while let Some(chunk) = response.chunk().await.map_err(download)? {
+        size += chunk.len();
+
+        if size > MAX_ARCHIVE_SIZE {
+            return Err(ImportError::Download(
+                "The archive is larger than 256 MiB.".to_string(),
+            ));
+        }
+
+        file.write_all(&chunk).await.map_err(write)?;
+    }
+
+    file.flush().await.map_err(write)?;
+
+    Ok(spool)
+}
+
+/// Reads the next line of an NDJSON file into `line`, returning `false` at the end of the file.
+fn read_line(reader: &mut impl BufRead, line: &mut Vec<u8>) -> Result<bool, ImportError> {
+    line.clear();
+
+    reader
+        .by_ref()
+        .take(MAX_ROW_SIZE + 1)
+        .read_until(b'\n', line)
+        .map_err(invalid)?;
+
+    if line.len() as u64 > MAX_ROW_SIZE {
+        return Err(invalid("A row is larger than 1 MiB."));
+    }
+
+    Ok(!line.is_empty())
+}
+
+/// Parses a line of an NDJSON file, skipping blank ones.
+fn parse(line: &[u8]) -> Result<Option<Map<String, Value>>, ImportError> {
+    if line.trim_ascii().is_empty() {
+        return Ok(None);
+    }
+
+    serde_json::from_slice(line).map(Some).map_err(invalid)
+}
+
+/// Reads through an archive, checking every file against the manifest, without keeping the
+/// files.
+fn verify(spool: &Spool) -> Result<Manifest, ImportError> {
+    let mut archive = spool.open()?;
+    let mut files: HashMap<String, (String, u64)> = HashMap::new();
+    let mut manifest: Option<Manifest> = None;
+    let mut size = 0;
+    let mut line = vec![];
+
+    for entry in archive.entries().map_err(invalid)? {
+        let entry = entry.map_err(invalid)?;
+
+        if entry.header().entry_type() != tar::EntryType::Regular {
+            continue;
+        }
+
+        size += entry.size();
+
+        if size > MAX_UNPACKED_SIZE {
+            return Err(invalid("It's larger than 2 GiB once decompressed."));
+        }
+
+        let path = entry.path().map_err(invalid)?.to_string_lossy().to_string();
+
+        if files.contains_key(&path) || (path == MANIFEST_PATH && manifest.is_some()) {
+            return Err(invalid(format!("The file {path} appears twice.")));
+        }
+
+        if path == MANIFEST_PATH {
+            manifest =
+                Some(serde_json::from_reader(entry.take(MAX_MANIFEST_SIZE)).map_err(invalid)?);
+
+            continue;
+        }
+
+        let mut reader = BufReader::new(entry);
+        let mut hasher = Sha256::new();
+        let mut rows = 0;
+
+        while read_line(&mut reader, &mut line)? {
+            hasher.update(&line);
+
+            if parse(&line)?.is_some() {
+                rows += 1;
+            }
+        }
+
+        files.insert(path, (format!("{:x}", hasher.finalize()), rows));
+    }
+
+    let manifest = manifest.ok_or_else(|| invalid("It has no manifest."))?;
+
+    if manifest.format != FORMAT {
+        return Err(invalid("It isn't a tenant archive."));
+    }
+
+    if manifest.version > VERSION {
+        return Err(invalid(format!(
+            "Its version {} is newer than the supported version {VERSION}.",
+            manifest.version
+        )));
+    }
+
+    if manifest
+        .files
+        .iter()
+        .filter(|file| file.table == "tenants")
+        .map(|file| file.rows)
+        .sum::<u64>()
+        != 1
+    {
+        return Err(invalid("It doesn't hold exactly one tenant."));
+    }
+
+    for file in &manifest.files {
+        if DROPPED.contains(&file.table.as_str()) {
+            continue;
+        }
+
+        if !tables().any(|table| table.name == file.table) {
+            return Err(invalid(format!("The table {} is unknown.", file.table)));
+        }
+
+        let (sha256, rows) = files
+            .get(&file.path)
+            .ok_or_else(|| invalid(format!("The file {} is missing.", file.path)))?;
+
+        if *sha256 != file.sha256 {
+            return Err(invalid(format!(
+                "The checksum of {} doesn't match.",
+                file.path
+            )));
+        }
+
+        if *rows != file.rows {
+            return Err(invalid(format!(
+                "The row count of {} doesn't match.",
+                file.path
+            )));
+        }
+    }
+
+    Ok(manifest)
+}
+
+/// Rows of a table read from an archive, under the new tenant ID.
+struct Batch {
+    table: String,
+    rows: Vec<Value>,
+}
+
+/// Reads the rows of a verified archive in batches, sending the tenant row last as it
+/// overwrites the suspended one reserving the ID. Stops early once the receiver is gone.
+fn read(
+    spool: &Spool,
+    tables: &HashMap<String, String>,
+    tenant_id: &str,
+    sender: &mpsc::Sender<Batch>,
+) -> Result<(), ImportError> {
+    let mut archive = spool.open()?;
+    let mut tenant = vec![];
+    let mut line = vec![];
+
+    for entry in archive.entries().map_err(invalid)? {
+        let entry = entry.map_err(invalid)?;
+
+        if entry.header().entry_type() != tar::EntryType::Regular {
+            continue;
+        }
+
+        let path = entry.path().map_err(invalid)?.to_string_lossy().to_string();
+
+        let Some(table) = tables.get(&path) else {
+            continue;
+        };
+
+        let mut reader = BufReader::new(entry);
+        let mut batch = Batch {
+            table: table.clone(),
+            rows: vec![],
+        };
+
+        while read_line(&mut reader, &mut line)? {
+            let Some(mut row) = parse(&line)? else {
+                continue;
+            };
+
+            row.insert("tenant_id".to_string(), json!(tenant_id));
+
+            if table == "tenants" {
+                tenant.push(Value::Object(row));
+                continue;
+            }
+
+            batch.rows.push(Value::Object(row));
+
+            if batch.rows.len() == BATCH_SIZE {
+                let rows = mem::take(&mut batch.rows);
+
+                if sender
+                    .blocking_send(Batch {
+                        table: table.clone(),
+                        rows,
+                    })
+                    .is_err()
+                {
+                    return Ok(());
+                }
+            }
+        }
+
+        if !batch.rows.is_empty() && sender.blocking_send(batch).is_err() {
+            return Ok(());
+        }
+    }
+
+    let _ = sender.blocking_send(Batch {
+        table: "tenants".to_string(),
+        rows: tenant,
+    });
+
+    Ok(())
+}
+
+/// Writes rows of a table.
+async fn insert(
+    db: &Session,
+    statement: &PreparedStatement,
+    rows: &[Value],
+) -> Result<(), QueryError> {
+    // The writes are built up front, as a stream mapping over borrowed rows isn't `Send`.
+    let writes: Vec<_> = rows
+        .iter()
+        .map(|row| db.execute_unpaged(statement, (row.to_string(),)))
+        .collect();
+
+    let mut writes = stream::iter(writes).buffer_unordered(CONCURRENCY);
+
+    while let Some(result) = writes.next().await {
+        result?;
+    }
+
+    Ok(())
+}
+
+/// Writes every row of a verified archive as it's read, without holding more than a few
+/// batches in memory.
+async fn load(
+    state: &State,
+    tenant_id: &str,
+    manifest: &Manifest,
+    spool: Arc<Spool>,
+) -> Result<Vec<String>, ImportError> {
+    let mut statements: HashMap<String, PreparedStatement> = HashMap::new();
+    let mut imported: HashMap<String, i64> = HashMap::new();
+    let mut hosts_to_activate = vec![];
+
+    let tables: HashMap<String, String> = manifest
+        .files
+        .iter()
+        .filter(|file| !DROPPED.contains(&file.table.as_str()))
+        .map(|file| (file.path.clone(), file.table.clone()))
+        .collect();
+
+    let (sender, mut receiver) = mpsc::channel(2);
+
+    let reader = {
+        let tenant_id = tenant_id.to_string();
+        tokio::task::spawn_blocking(move || read(&spool, &tables, &tenant_id, &sender))
+    };
+
+    while let Some(batch) = receiver.recv().await {
+        let statement = match statements.get(&batch.table) {
+            Some(statement) => statement,
+            None => {
+                let statement = state
+                    .db
+                    .prepare(format!("INSERT INTO {} JSON ?", batch.table))
+                    .await?;
+
+                statements.entry(batch.table.clone()).or_insert(statement)
+            }
+        };
+
+        insert(&state.db, statement, &batch.rows).await?;
+
+        if batch.table == "tenant_hosts" {
+            hosts_to_activate.extend(
+                batch
+                    .rows
+                    .iter()
+                    .filter(|row| row["is_verified"] == json!(true))
+                    .filter_map(|row| row["host"].as_str().map(str::to_string)),
+            );
+        }
+
+        let count = imported.entry(batch.table.clone()).or_default();
+        *count += batch.rows.len() as i64;
+
+        state
+            .db
+            .query_unpaged(
+                "UPDATE tenant_imports SET imported[?] = ? WHERE tenant_id = ?",
+                (&batch.table, *count, tenant_id),
+            )
+            .await?;
+    }
+
+    reader.await.map_err(invalid)??;
+
+    // Usage isn't archived, so imported users count as added.
+    if let Some(users) = imported.get("users") {
+        usage::record(tenant_id, Metric::Users, *users);
+    }
+
+    let mut skipped_hosts = vec![];
+
+    for host in hosts_to_activate {
+        if !hosts::activate(state, tenant_id, &host).await? {
+            state
+                .db
+                .query_unpaged(
+                    "DELETE FROM tenant_hosts WHERE tenant_id = ? AND host = ?",
+                    (tenant_id, &host),
+                )
+                .await?;
+
+            skipped_hosts.push(host);
+        }
+    }
+
+    cache::TENANTS.invalidate(&state.redis, tenant_id).await;
+    cache::SETTINGS.invalidate(&state.redis, tenant_id).await;
+
+    Ok(skipped_hosts)
+}
+
+/// Downloads an archive and writes it under `tenant_id`, which must be reserved with
+/// `start_import`. Nothing is written unless the whole archive matches its manifest. The
+/// outcome is recorded on the import.
+pub async fn import(state: AppState, tenant_id: String, url: String) {
+    let state = state.read().await;
+
+    let result = async {
+        let spool = Arc::new(download(&url).await?);
+        let manifest = {
+            let spool = spool.clone();
+            tokio::task::spawn_blocking(move || verify(&spool))
+                .await
+                .map_err(invalid)??
+        };
+
+        state
+            .db
+            .query_unpaged(
+                "UPDATE tenant_imports SET source_tenant_id = ? WHERE tenant_id = ?",
+                (&manifest.tenant_id, &tenant_id),
+            )
+            .await?;
+
+        load(&state, &tenant_id, &manifest, spool).await
+    }
+    .await;
+
+    let (status, skipped_hosts, error) = match result {
+        Ok(skipped_hosts) => {
+            event!(Level::INFO, tenant_id, "Imported tenant.");
+            (Status::Completed, skipped_hosts, None)
+        }
+        Err(e) => {
+            event!(Level::WARN, error = format!("{e}"), tenant_id);
+            (Status::Failed, vec![], Some(e.to_string()))
+        }
+    };
+
+    if let Err(e) = state
+        .db
+        .query_unpaged(
+            "UPDATE tenant_imports SET status = ?, completed_at = ?, skipped_hosts = ?, error = ? WHERE tenant_id = ?",
+            (
+                status.as_str(),
+                CqlTimestamp(Utc::now().timestamp_millis()),
+                skipped_hosts,
+                error,
+                &tenant_id,
+            ),
+        )
+        .await
+    {
+        event!(Level::WARN, error = format!("{e}"), tenant_id);
+    }
+}
@@ -4,4 +4,5 @@ pub struct Auth {
     pub token: Option<String>,
     pub scopes: Vec<String>,
     pub client_id: Option<String>,
+    pub device_id: Option<String>,
 }
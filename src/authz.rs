@@ -0,0 +1,295 @@
+use redis::AsyncCommands;
+use scylla::transport::errors::QueryError;
+use serde::{Deserialize, Serialize};
+use tracing::{event, Level};
+
+use crate::{
+    organizations,
+    permissions::{self, TraceEntry},
+    policies::{self, Evaluation, Policy, RequestContext},
+    state::State,
+};
+
+/// Permission required to check what users other than oneself can do.
+pub const CHECK_PERMISSION: &str = "authz.check";
+
+/// Maximum number of checks in a batch.
+pub const MAX_BATCH_SIZE: usize = 100;
+
+/// Seconds a decision is cached for, unless invalidated earlier.
+const CACHE_TTL: u64 = 300;
+
+/// What a check is about. Checks without a resource are about the whole tenant.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Resource {
+    Tenant,
+
+    /// `organization:{organization_id}`, which requires the subject to be a member and adds
+    /// the roles of their membership.
+    Organization(String),
+
+    /// `user:{user_id}`, decided like the tenant but exposing the user's attributes to
+    /// policies.
+    User(String),
+}
+
+impl Resource {
+    /// Parses a resource reference, returning `None` if it's malformed or of an unknown kind.
+    pub fn parse(resource: Option<&str>) -> Option<Self> {
+        let Some(resource) = resource else {
+            return Some(Self::Tenant);
+        };
+
+        match resource.split_once(':') {
+            Some(("organization", id)) if !id.is_empty() => {
+                Some(Self::Organization(id.to_string()))
+            }
+            Some(("user", id)) if !id.is_empty() => Some(Self::User(id.to_string())),
+            _ => None,
+        }
+    }
+
+    fn key(&self) -> String {
+        match self {
+            Self::Tenant => "tenant".to_string(),
+            Self::Organization(id) => format!("organization:{id}"),
+            Self::User(_) => "tenant".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Reason {
+    Granted,
+
+    /// A deny rule or policy took precedence over any grant.
+    Denied,
+
+    /// Nothing grants the permission, neither a rule nor a policy.
+    NotGranted,
+
+    /// The resource belongs to an organization the subject isn't a member of.
+    NotMember,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Decision {
+    pub allowed: bool,
+    pub reason: Reason,
+
+    /// The policy that decided, if any did.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub policy: Option<String>,
+
+    /// The rules that matched, only included when explaining the decision.
+    #[serde(skip_serializing_if = "Option::is_none", skip_deserializing)]
+    pub trace: Option<Vec<TraceEntry>>,
+
+    /// The policies applying to the action, only included when explaining the decision.
+    #[serde(skip_serializing_if = "Option::is_none", skip_deserializing)]
+    pub policies: Option<Vec<Evaluation>>,
+}
+
+impl Decision {
+    fn new(reason: Reason) -> Self {
+        Self {
+            allowed: reason == Reason::Granted,
+            reason,
+            policy: None,
+            trace: None,
+            policies: None,
+        }
+    }
+}
+
+fn version_key(tenant_id: &str) -> String {
+    format!("authz:{tenant_id}:version")
+}
+
+fn cache_key(
+    tenant_id: &str,
+    version: u64,
+    subject: &str,
+    resource: &Resource,
+    action: &str,
+) -> String {
+    format!(
+        "authz:{tenant_id}:{version}:{subject}:{}:{action}",
+        resource.key()
+    )
+}
+
+/// Decides out of the subject's own permissions, their roles, their groups' and, for
+/// organization resources, their membership. Decisions are cached in Redis until anything they
+/// depend on changes. When `explain` is set, the cache is bypassed and the matching rules are
+/// returned along with the decision.
+async fn rules(
+    state: &State,
+    tenant_id: &str,
+    subject: &str,
+    action: &str,
+    resource: &Resource,
+    explain: bool,
+) -> Result<Decision, QueryError> {
+    let mut redis = None;
+    let mut key: Option<String> = None;
+
+    if !explain {
+        match state.redis.get_multiplexed_async_connection().await {
+            Ok(mut conn) => {
+                let version: Option<u64> = conn.get(version_key(tenant_id)).await.unwrap_or(None);
+                let cache_key =
+                    cache_key(tenant_id, version.unwrap_or(0), subject, resource, action);
+                let cached: Option<String> = conn.get(&cache_key).await.unwrap_or(None);
+
+                if let Some(decision) = cached.and_then(|c| serde_json::from_str(&c).ok()) {
+                    return Ok(decision);
+                }
+
+                key = Some(cache_key);
+                redis = Some(conn);
+            }
+            Err(e) => event!(Level::WARN, error = format!("{e}")),
+        }
+    }
+
+    let organization_id = match resource {
+        Resource::Tenant | Resource::User(_) => None,
+        Resource::Organization(id) => Some(id.as_str()),
+    };
+
+    let decision = match organization_id {
+        Some(id)
+            if organizations::role(&state.db, tenant_id, id, subject)
+                .await?
+                .is_none() =>
+        {
+            Decision::new(Reason::NotMember)
+        }
+        _ if explain => {
+            let trace =
+                permissions::explain(state, tenant_id, subject, organization_id, action).await?;
+            let reason = match trace.first() {
+                Some(entry) if entry.deny => Reason::Denied,
+                Some(_) => Reason::Granted,
+                None => Reason::NotGranted,
+            };
+
+            Decision {
+                trace: Some(trace),
+                ..Decision::new(reason)
+            }
+        }
+        _ => {
+            let effective = match organization_id {
+                Some(id) => {
+                    permissions::organization_permissions(state, tenant_id, subject, id).await?
+                }
+                None => permissions::effective_permissions(state, tenant_id, subject).await?,
+            };
+
+            Decision::new(match effective.decide(action) {
+                Some(true) => Reason::Granted,
+                Some(false) => Reason::Denied,
+                None => Reason::NotGranted,
+            })
+        }
+    };
+
+    if let (Some(conn), Some(key)) = (redis.as_mut(), key) {
+        let result: redis::RedisResult<()> = conn
+            .set_ex(
+                key,
+                serde_json::to_string(&decision).unwrap_or_default(),
+                CACHE_TTL,
+            )
+            .await;
+
+        if let Err(e) = result {
+            event!(Level::WARN, error = format!("{e}"));
+        }
+    }
+
+    Ok(decision)
+}
+
+/// Decides whether `subject` can perform `action` on `resource` in the context of `request`,
+/// out of their permissions and the tenant's policies.
+pub async fn check(
+    state: &State,
+    tenant_id: &str,
+    subject: &str,
+    action: &str,
+    resource: &Resource,
+    request: &RequestContext,
+    explain: bool,
+) -> Result<Decision, QueryError> {
+    let policies = policies::cached(state, tenant_id).await?;
+
+    decide(
+        state, tenant_id, subject, action, resource, request, &policies, explain,
+    )
+    .await
+}
+
+/// Decides like [`check`] but with the given policies, which is how dry-runs try policies out
+/// before saving them. Policies are evaluated on every decision, as their conditions may depend
+/// on the request. A deny policy whose condition holds overrides any grant, while an allow
+/// policy only grants what no rule denies.
+#[allow(clippy::too_many_arguments)]
+pub async fn decide(
+    state: &State,
+    tenant_id: &str,
+    subject: &str,
+    action: &str,
+    resource: &Resource,
+    request: &RequestContext,
+    policies: &[Policy],
+    explain: bool,
+) -> Result<Decision, QueryError> {
+    let mut decision = rules(state, tenant_id, subject, action, resource, explain).await?;
+
+    if decision.reason == Reason::NotMember
+        || !policies.iter().any(|policy| policy.applies_to(action))
+    {
+        return Ok(decision);
+    }
+
+    let context = policies::context(state, tenant_id, subject, resource, request).await?;
+    let evaluations = policies::evaluate(policies, action, &context);
+
+    if let Some(evaluation) = evaluations.iter().find(|e| e.denies()) {
+        decision.policy = Some(evaluation.name.clone());
+        decision.reason = Reason::Denied;
+        decision.allowed = false;
+    } else if decision.reason == Reason::NotGranted {
+        if let Some(evaluation) = evaluations.iter().find(|e| e.allows()) {
+            decision.policy = Some(evaluation.name.clone());
+            decision.reason = Reason::Granted;
+            decision.allowed = true;
+        }
+    }
+
+    if explain {
+        decision.policies = Some(evaluations);
+    }
+
+    Ok(decision)
+}
+
+/// Drops every cached decision of the tenant by bumping the cache version, letting the stale
+/// entries expire on their own. Called whenever permissions, roles, groups or organization
+/// memberships change.
+pub async fn invalidate(state: &State, tenant_id: &str) {
+    let result: redis::RedisResult<()> = async {
+        let mut conn = state.redis.get_multiplexed_async_connection().await?;
+
+        conn.incr(version_key(tenant_id), 1).await
+    }
+    .await;
+
+    if let Err(e) = result {
+        event!(Level::WARN, error = format!("{e}"));
+    }
+}
@@ -0,0 +1,328 @@
+use std::{
+    future::Future,
+    num::NonZeroUsize,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        LazyLock, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use futures::StreamExt;
+use lru::LruCache;
+use redis::AsyncCommands;
+use redis_pool::SingleRedisPool;
+use scylla::transport::errors::QueryError;
+use serde::{de::DeserializeOwned, Serialize};
+use tracing::{event, Level};
+
+/// Redis channel on which invalidated keys are published as `{cache}:{key}`, so every instance
+/// drops them from its in-process layer.
+pub const INVALIDATION_CHANNEL: &str = "cache:invalidate";
+
+/// Tenants owning each host or wildcard, as resolved by the tenant middleware.
+pub static HOSTS: LazyLock<Cache> = LazyLock::new(|| {
+    Cache::new(
+        "hosts",
+        10_000,
+        Duration::from_secs(300),
+        Duration::from_secs(30),
+    )
+});
+
+/// Whether each tenant is suspended or a sandbox, as checked by the tenant middleware.
+pub static TENANTS: LazyLock<Cache> = LazyLock::new(|| {
+    Cache::new(
+        "tenants",
+        10_000,
+        Duration::from_secs(300),
+        Duration::from_secs(30),
+    )
+});
+
+/// Access tokens, as looked up by the authentication middleware. Kept briefly, since a token
+/// stays usable from the cache until it's invalidated or its entry expires.
+pub static TOKENS: LazyLock<Cache> = LazyLock::new(|| {
+    Cache::new(
+        "tokens",
+        100_000,
+        Duration::from_secs(60),
+        Duration::from_secs(10),
+    )
+});
+
+/// The feature flags of each tenant, all in one entry, as checked by handlers.
+pub static FLAGS: LazyLock<Cache> = LazyLock::new(|| {
+    Cache::new(
+        "flags",
+        10_000,
+        Duration::from_secs(300),
+        Duration::from_secs(30),
+    )
+});
+
+/// The stored settings of each tenant, all in one entry, as read by handlers.
+pub static SETTINGS: LazyLock<Cache> = LazyLock::new(|| {
+    Cache::new(
+        "settings",
+        10_000,
+        Duration::from_secs(300),
+        Duration::from_secs(30),
+    )
+});
+
+/// Every cache, as named in invalidations and metrics.
+fn caches() -> [&'static Cache; 5] {
+    [&HOSTS, &TENANTS, &TOKENS, &FLAGS, &SETTINGS]
+}
+
+struct Entry {
+    /// The value as JSON, where `null` records that there's none.
+    value: String,
+    expires_at: Instant,
+}
+
+/// Hit counts of a cache since the instance started.
+#[derive(Debug, Serialize)]
+pub struct Stats {
+    pub name: &'static str,
+    pub size: usize,
+    pub local_hits: u64,
+    pub redis_hits: u64,
+    pub misses: u64,
+
+    /// The share of lookups answered by either layer, from 0 to 1.
+    pub hit_rate: f64,
+}
+
+/// A read-through cache in front of Scylla, with an in-process LRU layer, then a Redis layer.
+/// Missing values are cached too, for a shorter time, so lookups of unknown keys don't all reach
+/// Scylla.
+pub struct Cache {
+    name: &'static str,
+    ttl: Duration,
+    negative_ttl: Duration,
+    local: Mutex<LruCache<String, Entry>>,
+
+    /// Bumped on every eviction, so values loaded before one aren't cached after it.
+    generation: AtomicU64,
+    local_hits: AtomicU64,
+    redis_hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl Cache {
+    fn new(name: &'static str, capacity: usize, ttl: Duration, negative_ttl: Duration) -> Self {
+        Self {
+            name,
+            ttl,
+            negative_ttl,
+            local: Mutex::new(LruCache::new(NonZeroUsize::new(capacity).unwrap())),
+            generation: AtomicU64::new(0),
+            local_hits: AtomicU64::new(0),
+            redis_hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    fn redis_key(&self, key: &str) -> String {
+        format!("cache:{}:{key}", self.name)
+    }
+
+    /// Returns the value of `key` from the first layer holding it, loading it from Scylla with
+    /// `load` if none does. Redis failures are logged and skipped.
+    pub async fn get<T, F, Fut>(
+        &self,
+        redis: &SingleRedisPool,
+        key: &str,
+        load: F,
+    ) -> Result<Option<T>, QueryError>
+    where
+        T: Serialize + DeserializeOwned,
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<Option<T>, QueryError>>,
+    {
+        let local = {
+            let mut local = self.local.lock().unwrap();
+
+            match local.get(key) {
+                Some(entry) if entry.expires_at > Instant::now() => Some(entry.value.clone()),
+                Some(_) => {
+                    local.pop(key);
+                    None
+                }
+                None => None,
+            }
+        };
+
+        if let Some(value) = local.and_then(|value| serde_json::from_str(&value).ok()) {
+            self.local_hits.fetch_add(1, Ordering::Relaxed);
+            return Ok(value);
+        }
+
+        let generation = self.generation.load(Ordering::Acquire);
+
+        let mut connection = match redis.get_multiplexed_async_connection().await {
+            Ok(connection) => Some(connection),
+            Err(e) => {
+                event!(Level::WARN, error = format!("{e}"), cache = self.name);
+                None
+            }
+        };
+
+        if let Some(connection) = &mut connection {
+            match connection
+                .get::<_, Option<String>>(self.redis_key(key))
+                .await
+            {
+                Ok(Some(json)) => {
+                    if let Ok(value) = serde_json::from_str::<Option<T>>(&json) {
+                        self.redis_hits.fetch_add(1, Ordering::Relaxed);
+                        self.store_local(key, json, value.is_some(), generation);
+
+                        return Ok(value);
+                    }
+                }
+                Ok(None) => {}
+                Err(e) => event!(Level::WARN, error = format!("{e}"), cache = self.name),
+            }
+        }
+
+        self.misses.fetch_add(1, Ordering::Relaxed);
+
+        let value = load().await?;
+        let json = serde_json::to_string(&value).unwrap();
+        let ttl = self.ttl_of(value.is_some());
+
+        if let Some(connection) = &mut connection {
+            if let Err(e) = connection
+                .set_ex::<_, _, ()>(self.redis_key(key), &json, ttl.as_secs())
+                .await
+            {
+                event!(Level::WARN, error = format!("{e}"), cache = self.name);
+            }
+        }
+
+        self.store_local(key, json, value.is_some(), generation);
+
+        Ok(value)
+    }
+
+    fn ttl_of(&self, is_some: bool) -> Duration {
+        if is_some {
+            self.ttl
+        } else {
+            self.negative_ttl
+        }
+    }
+
+    fn store_local(&self, key: &str, value: String, is_some: bool, generation: u64) {
+        let mut local = self.local.lock().unwrap();
+
+        if self.generation.load(Ordering::Acquire) == generation {
+            local.put(
+                key.to_string(),
+                Entry {
+                    value,
+                    expires_at: Instant::now() + self.ttl_of(is_some),
+                },
+            );
+        }
+    }
+
+    /// Drops a key from the in-process layer, or every key.
+    fn evict(&self, key: Option<&str>) {
+        let mut local = self.local.lock().unwrap();
+        self.generation.fetch_add(1, Ordering::AcqRel);
+
+        match key {
+            Some(key) => {
+                local.pop(key);
+            }
+            None => local.clear(),
+        }
+    }
+
+    /// Drops a key from every layer, and tells the other instances to drop it too. Call it
+    /// after the change is written to Scylla.
+    pub async fn invalidate(&self, redis: &SingleRedisPool, key: &str) {
+        self.evict(Some(key));
+
+        match redis.get_multiplexed_async_connection().await {
+            Ok(mut connection) => {
+                if let Err(e) = connection.del::<_, ()>(self.redis_key(key)).await {
+                    event!(Level::WARN, error = format!("{e}"), cache = self.name);
+                }
+
+                if let Err(e) = connection
+                    .publish::<_, _, ()>(INVALIDATION_CHANNEL, format!("{}:{key}", self.name))
+                    .await
+                {
+                    event!(Level::WARN, error = format!("{e}"), cache = self.name);
+                }
+            }
+            Err(e) => event!(Level::WARN, error = format!("{e}"), cache = self.name),
+        }
+    }
+
+    pub fn stats(&self) -> Stats {
+        let local_hits = self.local_hits.load(Ordering::Relaxed);
+        let redis_hits = self.redis_hits.load(Ordering::Relaxed);
+        let misses = self.misses.load(Ordering::Relaxed);
+        let lookups = local_hits + redis_hits + misses;
+
+        Stats {
+            name: self.name,
+            size: self.local.lock().unwrap().len(),
+            local_hits,
+            redis_hits,
+            misses,
+            hit_rate: if lookups == 0 {
+                0.0
+            } else {
+                (local_hits + redis_hits) as f64 / lookups as f64
+            },
+        }
+    }
+}
+
+/// Returns the metrics of every cache.
+pub fn stats() -> Vec<Stats> {
+    caches().iter().map(|cache| cache.stats()).collect()
+}
+
+/// Listens for invalidations published by any instance, reconnecting when the subscription
+/// drops. Invalidations can be missed while reconnecting, so the in-process layers are cleared
+/// then.
+pub async fn subscribe(redis: SingleRedisPool) {
+    loop {
+        match redis.factory().get_async_pubsub().await {
+            Ok(mut pubsub) => match pubsub.subscribe(INVALIDATION_CHANNEL).await {
+                Ok(()) => {
+                    caches().iter().for_each(|cache| cache.evict(None));
+
+                    let mut messages = pubsub.on_message();
+
+                    while let Some(message) = messages.next().await {
+                        let payload = message.get_payload::<String>().ok();
+
+                        match payload
+                            .as_deref()
+                            .and_then(|payload| payload.split_once(':'))
+                        {
+                            Some((name, key)) => caches()
+                                .iter()
+                                .filter(|cache| cache.name == name)
+                                .for_each(|cache| cache.evict(Some(key))),
+                            None => caches().iter().for_each(|cache| cache.evict(None)),
+                        }
+                    }
+                }
+                Err(e) => event!(Level::WARN, error = format!("{e}")),
+            },
+            Err(e) => event!(Level::WARN, error = format!("{e}")),
+        }
+
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+}
@@ -0,0 +1,90 @@
+use rand::{rngs::OsRng, Rng};
+use redis::{aio::MultiplexedConnection, RedisResult};
+
+use crate::sandbox;
+
+/// How many times a code can be checked before it's invalidated.
+pub const MAX_ATTEMPTS: i64 = 5;
+
+/// Issues a 6-digit verification code for `subject`, valid for `ttl` seconds. Issuing a new code
+/// for the same `purpose` and `subject` replaces the previous one. Sandbox tenants always get
+/// `sandbox::CODE`.
+pub async fn issue(
+    redis: &mut MultiplexedConnection,
+    tenant_id: &str,
+    purpose: &str,
+    subject: &str,
+    ttl: u64,
+    is_sandbox: bool,
+) -> RedisResult<String> {
+    let code = if is_sandbox {
+        sandbox::CODE.to_string()
+    } else {
+        format!("{:06}", OsRng.gen_range(0..1_000_000))
+    };
+    let key = key(tenant_id, purpose, subject);
+
+    redis::pipe()
+        .atomic()
+        .cmd("SET")
+        .arg(&key)
+        .arg(&code)
+        .arg("EX")
+        .arg(ttl)
+        .cmd("DEL")
+        .arg(format!("{key}:a"))
+        .exec_async(redis)
+        .await?;
+
+    Ok(code)
+}
+
+/// Checks a code issued with `issue()`. The code is consumed if it matches, and discarded after
+/// `MAX_ATTEMPTS` failed checks.
+pub async fn check(
+    redis: &mut MultiplexedConnection,
+    tenant_id: &str,
+    purpose: &str,
+    subject: &str,
+    code: &str,
+) -> RedisResult<bool> {
+    let key = key(tenant_id, purpose, subject);
+    let attempts_key = format!("{key}:a");
+
+    let (stored, attempts): (Option<String>, i64) = redis::pipe()
+        .atomic()
+        .cmd("GET")
+        .arg(&key)
+        .cmd("INCR")
+        .arg(&attempts_key)
+        .query_async(redis)
+        .await?;
+
+    let Some(stored) = stored else {
+        redis::cmd("DEL")
+            .arg(&attempts_key)
+            .exec_async(redis)
+            .await?;
+        return Ok(false);
+    };
+
+    if stored == code || attempts >= MAX_ATTEMPTS {
+        redis::cmd("DEL")
+            .arg(&key)
+            .arg(&attempts_key)
+            .exec_async(redis)
+            .await?;
+    } else {
+        redis::cmd("EXPIRE")
+            .arg(&attempts_key)
+            .arg(900)
+            .exec_async(redis)
+            .await?;
+    }
+
+    Ok(stored == code && attempts <= MAX_ATTEMPTS)
+}
+
+fn key(tenant_id: &str, purpose: &str, subject: &str) -> String {
+    format!("code:{tenant_id}:{purpose}:{subject}")
+}
@@ -2,10 +2,19 @@ use std::collections::HashMap;
 use charybdis::{
     macros::{charybdis_model, charybdis_udt_model, charybdis_view_model},
     scylla::SerializeValue,
-    types::{Ascii, Blob, Boolean, Inet, Int, Map, SmallInt, Text, Timestamp},
+    types::{
+        Ascii, BigInt, Blob, Boolean, Counter, Date, Inet, Int, Map, SmallInt, Text, Timestamp,
+        TinyInt,
+    },
 };
 use num_derive::FromPrimitive;
 use num_traits::FromPrimitive;
+
+// Enums also used at runtime are defined there, so the two can't drift apart.
+pub use crate::notifications::{
+    Channel as NotificationType, Event as NotificationEvent, Priority as NotificationPriority,
+};
+pub use crate::settings::Category as TenantSettingCategory;
 use scylla::cql_to_rust::{FromCqlVal, FromCqlValError};
 use scylla::frame::response::result::{ColumnType, CqlValue};
 use scylla::serialize::{writers::WrittenCellProof, CellWriter, SerializationError};
@@ -19,20 +28,219 @@ use scylla::serialize::{writers::WrittenCellProof, CellWriter, SerializationErro
 pub struct Tenant {
     pub tenant_id: Ascii,
     pub name: Ascii,
-    pub host: Ascii,
-    pub created_at: Ascii
+    pub is_suspended: Boolean,
+    pub suspended_at: Option<Timestamp>,
+    pub is_sandbox: Option<Boolean>,
+    pub created_at: Ascii,
+    pub updated_at: Option<Timestamp>
 }
 
-#[charybdis_view_model(
-    table_name = tenants_by_host,
-    base_table = tenants,
-    partition_keys = [host],
+/// The purge of a tenant's data, kept once completed as the record of the deletion.
+#[charybdis_model(
+    table_name = tenant_purges,
+    partition_keys = [tenant_id],
+    clustering_keys = []
+)]
+#[derive(Debug, Default)]
+pub struct TenantPurge {
+    pub tenant_id: Ascii,
+
+    /// `scheduled`, `running` or `completed`.
+    pub status: Ascii,
+    pub requested_by: Ascii,
+    pub requested_at: Timestamp,
+
+    /// When the grace period ends.
+    pub purge_after: Timestamp,
+    pub started_at: Option<Timestamp>,
+    pub completed_at: Option<Timestamp>,
+
+    /// Until when a worker holds the purge. Another one resumes it once this has passed.
+    pub lease_until: Option<Timestamp>,
+
+    /// The next step to run, from which the purge is resumed.
+    pub step: Int,
+
+    /// The rows deleted from each table.
+    pub deleted: Map<Ascii, BigInt>,
+
+    /// The signed deletion receipt as JSON, once completed.
+    pub receipt: Option<Text>
+}
+
+/// The import of an archive into a tenant, kept once completed.
+#[charybdis_model(
+    table_name = tenant_imports,
+    partition_keys = [tenant_id],
     clustering_keys = []
 )]
 #[derive(Debug, Default)]
-pub struct TenantByHost {
+pub struct TenantImport {
+    pub tenant_id: Ascii,
+    pub source_tenant_id: Option<Ascii>,
+
+    /// `running`, `completed` or `failed`.
+    pub status: Ascii,
+    pub requested_by: Ascii,
+    pub requested_at: Timestamp,
+    pub completed_at: Option<Timestamp>,
+
+    /// The rows written to each table.
+    pub imported: Map<Ascii, BigInt>,
+
+    /// Hosts left out, as other tenants hold them.
+    pub skipped_hosts: Option<Vec<Text>>,
+    pub error: Option<Text>
+}
+
+/// Monthly limits of a tenant on a usage metric, set by the platform.
+#[charybdis_model(
+    table_name = tenant_quotas,
+    partition_keys = [tenant_id],
+    clustering_keys = [metric]
+)]
+#[derive(Debug, Default)]
+pub struct TenantQuota {
+    pub tenant_id: Ascii,
+
+    /// `active_users`, `sign_ups`, `token_issuances`, `notifications` or `api_requests`.
+    pub metric: Ascii,
+    pub soft_limit: Option<BigInt>,
+    pub hard_limit: Option<BigInt>
+}
+
+/// The usage of a metric by a tenant on a day.
+#[charybdis_model(
+    table_name = tenant_usage,
+    partition_keys = [tenant_id],
+    clustering_keys = [metric, day]
+)]
+#[derive(Debug, Default)]
+pub struct TenantUsage {
+    pub tenant_id: Ascii,
+    pub metric: Ascii,
+    pub day: Date,
+    pub count: Counter
+}
+
+/// A user issued a token during a month, by the first day of the month.
+#[charybdis_model(
+    table_name = tenant_active_users,
+    partition_keys = [tenant_id, month],
+    clustering_keys = [user_id],
+
+    // Default TTL: 400 days.
+    table_options = r#"
+        default_time_to_live = 34560000
+    "#
+)]
+#[derive(Debug, Default)]
+pub struct TenantActiveUser {
+    pub tenant_id: Ascii,
+    pub month: Date,
+    pub user_id: Ascii,
+    pub first_seen_at: Timestamp
+}
+
+/// A feature flag of a tenant.
+#[charybdis_model(
+    table_name = tenant_flags,
+    partition_keys = [tenant_id],
+    clustering_keys = [flag]
+)]
+#[derive(Debug, Default)]
+pub struct TenantFlag {
     pub tenant_id: Ascii,
-    pub host: Ascii
+    pub flag: Ascii,
+    pub is_enabled: Boolean,
+
+    /// The percentage of users the flag is on for, or everyone if unset.
+    pub rollout: Option<TinyInt>,
+    pub description: Option<Text>,
+    pub updated_by: Ascii,
+    pub updated_at: Timestamp
+}
+
+/// A change of a tenant's feature flag, kept as its audit trail.
+#[charybdis_model(
+    table_name = tenant_flag_changes,
+    partition_keys = [tenant_id],
+    clustering_keys = [changed_at, change_id],
+    table_options = r#"
+        CLUSTERING ORDER BY (changed_at DESC, change_id ASC)
+    "#
+)]
+#[derive(Debug, Default)]
+pub struct TenantFlagChange {
+    pub tenant_id: Ascii,
+    pub changed_at: Timestamp,
+    pub change_id: Ascii,
+    pub flag: Ascii,
+    pub changed_by: Ascii,
+
+    /// The flag as JSON before and after the change, unset where there was none.
+    pub old_value: Option<Text>,
+    pub new_value: Option<Text>
+}
+
+/// An email of a sandbox tenant, kept for tests to read instead of being delivered.
+#[charybdis_model(
+    table_name = sandbox_messages,
+    partition_keys = [tenant_id],
+    clustering_keys = [created_at, message_id],
+
+    // Default TTL: 1 day.
+    table_options = r#"
+        CLUSTERING ORDER BY (created_at DESC, message_id ASC)
+        AND default_time_to_live = 86400
+    "#
+)]
+#[derive(Debug, Default)]
+pub struct SandboxMessage {
+    pub tenant_id: Ascii,
+    pub created_at: Timestamp,
+    pub message_id: Ascii,
+    pub recipient: Text,
+    pub user_id: Option<Ascii>,
+    pub event_type: SmallInt,
+    pub title: Text,
+    pub message: Text,
+    pub data: Map<Ascii, Text>
+}
+
+#[charybdis_model(
+    table_name = tenant_hosts,
+    partition_keys = [tenant_id],
+    clustering_keys = [host]
+)]
+#[derive(Debug, Default)]
+pub struct TenantHost {
+    pub tenant_id: Ascii,
+
+    /// A host name, optionally with a port, or a wildcard like `*.acme.example`.
+    pub host: Text,
+
+    /// Whether the tenant added the host as a custom domain, rather than the platform.
+    pub is_custom: Boolean,
+
+    /// How the custom domain is verified: `dns` or `http`.
+    pub method: Option<Ascii>,
+    pub challenge: Option<Ascii>,
+    pub is_verified: Boolean,
+    pub created_at: Timestamp,
+    pub verified_at: Option<Timestamp>
+}
+
+/// Active hosts, which belong to a single tenant.
+#[charybdis_model(
+    table_name = hosts,
+    partition_keys = [host],
+    clustering_keys = []
+)]
+#[derive(Debug, Default)]
+pub struct HostTenant {
+    pub host: Text,
+    pub tenant_id: Ascii
 }
 
 #[charybdis_model(
@@ -66,6 +274,21 @@ pub struct AdminUserByTenant {
     pub tenant_id: Ascii
 }
 
+#[charybdis_model(
+    table_name = tenant_signing_keys,
+    partition_keys = [tenant_id],
+    clustering_keys = [key_id]
+)]
+#[derive(Debug, Default)]
+pub struct TenantSigningKey {
+    pub tenant_id: Ascii,
+    pub key_id: Ascii,
+    pub algorithm: Ascii,
+    pub secret: Blob,
+    pub is_active: Boolean,
+    pub created_at: Timestamp,
+}
+
 #[charybdis_model(
     table_name = oauth_providers,
     partition_keys = [tenant_id],
@@ -94,12 +317,6 @@ pub struct TenantSetting {
     pub value: Ascii,
 }
 
-#[derive(Clone, Copy, FromPrimitive, Debug, Default)]
-pub enum TenantSettingCategory {
-    #[default]
-    Security,
-}
-
 impl SerializeValue for TenantSettingCategory {
     fn serialize<'b>(
         &self,
@@ -130,7 +347,8 @@ pub struct Organization {
     pub tenant_id: Ascii,
     pub organization_id: Ascii,
     pub name: Text,
-    pub metadata: Map<Ascii, Ascii>
+    pub metadata: Map<Ascii, Ascii>,
+    pub created_at: Timestamp
 }
 
 #[charybdis_model(
@@ -142,7 +360,38 @@ pub struct Organization {
 pub struct OrganizationByUser {
     pub tenant_id: Ascii,
     pub user_id: Ascii,
-    pub organization_id: Ascii
+    pub organization_id: Ascii,
+    pub role: OrganizationRole,
+    pub roles: Vec<Text>,
+    pub joined_at: Timestamp
+}
+
+#[derive(Clone, Copy, FromPrimitive, Debug, Default)]
+pub enum OrganizationRole {
+    Owner = 0,
+    Admin = 1,
+    #[default]
+    Member = 2,
+}
+
+impl SerializeValue for OrganizationRole {
+    fn serialize<'b>(
+        &self,
+        typ: &ColumnType,
+        writer: CellWriter<'b>,
+    ) -> Result<WrittenCellProof<'b>, SerializationError> {
+        (*self as i8).serialize(typ, writer)
+    }
+}
+
+impl FromCqlVal<CqlValue> for OrganizationRole {
+    fn from_cql(cql_val: CqlValue) -> Result<Self, FromCqlValError> {
+        let raw_val: Result<i8, FromCqlValError> = FromCqlVal::<CqlValue>::from_cql(cql_val);
+        raw_val.and_then(|v| match FromPrimitive::from_i8(v) {
+            Some(e) => Ok(e),
+            None => Err(FromCqlValError::BadVal),
+        })
+    }
 }
 
 #[charybdis_view_model(
@@ -155,7 +404,58 @@ pub struct OrganizationByUser {
 pub struct UserByOrganization {
     pub tenant_id: Ascii,
     pub user_id: Ascii,
-    pub organization_id: Ascii
+    pub organization_id: Ascii,
+    pub role: OrganizationRole,
+    pub roles: Vec<Text>,
+    pub joined_at: Timestamp
+}
+
+#[charybdis_model(
+    table_name = organization_invitations,
+    partition_keys = [tenant_id, organization_id],
+    clustering_keys = [invitation_id]
+)]
+#[derive(Debug, Default)]
+pub struct OrganizationInvitation {
+    pub tenant_id: Ascii,
+    pub organization_id: Ascii,
+    pub invitation_id: Ascii,
+    pub email: Text,
+    pub address: Text,
+    pub role: OrganizationRole,
+    pub invited_by: Ascii,
+    pub created_at: Timestamp,
+    pub expires_at: Timestamp
+}
+
+#[charybdis_model(
+    table_name = organization_domains,
+    partition_keys = [tenant_id, organization_id],
+    clustering_keys = [domain]
+)]
+#[derive(Debug, Default)]
+pub struct OrganizationDomain {
+    pub tenant_id: Ascii,
+    pub organization_id: Ascii,
+    pub domain: Text,
+    pub challenge: Ascii,
+    pub is_verified: Boolean,
+    pub require_sso: Boolean,
+    pub created_at: Timestamp,
+    pub verified_at: Option<Timestamp>
+}
+
+#[charybdis_model(
+    table_name = organizations_by_domain,
+    partition_keys = [tenant_id, domain],
+    clustering_keys = []
+)]
+#[derive(Debug, Default)]
+pub struct OrganizationByDomain {
+    pub tenant_id: Ascii,
+    pub domain: Text,
+    pub organization_id: Ascii,
+    pub require_sso: Boolean
 }
 
 #[charybdis_model(
@@ -252,6 +552,55 @@ pub struct UserByUsername {
     pub username: Text,
 }
 
+#[charybdis_model(
+    table_name = username_keys,
+    partition_keys = [tenant_id, key],
+    clustering_keys = []
+)]
+#[derive(Debug, Default)]
+pub struct UsernameKey {
+    pub tenant_id: Ascii,
+
+    /// The username's uniqueness key, as calculated by the tenant's username policy. E.g. both
+    /// "Admin" and "аdmin" (with a Cyrillic "а") have the "admin" key.
+    pub key: Text,
+    pub user_id: Ascii,
+    pub username: Text,
+}
+
+#[charybdis_model(
+    table_name = username_history,
+    partition_keys = [tenant_id, user_id],
+    clustering_keys = [changed_at],
+    table_options = r#"
+        CLUSTERING ORDER BY (changed_at DESC)
+    "#
+)]
+#[derive(Debug, Default)]
+pub struct UsernameHistory {
+    pub tenant_id: Ascii,
+    pub user_id: Ascii,
+    pub changed_at: Timestamp,
+    pub old_username: Option<Text>,
+    pub new_username: Text,
+}
+
+#[charybdis_model(
+    table_name = username_reservations,
+    partition_keys = [tenant_id, username],
+    clustering_keys = []
+)]
+#[derive(Debug, Default)]
+pub struct UsernameReservation {
+    pub tenant_id: Ascii,
+
+    /// The key of a username released by a rename. Rows are inserted with a TTL equal to the
+    /// tenant's hold period, during which only `user_id` can take the username back.
+    pub username: Text,
+    pub user_id: Ascii,
+    pub created_at: Timestamp,
+}
+
 #[charybdis_model(
     table_name = emails,
     partition_keys = [tenant_id, user_id],
@@ -261,7 +610,12 @@ pub struct UserByUsername {
 pub struct Email {
     pub tenant_id: Ascii,
     pub user_id: Ascii,
+
+    /// The canonical form of the email, used to look users up by email.
     pub email: Text,
+
+    /// The email as provided by the user.
+    pub address: Option<Text>,
     pub is_main: Boolean,
     pub is_work: Boolean,
     pub is_verified: Boolean,
@@ -282,6 +636,62 @@ pub struct UserByEmail {
     pub email: Text,
 }
 
+/// Held while an email is being linked, so two users can't link it at once.
+#[charybdis_model(
+    table_name = email_claims,
+    partition_keys = [tenant_id, email],
+    clustering_keys = [],
+
+    // Default TTL: 1 minute.
+    table_options = r#"
+        default_time_to_live = 60
+    "#
+)]
+#[derive(Debug, Default)]
+pub struct EmailClaim {
+    pub tenant_id: Ascii,
+    pub email: Text,
+    pub user_id: Ascii
+}
+
+#[charybdis_model(
+    table_name = email_changes,
+    partition_keys = [tenant_id, user_id],
+    clustering_keys = [],
+
+    // Default TTL: 15 minutes.
+    table_options = r#"
+        default_time_to_live = 900
+    "#
+)]
+#[derive(Debug, Default)]
+pub struct EmailChange {
+    pub tenant_id: Ascii,
+    pub user_id: Ascii,
+    pub old_email: Option<Text>,
+    pub new_email: Text,
+    pub new_address: Text,
+    pub created_at: Timestamp
+}
+
+#[charybdis_model(
+    table_name = email_rollbacks,
+    partition_keys = [tenant_id, rollback_token],
+    clustering_keys = []
+)]
+#[derive(Debug, Default)]
+pub struct EmailRollback {
+    pub tenant_id: Ascii,
+
+    /// Sent to the old address once the change is confirmed. Rows are inserted with a TTL equal
+    /// to the tenant's rollback window.
+    pub rollback_token: Blob,
+    pub user_id: Ascii,
+    pub old_email: Text,
+    pub new_email: Text,
+    pub created_at: Timestamp
+}
+
 #[charybdis_model(
     table_name = phone_numbers,
     partition_keys = [tenant_id, user_id],
@@ -456,6 +866,7 @@ pub struct APIToken {
     pub token: Blob,
     pub is_refresh: Boolean,
     pub scopes: Vec<Ascii>,
+    pub claims: Option<Text>,
     pub device_id: Option<Ascii>,
     pub client_id: Option<Ascii>
 }
@@ -508,7 +919,7 @@ impl FromCqlVal<CqlValue> for APIClientType {
 #[charybdis_model(
     table_name = groups,
     partition_keys = [tenant_id, group_id],
-    clustering_keys = []
+    clustering_keys = [priority]
 )]
 #[derive(Debug, Default)]
 pub struct Group {
@@ -517,10 +928,43 @@ pub struct Group {
     pub name: Text,
     pub description: Option<Text>,
     pub permissions: Vec<Ascii>,
+    pub roles: Vec<Text>,
+    pub priority: SmallInt,
+    pub color: Option<Int>
+}
+
+#[charybdis_view_model(
+    table_name = groups_by_tenant,
+    base_table = groups,
+    partition_keys = [tenant_id],
+    clustering_keys = [priority, group_id]
+)]
+#[derive(Debug, Default)]
+pub struct GroupByTenant {
+    pub tenant_id: Ascii,
+    pub group_id: Ascii,
     pub priority: SmallInt,
+    pub name: Text,
+    pub description: Option<Text>,
+    pub permissions: Vec<Ascii>,
+    pub roles: Vec<Text>,
     pub color: Option<Int>
 }
 
+#[charybdis_model(
+    table_name = roles,
+    partition_keys = [tenant_id],
+    clustering_keys = [role]
+)]
+#[derive(Debug, Default)]
+pub struct Role {
+    pub tenant_id: Ascii,
+    pub role: Text,
+    pub description: Option<Text>,
+    pub permissions: Vec<Ascii>,
+    pub inherits: Vec<Text>
+}
+
 #[charybdis_model(
     table_name = users_by_group,
     partition_keys = [tenant_id, group_id],
@@ -546,6 +990,120 @@ pub struct GroupByUser {
     pub user_id: Ascii
 }
 
+#[charybdis_model(
+    table_name = group_children,
+    partition_keys = [tenant_id, group_id],
+    clustering_keys = [child_group_id]
+)]
+#[derive(Debug, Default)]
+pub struct GroupChild {
+    pub tenant_id: Ascii,
+    pub group_id: Ascii,
+    pub child_group_id: Ascii
+}
+
+#[charybdis_view_model(
+    table_name = group_parents,
+    base_table = group_children,
+    partition_keys = [tenant_id, child_group_id],
+    clustering_keys = [group_id]
+)]
+#[derive(Debug, Default)]
+pub struct GroupParent {
+    pub tenant_id: Ascii,
+    pub group_id: Ascii,
+    pub child_group_id: Ascii
+}
+
+#[charybdis_model(
+    table_name = relation_namespaces,
+    partition_keys = [tenant_id],
+    clustering_keys = [namespace]
+)]
+#[derive(Debug, Default)]
+pub struct RelationNamespace {
+    pub tenant_id: Ascii,
+    pub namespace: Ascii,
+    pub config: Text,
+    pub updated_at: Timestamp
+}
+
+#[charybdis_model(
+    table_name = relation_tuples,
+    partition_keys = [tenant_id, namespace],
+    clustering_keys = [object_id, relation, subject]
+)]
+#[derive(Debug, Default)]
+pub struct RelationTuple {
+    pub tenant_id: Ascii,
+    pub namespace: Ascii,
+    pub object_id: Text,
+    pub relation: Ascii,
+    pub subject: Text
+}
+
+#[charybdis_model(
+    table_name = policies,
+    partition_keys = [tenant_id],
+    clustering_keys = [name]
+)]
+#[derive(Debug, Default)]
+pub struct Policy {
+    pub tenant_id: Ascii,
+    pub name: Ascii,
+    pub version: Int,
+    pub description: Option<Text>,
+    pub effect: Ascii,
+    pub actions: Set<Ascii>,
+    pub condition: Text,
+    pub updated_by: Option<Ascii>,
+    pub updated_at: Timestamp,
+}
+
+#[charybdis_model(
+    table_name = policy_versions,
+    partition_keys = [tenant_id, name],
+    clustering_keys = [version],
+    table_options = r#"
+        CLUSTERING ORDER BY (version DESC)
+    "#
+)]
+#[derive(Debug, Default)]
+pub struct PolicyVersion {
+    pub tenant_id: Ascii,
+    pub name: Ascii,
+    pub version: Int,
+    pub description: Option<Text>,
+    pub effect: Ascii,
+    pub actions: Set<Ascii>,
+    pub condition: Text,
+    pub updated_by: Option<Ascii>,
+    pub updated_at: Timestamp,
+}
+
+#[charybdis_model(
+    table_name = hooks,
+    partition_keys = [tenant_id],
+    clustering_keys = [hook_id]
+)]
+#[derive(Debug, Default)]
+pub struct Hook {
+    pub tenant_id: Ascii,
+    pub hook_id: Ascii,
+    pub trigger: Ascii,
+    pub name: Text,
+    pub kind: Ascii,
+    pub source: Option<Text>,
+    pub url: Option<Text>,
+    pub secret: Ascii,
+    pub position: Int,
+    pub timeout_ms: Int,
+    pub fail_open: Boolean,
+    pub is_enabled: Boolean,
+    pub created_at: Timestamp,
+    pub updated_at: Timestamp,
+}
+
 #[charybdis_model(
     table_name = activity_logs,
     partition_keys = [tenant_id, user_id],
@@ -610,16 +1168,6 @@ impl FromCqlVal<CqlValue> for NotificationStatus {
     }
 }
 
-#[derive(Clone, Copy, FromPrimitive, Debug, Default)]
-pub enum NotificationType {
-    #[default]
-    InApp,
-    Whatsapp,
-    Email,
-    SMS,
-    Push
-}
-
 impl SerializeValue for NotificationType {
     fn serialize<'b>(
         &self,
@@ -662,55 +1210,40 @@ pub struct Notification {
     pub created_at: Timestamp
 }
 
-#[derive(Clone, Copy, FromPrimitive, Debug, Default)]
-pub enum NotificationEvent {
-    #[default]
-    AdminMessage,
-}
-
 impl SerializeValue for NotificationEvent {
     fn serialize<'b>(
         &self,
         typ: &ColumnType,
         writer: CellWriter<'b>,
     ) -> Result<WrittenCellProof<'b>, SerializationError> {
-        (*self as i32).serialize(typ, writer)
+        (*self as i16).serialize(typ, writer)
     }
 }
 
 impl FromCqlVal<CqlValue> for NotificationEvent {
     fn from_cql(cql_val: CqlValue) -> Result<Self, FromCqlValError> {
-        let raw_val: Result<i32, FromCqlValError> = FromCqlVal::<CqlValue>::from_cql(cql_val);
-        raw_val.and_then(|v| match FromPrimitive::from_i32(v) {
+        let raw_val: Result<i16, FromCqlValError> = FromCqlVal::<CqlValue>::from_cql(cql_val);
+        raw_val.and_then(|v| match FromPrimitive::from_i16(v) {
             Some(e) => Ok(e),
             None => Err(FromCqlValError::BadVal),
         })
     }
 }
 
-#[derive(Clone, Copy, FromPrimitive, Debug, Default)]
-pub enum NotificationPriority {
-    #[default]
-    Low,
-    Medium,
-    High,
-    Critical
-}
-
 impl SerializeValue for NotificationPriority {
     fn serialize<'b>(
         &self,
         typ: &ColumnType,
         writer: CellWriter<'b>,
     ) -> Result<WrittenCellProof<'b>, SerializationError> {
-        (*self as i32).serialize(typ, writer)
+        (*self as i8).serialize(typ, writer)
     }
 }
 
 impl FromCqlVal<CqlValue> for NotificationPriority {
     fn from_cql(cql_val: CqlValue) -> Result<Self, FromCqlValError> {
-        let raw_val: Result<i32, FromCqlValError> = FromCqlVal::<CqlValue>::from_cql(cql_val);
-        raw_val.and_then(|v| match FromPrimitive::from_i32(v) {
+        let raw_val: Result<i8, FromCqlValError> = FromCqlVal::<CqlValue>::from_cql(cql_val);
+        raw_val.and_then(|v| match FromPrimitive::from_i8(v) {
             Some(e) => Ok(e),
             None => Err(FromCqlValError::BadVal),
         })
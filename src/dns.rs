@@ -0,0 +1,277 @@
+use std::{
+    collections::HashMap,
+    env,
+    net::{IpAddr, SocketAddr},
+};
+
+use hickory_resolver::{
+    error::{ResolveError, ResolveErrorKind},
+    TokioAsyncResolver,
+};
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+
+/// Suffixes of names only meaningful on a private network, which never point at a tenant.
+const PRIVATE_SUFFIXES: [&str; 8] = [
+    "localhost",
+    "local",
+    "localdomain",
+    "internal",
+    "intranet",
+    "lan",
+    "corp",
+    "home.arpa",
+];
+
+/// Resolves the DNS records used to verify domain ownership.
+pub enum Resolver {
+    /// Uses the system's resolver configuration.
+    System(Box<TokioAsyncResolver>),
+
+    /// Answers from a fixed set of TXT records instead of querying DNS, which stands in for a
+    /// real resolver in development and tests.
+    Static(HashMap<String, Vec<String>>),
+}
+
+impl Resolver {
+    /// Returns the TXT records of `name`, which are empty if it has none.
+    pub async fn txt(&self, name: &str) -> Result<Vec<String>, ResolveError> {
+        match self {
+            Self::System(resolver) => match resolver.txt_lookup(name).await {
+                Ok(lookup) => Ok(lookup.iter().map(|txt| txt.to_string()).collect()),
+                Err(e) if matches!(e.kind(), ResolveErrorKind::NoRecordsFound { .. }) => Ok(vec![]),
+                Err(e) => Err(e),
+            },
+            Self::Static(records) => Ok(records
+                .get(name.trim_end_matches('.'))
+                .cloned()
+                .unwrap_or_default()),
+        }
+    }
+}
+
+/// Creates the resolver. If `DNS_STATIC_TXT_RECORDS` is set, it's parsed as `name=value` pairs
+/// separated by `;` and used instead of the system's resolver.
+pub fn resolver() -> Resolver {
+    if let Ok(records) = env::var("DNS_STATIC_TXT_RECORDS") {
+        return parse_static(&records);
+    }
+
+    Resolver::System(Box::new(
+        TokioAsyncResolver::tokio_from_system_conf()
+            .expect("Should have been able to read the system's DNS configuration."),
+    ))
+}
+
+/// Parses `name=value` pairs separated by `;` into a static resolver. Only the first `=` splits
+/// a pair, as values often contain one.
+fn parse_static(records: &str) -> Resolver {
+    let mut map: HashMap<String, Vec<String>> = HashMap::new();
+
+    for (name, value) in records
+        .split(';')
+        .filter_map(|record| record.split_once('='))
+    {
+        map.entry(name.trim().trim_end_matches('.').to_string())
+            .or_default()
+            .push(value.trim().to_string());
+    }
+
+    Resolver::Static(map)
+}
+
+/// Checks whether `name` is a fully qualified domain name that could be public: at least two
+/// labels, a top-level label that isn't numeric, and no private suffix like `localhost`.
+pub fn is_public_name(name: &str) -> bool {
+    let name = name.trim_end_matches('.');
+
+    let Some((_, tld)) = name.rsplit_once('.') else {
+        return false;
+    };
+
+    tld.chars().any(|c| c.is_ascii_alphabetic())
+        && !PRIVATE_SUFFIXES.iter().any(|suffix| {
+            name == *suffix
+                || name
+                    .strip_suffix(suffix)
+                    .is_some_and(|name| name.ends_with('.'))
+        })
+}
+
+/// Checks whether the host of a URL could be public: either a public address, or a name
+/// `is_public_name` accepts. Addresses are checked here, as they don't go through
+/// `PublicResolver`.
+pub fn is_public_host(host: &str) -> bool {
+    match host
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse::<IpAddr>()
+    {
+        Ok(ip) => is_public(ip),
+        Err(_) => is_public_name(host),
+    }
+}
+
+/// Checks whether an address is reachable on the internet, as opposed to the server itself or
+/// a loopback, private, link-local, unique local or otherwise reserved network.
+pub fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || ip.is_multicast()
+                || a == 0
+                || a >= 240
+                || (a == 100 && (64..128).contains(&b))
+                || (a == 192 && b == 0 && ip.octets()[2] == 0)
+                || (a == 198 && (18..20).contains(&b)))
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public(IpAddr::V4(ip)),
+            None => {
+                let [first, second, ..] = ip.segments();
+
+                !(ip.is_unspecified()
+                    || ip.is_loopback()
+                    || ip.is_multicast()
+                    || ip.is_unique_local()
+                    || ip.is_unicast_link_local()
+                    || (first & 0xffc0) == 0xfec0
+                    || (first == 0x2001 && second == 0xdb8)
+                    || (first == 0x64 && second == 0xff9b)
+                    || first == 0)
+            }
+        },
+    }
+}
+
+/// Resolves the names of outgoing requests made on a tenant's behalf, such as HTTP challenges
+/// and hook callouts, refusing any name with an address that isn't public. Since connections
+/// use the addresses checked here, a name can't be pointed at the server's own network between
+/// the check and the request.
+pub struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> =
+                tokio::net::lookup_host((name.as_str(), 0)).await?.collect();
+
+            if addrs.is_empty() {
+                return Err(format!("{} has no address.", name.as_str()).into());
+            }
+
+            if let Some(addr) = addrs.iter().find(|addr| !is_public(addr.ip())) {
+                return Err(format!(
+                    "{} resolves to {}, which isn't a public address.",
+                    name.as_str(),
+                    addr.ip()
+                )
+                .into());
+            }
+
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn static_records_are_parsed() {
+        let dns = parse_static(
+            "_accesscore-challenge.acme.example=accesscore-domain-verification=abc; other.example = v=spf1 -all",
+        );
+
+        assert_eq!(
+            dns.txt("_accesscore-challenge.acme.example").await.unwrap(),
+            ["accesscore-domain-verification=abc"]
+        );
+        assert_eq!(dns.txt("other.example").await.unwrap(), ["v=spf1 -all"]);
+    }
+
+    #[tokio::test]
+    async fn static_names_can_have_several_records() {
+        let dns = parse_static("acme.example=first;acme.example=second");
+
+        assert_eq!(dns.txt("acme.example").await.unwrap(), ["first", "second"]);
+    }
+
+    #[tokio::test]
+    async fn static_names_ignore_the_trailing_dot() {
+        let dns = parse_static("acme.example.=value");
+
+        assert_eq!(dns.txt("acme.example").await.unwrap(), ["value"]);
+        assert_eq!(dns.txt("acme.example.").await.unwrap(), ["value"]);
+    }
+
+    #[tokio::test]
+    async fn static_names_without_records_have_none() {
+        let dns = parse_static("acme.example=value;malformed");
+
+        assert!(dns.txt("other.example").await.unwrap().is_empty());
+        assert!(dns.txt("malformed").await.unwrap().is_empty());
+    }
+
+    #[test]
+    fn public_names_are_fully_qualified() {
+        assert!(is_public_name("acme.example.com"));
+        assert!(is_public_name("acme.io."));
+        assert!(!is_public_name("acme"));
+        assert!(!is_public_name("localhost"));
+        assert!(!is_public_name("api.localhost"));
+        assert!(!is_public_name("printer.local"));
+        assert!(!is_public_name("db.internal"));
+        assert!(!is_public_name("nas.home.arpa"));
+        assert!(!is_public_name("127.0.0.1"));
+        assert!(!is_public_name("10.0.0.1"));
+        assert!(is_public_name("internal.acme.com"));
+    }
+
+    #[test]
+    fn private_addresses_arent_public() {
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "255.255.255.255",
+            "::1",
+            "::",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+            "::ffff:10.0.0.1",
+        ] {
+            assert!(!is_public(ip.parse().unwrap()), "{ip}");
+        }
+    }
+
+    #[test]
+    fn public_hosts_are_public_addresses_or_names() {
+        assert!(is_public_host("acme.example.com"));
+        assert!(is_public_host("1.1.1.1"));
+        assert!(is_public_host("[2606:4700:4700::1111]"));
+        assert!(!is_public_host("localhost"));
+        assert!(!is_public_host("169.254.169.254"));
+        assert!(!is_public_host("[::1]"));
+        assert!(!is_public_host("[::ffff:10.0.0.1]"));
+    }
+
+    #[test]
+    fn internet_addresses_are_public() {
+        for ip in ["1.1.1.1", "93.184.216.34", "2606:4700:4700::1111"] {
+            assert!(is_public(ip.parse().unwrap()), "{ip}");
+        }
+    }
+}
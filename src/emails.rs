@@ -0,0 +1,472 @@
+use std::{
+    collections::{HashMap, HashSet},
+    str::FromStr,
+    sync::LazyLock,
+};
+
+use scylla::{
+    frame::{response::result::CqlValue, value::CqlTimestamp},
+    transport::errors::QueryError,
+    Session,
+};
+
+use crate::{
+    settings::{self, Category},
+    state::State,
+};
+
+/// Domains of disposable email providers, bundled from `data/disposable_email_domains.txt`.
+pub static DISPOSABLE_DOMAINS: LazyLock<HashSet<&'static str>> = LazyLock::new(|| {
+    include_str!("../data/disposable_email_domains.txt")
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .collect()
+});
+
+/// Domains that deliver to Gmail inboxes, where dots in the local part are ignored.
+const GMAIL_DOMAINS: [&str; 2] = ["gmail.com", "googlemail.com"];
+
+/// A row of the `emails` table.
+pub struct EmailRow {
+    /// The canonical form of the email, as calculated by the tenant's email policy.
+    pub email: String,
+
+    /// The email as provided by the user, which is where messages are delivered to.
+    pub address: String,
+    pub is_main: bool,
+    pub is_work: bool,
+    pub is_verified: bool,
+    pub created_at: Option<CqlTimestamp>,
+    pub verified_at: Option<CqlTimestamp>,
+}
+
+/// Statement that (re-)inserts a full `emails` row. Rows are re-inserted instead of updated so
+/// that `ttl` applies to the whole row, which makes a `ttl` of 0 persist it indefinitely.
+///
+/// Values: `(tenant_id, user_id, email, address, is_main, is_work, is_verified, created_at,
+/// verified_at)`.
+pub fn insert_statement(ttl: u64) -> String {
+    format!(
+        "
+            INSERT INTO emails (
+                tenant_id, user_id, email, address, is_main, is_work, is_verified, created_at, verified_at
+            ) VALUES (
+                ?, ?, ?, ?, ?, ?, ?, ?, ?
+            ) USING TTL {ttl}
+        "
+    )
+}
+
+/// Returns all the emails of a user.
+pub async fn list(
+    db: &Session,
+    tenant_id: &str,
+    user_id: &str,
+) -> Result<Vec<EmailRow>, QueryError> {
+    let result = db
+        .query_unpaged(
+            "SELECT email, address, is_main, is_work, is_verified, created_at, verified_at FROM emails WHERE tenant_id = ? AND user_id = ?",
+            (tenant_id, user_id),
+        )
+        .await?;
+
+    Ok(result
+        .rows_typed_or_empty::<(
+            String,
+            Option<String>,
+            Option<bool>,
+            Option<bool>,
+            Option<bool>,
+            Option<CqlTimestamp>,
+            Option<CqlTimestamp>,
+        )>()
+        .filter_map(|row| row.ok())
+        .map(
+            |(email, address, is_main, is_work, is_verified, created_at, verified_at)| EmailRow {
+                address: address.unwrap_or_else(|| email.clone()),
+                email,
+                is_main: is_main.unwrap_or(false),
+                is_work: is_work.unwrap_or(false),
+                is_verified: is_verified.unwrap_or(false),
+                created_at,
+                verified_at,
+            },
+        )
+        .collect())
+}
+
+/// Returns the rows to re-insert when rolling a user back to `old_email`: its row, made main
+/// again, and every other main email, which stops being main. Emails changed since `old_email`
+/// would stay main otherwise. The row of `new_email` is left out, as it's deleted. Returns
+/// `None` if `old_email` is gone.
+pub fn rollback(rows: Vec<EmailRow>, old_email: &str, new_email: &str) -> Option<Vec<EmailRow>> {
+    if !rows.iter().any(|row| row.email == old_email) {
+        return None;
+    }
+
+    Some(
+        rows.into_iter()
+            .filter(|row| row.email != new_email && (row.email == old_email || row.is_main))
+            .map(|row| EmailRow {
+                is_main: row.email == old_email,
+                ..row
+            })
+            .collect(),
+    )
+}
+
+/// Returns a user's main email, if any.
+pub async fn main_email(
+    db: &Session,
+    tenant_id: &str,
+    user_id: &str,
+) -> Result<Option<EmailRow>, QueryError> {
+    Ok(list(db, tenant_id, user_id)
+        .await?
+        .into_iter()
+        .find(|row| row.is_main))
+}
+
+/// Seconds an email stays claimed, which covers linking it and the update of `users_by_email`.
+const CLAIM_TTL: u64 = 60;
+
+/// Claims an email for a user about to link it, returning `false` if another user claimed it
+/// first. `users_by_email` is a view and can't be written conditionally, so concurrent sign-ups
+/// and email changes are settled here, and the view is checked once the claim is held.
+pub async fn claim(
+    db: &Session,
+    tenant_id: &str,
+    email: &str,
+    user_id: &str,
+) -> Result<bool, QueryError> {
+    let result = db
+        .query_unpaged(
+            format!("INSERT INTO email_claims (tenant_id, email, user_id) VALUES (?, ?, ?) USING TTL {CLAIM_TTL} IF NOT EXISTS"),
+            (tenant_id, email, user_id),
+        )
+        .await?;
+
+    let holder = result.get_column_spec("user_id").map(|(index, _)| index);
+
+    let Ok(row) = result.first_row() else {
+        return Ok(false);
+    };
+
+    let applied = row
+        .columns
+        .first()
+        .and_then(|applied| applied.as_ref())
+        .and_then(CqlValue::as_boolean)
+        .unwrap_or(false);
+
+    // The user may retry while still holding the claim.
+    let is_holder = holder
+        .and_then(|index| row.columns.get(index))
+        .and_then(|holder| holder.as_ref())
+        .and_then(CqlValue::as_ascii)
+        .is_some_and(|holder| holder == user_id);
+
+    Ok(applied || is_holder)
+}
+
+/// Checks whether an email is already linked to a user in the tenant.
+pub async fn in_use(db: &Session, tenant_id: &str, email: &str) -> Result<bool, QueryError> {
+    Ok(owner(db, tenant_id, email).await?.is_some())
+}
+
+/// Returns the ID of the user an email is linked to, if any.
+pub async fn owner(
+    db: &Session,
+    tenant_id: &str,
+    email: &str,
+) -> Result<Option<String>, QueryError> {
+    let result = db
+        .query_unpaged(
+            "SELECT user_id FROM users_by_email WHERE tenant_id = ? AND email = ? LIMIT 1",
+            (tenant_id, email),
+        )
+        .await?;
+
+    Ok(result
+        .maybe_first_row_typed::<(String,)>()
+        .ok()
+        .flatten()
+        .map(|(user_id,)| user_id))
+}
+
+/// Masks the local part of an email so it can be shown to someone who may not own it, e.g.
+/// `john.doe@example.com` becomes `j*******@example.com`.
+pub fn mask(email: &str) -> String {
+    match email.split_once('@') {
+        Some((local, domain)) => {
+            let mut chars = local.chars();
+            let first = chars.next().map(String::from).unwrap_or_default();
+
+            format!("{first}{}@{domain}", "*".repeat(chars.count()))
+        }
+        None => "*".repeat(email.chars().count()),
+    }
+}
+
+/// Why an email's domain was rejected by a `Policy`.
+#[derive(Debug, PartialEq)]
+pub enum DomainViolation {
+    Blocked,
+    NotAllowed,
+    Disposable,
+}
+
+/// A tenant's email policy, read from the `Users` settings category.
+///
+/// | Key                        | Default |
+/// |----------------------------|---------|
+/// | `email_lowercase_local`    | `true`  |
+/// | `email_remove_gmail_dots`  | `true`  |
+/// | `email_strip_plus_tags`    | `false` |
+/// | `email_block_disposable`   | `true`  |
+/// | `email_blocked_domains`    | Empty   |
+/// | `email_allowed_domains`    | Empty   |
+///
+/// Domain lists are comma-separated and match subdomains too. An empty allowlist allows every
+/// domain that isn't blocked.
+#[derive(Debug)]
+pub struct Policy {
+    pub lowercase_local: bool,
+    pub remove_gmail_dots: bool,
+    pub strip_plus_tags: bool,
+    pub block_disposable: bool,
+    pub blocked_domains: Vec<String>,
+    pub allowed_domains: Vec<String>,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Self {
+            lowercase_local: true,
+            remove_gmail_dots: true,
+            strip_plus_tags: false,
+            block_disposable: true,
+            blocked_domains: vec![],
+            allowed_domains: vec![],
+        }
+    }
+}
+
+impl Policy {
+    /// Reads the tenant's email policy.
+    pub async fn load(state: &State, tenant_id: &str) -> Result<Self, QueryError> {
+        Ok(Self::from_settings(
+            &settings::get_all(state, tenant_id, Category::Users).await?,
+        ))
+    }
+
+    /// Builds a policy out of the `Users` settings category, using the defaults for missing or
+    /// unparseable values.
+    pub fn from_settings(settings: &HashMap<String, String>) -> Self {
+        fn parse<T: FromStr>(settings: &HashMap<String, String>, key: &str) -> Option<T> {
+            settings.get(key).and_then(|v| v.trim().parse().ok())
+        }
+
+        fn domains(settings: &HashMap<String, String>, key: &str) -> Option<Vec<String>> {
+            settings.get(key).map(|v| {
+                v.split(',')
+                    .map(|domain| domain.trim().trim_end_matches('.').to_lowercase())
+                    .filter(|domain| !domain.is_empty())
+                    .collect()
+            })
+        }
+
+        let default = Self::default();
+
+        Self {
+            lowercase_local: parse(settings, "email_lowercase_local")
+                .unwrap_or(default.lowercase_local),
+            remove_gmail_dots: parse(settings, "email_remove_gmail_dots")
+                .unwrap_or(default.remove_gmail_dots),
+            strip_plus_tags: parse(settings, "email_strip_plus_tags")
+                .unwrap_or(default.strip_plus_tags),
+            block_disposable: parse(settings, "email_block_disposable")
+                .unwrap_or(default.block_disposable),
+            blocked_domains: domains(settings, "email_blocked_domains")
+                .unwrap_or(default.blocked_domains),
+            allowed_domains: domains(settings, "email_allowed_domains")
+                .unwrap_or(default.allowed_domains),
+        }
+    }
+
+    /// Returns the canonical form of an email. Emails with the same canonical form belong to the
+    /// same inbox, e.g. `John.Doe+news@GoogleMail.com` and `johndoe@gmail.com`.
+    pub fn normalize(&self, email: &str) -> String {
+        let email = email.trim();
+
+        let Some((local, domain)) = email.rsplit_once('@') else {
+            return email.to_string();
+        };
+
+        let mut domain = domain.trim_end_matches('.').to_lowercase();
+        let mut local = local.to_string();
+
+        if self.strip_plus_tags {
+            if let Some((untagged, _)) = local.split_once('+') {
+                local = untagged.to_string();
+            }
+        }
+
+        if self.remove_gmail_dots && GMAIL_DOMAINS.contains(&domain.as_str()) {
+            domain = GMAIL_DOMAINS[0].to_string();
+            local = local.replace('.', "");
+
+            // Gmail ignores tags regardless of the tenant's settings.
+            if let Some((untagged, _)) = local.split_once('+') {
+                local = untagged.to_string();
+            }
+        }
+
+        if self.lowercase_local {
+            local = local.to_lowercase();
+        }
+
+        format!("{local}@{domain}")
+    }
+
+    /// Checks the email's domain against the tenant's domain lists.
+    pub fn check_domain(&self, email: &str) -> Result<(), DomainViolation> {
+        let domain = domain(email);
+
+        if self
+            .blocked_domains
+            .iter()
+            .any(|blocked| domain_matches(&domain, blocked))
+        {
+            return Err(DomainViolation::Blocked);
+        }
+
+        if !self.allowed_domains.is_empty()
+            && !self
+                .allowed_domains
+                .iter()
+                .any(|allowed| domain_matches(&domain, allowed))
+        {
+            return Err(DomainViolation::NotAllowed);
+        }
+
+        if self.block_disposable && is_disposable(&domain) {
+            return Err(DomainViolation::Disposable);
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns the lowercased domain of an email.
+pub fn domain(email: &str) -> String {
+    email
+        .rsplit_once('@')
+        .map(|(_, domain)| domain.trim().trim_end_matches('.').to_lowercase())
+        .unwrap_or_default()
+}
+
+/// Checks whether `domain` is `parent` or one of its subdomains.
+pub fn domain_matches(domain: &str, parent: &str) -> bool {
+    domain == parent || domain.ends_with(&format!(".{parent}"))
+}
+
+/// Checks whether `domain` or any of its parent domains is a known disposable email domain.
+pub fn is_disposable(domain: &str) -> bool {
+    let mut domain = domain;
+
+    loop {
+        if DISPOSABLE_DOMAINS.contains(domain) {
+            return true;
+        }
+
+        match domain.split_once('.') {
+            Some((_, parent)) if parent.contains('.') => domain = parent,
+            _ => return false,
+        }
+    }
+}
+
+impl DomainViolation {
+    /// Returns the error message and detail for the violation.
+    pub fn details(&self) -> (&'static str, &'static str) {
+        match self {
+            Self::Blocked => (
+                "Email Domain Blocked",
+                "Emails from this domain are not allowed.",
+            ),
+            Self::NotAllowed => (
+                "Email Domain Not Allowed",
+                "Only emails from specific domains are allowed.",
+            ),
+            Self::Disposable => (
+                "Disposable Email",
+                "Emails from disposable email providers are not allowed.",
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(email: &str, is_main: bool) -> EmailRow {
+        EmailRow {
+            email: email.to_string(),
+            address: email.to_string(),
+            is_main,
+            is_work: false,
+            is_verified: true,
+            created_at: None,
+            verified_at: None,
+        }
+    }
+
+    #[test]
+    fn rollback_restores_the_old_email() {
+        let rows = rollback(
+            vec![row("a@acme.com", false), row("b@acme.com", true)],
+            "a@acme.com",
+            "b@acme.com",
+        )
+        .unwrap();
+
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].email, "a@acme.com");
+        assert!(rows[0].is_main);
+    }
+
+    #[test]
+    fn rollback_past_later_changes_leaves_one_main_email() {
+        // A changed to B, then B to C, then A's rollback token is used.
+        let rows = rollback(
+            vec![
+                row("a@acme.com", false),
+                row("b@acme.com", false),
+                row("c@acme.com", true),
+            ],
+            "a@acme.com",
+            "b@acme.com",
+        )
+        .unwrap();
+
+        let main: Vec<&str> = rows
+            .iter()
+            .filter(|row| row.is_main)
+            .map(|row| row.email.as_str())
+            .collect();
+
+        assert_eq!(main, ["a@acme.com"]);
+        assert!(rows
+            .iter()
+            .any(|row| row.email == "c@acme.com" && !row.is_main));
+        assert!(!rows.iter().any(|row| row.email == "b@acme.com"));
+    }
+
+    #[test]
+    fn rollback_needs_the_old_email() {
+        assert!(rollback(vec![row("c@acme.com", true)], "a@acme.com", "b@acme.com").is_none());
+    }
+}
@@ -1,10 +1,18 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, fmt::Display};
 
-use axum::{extract, http::StatusCode, Extension, Json};
+use axum::{
+    extract,
+    http::StatusCode,
+    response::{self, IntoResponse},
+    Extension, Json,
+};
 use serde_json::{json, Value};
+use tracing::{event, Level};
 
 use crate::{
-    responses::{Error, Response},
+    permissions,
+    responses::{CommonError, Error, Response},
+    state::State,
     types::{RequestID, TenantID},
 };
 
@@ -55,3 +63,51 @@ pub fn error_response(
         )),
     )
 }
+
+/// Logs an unexpected error and builds an internal server error response.
+pub fn internal_error(
+    error: impl Display,
+    request_id: String,
+    tenant_id: String,
+) -> response::Response {
+    event!(Level::ERROR, error = format!("{error}"));
+
+    CommonError::InternalServerError {
+        request_id,
+        tenant_id: Some(tenant_id),
+    }
+    .into_response()
+}
+
+/// Checks that a request is made by a user who has a permission, both granted to them and allowed
+/// by their token's scopes. Returns their ID, or the response to send if they don't.
+pub async fn authorize(
+    state: &State,
+    tenant_id: &str,
+    user_id: Option<String>,
+    scopes: &[String],
+    permission: &str,
+    request_id: &str,
+) -> Result<String, response::Response> {
+    let Some(user_id) = user_id else {
+        return Err(CommonError::Unauthorized {
+            request_id: request_id.to_string(),
+            tenant_id: Some(tenant_id.to_string()),
+        }
+        .into_response());
+    };
+
+    match permissions::has_scoped_permission(state, tenant_id, &user_id, scopes, permission).await {
+        Ok(true) => Ok(user_id),
+        Ok(false) => Err(CommonError::Forbidden {
+            request_id: request_id.to_string(),
+            tenant_id: Some(tenant_id.to_string()),
+        }
+        .into_response()),
+        Err(e) => Err(internal_error(
+            e,
+            request_id.to_string(),
+            tenant_id.to_string(),
+        )),
+    }
+}
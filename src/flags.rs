@@ -0,0 +1,334 @@
+use chrono::Utc;
+use scylla::{batch::Batch, frame::value::CqlTimestamp, transport::errors::QueryError, Session};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    cache,
+    state::{AppState, State},
+    utils::id::gen_id,
+};
+
+/// Longest flag name.
+pub const MAX_NAME_LENGTH: usize = 64;
+
+/// Most changes listed at once, newest first.
+pub const MAX_CHANGES: i32 = 100;
+
+/// A feature flag of a tenant. Flags that aren't set are off.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Flag {
+    pub flag: String,
+    pub is_enabled: bool,
+
+    /// The percentage of users the flag is on for, picked by a hash of their ID. It's on for
+    /// everyone if unset.
+    pub rollout: Option<u8>,
+    pub description: Option<String>,
+    pub updated_by: Option<String>,
+    pub updated_at: Option<i64>,
+}
+
+impl Flag {
+    /// Checks whether the flag is on for a user. Flags rolled out to part of the users are off
+    /// when there's no user.
+    pub fn is_on(&self, tenant_id: &str, user_id: Option<&str>) -> bool {
+        match (self.is_enabled, self.rollout, user_id) {
+            (false, _, _) => false,
+            (true, None | Some(100..), _) => true,
+            (true, Some(rollout), Some(user_id)) => {
+                bucket(tenant_id, &self.flag, user_id) < u64::from(rollout)
+            }
+            (true, Some(_), None) => false,
+        }
+    }
+}
+
+/// Places a user in one of 100 buckets for a flag, the same every time. Each flag has its own
+/// buckets, so users in the first rollouts of a flag aren't always the first of every flag.
+fn bucket(tenant_id: &str, flag: &str, user_id: &str) -> u64 {
+    let hash = Sha256::digest(format!("{tenant_id}:{flag}:{user_id}"));
+
+    u64::from_be_bytes(hash[..8].try_into().unwrap()) % 100
+}
+
+/// A change of a flag, as recorded in the audit trail.
+#[derive(Debug, Serialize)]
+pub struct Change {
+    pub change_id: String,
+    pub flag: String,
+    pub changed_by: Option<String>,
+    pub changed_at: i64,
+
+    /// The flag before the change, or `None` if it wasn't set.
+    pub before: Option<Value>,
+
+    /// The flag after the change, or `None` if it was removed.
+    pub after: Option<Value>,
+}
+
+/// Checks whether a flag name is 1 to 64 lowercase letters, digits, dots, hyphens or
+/// underscores.
+pub fn is_valid_name(flag: &str) -> bool {
+    (1..=MAX_NAME_LENGTH).contains(&flag.len())
+        && flag
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || ".-_".contains(c))
+}
+
+type FlagRow = (
+    String,
+    Option<bool>,
+    Option<i8>,
+    Option<String>,
+    Option<String>,
+    Option<CqlTimestamp>,
+);
+
+async fn load(db: &Session, tenant_id: &str) -> Result<Option<Vec<Flag>>, QueryError> {
+    let result = db
+        .query_unpaged(
+            "SELECT flag, is_enabled, rollout, description, updated_by, updated_at FROM tenant_flags WHERE tenant_id = ?",
+            (tenant_id,),
+        )
+        .await?;
+
+    Ok(Some(
+        result
+            .rows_typed_or_empty::<FlagRow>()
+            .filter_map(|row| row.ok())
+            .map(
+                |(flag, is_enabled, rollout, description, updated_by, updated_at)| Flag {
+                    flag,
+                    is_enabled: is_enabled.unwrap_or(false),
+                    rollout: rollout.and_then(|rollout| u8::try_from(rollout).ok()),
+                    description,
+                    updated_by,
+                    updated_at: updated_at.map(|CqlTimestamp(t)| t),
+                },
+            )
+            .collect(),
+    ))
+}
+
+/// Returns every flag set for a tenant, from the cache if possible.
+pub async fn all(state: &State, tenant_id: &str) -> Result<Vec<Flag>, QueryError> {
+    Ok(cache::FLAGS
+        .get(&state.redis, tenant_id, || load(&state.db, tenant_id))
+        .await?
+        .unwrap_or_default())
+}
+
+pub async fn get(state: &State, tenant_id: &str, flag: &str) -> Result<Option<Flag>, QueryError> {
+    Ok(all(state, tenant_id)
+        .await?
+        .into_iter()
+        .find(|candidate| candidate.flag == flag))
+}
+
+/// Checks whether a flag is on for a tenant, and for a user if it's being rolled out. Cheap
+/// enough to call on every request, as a tenant's flags are cached together.
+pub async fn is_on(
+    state: &State,
+    tenant_id: &str,
+    flag: &str,
+    user_id: Option<&str>,
+) -> Result<bool, QueryError> {
+    Ok(get(state, tenant_id, flag)
+        .await?
+        .is_some_and(|flag| flag.is_on(tenant_id, user_id)))
+}
+
+/// Evaluates the tenant's flags for the user making a request. Added to every request by the
+/// `flags` middleware, so handlers can check flags without knowing who's asking.
+#[derive(Clone)]
+pub struct Flags {
+    state: AppState,
+    tenant_id: String,
+    user_id: Option<String>,
+}
+
+impl Flags {
+    pub fn new(state: AppState, tenant_id: String, user_id: Option<String>) -> Self {
+        Self {
+            state,
+            tenant_id,
+            user_id,
+        }
+    }
+
+    pub async fn is_on(&self, flag: &str) -> Result<bool, QueryError> {
+        let state = self.state.read().await;
+
+        is_on(&state, &self.tenant_id, flag, self.user_id.as_deref()).await
+    }
+
+    /// Lists the flags that are on.
+    pub async fn on(&self) -> Result<Vec<String>, QueryError> {
+        let state = self.state.read().await;
+
+        Ok(all(&state, &self.tenant_id)
+            .await?
+            .into_iter()
+            .filter(|flag| flag.is_on(&self.tenant_id, self.user_id.as_deref()))
+            .map(|flag| flag.flag)
+            .collect())
+    }
+}
+
+/// Writes a flag, or removes it if `flag` is `None`, along with the change in the audit trail.
+async fn write(
+    state: &State,
+    tenant_id: &str,
+    name: &str,
+    flag: Option<&Flag>,
+    changed_by: &str,
+) -> Result<(), QueryError> {
+    // Read from Scylla, as the cache may lag behind a change made on another instance.
+    let before = load(&state.db, tenant_id)
+        .await?
+        .unwrap_or_default()
+        .into_iter()
+        .find(|candidate| candidate.flag == name);
+    let now = CqlTimestamp(Utc::now().timestamp_millis());
+
+    let mut batch = Batch::default();
+
+    match flag {
+        Some(_) => batch.append_statement(
+            "INSERT INTO tenant_flags (tenant_id, flag, is_enabled, rollout, description, updated_by, updated_at) VALUES (?, ?, ?, ?, ?, ?, ?)",
+        ),
+        None => batch.append_statement("DELETE FROM tenant_flags WHERE tenant_id = ? AND flag = ?"),
+    }
+
+    batch.append_statement(
+        "INSERT INTO tenant_flag_changes (tenant_id, changed_at, change_id, flag, changed_by, old_value, new_value) VALUES (?, ?, ?, ?, ?, ?, ?)",
+    );
+
+    let change = (
+        tenant_id,
+        now,
+        gen_id(None),
+        name,
+        changed_by,
+        before.map(|before| serde_json::to_string(&before).unwrap()),
+        flag.map(|flag| serde_json::to_string(flag).unwrap()),
+    );
+
+    match flag {
+        Some(flag) => {
+            state
+                .db
+                .batch(
+                    &batch,
+                    (
+                        (
+                            tenant_id,
+                            name,
+                            flag.is_enabled,
+                            flag.rollout.map(|rollout| rollout as i8),
+                            &flag.description,
+                            changed_by,
+                            now,
+                        ),
+                        change,
+                    ),
+                )
+                .await?;
+        }
+        None => {
+            state.db.batch(&batch, ((tenant_id, name), change)).await?;
+        }
+    }
+
+    cache::FLAGS.invalidate(&state.redis, tenant_id).await;
+
+    Ok(())
+}
+
+/// Sets a flag of a tenant, recording who changed it.
+pub async fn set(
+    state: &State,
+    tenant_id: &str,
+    name: &str,
+    is_enabled: bool,
+    rollout: Option<u8>,
+    description: Option<String>,
+    changed_by: &str,
+) -> Result<Flag, QueryError> {
+    let flag = Flag {
+        flag: name.to_string(),
+        is_enabled,
+        rollout,
+        description,
+        updated_by: Some(changed_by.to_string()),
+        updated_at: Some(Utc::now().timestamp_millis()),
+    };
+
+    write(state, tenant_id, name, Some(&flag), changed_by).await?;
+
+    Ok(flag)
+}
+
+/// Removes a flag of a tenant, which turns it off, recording who removed it.
+pub async fn remove(
+    state: &State,
+    tenant_id: &str,
+    name: &str,
+    changed_by: &str,
+) -> Result<(), QueryError> {
+    write(state, tenant_id, name, None, changed_by).await
+}
+
+type ChangeRow = (
+    String,
+    String,
+    Option<String>,
+    CqlTimestamp,
+    Option<String>,
+    Option<String>,
+);
+
+/// Lists the latest changes of a tenant's flags, or of one of them, newest first.
+pub async fn changes(
+    db: &Session,
+    tenant_id: &str,
+    flag: Option<&str>,
+) -> Result<Vec<Change>, QueryError> {
+    const COLUMNS: &str = "change_id, flag, changed_by, changed_at, old_value, new_value";
+
+    let result = match flag {
+        Some(flag) => {
+            db.query_unpaged(
+                format!("SELECT {COLUMNS} FROM tenant_flag_changes WHERE tenant_id = ? AND flag = ? LIMIT {MAX_CHANGES} ALLOW FILTERING"),
+                (tenant_id, flag),
+            )
+            .await?
+        }
+        None => {
+            db.query_unpaged(
+                format!("SELECT {COLUMNS} FROM tenant_flag_changes WHERE tenant_id = ? LIMIT {MAX_CHANGES}"),
+                (tenant_id,),
+            )
+            .await?
+        }
+    };
+
+    let parse = |json: Option<String>| json.and_then(|json| serde_json::from_str(&json).ok());
+
+    Ok(result
+        .rows_typed_or_empty::<ChangeRow>()
+        .filter_map(|row| row.ok())
+        .map(
+            |(change_id, flag, changed_by, CqlTimestamp(changed_at), before, after)| Change {
+                change_id,
+                flag,
+                changed_by,
+                changed_at,
+                before: parse(before),
+                after: parse(after),
+            },
+        )
+        .collect())
+}
@@ -0,0 +1,567 @@
+use std::{
+    cmp::Reverse,
+    collections::{HashMap, HashSet, VecDeque},
+};
+
+use redis::AsyncCommands;
+use scylla::{batch::Batch, transport::errors::QueryError, Session};
+use serde::Serialize;
+use tracing::{event, Level};
+
+use crate::{authz, state::State};
+
+/// Tenant-wide permission required to manage groups and their members.
+pub const MANAGE_PERMISSION: &str = "groups.manage";
+
+/// Seconds a user's transitive groups are cached for.
+const CACHE_TTL: u64 = 3600;
+
+/// A group of users sharing permissions. When groups disagree on a permission, the one with the
+/// highest `priority` wins.
+#[derive(Clone, Serialize)]
+pub struct Group {
+    pub group_id: String,
+    pub name: String,
+    pub description: Option<String>,
+
+    /// Granted permissions, plus denied ones prefixed with `-`.
+    pub permissions: HashSet<String>,
+
+    /// Names of the roles assigned to the group, whose permissions apply with its priority.
+    pub roles: HashSet<String>,
+    pub priority: i16,
+
+    /// An RGB color, like `0xFF0000` for red.
+    pub color: Option<i32>,
+}
+
+type GroupRow = (
+    String,
+    i16,
+    Option<String>,
+    Option<String>,
+    Option<HashSet<String>>,
+    Option<HashSet<String>>,
+    Option<i32>,
+);
+
+fn from_row((group_id, priority, name, description, permissions, roles, color): GroupRow) -> Group {
+    Group {
+        group_id,
+        name: name.unwrap_or_default(),
+        description,
+        permissions: permissions.unwrap_or_default(),
+        roles: roles.unwrap_or_default(),
+        priority,
+        color,
+    }
+}
+
+pub async fn get(
+    db: &Session,
+    tenant_id: &str,
+    group_id: &str,
+) -> Result<Option<Group>, QueryError> {
+    let result = db
+        .query_unpaged(
+            "SELECT group_id, priority, name, description, permissions, roles, color FROM groups WHERE tenant_id = ? AND group_id = ? LIMIT 1",
+            (tenant_id, group_id),
+        )
+        .await?;
+
+    Ok(result
+        .maybe_first_row_typed::<GroupRow>()
+        .ok()
+        .flatten()
+        .map(from_row))
+}
+
+/// Returns all the groups of the tenant, from the highest priority to the lowest.
+pub async fn list(db: &Session, tenant_id: &str) -> Result<Vec<Group>, QueryError> {
+    let result = db
+        .query_unpaged(
+            "SELECT group_id, priority, name, description, permissions, roles, color FROM groups_by_tenant WHERE tenant_id = ?",
+            (tenant_id,),
+        )
+        .await?;
+
+    Ok(result
+        .rows_typed_or_empty::<GroupRow>()
+        .filter_map(|row| row.ok())
+        .map(from_row)
+        .collect())
+}
+
+/// Inserts or replaces a group. Since `priority` is part of the primary key, changing it needs
+/// the previous priority to delete the old row.
+pub async fn save(
+    state: &State,
+    tenant_id: &str,
+    group: &Group,
+    previous_priority: Option<i16>,
+) -> Result<(), QueryError> {
+    let db = &state.db;
+    let insert = "INSERT INTO groups (tenant_id, group_id, priority, name, description, permissions, roles, color) VALUES (?, ?, ?, ?, ?, ?, ?, ?)";
+    let values = (
+        tenant_id,
+        &group.group_id,
+        group.priority,
+        &group.name,
+        &group.description,
+        &group.permissions,
+        &group.roles,
+        group.color,
+    );
+
+    match previous_priority {
+        Some(previous) if previous != group.priority => {
+            let mut batch = Batch::default();
+
+            batch.append_statement(
+                "DELETE FROM groups WHERE tenant_id = ? AND group_id = ? AND priority = ?",
+            );
+            batch.append_statement(insert);
+
+            db.batch(&batch, ((tenant_id, &group.group_id, previous), values))
+                .await?;
+        }
+        _ => {
+            db.query_unpaged(insert, values).await?;
+        }
+    }
+
+    authz::invalidate(state, tenant_id).await;
+
+    Ok(())
+}
+
+/// Deletes a group along with its memberships and its links to parent and child groups.
+pub async fn delete(state: &State, tenant_id: &str, group_id: &str) -> Result<(), QueryError> {
+    let db = &state.db;
+    let mut batch = Batch::default();
+
+    batch.append_statement("DELETE FROM groups WHERE tenant_id = ? AND group_id = ?");
+    batch.append_statement("DELETE FROM users_by_group WHERE tenant_id = ? AND group_id = ?");
+    batch.append_statement("DELETE FROM group_children WHERE tenant_id = ? AND group_id = ?");
+
+    db.batch(
+        &batch,
+        (
+            (tenant_id, group_id),
+            (tenant_id, group_id),
+            (tenant_id, group_id),
+        ),
+    )
+    .await?;
+
+    for parent_id in parents(db, tenant_id, group_id).await? {
+        db.query_unpaged(
+            "DELETE FROM group_children WHERE tenant_id = ? AND group_id = ? AND child_group_id = ?",
+            (tenant_id, &parent_id, group_id),
+        )
+        .await?;
+    }
+
+    invalidate_tenant(state, tenant_id).await;
+
+    Ok(())
+}
+
+/// Returns the IDs of the group's members.
+pub async fn members(
+    db: &Session,
+    tenant_id: &str,
+    group_id: &str,
+) -> Result<Vec<String>, QueryError> {
+    let result = db
+        .query_unpaged(
+            "SELECT user_id FROM users_by_group WHERE tenant_id = ? AND group_id = ?",
+            (tenant_id, group_id),
+        )
+        .await?;
+
+    Ok(result
+        .rows_typed_or_empty::<(String,)>()
+        .filter_map(|row| row.ok())
+        .map(|(user_id,)| user_id)
+        .collect())
+}
+
+/// Returns the IDs of the groups a user is a member of.
+pub async fn user_groups(
+    db: &Session,
+    tenant_id: &str,
+    user_id: &str,
+) -> Result<Vec<String>, QueryError> {
+    let result = db
+        .query_unpaged(
+            "SELECT group_id FROM groups_by_user WHERE tenant_id = ? AND user_id = ?",
+            (tenant_id, user_id),
+        )
+        .await?;
+
+    Ok(result
+        .rows_typed_or_empty::<(String,)>()
+        .filter_map(|row| row.ok())
+        .map(|(group_id,)| group_id)
+        .collect())
+}
+
+pub async fn add_member(
+    state: &State,
+    tenant_id: &str,
+    group_id: &str,
+    user_id: &str,
+) -> Result<(), QueryError> {
+    state
+        .db
+        .query_unpaged(
+            "INSERT INTO users_by_group (tenant_id, group_id, user_id) VALUES (?, ?, ?)",
+            (tenant_id, group_id, user_id),
+        )
+        .await?;
+
+    invalidate_user(state, tenant_id, user_id).await;
+
+    Ok(())
+}
+
+pub async fn remove_member(
+    state: &State,
+    tenant_id: &str,
+    group_id: &str,
+    user_id: &str,
+) -> Result<(), QueryError> {
+    state
+        .db
+        .query_unpaged(
+            "DELETE FROM users_by_group WHERE tenant_id = ? AND group_id = ? AND user_id = ?",
+            (tenant_id, group_id, user_id),
+        )
+        .await?;
+
+    invalidate_user(state, tenant_id, user_id).await;
+
+    Ok(())
+}
+
+/// Returns the IDs of the groups directly contained in a group.
+pub async fn children(
+    db: &Session,
+    tenant_id: &str,
+    group_id: &str,
+) -> Result<Vec<String>, QueryError> {
+    let result = db
+        .query_unpaged(
+            "SELECT child_group_id FROM group_children WHERE tenant_id = ? AND group_id = ?",
+            (tenant_id, group_id),
+        )
+        .await?;
+
+    Ok(result
+        .rows_typed_or_empty::<(String,)>()
+        .filter_map(|row| row.ok())
+        .map(|(child_group_id,)| child_group_id)
+        .collect())
+}
+
+/// Returns the IDs of the groups directly containing a group.
+pub async fn parents(
+    db: &Session,
+    tenant_id: &str,
+    group_id: &str,
+) -> Result<Vec<String>, QueryError> {
+    let result = db
+        .query_unpaged(
+            "SELECT group_id FROM group_parents WHERE tenant_id = ? AND child_group_id = ?",
+            (tenant_id, group_id),
+        )
+        .await?;
+
+    Ok(result
+        .rows_typed_or_empty::<(String,)>()
+        .filter_map(|row| row.ok())
+        .map(|(group_id,)| group_id)
+        .collect())
+}
+
+/// Returns the IDs of all the groups reachable from `start` through `step`, excluding `start`
+/// unless it's reachable from itself.
+async fn reachable<'a, F, Fut>(
+    db: &'a Session,
+    tenant_id: &'a str,
+    start: impl IntoIterator<Item = String>,
+    step: F,
+) -> Result<HashSet<String>, QueryError>
+where
+    F: Fn(&'a Session, &'a str, String) -> Fut,
+    Fut: std::future::Future<Output = Result<Vec<String>, QueryError>>,
+{
+    let mut found: HashSet<String> = HashSet::new();
+    let mut queue: VecDeque<String> = start.into_iter().collect();
+
+    while let Some(group_id) = queue.pop_front() {
+        for next in step(db, tenant_id, group_id).await? {
+            if found.insert(next.clone()) {
+                queue.push_back(next);
+            }
+        }
+    }
+
+    Ok(found)
+}
+
+/// Returns the IDs of all the groups contained in a group, directly or through other groups.
+pub async fn descendants(
+    db: &Session,
+    tenant_id: &str,
+    group_id: &str,
+) -> Result<HashSet<String>, QueryError> {
+    reachable(
+        db,
+        tenant_id,
+        [group_id.to_string()],
+        |db, tenant_id, id| async move { children(db, tenant_id, &id).await },
+    )
+    .await
+}
+
+/// Returns the IDs of all the groups containing any of `group_ids`, directly or through other
+/// groups.
+pub async fn ancestors(
+    db: &Session,
+    tenant_id: &str,
+    group_ids: impl IntoIterator<Item = String>,
+) -> Result<HashSet<String>, QueryError> {
+    reachable(db, tenant_id, group_ids, |db, tenant_id, id| async move {
+        parents(db, tenant_id, &id).await
+    })
+    .await
+}
+
+/// Makes `child_id` a member of `group_id`. Returns `false` without adding it if that would
+/// create a cycle, i.e. if `group_id` is `child_id` itself or one of its descendants.
+pub async fn add_child(
+    state: &State,
+    tenant_id: &str,
+    group_id: &str,
+    child_id: &str,
+) -> Result<bool, QueryError> {
+    if group_id == child_id
+        || descendants(&state.db, tenant_id, child_id)
+            .await?
+            .contains(group_id)
+    {
+        return Ok(false);
+    }
+
+    state
+        .db
+        .query_unpaged(
+            "INSERT INTO group_children (tenant_id, group_id, child_group_id) VALUES (?, ?, ?)",
+            (tenant_id, group_id, child_id),
+        )
+        .await?;
+
+    invalidate_tenant(state, tenant_id).await;
+
+    Ok(true)
+}
+
+pub async fn remove_child(
+    state: &State,
+    tenant_id: &str,
+    group_id: &str,
+    child_id: &str,
+) -> Result<(), QueryError> {
+    state
+        .db
+        .query_unpaged(
+            "DELETE FROM group_children WHERE tenant_id = ? AND group_id = ? AND child_group_id = ?",
+            (tenant_id, group_id, child_id),
+        )
+        .await?;
+
+    invalidate_tenant(state, tenant_id).await;
+
+    Ok(())
+}
+
+/// A group with its members and the trees of its child groups.
+#[derive(Serialize)]
+pub struct Tree {
+    pub group_id: String,
+    pub name: String,
+    pub members: Vec<String>,
+    pub children: Vec<Tree>,
+}
+
+/// Returns the full member tree of a group.
+pub async fn tree(db: &Session, tenant_id: &str, group_id: &str) -> Result<Tree, QueryError> {
+    let mut nodes: HashMap<String, (String, Vec<String>, Vec<String>)> = HashMap::new();
+    let mut queue: VecDeque<String> = VecDeque::from([group_id.to_string()]);
+
+    while let Some(id) = queue.pop_front() {
+        if nodes.contains_key(&id) {
+            continue;
+        }
+
+        let name = get(db, tenant_id, &id)
+            .await?
+            .map(|group| group.name)
+            .unwrap_or_default();
+        let children = children(db, tenant_id, &id).await?;
+
+        queue.extend(children.iter().cloned());
+        nodes.insert(
+            id.clone(),
+            (name, members(db, tenant_id, &id).await?, children),
+        );
+    }
+
+    fn build(id: &str, nodes: &HashMap<String, (String, Vec<String>, Vec<String>)>) -> Tree {
+        let (name, members, children) = nodes.get(id).cloned().unwrap_or_default();
+
+        Tree {
+            group_id: id.to_string(),
+            name,
+            members,
+            children: children.iter().map(|child| build(child, nodes)).collect(),
+        }
+    }
+
+    Ok(build(group_id, &nodes))
+}
+
+/// A group a user belongs to, directly or through one of its nested groups.
+#[derive(Serialize)]
+pub struct ExpandedMembership {
+    pub group_id: String,
+    pub name: String,
+    pub priority: i16,
+    pub is_direct: bool,
+}
+
+/// Returns all the groups a user belongs to, from the highest priority to the lowest.
+pub async fn expanded_membership(
+    state: &State,
+    tenant_id: &str,
+    user_id: &str,
+) -> Result<Vec<ExpandedMembership>, QueryError> {
+    let direct: HashSet<String> = user_groups(&state.db, tenant_id, user_id)
+        .await?
+        .into_iter()
+        .collect();
+    let mut memberships = vec![];
+
+    for group_id in transitive_groups(state, tenant_id, user_id).await? {
+        if let Some(group) = get(&state.db, tenant_id, &group_id).await? {
+            memberships.push(ExpandedMembership {
+                is_direct: direct.contains(&group_id),
+                group_id,
+                name: group.name,
+                priority: group.priority,
+            });
+        }
+    }
+
+    memberships.sort_by_key(|membership| Reverse(membership.priority));
+
+    Ok(memberships)
+}
+
+fn version_key(tenant_id: &str) -> String {
+    format!("groups:{tenant_id}:version")
+}
+
+fn cache_key(tenant_id: &str, version: u64, user_id: &str) -> String {
+    format!("groups:{tenant_id}:{version}:{user_id}")
+}
+
+/// Returns the IDs of all the groups a user is a member of, directly or through nested groups.
+/// Results are cached in Redis until the user's memberships or the tenant's group hierarchy
+/// change.
+pub async fn transitive_groups(
+    state: &State,
+    tenant_id: &str,
+    user_id: &str,
+) -> Result<HashSet<String>, QueryError> {
+    let mut redis = match state.redis.get_multiplexed_async_connection().await {
+        Ok(conn) => Some(conn),
+        Err(e) => {
+            event!(Level::WARN, error = format!("{e}"));
+            None
+        }
+    };
+
+    let mut key: Option<String> = None;
+
+    if let Some(conn) = redis.as_mut() {
+        let version: Option<u64> = conn.get(version_key(tenant_id)).await.unwrap_or(None);
+        let cache_key = cache_key(tenant_id, version.unwrap_or(0), user_id);
+        let cached: Option<String> = conn.get(&cache_key).await.unwrap_or(None);
+
+        if let Some(groups) = cached.and_then(|c| serde_json::from_str(&c).ok()) {
+            return Ok(groups);
+        }
+
+        key = Some(cache_key);
+    }
+
+    let direct = user_groups(&state.db, tenant_id, user_id).await?;
+    let mut groups = ancestors(&state.db, tenant_id, direct.iter().cloned()).await?;
+
+    groups.extend(direct);
+
+    if let (Some(conn), Some(key)) = (redis.as_mut(), key) {
+        let result: redis::RedisResult<()> = conn
+            .set_ex(
+                key,
+                serde_json::to_string(&groups).unwrap_or_default(),
+                CACHE_TTL,
+            )
+            .await;
+
+        if let Err(e) = result {
+            event!(Level::WARN, error = format!("{e}"));
+        }
+    }
+
+    Ok(groups)
+}
+
+/// Drops the cached transitive groups of a user, along with the tenant's cached authorization
+/// decisions.
+async fn invalidate_user(state: &State, tenant_id: &str, user_id: &str) {
+    let result: redis::RedisResult<()> = async {
+        let mut conn = state.redis.get_multiplexed_async_connection().await?;
+        let version: Option<u64> = conn.get(version_key(tenant_id)).await?;
+
+        conn.del(cache_key(tenant_id, version.unwrap_or(0), user_id))
+            .await
+    }
+    .await;
+
+    if let Err(e) = result {
+        event!(Level::WARN, error = format!("{e}"));
+    }
+
+    authz::invalidate(state, tenant_id).await;
+}
+
+/// Drops the cached transitive groups of every user in the tenant by bumping the cache version,
+/// letting the stale entries expire on their own. Cached authorization decisions go too.
+async fn invalidate_tenant(state: &State, tenant_id: &str) {
+    let result: redis::RedisResult<()> = async {
+        let mut conn = state.redis.get_multiplexed_async_connection().await?;
+
+        conn.incr(version_key(tenant_id), 1).await
+    }
+    .await;
+
+    if let Err(e) = result {
+        event!(Level::WARN, error = format!("{e}"));
+    }
+
+    authz::invalidate(state, tenant_id).await;
+}
@@ -0,0 +1,92 @@
+use std::{
+    fmt::Write,
+    sync::{Arc, LazyLock},
+    time::Duration,
+};
+
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use reqwest::{redirect, Client};
+use serde_json::Value;
+use sha2::Sha256;
+
+use super::HookResult;
+use crate::{dns::PublicResolver, utils::http::read_limited};
+
+/// Header carrying the signature of a callout, as `t={timestamp},v1={hex HMAC-SHA256}` where the
+/// HMAC covers `{timestamp}.{body}` with the hook's secret.
+pub const SIGNATURE_HEADER: &str = "x-accesscore-signature";
+
+/// Header carrying the ID of the hook a callout is made for.
+pub const HOOK_HEADER: &str = "x-accesscore-hook";
+
+/// Largest response body read from a tenant endpoint.
+const MAX_RESPONSE_SIZE: usize = 64 * 1024;
+
+static CLIENT: LazyLock<Client> = LazyLock::new(|| {
+    Client::builder()
+        .redirect(redirect::Policy::none())
+        .dns_resolver(Arc::new(PublicResolver))
+        .user_agent("AccessCore-Hooks/1")
+        .build()
+        .unwrap()
+});
+
+/// Signs a callout body the way tenant endpoints are expected to verify it.
+pub fn signature(secret: &str, timestamp: i64, body: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(format!("{timestamp}.{body}").as_bytes());
+
+    let mut hex = String::new();
+
+    for byte in mac.finalize().into_bytes() {
+        let _ = write!(hex, "{byte:02x}");
+    }
+
+    format!("t={timestamp},v1={hex}")
+}
+
+/// Posts the event to the tenant's endpoint, which must answer with a 2xx status and the
+/// hook's result as JSON, or an empty body for no changes. The endpoint's name must resolve to
+/// public addresses only, and redirects aren't followed.
+pub async fn run(
+    hook_id: &str,
+    url: &str,
+    secret: &str,
+    event: &Value,
+    timeout: Duration,
+) -> Result<HookResult, String> {
+    let body = event.to_string();
+
+    let response = CLIENT
+        .post(url)
+        .timeout(timeout)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .header(HOOK_HEADER, hook_id)
+        .header(
+            SIGNATURE_HEADER,
+            signature(secret, Utc::now().timestamp(), &body),
+        )
+        .body(body)
+        .send()
+        .await
+        .map_err(|e| format!("The callout failed: {e}"))?;
+
+    let status = response.status();
+
+    if !status.is_success() {
+        return Err(format!("The endpoint answered with {status}."));
+    }
+
+    let body = read_limited(response, MAX_RESPONSE_SIZE)
+        .await
+        .map_err(|e| format!("The response couldn't be read: {e}"))?
+        .ok_or("The response is too large.")?;
+
+    if body.iter().all(|b| b.is_ascii_whitespace()) {
+        return Ok(HookResult::default());
+    }
+
+    serde_json::from_slice(&body)
+        .map_err(|e| format!("The endpoint returned an invalid result: {e}"))
+}
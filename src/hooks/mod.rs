@@ -0,0 +1,498 @@
+pub mod callout;
+pub mod script;
+
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use base64::engine::{general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use chrono::Utc;
+use reqwest::Url;
+use scylla::{frame::value::CqlTimestamp, transport::errors::QueryError, Session};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use tracing::{event, Level};
+
+use crate::{dns, permissions, policies::RequestContext, state::State, tokens::token};
+
+/// Tenant-wide permission required to manage hooks.
+pub const MANAGE_PERMISSION: &str = "hooks.manage";
+
+/// Maximum number of hooks a tenant can have for a single trigger.
+pub const MAX_HOOKS_PER_TRIGGER: usize = 10;
+
+/// Bounds on how long a single hook can run, in milliseconds.
+pub const DEFAULT_TIMEOUT_MS: i32 = 500;
+pub const MAX_TIMEOUT_MS: i32 = 1000;
+
+/// How long all the enabled hooks of a trigger can run together, in milliseconds, which leaves
+/// the rest of the request well within its 2 second timeout.
+pub const MAX_TRIGGER_BUDGET_MS: i32 = 1000;
+
+/// Bounds on what hooks can add to users and tokens.
+const MAX_METADATA_ENTRIES: usize = 32;
+const MAX_METADATA_VALUE_LENGTH: usize = 256;
+const MAX_CLAIMS_SIZE: usize = 4096;
+const MAX_SCOPES: usize = 50;
+
+/// Claims hooks can't set, as they're part of every token response already.
+const RESERVED_CLAIMS: [&str; 4] = ["user_id", "scopes", "tenant_id", "token_type"];
+
+/// When a hook runs.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Trigger {
+    /// Before a user is created, once the sign-up is otherwise valid.
+    PreSignUp,
+
+    /// After a user is created. These hooks can't deny, and their failures are only logged.
+    PostSignUp,
+
+    /// Before a user is signed in, once their credentials are verified.
+    PreSignIn,
+
+    /// Before tokens are issued, on sign-in and on refresh.
+    PreTokenIssue,
+}
+
+impl Trigger {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::PreSignUp => "pre_sign_up",
+            Self::PostSignUp => "post_sign_up",
+            Self::PreSignIn => "pre_sign_in",
+            Self::PreTokenIssue => "pre_token_issue",
+        }
+    }
+
+    fn parse(trigger: &str) -> Option<Self> {
+        match trigger {
+            "pre_sign_up" => Some(Self::PreSignUp),
+            "post_sign_up" => Some(Self::PostSignUp),
+            "pre_sign_in" => Some(Self::PreSignIn),
+            "pre_token_issue" => Some(Self::PreTokenIssue),
+            _ => None,
+        }
+    }
+
+    fn can_deny(&self) -> bool {
+        *self != Self::PostSignUp
+    }
+}
+
+/// What a hook runs.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Action {
+    /// A Rhai script, run in a sandbox with the hook's timeout as its time budget.
+    Script { source: String },
+
+    /// A signed `POST` to a tenant endpoint over HTTPS.
+    Callout { url: String },
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Hook {
+    pub hook_id: String,
+    pub trigger: Trigger,
+    pub name: String,
+
+    #[serde(flatten)]
+    pub action: Action,
+    pub position: i32,
+    pub timeout_ms: i32,
+
+    /// Whether failures to run the hook are ignored rather than denying.
+    pub fail_open: bool,
+    pub is_enabled: bool,
+
+    /// Key signing callouts, which is only shown when created or rotated.
+    #[serde(skip)]
+    pub secret: String,
+    pub created_at: Option<i64>,
+    pub updated_at: Option<i64>,
+}
+
+/// How a hook denies, which is turned into the error of the response.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Denial {
+    /// A 4xx status, 403 by default.
+    pub status: Option<u16>,
+    pub title: String,
+    pub detail: String,
+}
+
+/// What a hook returns. Everything is optional.
+#[derive(Debug, Default, Deserialize)]
+pub struct HookResult {
+    pub deny: Option<Denial>,
+
+    /// Entries added to the user's metadata.
+    pub metadata: Option<HashMap<String, String>>,
+
+    /// Claims added to the issued tokens, only with `pre_token_issue`.
+    pub claims: Option<Map<String, Value>>,
+
+    /// Scopes added to the issued tokens, only with `pre_token_issue`. Deny scopes like
+    /// `-billing.*` narrow what the tokens allow.
+    pub scopes: Option<Vec<String>>,
+}
+
+/// The combined changes of the hooks of a trigger.
+#[derive(Debug, Default, Serialize)]
+pub struct Outcome {
+    pub metadata: HashMap<String, String>,
+    pub claims: Map<String, Value>,
+    pub scopes: Vec<String>,
+}
+
+pub enum Verdict {
+    Continue(Outcome),
+    Deny { hook_id: String, denial: Denial },
+}
+
+type HookRow = (
+    String,
+    String,
+    Option<String>,
+    String,
+    Option<String>,
+    Option<String>,
+    Option<String>,
+    Option<i32>,
+    Option<i32>,
+    Option<bool>,
+    Option<bool>,
+    Option<CqlTimestamp>,
+    Option<CqlTimestamp>,
+);
+
+const COLUMNS: &str = "hook_id, trigger, name, kind, source, url, secret, position, timeout_ms, fail_open, is_enabled, created_at, updated_at";
+
+fn from_row(
+    (
+        hook_id,
+        trigger,
+        name,
+        kind,
+        source,
+        url,
+        secret,
+        position,
+        timeout_ms,
+        fail_open,
+        is_enabled,
+        created_at,
+        updated_at,
+    ): HookRow,
+) -> Option<Hook> {
+    let action = match kind.as_str() {
+        "script" => Action::Script {
+            source: source.unwrap_or_default(),
+        },
+        "callout" => Action::Callout { url: url? },
+        _ => return None,
+    };
+
+    Some(Hook {
+        hook_id,
+        trigger: Trigger::parse(&trigger)?,
+        name: name.unwrap_or_default(),
+        action,
+        position: position.unwrap_or(0),
+        timeout_ms: timeout_ms.unwrap_or(DEFAULT_TIMEOUT_MS),
+        fail_open: fail_open.unwrap_or(false),
+        is_enabled: is_enabled.unwrap_or(true),
+        secret: secret.unwrap_or_default(),
+        created_at: created_at.map(|CqlTimestamp(t)| t),
+        updated_at: updated_at.map(|CqlTimestamp(t)| t),
+    })
+}
+
+/// Generates a secret for signing callouts.
+pub fn gen_secret() -> String {
+    URL_SAFE_NO_PAD.encode(token(Some(32)))
+}
+
+/// Checks an action before saving it: scripts must compile and callouts must go to an HTTPS
+/// URL on a public domain name, as opposed to an address or a name only meaningful on a private
+/// network. Returns why it's invalid otherwise.
+pub fn validate_action(action: &Action) -> Result<(), String> {
+    match action {
+        Action::Script { source } => script::compile(source),
+        Action::Callout { url } => {
+            let parsed = Url::parse(url).ok().filter(|parsed| {
+                url.len() <= 2048 && parsed.scheme() == "https" && parsed.username().is_empty()
+            });
+
+            // Addresses aren't public names, as their last label is numeric or they have none.
+            match parsed.as_ref().and_then(|parsed| parsed.host_str()) {
+                Some(name) if dns::is_public_name(name) => Ok(()),
+                _ => Err(
+                    "Callouts must go to an HTTPS URL on a public domain name, of at most 2048 characters."
+                        .to_string(),
+                ),
+            }
+        }
+    }
+}
+
+/// Checks whether the enabled hooks of a trigger can all run within its budget once `hook` is
+/// saved among `hooks`, which may contain a previous version of it.
+pub fn fits_budget(hooks: &[Hook], hook: &Hook) -> bool {
+    let used: i32 = hooks
+        .iter()
+        .filter(|other| {
+            other.trigger == hook.trigger && other.is_enabled && other.hook_id != hook.hook_id
+        })
+        .map(|other| other.timeout_ms.clamp(1, MAX_TIMEOUT_MS))
+        .sum();
+
+    !hook.is_enabled || used + hook.timeout_ms <= MAX_TRIGGER_BUDGET_MS
+}
+
+pub async fn get(db: &Session, tenant_id: &str, hook_id: &str) -> Result<Option<Hook>, QueryError> {
+    let result = db
+        .query_unpaged(
+            format!("SELECT {COLUMNS} FROM hooks WHERE tenant_id = ? AND hook_id = ?"),
+            (tenant_id, hook_id),
+        )
+        .await?;
+
+    Ok(result
+        .maybe_first_row_typed::<HookRow>()
+        .ok()
+        .flatten()
+        .and_then(from_row))
+}
+
+/// Returns the hooks of a tenant in the order they run: by trigger, then position.
+pub async fn all(db: &Session, tenant_id: &str) -> Result<Vec<Hook>, QueryError> {
+    let result = db
+        .query_unpaged(
+            format!("SELECT {COLUMNS} FROM hooks WHERE tenant_id = ?"),
+            (tenant_id,),
+        )
+        .await?;
+
+    let mut hooks: Vec<Hook> = result
+        .rows_typed_or_empty::<HookRow>()
+        .filter_map(|row| row.ok())
+        .filter_map(from_row)
+        .collect();
+
+    hooks.sort_by(|a, b| {
+        (a.trigger.as_str(), a.position, &a.hook_id).cmp(&(
+            b.trigger.as_str(),
+            b.position,
+            &b.hook_id,
+        ))
+    });
+
+    Ok(hooks)
+}
+
+pub async fn save(db: &Session, tenant_id: &str, hook: &Hook) -> Result<(), QueryError> {
+    let (kind, source, url) = match &hook.action {
+        Action::Script { source } => ("script", Some(source), None),
+        Action::Callout { url } => ("callout", None, Some(url)),
+    };
+
+    db.query_unpaged(
+        "INSERT INTO hooks (tenant_id, hook_id, trigger, name, kind, source, url, secret, position, timeout_ms, fail_open, is_enabled, created_at, updated_at) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+        (
+            tenant_id,
+            &hook.hook_id,
+            hook.trigger.as_str(),
+            &hook.name,
+            kind,
+            source,
+            url,
+            &hook.secret,
+            hook.position,
+            hook.timeout_ms,
+            hook.fail_open,
+            hook.is_enabled,
+            hook.created_at.map(CqlTimestamp),
+            CqlTimestamp(Utc::now().timestamp_millis()),
+        ),
+    )
+    .await?;
+
+    Ok(())
+}
+
+pub async fn delete(db: &Session, tenant_id: &str, hook_id: &str) -> Result<(), QueryError> {
+    db.query_unpaged(
+        "DELETE FROM hooks WHERE tenant_id = ? AND hook_id = ?",
+        (tenant_id, hook_id),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Runs a single hook over an event, for at most its own timeout or `limit`, whichever is
+/// shorter.
+pub async fn execute(hook: &Hook, event: &Value, limit: Duration) -> Result<HookResult, String> {
+    let budget = Duration::from_millis(hook.timeout_ms.clamp(1, MAX_TIMEOUT_MS) as u64).min(limit);
+
+    match &hook.action {
+        Action::Script { source } => script::run(source, event, budget).await,
+        Action::Callout { url } => {
+            callout::run(&hook.hook_id, url, &hook.secret, event, budget).await
+        }
+    }
+}
+
+/// Builds the event hooks receive, made of the trigger, the request and `data`, which depends
+/// on the trigger.
+pub fn event(trigger: Trigger, tenant_id: &str, request: &RequestContext, data: Value) -> Value {
+    serde_json::json!({
+        "trigger": trigger,
+        "tenant_id": tenant_id,
+        "request": {
+            "ip": request.ip,
+            "device_id": request.device_id,
+            "user_agent": request.user_agent,
+            "time": request.time.to_rfc3339(),
+        },
+        "data": data,
+    })
+}
+
+/// Folds a hook's result into the outcome, dropping what the trigger doesn't allow and what
+/// doesn't fit the bounds on metadata, claims and scopes.
+fn merge(outcome: &mut Outcome, trigger: Trigger, result: HookResult) {
+    for (key, value) in result.metadata.unwrap_or_default() {
+        if outcome.metadata.len() >= MAX_METADATA_ENTRIES && !outcome.metadata.contains_key(&key) {
+            break;
+        }
+
+        // User metadata is stored as ASCII.
+        if !key.is_empty()
+            && key.len() <= 64
+            && key.is_ascii()
+            && value.is_ascii()
+            && value.len() <= MAX_METADATA_VALUE_LENGTH
+        {
+            outcome.metadata.insert(key, value);
+        }
+    }
+
+    if trigger != Trigger::PreTokenIssue {
+        return;
+    }
+
+    for (key, value) in result.claims.unwrap_or_default() {
+        if RESERVED_CLAIMS.contains(&key.as_str()) {
+            continue;
+        }
+
+        let mut claims = outcome.claims.clone();
+        claims.insert(key, value);
+
+        if Value::Object(claims.clone()).to_string().len() <= MAX_CLAIMS_SIZE {
+            outcome.claims = claims;
+        }
+    }
+
+    for scope in result.scopes.unwrap_or_default() {
+        if outcome.scopes.len() < MAX_SCOPES
+            && permissions::is_valid(&scope)
+            && !outcome.scopes.contains(&scope)
+        {
+            outcome.scopes.push(scope);
+        }
+    }
+}
+
+/// Runs the tenant's enabled hooks for a trigger in order, stopping at the first that denies.
+/// Hooks that fail to run deny as well, unless they fail open, and so do hooks left without any
+/// of the trigger's budget. Later hooks see the metadata and claims added by earlier ones under
+/// `event.outcome`.
+pub async fn run(
+    state: &State,
+    tenant_id: &str,
+    trigger: Trigger,
+    mut event: Value,
+) -> Result<Verdict, QueryError> {
+    let mut outcome = Outcome::default();
+
+    let hooks = all(&state.db, tenant_id).await?;
+    let deadline = Instant::now() + Duration::from_millis(MAX_TRIGGER_BUDGET_MS as u64);
+
+    for hook in hooks
+        .iter()
+        .filter(|hook| hook.trigger == trigger && hook.is_enabled)
+    {
+        event["outcome"] = serde_json::to_value(&outcome).unwrap_or_default();
+
+        let remaining = deadline.saturating_duration_since(Instant::now());
+
+        let executed = if remaining.is_zero() {
+            Err("The hooks of the trigger ran out of time.".to_string())
+        } else {
+            execute(hook, &event, remaining).await
+        };
+
+        let mut result = match executed {
+            Ok(result) => result,
+            Err(e) => {
+                event!(
+                    Level::WARN,
+                    tenant_id = tenant_id,
+                    hook_id = hook.hook_id,
+                    error = e
+                );
+
+                if hook.fail_open || !trigger.can_deny() {
+                    continue;
+                }
+
+                return Ok(Verdict::Deny {
+                    hook_id: hook.hook_id.clone(),
+                    denial: Denial {
+                        status: Some(503),
+                        title: "Hook Failed".to_string(),
+                        detail: "A hook of the tenant failed to run. Try again later.".to_string(),
+                    },
+                });
+            }
+        };
+
+        match result.deny.take() {
+            Some(denial) if trigger.can_deny() => {
+                return Ok(Verdict::Deny {
+                    hook_id: hook.hook_id.clone(),
+                    denial,
+                })
+            }
+            Some(_) => event!(
+                Level::WARN,
+                tenant_id = tenant_id,
+                hook_id = hook.hook_id,
+                error = "post_sign_up hooks can't deny"
+            ),
+            None => {}
+        }
+
+        merge(&mut outcome, trigger, result);
+    }
+
+    Ok(Verdict::Continue(outcome))
+}
+
+/// Runs a single hook over an event without applying anything, returning how it would deny or
+/// what it would change.
+pub async fn test(hook: &Hook, event: &Value) -> Result<(Option<Denial>, Outcome), String> {
+    let limit = Duration::from_millis(MAX_TRIGGER_BUDGET_MS as u64);
+    let mut result = execute(hook, event, limit).await?;
+    let denial = result.deny.take().filter(|_| hook.trigger.can_deny());
+
+    let mut outcome = Outcome::default();
+    merge(&mut outcome, hook.trigger, result);
+
+    Ok((denial, outcome))
+}
@@ -0,0 +1,77 @@
+use std::time::{Duration, Instant};
+
+use rhai::{Dynamic, Engine, Scope};
+use serde_json::Value;
+
+use super::HookResult;
+
+/// Operations a script can run before it's stopped, whatever its time budget.
+const MAX_OPERATIONS: u64 = 1_000_000;
+
+/// Bounds on what a script can allocate, standing in for a memory budget.
+const MAX_STRING_SIZE: usize = 64 * 1024;
+const MAX_ARRAY_SIZE: usize = 10_000;
+const MAX_MAP_SIZE: usize = 10_000;
+
+/// Builds a sandboxed engine: no file system, no network, no `eval`, bounded recursion and
+/// allocations, and stopped once `budget` is spent.
+fn engine(budget: Option<Duration>) -> Engine {
+    let mut engine = Engine::new();
+    let started = Instant::now();
+
+    engine
+        .disable_symbol("eval")
+        .set_max_operations(MAX_OPERATIONS)
+        .set_max_call_levels(32)
+        .set_max_expr_depths(64, 32)
+        .set_max_string_size(MAX_STRING_SIZE)
+        .set_max_array_size(MAX_ARRAY_SIZE)
+        .set_max_map_size(MAX_MAP_SIZE)
+        .on_print(|_| {})
+        .on_debug(|_, _, _| {});
+
+    if let Some(budget) = budget {
+        engine.on_progress(move |_| (started.elapsed() > budget).then_some(Dynamic::UNIT));
+    }
+
+    engine
+}
+
+/// Checks that a script compiles, returning the compiler's message otherwise.
+pub fn compile(source: &str) -> Result<(), String> {
+    engine(None)
+        .compile(source)
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+/// Runs a script with the event in its scope as `event`. The script's last expression is the
+/// hook's result, a map like `#{ deny: #{ title: "...", detail: "..." } }`, or nothing at all.
+/// Scripts are CPU-bound, so they run on the blocking thread pool.
+pub async fn run(source: &str, event: &Value, budget: Duration) -> Result<HookResult, String> {
+    let source = source.to_string();
+    let event = rhai::serde::to_dynamic(event).map_err(|e| e.to_string())?;
+
+    let task = tokio::task::spawn_blocking(move || {
+        let engine = engine(Some(budget));
+        let mut scope = Scope::new();
+        scope.push_constant("event", event);
+
+        let result = engine
+            .eval_with_scope::<Dynamic>(&mut scope, &source)
+            .map_err(|e| e.to_string())?;
+
+        if result.is_unit() {
+            return Ok(HookResult::default());
+        }
+
+        rhai::serde::from_dynamic::<HookResult>(&result)
+            .map_err(|e| format!("The script returned an invalid result: {e}"))
+    });
+
+    match tokio::time::timeout(budget + Duration::from_millis(100), task).await {
+        Ok(Ok(result)) => result,
+        Ok(Err(e)) => Err(e.to_string()),
+        Err(_) => Err("The script ran out of time.".to_string()),
+    }
+}
@@ -0,0 +1,447 @@
+use std::{
+    sync::{Arc, LazyLock},
+    time::Duration,
+};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::Utc;
+use reqwest::{redirect, Client, StatusCode};
+use scylla::{frame::value::CqlTimestamp, transport::errors::QueryError, Session};
+use serde::{Deserialize, Serialize};
+use tracing::{event, Level};
+
+use crate::{
+    cache,
+    dns::{self, PublicResolver, Resolver},
+    state::State,
+    tokens::token,
+    utils::http::read_limited,
+};
+
+/// Permission a token must allow to manage the tenant's custom domains.
+pub const MANAGE_PERMISSION: &str = "hosts.manage";
+
+/// Prefix of the TXT record proving ownership of a custom domain.
+pub const CHALLENGE_PREFIX: &str = "_accesscore-challenge";
+
+/// Path under which a custom domain serves its HTTP challenge.
+pub const CHALLENGE_PATH: &str = "/.well-known/accesscore-challenge";
+
+/// How long an HTTP challenge may take, well within the request timeout.
+const CHALLENGE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Largest HTTP challenge response read.
+const MAX_CHALLENGE_SIZE: usize = 1024;
+
+static CLIENT: LazyLock<Client> = LazyLock::new(|| {
+    Client::builder()
+        .redirect(redirect::Policy::none())
+        .dns_resolver(Arc::new(PublicResolver))
+        .user_agent("AccessCore-Hosts/1")
+        .build()
+        .unwrap()
+});
+
+/// How ownership of a custom domain is proven.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Method {
+    /// A TXT record at `_accesscore-challenge.{domain}`.
+    Dns,
+
+    /// A file served at `http://{host}/.well-known/accesscore-challenge/{challenge}`. Wildcards
+    /// can't be verified this way.
+    Http,
+}
+
+impl Method {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Dns => "dns",
+            Self::Http => "http",
+        }
+    }
+
+    fn parse(method: &str) -> Option<Self> {
+        match method {
+            "dns" => Some(Self::Dns),
+            "http" => Some(Self::Http),
+            _ => None,
+        }
+    }
+}
+
+/// A host name, or a wildcard like `*.acme.example`, linked to a tenant. Hosts added by the
+/// platform are active right away, while custom domains added by the tenant only are once
+/// verified.
+#[derive(Debug, Clone, Serialize)]
+pub struct Host {
+    pub host: String,
+    pub tenant_id: String,
+    pub is_wildcard: bool,
+
+    /// Whether the tenant added the host itself, as opposed to the platform.
+    pub is_custom: bool,
+    pub method: Option<Method>,
+    #[serde(skip)]
+    pub challenge: String,
+    pub is_verified: bool,
+    pub created_at: Option<i64>,
+    pub verified_at: Option<i64>,
+}
+
+impl Host {
+    /// The domain the host is on, without its wildcard label or port.
+    pub fn domain(&self) -> &str {
+        let name = self.host.trim_start_matches("*.");
+
+        name.rsplit_once(':').map_or(name, |(name, _)| name)
+    }
+
+    /// The name of the TXT record that proves a DNS challenge.
+    pub fn record_name(&self) -> String {
+        format!("{CHALLENGE_PREFIX}.{}", self.domain())
+    }
+
+    /// The value the TXT record, or the body of the HTTP challenge, must have.
+    pub fn record_value(&self) -> String {
+        format!("accesscore-host-verification={}", self.challenge)
+    }
+
+    /// Where the HTTP challenge is fetched from.
+    pub fn challenge_url(&self) -> String {
+        format!("http://{}{CHALLENGE_PATH}/{}", self.host, self.challenge)
+    }
+}
+
+type HostRow = (
+    String,
+    Option<bool>,
+    Option<String>,
+    Option<String>,
+    Option<bool>,
+    Option<CqlTimestamp>,
+    Option<CqlTimestamp>,
+);
+
+const COLUMNS: &str = "host, is_custom, method, challenge, is_verified, created_at, verified_at";
+
+fn from_row(
+    tenant_id: &str,
+    (host, is_custom, method, challenge, is_verified, created_at, verified_at): HostRow,
+) -> Host {
+    Host {
+        is_wildcard: is_wildcard(&host),
+        host,
+        tenant_id: tenant_id.to_string(),
+        is_custom: is_custom.unwrap_or(false),
+        method: method.as_deref().and_then(Method::parse),
+        challenge: challenge.unwrap_or_default(),
+        is_verified: is_verified.unwrap_or(false),
+        created_at: created_at.map(|CqlTimestamp(t)| t),
+        verified_at: verified_at.map(|CqlTimestamp(t)| t),
+    }
+}
+
+/// Lowercases a host and drops the trailing dot of fully qualified names.
+pub fn normalize(host: &str) -> String {
+    host.trim()
+        .to_lowercase()
+        .replace(".:", ":")
+        .trim_end_matches('.')
+        .to_string()
+}
+
+pub fn is_wildcard(host: &str) -> bool {
+    host.starts_with("*.")
+}
+
+/// Checks whether `host` is a lowercase host name, optionally followed by a port, as matched
+/// against the `Host` header. Wildcards replace the first label with `*` and must keep at least
+/// two labels after it, so `*.example` isn't allowed. Only the platform links hosts like this,
+/// as they can be local ones like `localhost:3000`.
+pub fn is_valid_for_platform(host: &str) -> bool {
+    let (name, port) = match host.rsplit_once(':') {
+        Some((name, port)) => (name, Some(port)),
+        None => (host, None),
+    };
+
+    port.is_none_or(|port| port.parse::<u16>().is_ok_and(|port| port > 0)) && is_valid_name(name)
+}
+
+/// Checks whether `host` can be a tenant's custom domain: a public, fully qualified host name,
+/// or a wildcard of one, without a port. Since its challenge is fetched by the server, it can't
+/// be an address or a name only meaningful on a private network.
+pub fn is_valid(host: &str) -> bool {
+    is_valid_name(host) && dns::is_public_name(host.trim_start_matches("*."))
+}
+
+fn is_valid_name(name: &str) -> bool {
+    let name = match name.strip_prefix("*.") {
+        Some(name) if name.split('.').count() >= 2 => name,
+        Some(_) => return false,
+        None => name,
+    };
+
+    (1..=253).contains(&name.len())
+        && name.split('.').all(|label| {
+            (1..=63).contains(&label.len())
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+        })
+}
+
+/// The hosts that can match a `Host` header, most specific first: the host itself, then the
+/// wildcard of its parent domain. Wildcards match a single label, like in certificates.
+fn candidates(host: &str) -> Vec<String> {
+    let host = normalize(host);
+
+    let (name, port) = match host.rsplit_once(':') {
+        Some((name, port)) => (name, Some(port)),
+        None => (host.as_str(), None),
+    };
+
+    let mut candidates = vec![host.clone()];
+
+    if let Some((_, parent)) = name.split_once('.') {
+        if parent.contains('.') {
+            candidates.push(match port {
+                Some(port) => format!("*.{parent}:{port}"),
+                None => format!("*.{parent}"),
+            });
+        }
+    }
+
+    candidates
+}
+
+/// Returns the ID of the tenant a `Host` header resolves to, preferring an exact match over a
+/// wildcard. Goes through the cache, which also remembers hosts linked to no tenant.
+pub async fn resolve(state: &State, host: &str) -> Result<Option<String>, QueryError> {
+    for candidate in candidates(host) {
+        let tenant_id = cache::HOSTS
+            .get(&state.redis, &candidate, || owner(&state.db, &candidate))
+            .await?;
+
+        if tenant_id.is_some() {
+            return Ok(tenant_id);
+        }
+    }
+
+    Ok(None)
+}
+
+/// Returns the ID of the tenant an active host, or wildcard, belongs to.
+pub async fn owner(db: &Session, host: &str) -> Result<Option<String>, QueryError> {
+    let result = db
+        .query_unpaged("SELECT tenant_id FROM hosts WHERE host = ?", (host,))
+        .await?;
+
+    Ok(result
+        .maybe_first_row_typed::<(String,)>()
+        .ok()
+        .flatten()
+        .map(|(tenant_id,)| tenant_id))
+}
+
+pub async fn list(db: &Session, tenant_id: &str) -> Result<Vec<Host>, QueryError> {
+    let result = db
+        .query_unpaged(
+            format!("SELECT {COLUMNS} FROM tenant_hosts WHERE tenant_id = ?"),
+            (tenant_id,),
+        )
+        .await?;
+
+    Ok(result
+        .rows_typed_or_empty::<HostRow>()
+        .filter_map(|row| row.ok())
+        .map(|row| from_row(tenant_id, row))
+        .collect())
+}
+
+pub async fn get(db: &Session, tenant_id: &str, host: &str) -> Result<Option<Host>, QueryError> {
+    let result = db
+        .query_unpaged(
+            format!("SELECT {COLUMNS} FROM tenant_hosts WHERE tenant_id = ? AND host = ?"),
+            (tenant_id, host),
+        )
+        .await?;
+
+    Ok(result
+        .maybe_first_row_typed::<HostRow>()
+        .ok()
+        .flatten()
+        .map(|row| from_row(tenant_id, row)))
+}
+
+/// Takes a host for a tenant, returning `false` if another tenant holds it. Written with an LWT
+/// so a host belongs to one tenant at most.
+pub async fn activate(state: &State, tenant_id: &str, host: &str) -> Result<bool, QueryError> {
+    let result = state
+        .db
+        .query_unpaged(
+            "INSERT INTO hosts (host, tenant_id) VALUES (?, ?) IF NOT EXISTS",
+            (host, tenant_id),
+        )
+        .await?;
+
+    let applied = result
+        .first_row()
+        .ok()
+        .and_then(|row| row.columns.into_iter().next().flatten())
+        .and_then(|applied| applied.as_boolean())
+        .unwrap_or(false);
+
+    cache::HOSTS.invalidate(&state.redis, host).await;
+
+    // Activating a host the tenant already holds again is fine.
+    Ok(applied || owner(&state.db, host).await?.as_deref() == Some(tenant_id))
+}
+
+/// Links a host to a tenant on behalf of the platform, without verification. Returns `false` if
+/// another tenant holds it.
+pub async fn add(state: &State, tenant_id: &str, host: &str) -> Result<bool, QueryError> {
+    if !activate(state, tenant_id, host).await? {
+        return Ok(false);
+    }
+
+    state
+        .db
+        .query_unpaged(
+            "
+            INSERT INTO tenant_hosts (
+                tenant_id, host, is_custom, method, challenge, is_verified, created_at, verified_at
+            ) VALUES (
+                ?, ?, false, null, null, true, toTimestamp(now()), toTimestamp(now())
+            )
+        ",
+            (tenant_id, host),
+        )
+        .await?;
+
+    Ok(true)
+}
+
+/// Adds a custom domain for a tenant, with a new challenge proving its ownership. Adding it again
+/// replaces its challenge.
+pub async fn request(
+    db: &Session,
+    tenant_id: &str,
+    host: &str,
+    method: Method,
+) -> Result<Host, QueryError> {
+    let pending = Host {
+        host: host.to_string(),
+        tenant_id: tenant_id.to_string(),
+        is_wildcard: is_wildcard(host),
+        is_custom: true,
+        method: Some(method),
+        challenge: URL_SAFE_NO_PAD.encode(token(Some(24))),
+        is_verified: false,
+        created_at: Some(Utc::now().timestamp_millis()),
+        verified_at: None,
+    };
+
+    db.query_unpaged(
+        "
+            INSERT INTO tenant_hosts (
+                tenant_id, host, is_custom, method, challenge, is_verified, created_at, verified_at
+            ) VALUES (
+                ?, ?, true, ?, ?, false, ?, null
+            )
+        ",
+        (
+            tenant_id,
+            host,
+            method.as_str(),
+            &pending.challenge,
+            pending.created_at.map(CqlTimestamp),
+        ),
+    )
+    .await?;
+
+    Ok(pending)
+}
+
+/// Checks whether the challenge of a custom domain is in place. HTTP challenges are only fetched
+/// from public addresses, without following redirects.
+pub async fn check(dns: &Resolver, host: &Host) -> bool {
+    match host.method {
+        Some(Method::Dns) => match dns.txt(&host.record_name()).await {
+            Ok(records) => records.contains(&host.record_value()),
+            Err(e) => {
+                event!(Level::WARN, error = format!("{e}"), host = host.host);
+                false
+            }
+        },
+        // Domains added before custom domains had to be public may not be.
+        Some(Method::Http) if !is_valid(&host.host) => false,
+        Some(Method::Http) => {
+            let response = CLIENT
+                .get(host.challenge_url())
+                .timeout(CHALLENGE_TIMEOUT)
+                .send()
+                .await;
+
+            match response {
+                Ok(response) if response.status() == StatusCode::OK => {
+                    match read_limited(response, MAX_CHALLENGE_SIZE).await {
+                        Ok(Some(body)) => {
+                            String::from_utf8_lossy(&body).trim() == host.record_value()
+                        }
+                        _ => false,
+                    }
+                }
+                Ok(_) => false,
+                Err(e) => {
+                    event!(Level::WARN, error = format!("{e}"), host = host.host);
+                    false
+                }
+            }
+        }
+        None => false,
+    }
+}
+
+/// Activates a custom domain whose challenge passed. Returns `false` if another tenant holds it.
+pub async fn verify(state: &State, host: &Host) -> Result<bool, QueryError> {
+    if !activate(state, &host.tenant_id, &host.host).await? {
+        return Ok(false);
+    }
+
+    state.db.query_unpaged(
+        "UPDATE tenant_hosts SET is_verified = true, verified_at = toTimestamp(now()) WHERE tenant_id = ? AND host = ?",
+        (&host.tenant_id, &host.host),
+    )
+    .await?;
+
+    Ok(true)
+}
+
+/// Unlinks a host from a tenant, releasing it if it was active.
+pub async fn remove(state: &State, host: &Host) -> Result<(), QueryError> {
+    state
+        .db
+        .query_unpaged(
+            "DELETE FROM tenant_hosts WHERE tenant_id = ? AND host = ?",
+            (&host.tenant_id, &host.host),
+        )
+        .await?;
+
+    if host.is_verified {
+        state
+            .db
+            .query_unpaged(
+                "DELETE FROM hosts WHERE host = ? IF tenant_id = ?",
+                (&host.host, &host.tenant_id),
+            )
+            .await?;
+
+        cache::HOSTS.invalidate(&state.redis, &host.host).await;
+    }
+
+    Ok(())
+}
@@ -1,13 +1,36 @@
+pub mod archive;
 pub mod auth;
+pub mod authz;
+pub mod cache;
+pub mod codes;
 pub mod constants;
 pub mod db;
+pub mod dns;
+pub mod emails;
 pub mod error_handlers;
+pub mod flags;
+pub mod groups;
+pub mod hooks;
+pub mod hosts;
 pub mod middleware;
+pub mod notifications;
+pub mod organizations;
+pub mod permissions;
+pub mod policies;
+pub mod purge;
 pub mod redis;
+pub mod relations;
 pub mod requests;
 pub mod responses;
+pub mod roles;
 pub mod routes;
+pub mod sandbox;
+pub mod settings;
 pub mod state;
+pub mod tenants;
 pub mod tokens;
 pub mod types;
+pub mod usage;
+pub mod usernames;
+pub mod users;
 pub mod utils;
@@ -1,11 +1,16 @@
+use accesscore::cache;
 use accesscore::db;
+use accesscore::dns;
 use accesscore::error_handlers::handler_404;
 use accesscore::middleware as ac_middleware;
+use accesscore::purge;
 use accesscore::redis;
 use accesscore::state::State;
+use accesscore::tenants;
+use accesscore::usage;
 use accesscore::{routes, state::AppState};
 use axum::middleware as ax_middleware;
-use axum::Router;
+use axum::{extract::Request, Router, ServiceExt};
 use hmac::Hmac;
 use hmac::Mac;
 use sha2::Sha384;
@@ -14,6 +19,7 @@ use std::net::SocketAddr;
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::sync::RwLock;
+use tower::Layer;
 use tower_http::{
     compression::CompressionLayer, decompression::DecompressionLayer, limit::RequestBodyLimitLayer,
     timeout::TimeoutLayer, trace::TraceLayer,
@@ -44,18 +50,40 @@ async fn main() {
     let redis_session = redis::session().await;
     event!(Level::INFO, "Connected to Redis.");
 
+    tokio::spawn(cache::subscribe(redis_session.clone()));
+
     let key: Hmac<Sha384> = Hmac::new_from_slice(b"uwu nya").unwrap();
 
     let state: AppState = Arc::new(RwLock::new(State {
         db: scylla_session,
         redis: redis_session,
         hmac: key,
+        dns: dns::resolver(),
+        tenant_resolution: tenants::resolution(),
     }));
 
+    tokio::spawn(purge::run(state.clone()));
+    tokio::spawn(usage::run(state.clone()));
+
     let app = Router::new()
         .nest("/auth", routes::auth::router())
         .nest("/users", routes::users::router())
+        .nest("/organizations", routes::organizations::router())
+        .nest("/groups", routes::groups::router())
+        .nest("/roles", routes::roles::router())
+        .nest("/authz", routes::authz::router())
+        .nest("/relations", routes::relations::router())
+        .nest("/policies", routes::policies::router())
+        .nest("/hooks", routes::hooks::router())
+        .nest("/platform", routes::platform::router())
+        .nest("/settings", routes::settings::router())
+        .nest("/hosts", routes::hosts::router())
+        .nest("/sandbox", routes::sandbox::router())
         .fallback(handler_404)
+        .layer(
+            // Keep above authentication().
+            ax_middleware::from_fn_with_state(state.clone(), ac_middleware::flags),
+        )
         .layer(
             // Keep above request_id(), response_meta(), and tenant() middleware.
             ax_middleware::from_fn_with_state(state.clone(), ac_middleware::authentication),
@@ -96,18 +124,25 @@ async fn main() {
             state.clone(),
             ac_middleware::request_id,
         ))
-        .with_state(state);
+        .with_state(state.clone());
+
+    // Wraps the router, as path prefixes naming the tenant must be stripped before routing.
+    let app =
+        ax_middleware::from_fn_with_state(state.clone(), ac_middleware::tenant_path).layer(app);
 
     event!(Level::INFO, "Starting server...");
 
     let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
     axum::serve(
         listener,
-        app.into_make_service_with_connect_info::<SocketAddr>(),
+        ServiceExt::<Request>::into_make_service_with_connect_info::<SocketAddr>(app),
     )
     .with_graceful_shutdown(shutdown())
     .await
     .unwrap();
+
+    event!(Level::INFO, "Writing usage...");
+    usage::flush(&state.read().await.db).await;
 }
 
 async fn shutdown() {
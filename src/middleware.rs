@@ -1,7 +1,7 @@
 use axum::{
     body::Body,
     extract::{ConnectInfo, Host, Request, State},
-    http::{HeaderMap, HeaderValue, Response, StatusCode},
+    http::{HeaderMap, HeaderValue, Response, StatusCode, Uri},
     middleware::Next,
     response::IntoResponse,
     Extension,
@@ -17,9 +17,15 @@ use tracing::{event, Level};
 use crate::{
     auth::Auth,
     error_handlers::error_response,
+    flags::Flags,
+    hosts, permissions,
     responses::{self, CommonError, Error},
-    state::AppState,
-    types::{RequestID, TenantID},
+    sandbox,
+    state::{self, AppState},
+    tenants::{self, Resolution},
+    tokens,
+    types::{PathTenantID, RequestID, Sandbox, TenantID},
+    usage::{self, Metric},
     utils::id::gen_id,
 };
 
@@ -77,45 +83,56 @@ pub async fn authentication(
                 .split_ascii_whitespace()
                 .collect::<Vec<&str>>()[1];
 
-            let user_id = match state
-                .db
-                .query_unpaged(
-                    "SELECT user_id FROM api_tokens WHERE tenant_id = ? AND api_token = ? AND is_refresh = false LIMIT 1",
-                    (&tenant_id, &URL_SAFE_NO_PAD.decode(token).unwrap()
-                )
-            ).await {
-                Ok(result) => {
-                    match result.first_row_typed::<(String,)>() {
-                        Ok((user_id,)) => user_id,
-                        Err(_) => {
-                            return (
-                                StatusCode::UNAUTHORIZED,
-                                responses::Response::<Value>::new(
-                                    None,
-                                    Some(vec![
-                                        Error::new(
-                                            StatusCode::UNAUTHORIZED.into(),
-                                            "Unauthorized",
-                                            "The provided token is invalid. Check that it hasn't expired.",
-                                            Some("headers.authorization"),
-                                            HashMap::from([("input", json!(token))])
-                                        )
-                                    ]),
-                                    Some(response_meta),
-                                    None
-                                )
-                            ).into_response()
-                        }
+            let (user_id, scopes, device_id) = match tokens::access_token(
+                &state,
+                &tenant_id,
+                &URL_SAFE_NO_PAD.decode(token).unwrap(),
+            )
+            .await
+            {
+                Ok(Some(access_token)) => (
+                    access_token.user_id,
+                    access_token.scopes,
+                    access_token.device_id,
+                ),
+                Ok(None) => {
+                    return (
+                        StatusCode::UNAUTHORIZED,
+                        responses::Response::<Value>::new(
+                            None,
+                            Some(vec![Error::new(
+                                StatusCode::UNAUTHORIZED.into(),
+                                "Unauthorized",
+                                "The provided token is invalid. Check that it hasn't expired.",
+                                Some("headers.authorization"),
+                                HashMap::from([("input", json!(token))]),
+                            )]),
+                            Some(response_meta),
+                            None,
+                        ),
+                    )
+                        .into_response()
+                }
+                Err(_) => {
+                    return responses::CommonError::InternalServerError {
+                        request_id,
+                        tenant_id: Some(tenant_id),
                     }
-                },
-                Err(_) => return responses::CommonError::InternalServerError { request_id, tenant_id: Some(tenant_id) }.into_response()
+                    .into_response()
+                }
             };
 
             req.extensions_mut().insert(Auth {
                 user_id: Some(user_id),
                 token: Some(token.to_string()),
-                scopes: vec![],
+                scopes: scopes
+                    .into_iter()
+                    .filter(|scope| {
+                        scope == permissions::LEGACY_ALL_SCOPE || permissions::is_valid(scope)
+                    })
+                    .collect(),
                 client_id: None,
+                device_id,
             });
 
             return next.run(req).await;
@@ -143,44 +160,249 @@ pub async fn authentication(
             token: None,
             scopes: vec![],
             client_id: None,
+            device_id: None,
         });
 
         return next.run(req).await;
     }
 }
 
+/// Lets handlers check feature flags for the user making the request.
+pub async fn flags(
+    State(state): State<AppState>,
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(auth): Extension<Auth>,
+    mut req: Request,
+    next: Next,
+) -> Response<Body> {
+    req.extensions_mut()
+        .insert(Flags::new(state, tenant_id, auth.user_id));
+
+    next.run(req).await
+}
+
+/// Strips a `/t/{tenant_id}` prefix from the path, so routes match the same way under every
+/// resolution strategy. Wraps the router, as the path must be rewritten before routing.
+pub async fn tenant_path(
+    State(state): State<AppState>,
+    mut req: Request,
+    next: Next,
+) -> Response<Body> {
+    if !state
+        .read()
+        .await
+        .tenant_resolution
+        .contains(&Resolution::Path)
+    {
+        return next.run(req).await;
+    }
+
+    let Some(rest) = req.uri().path().strip_prefix(tenants::PATH_PREFIX) else {
+        return next.run(req).await;
+    };
+
+    let (tenant_id, path) = match rest.split_once('/') {
+        Some((tenant_id, path)) => (tenant_id.to_string(), format!("/{path}")),
+        None => (rest.to_string(), "/".to_string()),
+    };
+
+    if !tenants::is_valid_id(&tenant_id) {
+        return next.run(req).await;
+    }
+
+    let path_and_query = match req.uri().query() {
+        Some(query) => format!("{path}?{query}"),
+        None => path,
+    };
+
+    let mut parts = req.uri().clone().into_parts();
+    parts.path_and_query = path_and_query.parse().ok();
+
+    if let Ok(uri) = Uri::from_parts(parts) {
+        *req.uri_mut() = uri;
+        req.extensions_mut().insert(PathTenantID(tenant_id));
+    }
+
+    next.run(req).await
+}
+
+/// Checks that a request naming its tenant with the `X-Tenant-ID` header is authenticated with
+/// an access token of that tenant, whose user and scopes allow naming it. Routes taking no token
+/// refuse the header. Returns the response to send otherwise.
+async fn authorize_tenant_header(
+    state: &state::State,
+    tenant_id: &str,
+    headers: &HeaderMap,
+    path: &str,
+    request_id: &str,
+) -> Result<(), Response<Body>> {
+    let error = |status: StatusCode, title: &str, detail: &str| {
+        error_response(
+            status,
+            title,
+            detail,
+            Some("headers.x-tenant-id"),
+            HashMap::new(),
+            request_id.to_string(),
+            None,
+        )
+        .into_response()
+    };
+
+    if path.starts_with(tenants::UNAUTHENTICATED_PREFIX) {
+        return Err(error(
+            StatusCode::BAD_REQUEST,
+            "Invalid Header",
+            "The `X-Tenant-ID` header isn't accepted on routes taking no access token.",
+        ));
+    }
+
+    let token = headers
+        .get("authorization")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .and_then(|token| URL_SAFE_NO_PAD.decode(token).ok());
+
+    let access_token = match token {
+        Some(token) => match tokens::access_token(state, tenant_id, &token).await {
+            Ok(access_token) => access_token,
+            Err(_) => {
+                return Err(CommonError::InternalServerError {
+                    request_id: request_id.to_string(),
+                    tenant_id: None,
+                }
+                .into_response())
+            }
+        },
+        None => None,
+    };
+
+    let Some(access_token) = access_token else {
+        return Err(error(
+            StatusCode::UNAUTHORIZED,
+            "Unauthorized",
+            "The `X-Tenant-ID` header is only accepted along with a valid access token of the tenant it names.",
+        ));
+    };
+
+    match permissions::has_scoped_permission(
+        state,
+        tenant_id,
+        &access_token.user_id,
+        &access_token.scopes,
+        tenants::HEADER_PERMISSION,
+    )
+    .await
+    {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(error(
+            StatusCode::FORBIDDEN,
+            "Forbidden",
+            "The access token isn't allowed to name its tenant with the `X-Tenant-ID` header.",
+        )),
+        Err(_) => Err(CommonError::InternalServerError {
+            request_id: request_id.to_string(),
+            tenant_id: None,
+        }
+        .into_response()),
+    }
+}
+
+/// Finds the tenant of the request with each resolution strategy in priority order. Tenants named
+/// explicitly, by header or path, must agree with the one picked.
 pub async fn tenant(
     Extension(RequestID(request_id)): Extension<RequestID>,
     State(state): State<AppState>,
-    Host(host): Host,
+    host: Option<Host>,
+    headers: HeaderMap,
     mut req: Request,
     next: Next,
 ) -> Response<Body> {
     let state = state.read().await;
 
-    let result = state
-        .db
-        .query_unpaged(
-            "SELECT tenant_id FROM tenants_by_host WHERE host = ?",
-            (&host,),
-        )
-        .await;
+    let path_tenant_id = req
+        .extensions()
+        .get::<PathTenantID>()
+        .map(|PathTenantID(tenant_id)| tenant_id.clone());
+
+    let header_tenant_id = match headers.get(tenants::TENANT_HEADER) {
+        Some(value) if state.tenant_resolution.contains(&Resolution::Header) => {
+            let Some(tenant_id) = value
+                .to_str()
+                .ok()
+                .filter(|tenant_id| tenants::is_valid_id(tenant_id))
+            else {
+                return error_response(
+                    StatusCode::BAD_REQUEST,
+                    "Invalid Header",
+                    "The `X-Tenant-ID` header must contain a tenant ID.",
+                    Some("headers.x-tenant-id"),
+                    HashMap::new(),
+                    request_id,
+                    None,
+                )
+                .into_response();
+            };
 
-    if let Err(_) = result {
-        return responses::CommonError::InternalServerError {
-            request_id,
-            tenant_id: None,
+            if let Err(response) =
+                authorize_tenant_header(&state, tenant_id, &headers, req.uri().path(), &request_id)
+                    .await
+            {
+                return response;
+            }
+
+            Some(tenant_id.to_string())
+        }
+        _ => None,
+    };
+
+    let mut resolved: Option<(String, &str)> = None;
+
+    for strategy in &state.tenant_resolution {
+        resolved = match strategy {
+            Resolution::Host => match &host {
+                Some(Host(host)) => match hosts::resolve(&state, host).await {
+                    Ok(tenant_id) => tenant_id.map(|tenant_id| (tenant_id, "headers.host")),
+                    Err(_) => {
+                        return responses::CommonError::InternalServerError {
+                            request_id,
+                            tenant_id: None,
+                        }
+                        .into_response();
+                    }
+                },
+                None => None,
+            },
+            Resolution::Header => header_tenant_id
+                .clone()
+                .map(|tenant_id| (tenant_id, "headers.x-tenant-id")),
+            Resolution::Path => path_tenant_id
+                .clone()
+                .map(|tenant_id| (tenant_id, "path.tenant_id")),
+        };
+
+        if resolved.is_some() {
+            break;
         }
-        .into_response();
     }
 
-    let result = result.unwrap();
+    let Some((tenant_id, location)) = resolved else {
+        let (title, detail) = if state.tenant_resolution == [Resolution::Host] {
+            (
+                "Host Not Found",
+                "The host name is not linked to any AccessCore tenant.",
+            )
+        } else {
+            (
+                "Tenant Not Found",
+                "The request doesn't name any AccessCore tenant, and its host name is not linked to any.",
+            )
+        };
 
-    if result.rows_num().unwrap() == 0 {
         return error_response(
             StatusCode::NOT_FOUND,
-            "Host Not Found",
-            "The host name is not linked to any AccessCore tenant.",
+            title,
+            detail,
             Some("headers.host"),
             HashMap::from([
                 ("request_id", json!(request_id)),
@@ -190,31 +412,141 @@ pub async fn tenant(
             None,
         )
         .into_response();
+    };
+
+    for (named, location) in [
+        (&header_tenant_id, "headers.x-tenant-id"),
+        (&path_tenant_id, "path.tenant_id"),
+    ] {
+        if named.as_ref().is_some_and(|named| named != &tenant_id) {
+            return error_response(
+                StatusCode::BAD_REQUEST,
+                "Tenant Mismatch",
+                "The request names another tenant than the one it resolves to.",
+                Some(location),
+                HashMap::from([("resolved", json!(tenant_id))]),
+                request_id,
+                None,
+            )
+            .into_response();
+        }
+    }
+
+    let status = match tenants::status(&state, &tenant_id).await {
+        Ok(Some(status)) => status,
+        Ok(None) => {
+            return error_response(
+                StatusCode::NOT_FOUND,
+                "Tenant Not Found",
+                "There's no tenant with this ID.",
+                Some(location),
+                HashMap::from([("input", json!(tenant_id))]),
+                request_id,
+                None,
+            )
+            .into_response();
+        }
+        Err(_) => {
+            return responses::CommonError::InternalServerError {
+                request_id,
+                tenant_id: Some(tenant_id),
+            }
+            .into_response();
+        }
+    };
+
+    if status.is_suspended {
+        return error_response(
+            StatusCode::FORBIDDEN,
+            "Tenant Suspended",
+            "The tenant this request is made to is suspended.",
+            Some(location),
+            HashMap::new(),
+            request_id,
+            Some(tenant_id),
+        )
+        .into_response();
     }
 
-    let (tenant_id,): (String,) = result
-        .first_row()
-        .unwrap()
-        .into_typed::<(String,)>()
-        .unwrap();
+    usage::record(&tenant_id, Metric::ApiRequests, 1);
 
     req.extensions_mut().insert(TenantID(tenant_id));
+    req.extensions_mut().insert(Sandbox(status.is_sandbox));
 
-    next.run(req).await
+    let mut response = next.run(req).await;
+
+    response.extensions_mut().insert(Sandbox(status.is_sandbox));
+
+    response
 }
 
 pub async fn response_meta(
     Extension(RequestID(request_id)): Extension<RequestID>,
     Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(Sandbox(is_sandbox)): Extension<Sandbox>,
     mut req: Request,
     next: Next,
 ) -> Response<Body> {
-    req.extensions_mut().insert(HashMap::from([
+    let mut meta = HashMap::from([
         ("tenant_id", json!(tenant_id)),
         ("request_id", json!(request_id)),
-    ]));
+    ]);
 
-    next.run(req).await
+    if is_sandbox {
+        meta.insert("sandbox", json!(true));
+    }
+
+    req.extensions_mut().insert(meta);
+
+    let response = next.run(req).await;
+
+    // Errors build their own meta, so the mark is added to their body.
+    if is_sandbox && (response.status().is_client_error() || response.status().is_server_error()) {
+        return mark_sandbox(response).await;
+    }
+
+    response
+}
+
+/// Adds `"sandbox": true` to the meta of a JSON response.
+async fn mark_sandbox(response: Response<Body>) -> Response<Body> {
+    let is_json = response
+        .headers()
+        .get("content-type")
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/json"));
+
+    if !is_json {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+
+    let Ok(bytes) = axum::body::to_bytes(body, usize::MAX).await else {
+        return Response::from_parts(parts, Body::empty());
+    };
+
+    let mut value = match serde_json::from_slice::<Value>(&bytes) {
+        Ok(value) => value,
+        Err(_) => return Response::from_parts(parts, Body::from(bytes)),
+    };
+
+    if let Some(meta) = value.get_mut("meta").and_then(Value::as_object_mut) {
+        meta.insert("sandbox".to_string(), json!(true));
+    }
+
+    parts.headers.remove("content-length");
+
+    Response::from_parts(parts, Body::from(value.to_string()))
+}
+
+/// What a request costs the rate limiter, in fractions of a regular request. Requests to sandbox
+/// tenants are cheaper, so test suites aren't slowed down.
+fn ratelimit_cost(response: &Response<Body>) -> i64 {
+    match response.extensions().get::<Sandbox>() {
+        Some(Sandbox(true)) => 1,
+        _ => sandbox::RATELIMIT_DISCOUNT,
+    }
 }
 
 pub async fn global_ratelimit(
@@ -244,9 +576,10 @@ pub async fn global_ratelimit(
         Ok(conn) => conn,
     };
 
+    // Tokens are fractions of a request, as requests to sandbox tenants cost less.
     let bucket = "global";
-    let bucket_refill_every = 200;
-    let bucket_max_tokens: i64 = 10;
+    let bucket_refill_every = 200 / sandbox::RATELIMIT_DISCOUNT;
+    let bucket_max_tokens: i64 = 10 * sandbox::RATELIMIT_DISCOUNT;
     let mut used_tokens: i64 = 0;
     let mut single_refill_in: i64 = bucket_refill_every;
     let mut blocked = false;
@@ -296,12 +629,12 @@ pub async fn global_ratelimit(
             )
             .into_response();
         } else {
-            used_tokens += 1;
             response = next.run(req).await;
+            used_tokens += ratelimit_cost(&response);
         }
     } else {
-        used_tokens += 1;
         response = next.run(req).await;
+        used_tokens += ratelimit_cost(&response);
     }
 
     if !blocked {
@@ -350,11 +683,21 @@ pub async fn global_ratelimit(
         .insert("X-RateLimit-Bucket", HeaderValue::from_str(bucket).unwrap());
     response.headers_mut().insert(
         "X-RateLimit-Limit",
-        HeaderValue::from_str(bucket_max_tokens.to_string().as_str()).unwrap(),
+        HeaderValue::from_str(
+            (bucket_max_tokens / sandbox::RATELIMIT_DISCOUNT)
+                .to_string()
+                .as_str(),
+        )
+        .unwrap(),
     );
     response.headers_mut().insert(
         "X-RateLimit-Remaining",
-        HeaderValue::from_str((bucket_max_tokens - used_tokens).to_string().as_str()).unwrap(),
+        HeaderValue::from_str(
+            (cmp::max(bucket_max_tokens - used_tokens, 0) / sandbox::RATELIMIT_DISCOUNT)
+                .to_string()
+                .as_str(),
+        )
+        .unwrap(),
     );
     response.headers_mut().insert(
         "X-RateLimit-Refill",
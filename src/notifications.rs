@@ -0,0 +1,138 @@
+use std::collections::HashMap;
+
+use num_derive::FromPrimitive;
+use scylla::{batch::Batch, transport::errors::QueryError};
+
+use crate::{
+    sandbox,
+    state::State,
+    tenants,
+    usage::{self, Metric},
+    utils::id::gen_id,
+};
+
+/// The event that triggered a notification. Stored as `notifications.event_type`, and mapped as
+/// `NotificationEvent` in `db::orm`.
+#[derive(Clone, Copy, Debug, Default, FromPrimitive)]
+#[repr(i16)]
+pub enum Event {
+    #[default]
+    AdminMessage = 0,
+    EmailChangeVerification = 1,
+    EmailChangeRequested = 2,
+    EmailChanged = 3,
+    OrganizationInvitation = 4,
+}
+
+/// The channel a notification is delivered through. Stored as `notification_type`, and mapped as
+/// `NotificationType` in `db::orm`.
+#[derive(Clone, Copy, Debug, Default, FromPrimitive)]
+#[repr(i8)]
+pub enum Channel {
+    #[default]
+    InApp = 0,
+    Whatsapp = 1,
+    Email = 2,
+    SMS = 3,
+    Push = 4,
+}
+
+/// Stored as `notifications.priority`, and mapped as `NotificationPriority` in `db::orm`.
+#[derive(Clone, Copy, Debug, Default, FromPrimitive)]
+#[repr(i8)]
+pub enum Priority {
+    #[default]
+    Low = 0,
+    Medium = 1,
+    High = 2,
+    Critical = 3,
+}
+
+/// Queues an email for `user_id` to be delivered to `email`, which does not need to be one of
+/// the user's stored emails yet. The address is kept in the notification's `to` data field for
+/// the delivery worker. Emails to people without an account, like invitees, have no `user_id`
+/// and therefore no recipient row. Emails of sandbox tenants are only kept for tests to read.
+#[allow(clippy::too_many_arguments)]
+pub async fn send_email(
+    state: &State,
+    tenant_id: &str,
+    user_id: Option<&str>,
+    email: &str,
+    event: Event,
+    priority: Priority,
+    title: &str,
+    message: &str,
+    mut data: HashMap<String, String>,
+) -> Result<String, QueryError> {
+    if tenants::is_sandbox(state, tenant_id).await? {
+        return sandbox::record(
+            &state.db,
+            tenant_id,
+            user_id,
+            email,
+            event as i16,
+            title,
+            message,
+            &data,
+        )
+        .await;
+    }
+
+    let db = &state.db;
+    let notification_id = gen_id(None);
+
+    data.insert("to".to_string(), email.to_string());
+
+    let notification = (
+        tenant_id,
+        &notification_id,
+        event as i16,
+        Channel::Email as i8,
+        title,
+        message,
+        &data,
+        priority as i8,
+    );
+
+    let mut batch = Batch::default();
+
+    batch.append_statement(
+        "
+            INSERT INTO notifications (
+                tenant_id, notification_id, event_type, notification_type, title, message, data, priority, created_at
+            ) VALUES (
+                ?, ?, ?, ?, ?, ?, ?, ?, toTimestamp(now())
+            )
+        ",
+    );
+
+    match user_id {
+        Some(user_id) => {
+            batch.append_statement(
+                "
+                    INSERT INTO notification_recipients (
+                        tenant_id, notification_id, user_id, status, notification_type
+                    ) VALUES (
+                        ?, ?, ?, 0, ?
+                    )
+                ",
+            );
+
+            db.batch(
+                &batch,
+                (
+                    notification,
+                    (tenant_id, &notification_id, user_id, Channel::Email as i8),
+                ),
+            )
+            .await?;
+        }
+        None => {
+            db.batch(&batch, (notification,)).await?;
+        }
+    }
+
+    usage::record(tenant_id, Metric::Notifications, 1);
+
+    Ok(notification_id)
+}
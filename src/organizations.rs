@@ -0,0 +1,846 @@
+use std::collections::{HashMap, HashSet};
+
+use base64::engine::{general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use chrono::Utc;
+use hmac::Hmac;
+use scylla::{frame::value::CqlTimestamp, transport::errors::QueryError, Session};
+use serde::{Deserialize, Serialize};
+use sha2::Sha384;
+use tracing::{event, Level};
+
+use crate::{
+    authz,
+    dns::Resolver,
+    emails, permissions,
+    state::State,
+    tokens::{token, InvitationToken},
+};
+
+/// Tenant-wide permission that grants owner access to every organization.
+pub const MANAGE_PERMISSION: &str = "organizations.manage";
+
+/// Seconds an invitation is valid for.
+pub const INVITATION_EXPIRES_IN: u64 = 604800;
+
+/// Subdomain of a claimed domain whose TXT records prove the claim.
+pub const DOMAIN_CHALLENGE_PREFIX: &str = "_accesscore-challenge";
+
+/// A member's role in an organization. Stored as `organizations_by_user.role`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[repr(i8)]
+pub enum Role {
+    Owner = 0,
+    Admin = 1,
+    Member = 2,
+}
+
+impl Role {
+    pub fn from_i8(value: i8) -> Option<Self> {
+        match value {
+            0 => Some(Self::Owner),
+            1 => Some(Self::Admin),
+            2 => Some(Self::Member),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Owner => "owner",
+            Self::Admin => "admin",
+            Self::Member => "member",
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct Organization {
+    pub organization_id: String,
+    pub name: String,
+    pub metadata: HashMap<String, String>,
+    pub created_at: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub struct Membership {
+    pub organization_id: String,
+    pub user_id: String,
+    pub role: Role,
+
+    /// Tenant-defined roles that apply within the organization.
+    pub roles: HashSet<String>,
+    pub joined_at: Option<i64>,
+}
+
+/// A pending invitation to join an organization.
+#[derive(Serialize)]
+pub struct Invitation {
+    pub invitation_id: String,
+    pub organization_id: String,
+
+    /// The canonical email the invitation was sent to.
+    pub email: String,
+
+    /// The email as provided by the inviter, which is where the invitation was delivered to.
+    pub address: String,
+    pub role: Role,
+    pub invited_by: Option<String>,
+    pub created_at: Option<i64>,
+    pub expires_at: Option<i64>,
+}
+
+/// An email domain claimed by an organization. Once verified, users who verify an email on the
+/// domain join the organization automatically.
+#[derive(Serialize)]
+pub struct DomainClaim {
+    pub domain: String,
+    pub organization_id: String,
+    #[serde(skip)]
+    pub challenge: String,
+    pub is_verified: bool,
+
+    /// Whether members who joined through the domain must sign in with the organization's SSO
+    /// instead of a password.
+    pub require_sso: bool,
+    pub created_at: Option<i64>,
+    pub verified_at: Option<i64>,
+}
+
+impl DomainClaim {
+    /// The name of the TXT record that proves the claim.
+    pub fn record_name(&self) -> String {
+        format!("{DOMAIN_CHALLENGE_PREFIX}.{}", self.domain)
+    }
+
+    /// The value the TXT record must have.
+    pub fn record_value(&self) -> String {
+        format!("accesscore-domain-verification={}", self.challenge)
+    }
+}
+
+/// What a user can do in an organization.
+#[derive(Clone, Copy, Debug)]
+pub struct Access {
+    /// The user's role in the organization, if they're a member.
+    pub role: Option<Role>,
+
+    /// Whether the user has the tenant-wide `organizations.manage` permission.
+    pub is_tenant_admin: bool,
+}
+
+impl Access {
+    pub fn can_view(&self) -> bool {
+        self.is_tenant_admin || self.role.is_some()
+    }
+
+    /// Admins and owners can update the organization and manage its non-owner members.
+    pub fn can_manage(&self) -> bool {
+        self.is_tenant_admin || matches!(self.role, Some(Role::Owner) | Some(Role::Admin))
+    }
+
+    /// Only owners can delete the organization and grant or revoke the owner role.
+    pub fn is_owner(&self) -> bool {
+        self.is_tenant_admin || self.role == Some(Role::Owner)
+    }
+
+    /// Checks whether the user can set a member's role from `current` to `new`. `None` means
+    /// not being a member.
+    pub fn can_change_role(&self, current: Option<Role>, new: Option<Role>) -> bool {
+        if current == Some(Role::Owner) || new == Some(Role::Owner) {
+            self.is_owner()
+        } else {
+            self.can_manage()
+        }
+    }
+}
+
+/// Resolves what `user_id` can do in an organization.
+pub async fn access(
+    state: &State,
+    tenant_id: &str,
+    organization_id: &str,
+    user_id: &str,
+) -> Result<Access, QueryError> {
+    let role = role(&state.db, tenant_id, organization_id, user_id).await?;
+    let is_tenant_admin =
+        permissions::has_permission(state, tenant_id, user_id, MANAGE_PERMISSION).await?;
+
+    Ok(Access {
+        role,
+        is_tenant_admin,
+    })
+}
+
+pub async fn get(
+    db: &Session,
+    tenant_id: &str,
+    organization_id: &str,
+) -> Result<Option<Organization>, QueryError> {
+    let result = db
+        .query_unpaged(
+            "SELECT name, metadata, created_at FROM organizations WHERE tenant_id = ? AND organization_id = ?",
+            (tenant_id, organization_id),
+        )
+        .await?;
+
+    Ok(
+        match result.maybe_first_row_typed::<(
+            Option<String>,
+            Option<HashMap<String, String>>,
+            Option<CqlTimestamp>,
+        )>() {
+            Ok(Some((name, metadata, created_at))) => Some(Organization {
+                organization_id: organization_id.to_string(),
+                name: name.unwrap_or_default(),
+                metadata: metadata.unwrap_or_default(),
+                created_at: created_at.map(|CqlTimestamp(t)| t),
+            }),
+            _ => None,
+        },
+    )
+}
+
+/// Returns the user's role in an organization, or `None` if they're not a member.
+pub async fn role(
+    db: &Session,
+    tenant_id: &str,
+    organization_id: &str,
+    user_id: &str,
+) -> Result<Option<Role>, QueryError> {
+    let result = db
+        .query_unpaged(
+            "SELECT role FROM organizations_by_user WHERE tenant_id = ? AND user_id = ? AND organization_id = ?",
+            (tenant_id, user_id, organization_id),
+        )
+        .await?;
+
+    Ok(match result.maybe_first_row_typed::<(Option<i8>,)>() {
+        // Memberships created before roles existed are plain members.
+        Ok(Some((role,))) => Some(role.and_then(Role::from_i8).unwrap_or(Role::Member)),
+        _ => None,
+    })
+}
+
+/// The other ID of the membership, its role, its tenant-defined roles and when it started.
+type MembershipRow = (
+    String,
+    Option<i8>,
+    Option<HashSet<String>>,
+    Option<CqlTimestamp>,
+);
+
+pub async fn members(
+    db: &Session,
+    tenant_id: &str,
+    organization_id: &str,
+) -> Result<Vec<Membership>, QueryError> {
+    let result = db
+        .query_unpaged(
+            "SELECT user_id, role, roles, joined_at FROM users_by_organization WHERE tenant_id = ? AND organization_id = ?",
+            (tenant_id, organization_id),
+        )
+        .await?;
+
+    Ok(result
+        .rows_typed_or_empty::<MembershipRow>()
+        .filter_map(|row| row.ok())
+        .map(|(user_id, role, roles, joined_at)| Membership {
+            organization_id: organization_id.to_string(),
+            user_id,
+            role: role.and_then(Role::from_i8).unwrap_or(Role::Member),
+            roles: roles.unwrap_or_default(),
+            joined_at: joined_at.map(|CqlTimestamp(t)| t),
+        })
+        .collect())
+}
+
+pub async fn memberships(
+    db: &Session,
+    tenant_id: &str,
+    user_id: &str,
+) -> Result<Vec<Membership>, QueryError> {
+    let result = db
+        .query_unpaged(
+            "SELECT organization_id, role, roles, joined_at FROM organizations_by_user WHERE tenant_id = ? AND user_id = ?",
+            (tenant_id, user_id),
+        )
+        .await?;
+
+    Ok(result
+        .rows_typed_or_empty::<MembershipRow>()
+        .filter_map(|row| row.ok())
+        .map(|(organization_id, role, roles, joined_at)| Membership {
+            organization_id,
+            user_id: user_id.to_string(),
+            role: role.and_then(Role::from_i8).unwrap_or(Role::Member),
+            roles: roles.unwrap_or_default(),
+            joined_at: joined_at.map(|CqlTimestamp(t)| t),
+        })
+        .collect())
+}
+
+/// Adds a user to an organization. Adding an existing member resets their `joined_at`, so
+/// role changes should go through `set_role()`.
+pub async fn add_member(
+    state: &State,
+    tenant_id: &str,
+    organization_id: &str,
+    user_id: &str,
+    role: Role,
+) -> Result<(), QueryError> {
+    let db = &state.db;
+    db.query_unpaged(
+        "INSERT INTO organizations_by_user (tenant_id, user_id, organization_id, role, joined_at) VALUES (?, ?, ?, ?, toTimestamp(now()))",
+        (tenant_id, user_id, organization_id, role as i8),
+    )
+    .await?;
+
+    authz::invalidate(state, tenant_id).await;
+
+    Ok(())
+}
+
+pub async fn set_role(
+    state: &State,
+    tenant_id: &str,
+    organization_id: &str,
+    user_id: &str,
+    role: Role,
+) -> Result<(), QueryError> {
+    let db = &state.db;
+    db.query_unpaged(
+        "UPDATE organizations_by_user SET role = ? WHERE tenant_id = ? AND user_id = ? AND organization_id = ?",
+        (role as i8, tenant_id, user_id, organization_id),
+    )
+    .await?;
+
+    authz::invalidate(state, tenant_id).await;
+
+    Ok(())
+}
+
+pub async fn remove_member(
+    state: &State,
+    tenant_id: &str,
+    organization_id: &str,
+    user_id: &str,
+) -> Result<(), QueryError> {
+    let db = &state.db;
+    db.query_unpaged(
+        "DELETE FROM organizations_by_user WHERE tenant_id = ? AND user_id = ? AND organization_id = ?",
+        (tenant_id, user_id, organization_id),
+    )
+    .await?;
+
+    authz::invalidate(state, tenant_id).await;
+
+    Ok(())
+}
+
+/// Stores a pending invitation, which expires after `INVITATION_EXPIRES_IN` seconds.
+pub async fn create_invitation(
+    db: &Session,
+    tenant_id: &str,
+    invitation: &Invitation,
+) -> Result<(), QueryError> {
+    db.query_unpaged(
+        format!(
+            "
+                INSERT INTO organization_invitations (
+                    tenant_id, organization_id, invitation_id, email, address, role, invited_by, created_at, expires_at
+                ) VALUES (
+                    ?, ?, ?, ?, ?, ?, ?, ?, ?
+                ) USING TTL {INVITATION_EXPIRES_IN}
+            "
+        ),
+        (
+            tenant_id,
+            &invitation.organization_id,
+            &invitation.invitation_id,
+            &invitation.email,
+            &invitation.address,
+            invitation.role as i8,
+            &invitation.invited_by,
+            invitation.created_at.map(CqlTimestamp),
+            invitation.expires_at.map(CqlTimestamp),
+        ),
+    )
+    .await?;
+
+    Ok(())
+}
+
+type InvitationRow = (
+    String,
+    Option<String>,
+    Option<String>,
+    Option<i8>,
+    Option<String>,
+    Option<CqlTimestamp>,
+    Option<CqlTimestamp>,
+);
+
+fn invitation_from_row(
+    organization_id: &str,
+    (invitation_id, email, address, role, invited_by, created_at, expires_at): InvitationRow,
+) -> Invitation {
+    let email = email.unwrap_or_default();
+
+    Invitation {
+        invitation_id,
+        organization_id: organization_id.to_string(),
+        address: address.unwrap_or_else(|| email.clone()),
+        email,
+        role: role.and_then(Role::from_i8).unwrap_or(Role::Member),
+        invited_by,
+        created_at: created_at.map(|CqlTimestamp(t)| t),
+        expires_at: expires_at.map(|CqlTimestamp(t)| t),
+    }
+}
+
+/// Verifies an invitation token and returns its invitation if it's still pending.
+pub async fn pending_invitation(
+    db: &Session,
+    key: &Hmac<Sha384>,
+    tenant_id: &str,
+    token: &str,
+) -> Result<Option<Invitation>, QueryError> {
+    let Some(claims) = InvitationToken::verify(token, key, tenant_id) else {
+        return Ok(None);
+    };
+
+    Ok(invitation(
+        db,
+        tenant_id,
+        &claims.organization_id,
+        &claims.invitation_id,
+    )
+    .await?
+    .filter(|invitation| invitation.email == claims.email))
+}
+
+/// Returns a pending invitation, or `None` if it was revoked, accepted or has expired.
+pub async fn invitation(
+    db: &Session,
+    tenant_id: &str,
+    organization_id: &str,
+    invitation_id: &str,
+) -> Result<Option<Invitation>, QueryError> {
+    let result = db
+        .query_unpaged(
+            "SELECT invitation_id, email, address, role, invited_by, created_at, expires_at FROM organization_invitations WHERE tenant_id = ? AND organization_id = ? AND invitation_id = ?",
+            (tenant_id, organization_id, invitation_id),
+        )
+        .await?;
+
+    Ok(result
+        .maybe_first_row_typed::<InvitationRow>()
+        .ok()
+        .flatten()
+        .map(|row| invitation_from_row(organization_id, row)))
+}
+
+pub async fn invitations(
+    db: &Session,
+    tenant_id: &str,
+    organization_id: &str,
+) -> Result<Vec<Invitation>, QueryError> {
+    let result = db
+        .query_unpaged(
+            "SELECT invitation_id, email, address, role, invited_by, created_at, expires_at FROM organization_invitations WHERE tenant_id = ? AND organization_id = ?",
+            (tenant_id, organization_id),
+        )
+        .await?;
+
+    Ok(result
+        .rows_typed_or_empty::<InvitationRow>()
+        .filter_map(|row| row.ok())
+        .map(|row| invitation_from_row(organization_id, row))
+        .collect())
+}
+
+pub async fn delete_invitation(
+    db: &Session,
+    tenant_id: &str,
+    organization_id: &str,
+    invitation_id: &str,
+) -> Result<(), QueryError> {
+    db.query_unpaged(
+        "DELETE FROM organization_invitations WHERE tenant_id = ? AND organization_id = ? AND invitation_id = ?",
+        (tenant_id, organization_id, invitation_id),
+    )
+    .await?;
+
+    Ok(())
+}
+
+type DomainClaimRow = (
+    String,
+    Option<String>,
+    Option<bool>,
+    Option<bool>,
+    Option<CqlTimestamp>,
+    Option<CqlTimestamp>,
+);
+
+fn domain_claim_from_row(
+    organization_id: &str,
+    (domain, challenge, is_verified, require_sso, created_at, verified_at): DomainClaimRow,
+) -> DomainClaim {
+    DomainClaim {
+        domain,
+        organization_id: organization_id.to_string(),
+        challenge: challenge.unwrap_or_default(),
+        is_verified: is_verified.unwrap_or(false),
+        require_sso: require_sso.unwrap_or(false),
+        created_at: created_at.map(|CqlTimestamp(t)| t),
+        verified_at: verified_at.map(|CqlTimestamp(t)| t),
+    }
+}
+
+/// Starts a claim of `domain` for an organization, with a new challenge to publish in DNS.
+/// Claiming a domain again replaces its challenge and resets its verification.
+pub async fn claim_domain(
+    db: &Session,
+    tenant_id: &str,
+    organization_id: &str,
+    domain: &str,
+    require_sso: bool,
+) -> Result<DomainClaim, QueryError> {
+    let claim = DomainClaim {
+        domain: domain.to_string(),
+        organization_id: organization_id.to_string(),
+        challenge: URL_SAFE_NO_PAD.encode(token(Some(24))),
+        is_verified: false,
+        require_sso,
+        created_at: Some(Utc::now().timestamp_millis()),
+        verified_at: None,
+    };
+
+    db.query_unpaged(
+        "
+            INSERT INTO organization_domains (
+                tenant_id, organization_id, domain, challenge, is_verified, require_sso, created_at, verified_at
+            ) VALUES (
+                ?, ?, ?, ?, false, ?, ?, null
+            )
+        ",
+        (
+            tenant_id,
+            organization_id,
+            domain,
+            &claim.challenge,
+            require_sso,
+            claim.created_at.map(CqlTimestamp),
+        ),
+    )
+    .await?;
+
+    Ok(claim)
+}
+
+pub async fn domain_claim(
+    db: &Session,
+    tenant_id: &str,
+    organization_id: &str,
+    domain: &str,
+) -> Result<Option<DomainClaim>, QueryError> {
+    let result = db
+        .query_unpaged(
+            "SELECT domain, challenge, is_verified, require_sso, created_at, verified_at FROM organization_domains WHERE tenant_id = ? AND organization_id = ? AND domain = ?",
+            (tenant_id, organization_id, domain),
+        )
+        .await?;
+
+    Ok(result
+        .maybe_first_row_typed::<DomainClaimRow>()
+        .ok()
+        .flatten()
+        .map(|row| domain_claim_from_row(organization_id, row)))
+}
+
+pub async fn domain_claims(
+    db: &Session,
+    tenant_id: &str,
+    organization_id: &str,
+) -> Result<Vec<DomainClaim>, QueryError> {
+    let result = db
+        .query_unpaged(
+            "SELECT domain, challenge, is_verified, require_sso, created_at, verified_at FROM organization_domains WHERE tenant_id = ? AND organization_id = ?",
+            (tenant_id, organization_id),
+        )
+        .await?;
+
+    Ok(result
+        .rows_typed_or_empty::<DomainClaimRow>()
+        .filter_map(|row| row.ok())
+        .map(|row| domain_claim_from_row(organization_id, row))
+        .collect())
+}
+
+/// Returns the organization that verified `domain` and whether it requires SSO, if any.
+pub async fn domain_owner(
+    db: &Session,
+    tenant_id: &str,
+    domain: &str,
+) -> Result<Option<(String, bool)>, QueryError> {
+    let result = db
+        .query_unpaged(
+            "SELECT organization_id, require_sso FROM organizations_by_domain WHERE tenant_id = ? AND domain = ?",
+            (tenant_id, domain),
+        )
+        .await?;
+
+    Ok(result
+        .maybe_first_row_typed::<(String, Option<bool>)>()
+        .ok()
+        .flatten()
+        .map(|(organization_id, require_sso)| (organization_id, require_sso.unwrap_or(false))))
+}
+
+/// Checks whether the TXT record proving a claim is published. Failed lookups are logged and
+/// count as missing, since DNS changes can take a while to propagate.
+pub async fn is_proven(dns: &Resolver, claim: &DomainClaim) -> bool {
+    match dns.txt(&claim.record_name()).await {
+        Ok(records) => records.contains(&claim.record_value()),
+        Err(e) => {
+            event!(Level::WARN, error = format!("{e}"), domain = claim.domain);
+            false
+        }
+    }
+}
+
+/// Marks a claim as verified and assigns the domain to the organization. Returns `false` if
+/// another organization verified the domain first.
+pub async fn verify_domain(
+    db: &Session,
+    tenant_id: &str,
+    claim: &DomainClaim,
+) -> Result<bool, QueryError> {
+    let result = db
+        .query_unpaged(
+            "INSERT INTO organizations_by_domain (tenant_id, domain, organization_id, require_sso) VALUES (?, ?, ?, ?) IF NOT EXISTS",
+            (tenant_id, &claim.domain, &claim.organization_id, claim.require_sso),
+        )
+        .await?;
+
+    let applied = result
+        .first_row()
+        .ok()
+        .and_then(|row| row.columns.into_iter().next().flatten())
+        .and_then(|applied| applied.as_boolean())
+        .unwrap_or(false);
+
+    // Verifying a domain the organization already holds again is fine.
+    if !applied
+        && domain_owner(db, tenant_id, &claim.domain)
+            .await?
+            .map(|(organization_id, _)| organization_id)
+            != Some(claim.organization_id.clone())
+    {
+        return Ok(false);
+    }
+
+    db.query_unpaged(
+        "UPDATE organization_domains SET is_verified = true, verified_at = toTimestamp(now()) WHERE tenant_id = ? AND organization_id = ? AND domain = ?",
+        (tenant_id, &claim.organization_id, &claim.domain),
+    )
+    .await?;
+
+    Ok(true)
+}
+
+pub async fn set_require_sso(
+    db: &Session,
+    tenant_id: &str,
+    claim: &DomainClaim,
+    require_sso: bool,
+) -> Result<(), QueryError> {
+    db.query_unpaged(
+        "UPDATE organization_domains SET require_sso = ? WHERE tenant_id = ? AND organization_id = ? AND domain = ?",
+        (require_sso, tenant_id, &claim.organization_id, &claim.domain),
+    )
+    .await?;
+
+    if claim.is_verified {
+        db.query_unpaged(
+            "UPDATE organizations_by_domain SET require_sso = ? WHERE tenant_id = ? AND domain = ? IF organization_id = ?",
+            (require_sso, tenant_id, &claim.domain, &claim.organization_id),
+        )
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// Removes a domain claim, releasing the domain if the organization had verified it.
+pub async fn remove_domain(
+    db: &Session,
+    tenant_id: &str,
+    claim: &DomainClaim,
+) -> Result<(), QueryError> {
+    db.query_unpaged(
+        "DELETE FROM organization_domains WHERE tenant_id = ? AND organization_id = ? AND domain = ?",
+        (tenant_id, &claim.organization_id, &claim.domain),
+    )
+    .await?;
+
+    if claim.is_verified {
+        db.query_unpaged(
+            "DELETE FROM organizations_by_domain WHERE tenant_id = ? AND domain = ? IF organization_id = ?",
+            (tenant_id, &claim.domain, &claim.organization_id),
+        )
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// Adds a user who just verified `email` to the organization that verified its domain, if any
+/// and they're not a member yet. Returns the ID of the organization joined.
+pub async fn auto_join(
+    state: &State,
+    tenant_id: &str,
+    user_id: &str,
+    email: &str,
+) -> Result<Option<String>, QueryError> {
+    let db = &state.db;
+    let Some((organization_id, _)) = domain_owner(db, tenant_id, &emails::domain(email)).await?
+    else {
+        return Ok(None);
+    };
+
+    if role(db, tenant_id, &organization_id, user_id)
+        .await?
+        .is_some()
+    {
+        return Ok(None);
+    }
+
+    add_member(state, tenant_id, &organization_id, user_id, Role::Member).await?;
+
+    Ok(Some(organization_id))
+}
+
+/// Returns the organization whose SSO the user must sign in with, if any of their verified
+/// emails is on a domain that requires it and they're a member of its organization.
+pub async fn required_sso(
+    db: &Session,
+    tenant_id: &str,
+    user_id: &str,
+) -> Result<Option<String>, QueryError> {
+    for row in emails::list(db, tenant_id, user_id).await? {
+        if !row.is_verified {
+            continue;
+        }
+
+        if let Some((organization_id, true)) =
+            domain_owner(db, tenant_id, &emails::domain(&row.email)).await?
+        {
+            if role(db, tenant_id, &organization_id, user_id)
+                .await?
+                .is_some()
+            {
+                return Ok(Some(organization_id));
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn claim(domain: &str) -> DomainClaim {
+        DomainClaim {
+            domain: domain.to_string(),
+            organization_id: "org".to_string(),
+            challenge: "abc123".to_string(),
+            is_verified: false,
+            require_sso: false,
+            created_at: None,
+            verified_at: None,
+        }
+    }
+
+    fn resolver(records: &[(&str, &str)]) -> Resolver {
+        let mut map: HashMap<String, Vec<String>> = HashMap::new();
+
+        for (name, value) in records {
+            map.entry(name.to_string())
+                .or_default()
+                .push(value.to_string());
+        }
+
+        Resolver::Static(map)
+    }
+
+    #[test]
+    fn record_is_published_under_the_challenge_subdomain() {
+        let claim = claim("acme.example");
+
+        assert_eq!(claim.record_name(), "_accesscore-challenge.acme.example");
+        assert_eq!(
+            claim.record_value(),
+            "accesscore-domain-verification=abc123"
+        );
+    }
+
+    #[tokio::test]
+    async fn claim_is_proven_by_its_record() {
+        let dns = resolver(&[(
+            "_accesscore-challenge.acme.example",
+            "accesscore-domain-verification=abc123",
+        )]);
+
+        assert!(is_proven(&dns, &claim("acme.example")).await);
+    }
+
+    #[tokio::test]
+    async fn claim_is_proven_among_other_records() {
+        let dns = resolver(&[
+            ("_accesscore-challenge.acme.example", "v=spf1 -all"),
+            (
+                "_accesscore-challenge.acme.example",
+                "accesscore-domain-verification=abc123",
+            ),
+        ]);
+
+        assert!(is_proven(&dns, &claim("acme.example")).await);
+    }
+
+    #[tokio::test]
+    async fn claim_is_not_proven_without_a_record() {
+        assert!(!is_proven(&resolver(&[]), &claim("acme.example")).await);
+    }
+
+    #[tokio::test]
+    async fn claim_is_not_proven_by_another_challenge() {
+        let dns = resolver(&[(
+            "_accesscore-challenge.acme.example",
+            "accesscore-domain-verification=other",
+        )]);
+
+        assert!(!is_proven(&dns, &claim("acme.example")).await);
+    }
+
+    #[tokio::test]
+    async fn claim_is_not_proven_by_a_record_on_the_domain_itself() {
+        let dns = resolver(&[("acme.example", "accesscore-domain-verification=abc123")]);
+
+        assert!(!is_proven(&dns, &claim("acme.example")).await);
+    }
+
+    #[tokio::test]
+    async fn claim_is_not_proven_by_the_record_of_another_domain() {
+        let dns = resolver(&[(
+            "_accesscore-challenge.other.example",
+            "accesscore-domain-verification=abc123",
+        )]);
+
+        assert!(!is_proven(&dns, &claim("acme.example")).await);
+    }
+}
@@ -0,0 +1,596 @@
+use std::collections::{BTreeSet, HashSet};
+
+use scylla::{transport::errors::QueryError, Session};
+use serde::Serialize;
+
+use crate::{groups, organizations, roles, state::State};
+
+/// Permission required to read the effective permissions of any user in the tenant.
+pub const READ_PERMISSION: &str = "users.permissions.read";
+
+/// Prefix that turns a permission into an explicit deny, e.g. `-billing.read`.
+pub const DENY_PREFIX: char = '-';
+
+/// Separates the segments of a permission, e.g. `billing.invoices.read`.
+pub const SEPARATOR: char = '.';
+
+/// A segment matching any one segment, or any number of segments at the end of a permission,
+/// so both `billing.*.read` and `billing.*` match `billing.invoices.read`.
+pub const WILDCARD: &str = "*";
+
+/// Token scope issued before scopes had wildcards, which is kept as an alias of `*`.
+pub const LEGACY_ALL_SCOPE: &str = "all";
+
+/// Priority of the permissions granted directly to a user, above any other source.
+const USER_PRIORITY: i32 = i32::MAX;
+
+/// Priority of the permissions of a user's own roles.
+const USER_ROLE_PRIORITY: i32 = i32::MAX - 1;
+
+/// Priority of the permissions of a user's roles within an organization, above any group's,
+/// which are at most `i16::MAX`.
+const ORGANIZATION_ROLE_PRIORITY: i32 = i32::MAX - 2;
+
+/// Checks whether `pattern` matches `permission`, segment by segment.
+pub fn matches(pattern: &str, permission: &str) -> bool {
+    let mut patterns = pattern.split(SEPARATOR).peekable();
+    let mut segments = permission.split(SEPARATOR);
+
+    loop {
+        match (patterns.next(), segments.next()) {
+            (Some(WILDCARD), Some(_)) if patterns.peek().is_none() => return true,
+            (Some(WILDCARD), Some(_)) => {}
+            (Some(expected), Some(segment)) if expected == segment => {}
+            (None, None) => return true,
+            _ => return false,
+        }
+    }
+}
+
+/// How specific a pattern is: more literal segments first, then more segments overall.
+fn specificity(pattern: &str) -> (usize, usize) {
+    let segments = pattern.split(SEPARATOR);
+
+    (
+        segments.clone().filter(|s| *s != WILDCARD).count(),
+        segments.count(),
+    )
+}
+
+/// A user's permissions after resolving the grants and denies of their own, their roles' and
+/// their groups'.
+#[derive(Debug, Default, Serialize)]
+pub struct Effective {
+    /// Granted permissions, which may contain wildcards.
+    pub granted: BTreeSet<String>,
+
+    /// Permissions explicitly denied, which override grants of a lower priority or of a less
+    /// specific pattern.
+    pub denied: BTreeSet<String>,
+
+    /// The rules left after resolving, in the order they're matched in.
+    #[serde(skip)]
+    rules: Vec<Rule>,
+}
+
+impl Effective {
+    /// Returns whether the first matching rule grants (`Some(true)`) or denies (`Some(false)`)
+    /// `permission`, or `None` if no rule matches it.
+    pub fn decide(&self, permission: &str) -> Option<bool> {
+        self.rules
+            .iter()
+            .find(|rule| matches(&rule.permission, permission))
+            .map(|rule| !rule.deny)
+    }
+
+    pub fn allows(&self, permission: &str) -> bool {
+        self.decide(permission) == Some(true)
+    }
+}
+
+/// A grant or deny of a permission pattern at some priority.
+#[derive(Debug)]
+struct Rule {
+    priority: i32,
+    permission: String,
+    deny: bool,
+
+    /// Where the rule comes from, e.g. `user` or `group:{group_id}.role:{role}`.
+    source: String,
+}
+
+/// Resolves rules into effective permissions. A permission is decided by the matching rule with
+/// the highest priority, then the most specific pattern, and denies win over grants otherwise.
+fn resolve(mut rules: Vec<Rule>) -> Effective {
+    sort(&mut rules);
+
+    let mut effective = Effective::default();
+
+    for rule in rules {
+        if effective.granted.contains(&rule.permission)
+            || effective.denied.contains(&rule.permission)
+        {
+            continue;
+        }
+
+        if rule.deny {
+            effective.denied.insert(rule.permission.clone());
+        } else {
+            effective.granted.insert(rule.permission.clone());
+        }
+
+        effective.rules.push(rule);
+    }
+
+    effective
+}
+
+fn rules(
+    permissions: impl IntoIterator<Item = String>,
+    priority: i32,
+    source: String,
+) -> impl Iterator<Item = Rule> {
+    permissions.into_iter().map(
+        move |permission| match permission.strip_prefix(DENY_PREFIX) {
+            Some(denied) => Rule {
+                priority,
+                permission: denied.to_string(),
+                deny: true,
+                source: source.clone(),
+            },
+            None => Rule {
+                priority,
+                permission,
+                deny: false,
+                source: source.clone(),
+            },
+        },
+    )
+}
+
+/// Sorts rules in the order they're matched in: highest priority first, then most specific
+/// first, then denies first.
+fn sort(rules: &mut [Rule]) {
+    rules.sort_by(|a, b| {
+        b.priority
+            .cmp(&a.priority)
+            .then(specificity(&b.permission).cmp(&specificity(&a.permission)))
+            .then(b.deny.cmp(&a.deny))
+    });
+}
+
+/// Checks whether a token's scopes allow `permission`, resolving them like a single set of
+/// permissions, so `-billing.*` in the scopes takes `billing.invoices.read` away from `*`.
+pub fn scopes_allow(scopes: &[String], permission: &str) -> bool {
+    let scopes = scopes.iter().map(|scope| match scope.as_str() {
+        LEGACY_ALL_SCOPE => WILDCARD.to_string(),
+        scope => scope.to_string(),
+    });
+
+    resolve(rules(scopes, 0, "scope".to_string()).collect()).allows(permission)
+}
+
+/// Returns the permissions granted directly to a user.
+pub async fn user_permissions(
+    db: &Session,
+    tenant_id: &str,
+    user_id: &str,
+) -> Result<HashSet<String>, QueryError> {
+    let result = db
+        .query_unpaged(
+            "SELECT permissions FROM users WHERE tenant_id = ? AND user_id = ?",
+            (tenant_id, user_id),
+        )
+        .await?;
+
+    Ok(
+        match result.maybe_first_row_typed::<(Option<HashSet<String>>,)>() {
+            Ok(Some((Some(permissions),))) => permissions,
+            _ => HashSet::new(),
+        },
+    )
+}
+
+/// Collects the rules that apply to a user: their own permissions and roles, the permissions
+/// and roles of their groups, including the groups containing them, and, within
+/// `organization_id`, the roles of their membership.
+async fn collect(
+    state: &State,
+    tenant_id: &str,
+    user_id: &str,
+    organization_id: Option<&str>,
+) -> Result<Vec<Rule>, QueryError> {
+    let db = &state.db;
+    let all_roles = roles::all(db, tenant_id).await?;
+    let mut all: Vec<Rule> = rules(
+        user_permissions(db, tenant_id, user_id).await?,
+        USER_PRIORITY,
+        "user".to_string(),
+    )
+    .collect();
+
+    for role in roles::user_roles(db, tenant_id, user_id).await? {
+        all.extend(rules(
+            roles::permissions(&all_roles, &role),
+            USER_ROLE_PRIORITY,
+            format!("user.role:{role}"),
+        ));
+    }
+
+    if let Some(organization_id) = organization_id {
+        for role in roles::membership_roles(db, tenant_id, organization_id, user_id).await? {
+            all.extend(rules(
+                roles::permissions(&all_roles, &role),
+                ORGANIZATION_ROLE_PRIORITY,
+                format!("organization:{organization_id}.role:{role}"),
+            ));
+        }
+    }
+
+    for group_id in groups::transitive_groups(state, tenant_id, user_id).await? {
+        if let Some(group) = groups::get(db, tenant_id, &group_id).await? {
+            let priority = group.priority as i32;
+
+            all.extend(rules(
+                group.permissions,
+                priority,
+                format!("group:{group_id}"),
+            ));
+
+            for role in &group.roles {
+                all.extend(rules(
+                    roles::permissions(&all_roles, role),
+                    priority,
+                    format!("group:{group_id}.role:{role}"),
+                ));
+            }
+        }
+    }
+
+    Ok(all)
+}
+
+/// Computes a user's effective permissions out of their own permissions and roles and the ones
+/// of their groups, including the groups containing them. The user's own permissions take
+/// precedence over their roles, which take precedence over any group's.
+pub async fn effective_permissions(
+    state: &State,
+    tenant_id: &str,
+    user_id: &str,
+) -> Result<Effective, QueryError> {
+    Ok(resolve(collect(state, tenant_id, user_id, None).await?))
+}
+
+/// Computes a user's effective permissions within an organization, which adds the roles of their
+/// membership below their own roles and above their groups'.
+pub async fn organization_permissions(
+    state: &State,
+    tenant_id: &str,
+    user_id: &str,
+    organization_id: &str,
+) -> Result<Effective, QueryError> {
+    Ok(resolve(
+        collect(state, tenant_id, user_id, Some(organization_id)).await?,
+    ))
+}
+
+/// A rule that matched a permission while explaining a decision.
+#[derive(Serialize)]
+pub struct TraceEntry {
+    pub source: String,
+    pub priority: i32,
+    pub deny: bool,
+
+    /// Whether this rule decided the outcome, being the first one in resolution order.
+    pub decisive: bool,
+}
+
+/// Lists every rule about `permission` that applies to a user, in resolution order, so the
+/// first one is the one that decides. An empty trace means the permission isn't granted.
+pub async fn explain(
+    state: &State,
+    tenant_id: &str,
+    user_id: &str,
+    organization_id: Option<&str>,
+    permission: &str,
+) -> Result<Vec<TraceEntry>, QueryError> {
+    let mut matching: Vec<Rule> = collect(state, tenant_id, user_id, organization_id)
+        .await?
+        .into_iter()
+        .filter(|rule| matches(&rule.permission, permission))
+        .collect();
+
+    sort(&mut matching);
+
+    Ok(matching
+        .into_iter()
+        .enumerate()
+        .map(|(i, rule)| TraceEntry {
+            source: rule.source,
+            priority: rule.priority,
+            deny: rule.deny,
+            decisive: i == 0,
+        })
+        .collect())
+}
+
+/// A role that applies to a user, where it comes from and the permissions it carries.
+#[derive(Serialize)]
+pub struct RoleGrant {
+    pub role: String,
+
+    /// `user`, `group:{group_id}` or `organization:{organization_id}`.
+    pub source: String,
+
+    /// Granted and denied permissions, including inherited ones. Empty if the role doesn't exist.
+    pub permissions: BTreeSet<String>,
+}
+
+/// Lists every role that applies to a user: their own, their groups' and their organization
+/// memberships'.
+pub async fn role_grants(
+    state: &State,
+    tenant_id: &str,
+    user_id: &str,
+) -> Result<Vec<RoleGrant>, QueryError> {
+    let db = &state.db;
+    let all_roles = roles::all(db, tenant_id).await?;
+    let mut grants: Vec<(String, String)> = roles::user_roles(db, tenant_id, user_id)
+        .await?
+        .into_iter()
+        .map(|role| (role, "user".to_string()))
+        .collect();
+
+    for group_id in groups::transitive_groups(state, tenant_id, user_id).await? {
+        if let Some(group) = groups::get(db, tenant_id, &group_id).await? {
+            grants.extend(
+                group
+                    .roles
+                    .into_iter()
+                    .map(|role| (role, format!("group:{group_id}"))),
+            );
+        }
+    }
+
+    for membership in organizations::memberships(db, tenant_id, user_id).await? {
+        grants.extend(
+            membership
+                .roles
+                .into_iter()
+                .map(|role| (role, format!("organization:{}", membership.organization_id))),
+        );
+    }
+
+    grants.sort();
+
+    Ok(grants
+        .into_iter()
+        .map(|(role, source)| RoleGrant {
+            permissions: roles::permissions(&all_roles, &role),
+            role,
+            source,
+        })
+        .collect())
+}
+
+/// Checks whether a user has been granted `permission`, directly or through their groups.
+pub async fn has_permission(
+    state: &State,
+    tenant_id: &str,
+    user_id: &str,
+    permission: &str,
+) -> Result<bool, QueryError> {
+    Ok(effective_permissions(state, tenant_id, user_id)
+        .await?
+        .allows(permission))
+}
+
+/// Checks whether a user has been granted `permission` and the token they authenticated with
+/// has a scope allowing it.
+pub async fn has_scoped_permission(
+    state: &State,
+    tenant_id: &str,
+    user_id: &str,
+    scopes: &[String],
+    permission: &str,
+) -> Result<bool, QueryError> {
+    if !scopes_allow(scopes, permission) {
+        return Ok(false);
+    }
+
+    has_permission(state, tenant_id, user_id, permission).await
+}
+
+/// Checks whether `permission` is well-formed: non-empty ASCII segments without whitespace,
+/// separated by `.`, where `*` can only make up a whole segment. It can be prefixed with `-` to
+/// deny it.
+pub fn is_valid(permission: &str) -> bool {
+    let name = permission.strip_prefix(DENY_PREFIX).unwrap_or(permission);
+
+    !name.is_empty()
+        && name.len() <= 128
+        && !name.starts_with(DENY_PREFIX)
+        && name.split(SEPARATOR).all(|segment| {
+            segment == WILDCARD
+                || (!segment.is_empty()
+                    && segment.chars().all(|c| c.is_ascii_graphic() && c != '*'))
+        })
+}
+
+/// Checks whether `permission` names a single permission, without wildcards or a deny prefix,
+/// as is required when checking for one.
+pub fn is_concrete(permission: &str) -> bool {
+    is_valid(permission)
+        && !permission.starts_with(DENY_PREFIX)
+        && !permission
+            .split(SEPARATOR)
+            .any(|segment| segment == WILDCARD)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(permissions: &[&str]) -> Vec<String> {
+        permissions.iter().map(|p| p.to_string()).collect()
+    }
+
+    /// Resolves layers of permissions, each at its own priority.
+    fn layered(layers: &[(i32, &[&str])]) -> Effective {
+        resolve(
+            layers
+                .iter()
+                .flat_map(|(priority, permissions)| {
+                    rules(strings(permissions), *priority, "test".to_string())
+                })
+                .collect(),
+        )
+    }
+
+    fn effective(permissions: &[&str]) -> Effective {
+        layered(&[(0, permissions)])
+    }
+
+    #[test]
+    fn wildcard_matches_one_middle_segment() {
+        assert!(matches("billing.*.read", "billing.invoices.read"));
+        assert!(!matches("billing.*.read", "billing.read"));
+        assert!(!matches("billing.*.read", "billing.invoices.items.read"));
+        assert!(!matches("billing.*.read", "billing.invoices.write"));
+    }
+
+    #[test]
+    fn trailing_wildcard_matches_any_remaining_segments() {
+        assert!(matches("billing.*", "billing.invoices"));
+        assert!(matches("billing.*", "billing.invoices.read"));
+        assert!(!matches("billing.*", "billing"));
+        assert!(!matches("billing.*", "users.read"));
+        assert!(matches("*", "users.read"));
+    }
+
+    #[test]
+    fn literal_patterns_match_exactly() {
+        assert!(matches("users.read", "users.read"));
+        assert!(!matches("users.read", "users.read.all"));
+        assert!(!matches("users.read.all", "users.read"));
+    }
+
+    #[test]
+    fn deny_wins_at_equal_priority_and_specificity() {
+        let effective = effective(&["billing.read", "-billing.read"]);
+
+        assert_eq!(effective.decide("billing.read"), Some(false));
+        assert!(effective.denied.contains("billing.read"));
+        assert!(!effective.granted.contains("billing.read"));
+    }
+
+    #[test]
+    fn more_specific_pattern_wins_at_equal_priority() {
+        let first = effective(&["-billing.*", "billing.invoices.read", "*"]);
+
+        assert!(first.allows("billing.invoices.read"));
+        assert!(!first.allows("billing.invoices.write"));
+        assert!(first.allows("users.read"));
+
+        // More literal segments beat more segments overall.
+        let second = effective(&["-*.*.*", "billing.*"]);
+
+        assert!(second.allows("billing.invoices.read"));
+        assert!(!second.allows("users.invoices.read"));
+    }
+
+    #[test]
+    fn higher_priority_wins_over_specificity() {
+        let effective = layered(&[(1, &["-billing.*"]), (0, &["billing.invoices.read"])]);
+
+        assert!(!effective.allows("billing.invoices.read"));
+    }
+
+    #[test]
+    fn unmatched_permissions_are_undecided() {
+        let effective = effective(&["billing.read"]);
+
+        assert_eq!(effective.decide("users.read"), None);
+        assert!(!effective.allows("users.read"));
+    }
+
+    #[test]
+    fn collected_sources_are_ordered_by_priority() {
+        // Each source overrides the ones below it, even a group of the highest priority.
+        let group = i16::MAX as i32;
+        let effective = layered(&[
+            (
+                group,
+                &["billing.read", "users.read", "roles.read", "-groups.read"],
+            ),
+            (
+                ORGANIZATION_ROLE_PRIORITY,
+                &["-billing.read", "users.read", "groups.read"],
+            ),
+            (USER_ROLE_PRIORITY, &["-users.read", "roles.read"]),
+            (USER_PRIORITY, &["users.read", "-roles.read"]),
+        ]);
+
+        assert!(!effective.allows("billing.read"));
+        assert!(effective.allows("groups.read"));
+        assert!(effective.allows("users.read"));
+        assert!(!effective.allows("roles.read"));
+    }
+
+    #[test]
+    fn legacy_all_scope_allows_everything() {
+        assert!(scopes_allow(&strings(&["all"]), "billing.invoices.read"));
+        assert!(scopes_allow(&strings(&["*"]), "billing.invoices.read"));
+        assert!(!scopes_allow(
+            &strings(&["all", "-billing.*"]),
+            "billing.invoices.read"
+        ));
+        assert!(scopes_allow(&strings(&["all", "-billing.*"]), "users.read"));
+        assert!(!scopes_allow(
+            &strings(&["users.read"]),
+            "billing.invoices.read"
+        ));
+        assert!(!scopes_allow(&[], "users.read"));
+    }
+
+    #[test]
+    fn is_valid_accepts_the_grammar() {
+        for permission in [
+            "users.read",
+            "billing.*.read",
+            "billing.*",
+            "*",
+            "-billing.read",
+        ] {
+            assert!(is_valid(permission), "{permission}");
+        }
+    }
+
+    #[test]
+    fn is_valid_rejects_malformed_permissions() {
+        let long = "a".repeat(129);
+
+        for permission in [
+            "",
+            "-",
+            "--billing.read",
+            "billing..read",
+            ".billing",
+            "billing.",
+            "billing.inv*",
+            "billing.**",
+            "billing read",
+            "billing.r\u{e9}ad",
+            long.as_str(),
+        ] {
+            assert!(!is_valid(permission), "{permission:?}");
+        }
+    }
+
+    #[test]
+    fn is_concrete_rejects_wildcards_and_denies() {
+        assert!(is_concrete("billing.invoices.read"));
+        assert!(!is_concrete("billing.*"));
+        assert!(!is_concrete("-billing.read"));
+    }
+}
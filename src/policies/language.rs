@@ -0,0 +1,591 @@
+use std::net::IpAddr;
+
+use serde::Serialize;
+use serde_json::Value;
+
+/// Functions a condition can call, along with how many arguments they take.
+const FUNCTIONS: [(&str, usize); 6] = [
+    ("contains", 2),
+    ("starts_with", 2),
+    ("ends_with", 2),
+    ("lower", 1),
+    ("len", 1),
+    ("in_cidr", 2),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    In,
+}
+
+/// A parsed condition.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Literal(Value),
+
+    /// An attribute of the context, like `user.employment.manager_id`.
+    Path(Vec<String>),
+    List(Vec<Expr>),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Compare(Comparison, Box<Expr>, Box<Expr>),
+    Call(String, Vec<Expr>),
+}
+
+#[derive(Debug, Serialize)]
+pub struct ParseError {
+    /// 1-based character position in the condition.
+    pub position: usize,
+    pub message: String,
+}
+
+impl ParseError {
+    fn new(position: usize, message: impl Into<String>) -> Self {
+        Self {
+            position,
+            message: message.into(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Name(String),
+    String(String),
+    Number(f64),
+    Dot,
+    Comma,
+    Open,
+    Close,
+    OpenBracket,
+    CloseBracket,
+    Not,
+    And,
+    Or,
+    Compare(Comparison),
+}
+
+fn tokenize(condition: &str) -> Result<Vec<(usize, Token)>, ParseError> {
+    let mut tokens: Vec<(usize, Token)> = vec![];
+    let mut chars = condition.chars().enumerate().peekable();
+
+    while let Some((i, c)) = chars.next() {
+        let position = i + 1;
+        let mut next_is = |expected: char| {
+            if chars.peek().map(|(_, c)| *c) == Some(expected) {
+                chars.next();
+                true
+            } else {
+                false
+            }
+        };
+
+        let token = match c {
+            c if c.is_whitespace() => continue,
+            '.' => Token::Dot,
+            ',' => Token::Comma,
+            '(' => Token::Open,
+            ')' => Token::Close,
+            '[' => Token::OpenBracket,
+            ']' => Token::CloseBracket,
+            '&' if next_is('&') => Token::And,
+            '|' if next_is('|') => Token::Or,
+            '=' if next_is('=') => Token::Compare(Comparison::Eq),
+            '!' if next_is('=') => Token::Compare(Comparison::Ne),
+            '!' => Token::Not,
+            '<' if next_is('=') => Token::Compare(Comparison::Le),
+            '<' => Token::Compare(Comparison::Lt),
+            '>' if next_is('=') => Token::Compare(Comparison::Ge),
+            '>' => Token::Compare(Comparison::Gt),
+            '"' => {
+                let mut string = String::new();
+
+                loop {
+                    match chars.next() {
+                        Some((_, '"')) => break,
+                        Some((_, '\\')) => match chars.next() {
+                            Some((_, c @ ('"' | '\\'))) => string.push(c),
+                            _ => {
+                                return Err(ParseError::new(
+                                    position,
+                                    "Only \\\" and \\\\ can be escaped in strings.",
+                                ))
+                            }
+                        },
+                        Some((_, c)) => string.push(c),
+                        None => return Err(ParseError::new(position, "Unterminated string.")),
+                    }
+                }
+
+                Token::String(string)
+            }
+            c if c.is_ascii_digit() || c == '-' => {
+                let mut number = c.to_string();
+
+                while let Some(&(_, next)) = chars.peek() {
+                    if !(next.is_ascii_digit() || next == '.') {
+                        break;
+                    }
+
+                    number.push(next);
+                    chars.next();
+                }
+
+                match number.parse::<f64>() {
+                    Ok(number) => Token::Number(number),
+                    Err(_) => {
+                        return Err(ParseError::new(
+                            position,
+                            format!("'{number}' is not a valid number."),
+                        ))
+                    }
+                }
+            }
+            c if c.is_ascii_alphabetic() || c == '_' => {
+                let mut name = c.to_string();
+
+                while let Some(&(_, next)) = chars.peek() {
+                    if !(next.is_ascii_alphanumeric() || next == '_') {
+                        break;
+                    }
+
+                    name.push(next);
+                    chars.next();
+                }
+
+                Token::Name(name)
+            }
+            c => {
+                return Err(ParseError::new(
+                    position,
+                    format!("Unexpected character '{c}'."),
+                ))
+            }
+        };
+
+        tokens.push((position, token));
+    }
+
+    Ok(tokens)
+}
+
+/// A recursive descent parser over a condition, where comparisons bind tighter than `!`, `!`
+/// tighter than `&&`, and `&&` tighter than `||`.
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    position: usize,
+    end: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position).map(|(_, token)| token)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self
+            .tokens
+            .get(self.position)
+            .map(|(_, token)| token.clone());
+        self.position += 1;
+        token
+    }
+
+    /// Where the next token starts, for error messages.
+    fn here(&self) -> usize {
+        self.tokens
+            .get(self.position)
+            .map_or(self.end, |(position, _)| *position)
+    }
+
+    fn expect(&mut self, expected: Token, message: &str) -> Result<(), ParseError> {
+        let position = self.here();
+
+        match self.next() {
+            Some(token) if token == expected => Ok(()),
+            _ => Err(ParseError::new(position, message)),
+        }
+    }
+
+    fn or(&mut self) -> Result<Expr, ParseError> {
+        let mut left = self.and()?;
+
+        while self.peek() == Some(&Token::Or) {
+            self.next();
+            left = Expr::Or(Box::new(left), Box::new(self.and()?));
+        }
+
+        Ok(left)
+    }
+
+    fn and(&mut self) -> Result<Expr, ParseError> {
+        let mut left = self.not()?;
+
+        while self.peek() == Some(&Token::And) {
+            self.next();
+            left = Expr::And(Box::new(left), Box::new(self.not()?));
+        }
+
+        Ok(left)
+    }
+
+    fn not(&mut self) -> Result<Expr, ParseError> {
+        if self.peek() == Some(&Token::Not) {
+            self.next();
+
+            return Ok(Expr::Not(Box::new(self.not()?)));
+        }
+
+        self.comparison()
+    }
+
+    fn comparison(&mut self) -> Result<Expr, ParseError> {
+        let left = self.operand()?;
+
+        let comparison = match self.peek() {
+            Some(Token::Compare(comparison)) => *comparison,
+            Some(Token::Name(name)) if name == "in" => Comparison::In,
+            _ => return Ok(left),
+        };
+
+        self.next();
+
+        Ok(Expr::Compare(
+            comparison,
+            Box::new(left),
+            Box::new(self.operand()?),
+        ))
+    }
+
+    /// Parses comma-separated expressions up to `close`, which is consumed.
+    fn list(&mut self, close: Token) -> Result<Vec<Expr>, ParseError> {
+        let mut items = vec![];
+
+        if self.peek() == Some(&close) {
+            self.next();
+            return Ok(items);
+        }
+
+        loop {
+            items.push(self.or()?);
+
+            let position = self.here();
+
+            match self.next() {
+                Some(Token::Comma) => {}
+                Some(token) if token == close => return Ok(items),
+                _ => {
+                    return Err(ParseError::new(
+                        position,
+                        "Expected ',' or a closing bracket.",
+                    ))
+                }
+            }
+        }
+    }
+
+    fn operand(&mut self) -> Result<Expr, ParseError> {
+        let position = self.here();
+
+        match self.next() {
+            Some(Token::Open) => {
+                let expr = self.or()?;
+                self.expect(Token::Close, "Expected ')'.")?;
+
+                Ok(expr)
+            }
+            Some(Token::OpenBracket) => Ok(Expr::List(self.list(Token::CloseBracket)?)),
+            Some(Token::String(string)) => Ok(Expr::Literal(Value::String(string))),
+            Some(Token::Number(number)) => Ok(Expr::Literal(number_value(number))),
+            Some(Token::Name(name)) => match name.as_str() {
+                "true" => Ok(Expr::Literal(Value::Bool(true))),
+                "false" => Ok(Expr::Literal(Value::Bool(false))),
+                "null" => Ok(Expr::Literal(Value::Null)),
+                _ if self.peek() == Some(&Token::Open) => {
+                    self.next();
+
+                    let Some((_, arity)) = FUNCTIONS.iter().find(|(f, _)| *f == name) else {
+                        return Err(ParseError::new(
+                            position,
+                            format!("'{name}' is not a known function."),
+                        ));
+                    };
+                    let arguments = self.list(Token::Close)?;
+
+                    if arguments.len() != *arity {
+                        return Err(ParseError::new(
+                            position,
+                            format!("'{name}' takes {arity} argument(s)."),
+                        ));
+                    }
+
+                    Ok(Expr::Call(name, arguments))
+                }
+                _ => {
+                    let mut path = vec![name];
+
+                    while self.peek() == Some(&Token::Dot) {
+                        self.next();
+
+                        let position = self.here();
+
+                        match self.next() {
+                            Some(Token::Name(name)) => path.push(name),
+                            _ => {
+                                return Err(ParseError::new(
+                                    position,
+                                    "Expected an attribute name after '.'.",
+                                ))
+                            }
+                        }
+                    }
+
+                    Ok(Expr::Path(path))
+                }
+            },
+            _ => Err(ParseError::new(
+                position,
+                "Expected a value, an attribute, a function call, '(' or '['.",
+            )),
+        }
+    }
+}
+
+fn number_value(number: f64) -> Value {
+    if number.fract() == 0.0 && number.abs() < i64::MAX as f64 {
+        Value::from(number as i64)
+    } else {
+        Value::from(number)
+    }
+}
+
+/// Parses a condition, such as:
+///
+/// ```text
+/// resource.employment.manager_id == user.user_id
+///     && request.time.weekday <= 5
+///     && request.time.hour >= 9 && request.time.hour < 17
+/// ```
+///
+/// Conditions are made of attributes of the context (`user`, `resource` and `request`),
+/// strings, numbers, `true`, `false`, `null` and lists, compared with `==`, `!=`, `<`, `<=`,
+/// `>`, `>=` and `in`, and combined with `!`, `&&`, `||` and parentheses. The functions in
+/// [`FUNCTIONS`] are available as well.
+pub fn parse(condition: &str) -> Result<Expr, ParseError> {
+    let mut parser = Parser {
+        tokens: tokenize(condition)?,
+        position: 0,
+        end: condition.chars().count() + 1,
+    };
+    let expr = parser.or()?;
+
+    if parser.peek().is_some() {
+        return Err(ParseError::new(
+            parser.here(),
+            "Unexpected tokens at the end.",
+        ));
+    }
+
+    Ok(expr)
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "a boolean",
+        Value::Number(_) => "a number",
+        Value::String(_) => "a string",
+        Value::Array(_) => "a list",
+        Value::Object(_) => "an object",
+    }
+}
+
+/// Compares values the way `==` does, where numbers are equal regardless of their
+/// representation.
+fn equals(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Number(a), Value::Number(b)) => a.as_f64() == b.as_f64(),
+        _ => a == b,
+    }
+}
+
+fn boolean(value: Value, operator: &str) -> Result<bool, String> {
+    match value {
+        Value::Bool(b) => Ok(b),
+        value => Err(format!(
+            "'{operator}' expects booleans but got {}.",
+            type_name(&value)
+        )),
+    }
+}
+
+fn string<'a>(value: &'a Value, function: &str) -> Result<Option<&'a str>, String> {
+    match value {
+        Value::Null => Ok(None),
+        Value::String(s) => Ok(Some(s)),
+        value => Err(format!(
+            "'{function}' expects strings but got {}.",
+            type_name(value)
+        )),
+    }
+}
+
+/// Checks whether `ip` is in `cidr`, like `10.0.0.0/8` or `2001:db8::/32`.
+fn in_cidr(ip: &str, cidr: &str) -> Result<bool, String> {
+    let invalid = || format!("'{cidr}' is not a valid CIDR block.");
+    let (network, length) = cidr.split_once('/').ok_or_else(invalid)?;
+    let network: IpAddr = network.parse().map_err(|_| invalid())?;
+    let length: u32 = length.parse().map_err(|_| invalid())?;
+
+    let Ok(ip) = ip.parse::<IpAddr>() else {
+        return Ok(false);
+    };
+
+    Ok(match (ip, network) {
+        (IpAddr::V4(ip), IpAddr::V4(network)) if length <= 32 => {
+            let mask = u32::MAX.checked_shl(32 - length).unwrap_or(0);
+            u32::from(ip) & mask == u32::from(network) & mask
+        }
+        (IpAddr::V6(ip), IpAddr::V6(network)) if length <= 128 => {
+            let mask = u128::MAX.checked_shl(128 - length).unwrap_or(0);
+            u128::from(ip) & mask == u128::from(network) & mask
+        }
+        (IpAddr::V4(_), IpAddr::V4(_)) | (IpAddr::V6(_), IpAddr::V6(_)) => return Err(invalid()),
+        _ => false,
+    })
+}
+
+fn call(function: &str, arguments: Vec<Value>) -> Result<Value, String> {
+    Ok(match (function, arguments.as_slice()) {
+        ("contains", [Value::Array(items), needle]) => {
+            Value::Bool(items.iter().any(|item| equals(item, needle)))
+        }
+        ("contains" | "starts_with" | "ends_with", [haystack, needle]) => {
+            match (string(haystack, function)?, string(needle, function)?) {
+                (Some(haystack), Some(needle)) => Value::Bool(match function {
+                    "contains" => haystack.contains(needle),
+                    "starts_with" => haystack.starts_with(needle),
+                    _ => haystack.ends_with(needle),
+                }),
+                _ => Value::Bool(false),
+            }
+        }
+        ("lower", [value]) => match string(value, function)? {
+            Some(s) => Value::String(s.to_lowercase()),
+            None => Value::Null,
+        },
+        ("len", [value]) => match value {
+            Value::Null => Value::from(0),
+            Value::String(s) => Value::from(s.chars().count()),
+            Value::Array(items) => Value::from(items.len()),
+            Value::Object(fields) => Value::from(fields.len()),
+            value => return Err(format!("'len' can't measure {}.", type_name(value))),
+        },
+        ("in_cidr", [ip, cidr]) => match (string(ip, function)?, string(cidr, function)?) {
+            (Some(ip), Some(cidr)) => Value::Bool(in_cidr(ip, cidr)?),
+            _ => Value::Bool(false),
+        },
+        _ => return Err(format!("'{function}' can't be called like this.")),
+    })
+}
+
+fn compare(comparison: Comparison, left: &Value, right: &Value) -> Result<bool, String> {
+    match comparison {
+        Comparison::Eq => return Ok(equals(left, right)),
+        Comparison::Ne => return Ok(!equals(left, right)),
+        Comparison::In => {
+            return match (left, right) {
+                (_, Value::Null) => Ok(false),
+                (_, Value::Array(items)) => Ok(items.iter().any(|item| equals(item, left))),
+                (Value::String(needle), Value::String(haystack)) => Ok(haystack.contains(needle)),
+                (Value::String(key), Value::Object(fields)) => Ok(fields.contains_key(key)),
+                _ => Err(format!(
+                    "'in' can't look for {} in {}.",
+                    type_name(left),
+                    type_name(right)
+                )),
+            }
+        }
+        _ => {}
+    }
+
+    let ordering = match (left, right) {
+        // Missing attributes never compare, so conditions over them are simply false.
+        (Value::Null, _) | (_, Value::Null) => return Ok(false),
+        (Value::Number(a), Value::Number(b)) => a.as_f64().partial_cmp(&b.as_f64()),
+        (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+        _ => {
+            return Err(format!(
+                "Can't order {} and {}.",
+                type_name(left),
+                type_name(right)
+            ))
+        }
+    };
+
+    let Some(ordering) = ordering else {
+        return Ok(false);
+    };
+
+    Ok(match comparison {
+        Comparison::Lt => ordering.is_lt(),
+        Comparison::Le => ordering.is_le(),
+        Comparison::Gt => ordering.is_gt(),
+        _ => ordering.is_ge(),
+    })
+}
+
+/// Evaluates an expression over a context, where missing attributes are `null`.
+pub fn evaluate(expr: &Expr, context: &Value) -> Result<Value, String> {
+    Ok(match expr {
+        Expr::Literal(value) => value.clone(),
+        Expr::Path(path) => path
+            .iter()
+            .try_fold(context, |value, key| value.get(key))
+            .cloned()
+            .unwrap_or(Value::Null),
+        Expr::List(items) => Value::Array(
+            items
+                .iter()
+                .map(|item| evaluate(item, context))
+                .collect::<Result<_, _>>()?,
+        ),
+        Expr::Not(expr) => Value::Bool(!boolean(evaluate(expr, context)?, "!")?),
+        Expr::And(left, right) => Value::Bool(
+            boolean(evaluate(left, context)?, "&&")? && boolean(evaluate(right, context)?, "&&")?,
+        ),
+        Expr::Or(left, right) => Value::Bool(
+            boolean(evaluate(left, context)?, "||")? || boolean(evaluate(right, context)?, "||")?,
+        ),
+        Expr::Compare(comparison, left, right) => Value::Bool(compare(
+            *comparison,
+            &evaluate(left, context)?,
+            &evaluate(right, context)?,
+        )?),
+        Expr::Call(function, arguments) => call(
+            function,
+            arguments
+                .iter()
+                .map(|argument| evaluate(argument, context))
+                .collect::<Result<_, _>>()?,
+        )?,
+    })
+}
+
+/// Evaluates a condition, which must result in a boolean.
+pub fn holds(expr: &Expr, context: &Value) -> Result<bool, String> {
+    match evaluate(expr, context)? {
+        Value::Bool(b) => Ok(b),
+        value => Err(format!(
+            "The condition must result in a boolean but got {}.",
+            type_name(&value)
+        )),
+    }
+}
@@ -0,0 +1,486 @@
+pub mod language;
+
+use std::{collections::BTreeSet, net::IpAddr};
+
+use axum::http::{header::USER_AGENT, HeaderMap};
+
+use chrono::{DateTime, Datelike, Timelike, Utc};
+use chrono_tz::Tz;
+use redis::AsyncCommands;
+use scylla::{frame::value::CqlTimestamp, transport::errors::QueryError, Session};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tracing::{event, Level};
+
+use crate::{authz::Resource, groups, permissions, roles, state::State};
+
+/// Tenant-wide permission required to manage policies and run dry-runs.
+pub const MANAGE_PERMISSION: &str = "policies.manage";
+
+/// Maximum length of a condition, in characters.
+pub const MAX_CONDITION_LENGTH: usize = 4096;
+
+/// Seconds the policies of a tenant are cached for, unless changed earlier.
+const CACHE_TTL: u64 = 300;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Effect {
+    /// Grants the actions when the condition holds, unless a rule or a policy denies them.
+    Allow,
+
+    /// Denies the actions when the condition holds, overriding any grant.
+    Deny,
+}
+
+impl Effect {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Allow => "allow",
+            Self::Deny => "deny",
+        }
+    }
+
+    fn parse(effect: &str) -> Option<Self> {
+        match effect {
+            "allow" => Some(Self::Allow),
+            "deny" => Some(Self::Deny),
+            _ => None,
+        }
+    }
+}
+
+/// An attribute-based rule applying to the actions matching any of `actions`, which are
+/// permission patterns like `users.profiles.*`, when its condition holds.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Policy {
+    pub name: String,
+    pub version: i32,
+    pub description: Option<String>,
+    pub effect: Effect,
+    pub actions: BTreeSet<String>,
+    pub condition: String,
+    pub updated_by: Option<String>,
+    pub updated_at: Option<i64>,
+}
+
+impl Policy {
+    pub fn applies_to(&self, action: &str) -> bool {
+        self.actions
+            .iter()
+            .any(|pattern| permissions::matches(pattern, action))
+    }
+}
+
+type PolicyRow = (
+    String,
+    i32,
+    Option<String>,
+    String,
+    Option<BTreeSet<String>>,
+    Option<String>,
+    Option<String>,
+    Option<CqlTimestamp>,
+);
+
+const COLUMNS: &str =
+    "name, version, description, effect, actions, condition, updated_by, updated_at";
+
+fn from_row(
+    (name, version, description, effect, actions, condition, updated_by, updated_at): PolicyRow,
+) -> Option<Policy> {
+    Some(Policy {
+        name,
+        version,
+        description,
+        effect: Effect::parse(&effect)?,
+        actions: actions.unwrap_or_default(),
+        condition: condition.unwrap_or_default(),
+        updated_by,
+        updated_at: updated_at.map(|CqlTimestamp(t)| t),
+    })
+}
+
+/// Checks whether `name` can name a policy: 1 to 64 lowercase ASCII letters, digits, dots,
+/// underscores or hyphens.
+pub fn is_valid_name(name: &str) -> bool {
+    (1..=64).contains(&name.len())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || "._-".contains(c))
+}
+
+pub async fn get(db: &Session, tenant_id: &str, name: &str) -> Result<Option<Policy>, QueryError> {
+    let result = db
+        .query_unpaged(
+            format!("SELECT {COLUMNS} FROM policies WHERE tenant_id = ? AND name = ?"),
+            (tenant_id, name),
+        )
+        .await?;
+
+    Ok(result
+        .maybe_first_row_typed::<PolicyRow>()
+        .ok()
+        .flatten()
+        .and_then(from_row))
+}
+
+pub async fn all(db: &Session, tenant_id: &str) -> Result<Vec<Policy>, QueryError> {
+    let result = db
+        .query_unpaged(
+            format!("SELECT {COLUMNS} FROM policies WHERE tenant_id = ?"),
+            (tenant_id,),
+        )
+        .await?;
+
+    Ok(result
+        .rows_typed_or_empty::<PolicyRow>()
+        .filter_map(|row| row.ok())
+        .filter_map(from_row)
+        .collect())
+}
+
+/// Lists every version of a policy, newest first, including the versions of a deleted policy.
+pub async fn versions(
+    db: &Session,
+    tenant_id: &str,
+    name: &str,
+) -> Result<Vec<Policy>, QueryError> {
+    let result = db
+        .query_unpaged(
+            format!("SELECT {COLUMNS} FROM policy_versions WHERE tenant_id = ? AND name = ?"),
+            (tenant_id, name),
+        )
+        .await?;
+
+    Ok(result
+        .rows_typed_or_empty::<PolicyRow>()
+        .filter_map(|row| row.ok())
+        .filter_map(from_row)
+        .collect())
+}
+
+pub async fn version(
+    db: &Session,
+    tenant_id: &str,
+    name: &str,
+    version: i32,
+) -> Result<Option<Policy>, QueryError> {
+    let result = db
+        .query_unpaged(
+            format!(
+                "SELECT {COLUMNS} FROM policy_versions WHERE tenant_id = ? AND name = ? AND version = ?"
+            ),
+            (tenant_id, name, version),
+        )
+        .await?;
+
+    Ok(result
+        .maybe_first_row_typed::<PolicyRow>()
+        .ok()
+        .flatten()
+        .and_then(from_row))
+}
+
+/// Saves a policy as a new version, following the latest one ever saved under its name.
+/// Returns `None` if another version was saved concurrently.
+pub async fn save(
+    state: &State,
+    tenant_id: &str,
+    policy: Policy,
+) -> Result<Option<Policy>, QueryError> {
+    let result = state
+        .db
+        .query_unpaged(
+            "SELECT version FROM policy_versions WHERE tenant_id = ? AND name = ? LIMIT 1",
+            (tenant_id, &policy.name),
+        )
+        .await?;
+    let latest = result
+        .maybe_first_row_typed::<(i32,)>()
+        .ok()
+        .flatten()
+        .map_or(0, |(version,)| version);
+
+    let policy = Policy {
+        version: latest + 1,
+        updated_at: Some(Utc::now().timestamp_millis()),
+        ..policy
+    };
+    let values = (
+        tenant_id,
+        &policy.name,
+        policy.version,
+        &policy.description,
+        policy.effect.as_str(),
+        &policy.actions,
+        &policy.condition,
+        &policy.updated_by,
+        policy.updated_at.map(CqlTimestamp),
+    );
+
+    // Versions are immutable, so the lightweight transaction settles concurrent saves.
+    let result = state
+        .db
+        .query_unpaged(
+            "INSERT INTO policy_versions (tenant_id, name, version, description, effect, actions, condition, updated_by, updated_at) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?) IF NOT EXISTS",
+            values,
+        )
+        .await?;
+
+    let applied = result
+        .first_row()
+        .ok()
+        .and_then(|row| row.columns.into_iter().next().flatten())
+        .and_then(|applied| applied.as_boolean())
+        .unwrap_or(false);
+
+    if !applied {
+        return Ok(None);
+    }
+
+    state
+        .db
+        .query_unpaged(
+            "INSERT INTO policies (tenant_id, name, version, description, effect, actions, condition, updated_by, updated_at) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            values,
+        )
+        .await?;
+
+    invalidate(state, tenant_id).await;
+
+    Ok(Some(policy))
+}
+
+/// Deletes a policy, keeping its versions so that it can be restored.
+pub async fn delete(state: &State, tenant_id: &str, name: &str) -> Result<(), QueryError> {
+    state
+        .db
+        .query_unpaged(
+            "DELETE FROM policies WHERE tenant_id = ? AND name = ?",
+            (tenant_id, name),
+        )
+        .await?;
+
+    invalidate(state, tenant_id).await;
+
+    Ok(())
+}
+
+fn cache_key(tenant_id: &str) -> String {
+    format!("policies:{tenant_id}")
+}
+
+/// Returns the policies of a tenant, cached in Redis until they change.
+pub async fn cached(state: &State, tenant_id: &str) -> Result<Vec<Policy>, QueryError> {
+    let mut redis = match state.redis.get_multiplexed_async_connection().await {
+        Ok(conn) => Some(conn),
+        Err(e) => {
+            event!(Level::WARN, error = format!("{e}"));
+            None
+        }
+    };
+
+    if let Some(conn) = redis.as_mut() {
+        let cached: Option<String> = conn.get(cache_key(tenant_id)).await.unwrap_or(None);
+
+        if let Some(policies) = cached.and_then(|c| serde_json::from_str(&c).ok()) {
+            return Ok(policies);
+        }
+    }
+
+    let policies = all(&state.db, tenant_id).await?;
+
+    if let Some(conn) = redis.as_mut() {
+        let result: redis::RedisResult<()> = conn
+            .set_ex(
+                cache_key(tenant_id),
+                serde_json::to_string(&policies).unwrap_or_default(),
+                CACHE_TTL,
+            )
+            .await;
+
+        if let Err(e) = result {
+            event!(Level::WARN, error = format!("{e}"));
+        }
+    }
+
+    Ok(policies)
+}
+
+async fn invalidate(state: &State, tenant_id: &str) {
+    let result: redis::RedisResult<()> = async {
+        let mut conn = state.redis.get_multiplexed_async_connection().await?;
+
+        conn.del(cache_key(tenant_id)).await
+    }
+    .await;
+
+    if let Err(e) = result {
+        event!(Level::WARN, error = format!("{e}"));
+    }
+}
+
+/// What's known about the request a decision is made for.
+#[derive(Debug, Clone)]
+pub struct RequestContext {
+    pub ip: Option<String>,
+    pub device_id: Option<String>,
+    pub user_agent: Option<String>,
+    pub time: DateTime<Utc>,
+}
+
+impl RequestContext {
+    pub fn new(ip: IpAddr, headers: &HeaderMap, device_id: Option<String>) -> Self {
+        Self {
+            ip: Some(ip.to_string()),
+            device_id,
+            user_agent: headers
+                .get(USER_AGENT)
+                .and_then(|value| value.to_str().ok())
+                .map(|value| value.to_string()),
+            time: Utc::now(),
+        }
+    }
+}
+
+/// Describes `time` in `timezone`, falling back to UTC for unknown timezones. Weekdays go from
+/// 1 for Monday to 7 for Sunday.
+fn time_attributes(time: DateTime<Utc>, timezone: Option<&str>) -> Value {
+    let timezone: Tz = timezone.and_then(|tz| tz.parse().ok()).unwrap_or(Tz::UTC);
+    let local = time.with_timezone(&timezone);
+
+    json!({
+        "timestamp": time.timestamp(),
+        "timezone": timezone.name(),
+        "iso": local.to_rfc3339(),
+        "year": local.year(),
+        "month": local.month(),
+        "day": local.day(),
+        "hour": local.hour(),
+        "minute": local.minute(),
+        "weekday": local.weekday().number_from_monday(),
+    })
+}
+
+/// Loads the attributes of a user: their profile, employment, metadata, roles and groups.
+/// Unknown users only have their ID.
+pub async fn user_attributes(
+    state: &State,
+    tenant_id: &str,
+    user_id: &str,
+) -> Result<Value, QueryError> {
+    let result = state
+        .db
+        .query_unpaged(
+            "SELECT JSON user_id, username, location, locale, timezone, is_verified, employment, metadata FROM users WHERE tenant_id = ? AND user_id = ?",
+            (tenant_id, user_id),
+        )
+        .await?;
+
+    let mut attributes = result
+        .maybe_first_row_typed::<(String,)>()
+        .ok()
+        .flatten()
+        .and_then(|(row,)| serde_json::from_str::<Value>(&row).ok())
+        .unwrap_or_else(|| json!({ "user_id": user_id }));
+
+    let groups: BTreeSet<String> = groups::transitive_groups(state, tenant_id, user_id)
+        .await?
+        .into_iter()
+        .collect();
+    let roles: BTreeSet<String> = roles::user_roles(&state.db, tenant_id, user_id)
+        .await?
+        .into_iter()
+        .collect();
+
+    attributes["groups"] = json!(groups);
+    attributes["roles"] = json!(roles);
+
+    Ok(attributes)
+}
+
+/// Builds the context conditions are evaluated over, made of `user`, `resource` and `request`.
+/// The request time is described in the user's timezone.
+pub async fn context(
+    state: &State,
+    tenant_id: &str,
+    subject: &str,
+    resource: &Resource,
+    request: &RequestContext,
+) -> Result<Value, QueryError> {
+    let user = user_attributes(state, tenant_id, subject).await?;
+
+    let resource = match resource {
+        Resource::Tenant => json!({ "type": "tenant", "tenant_id": tenant_id }),
+        Resource::Organization(id) => json!({ "type": "organization", "organization_id": id }),
+        Resource::User(id) => {
+            let mut attributes = user_attributes(state, tenant_id, id).await?;
+            attributes["type"] = json!("user");
+            attributes
+        }
+    };
+
+    let time = time_attributes(
+        request.time,
+        user.get("timezone").and_then(|timezone| timezone.as_str()),
+    );
+
+    Ok(json!({
+        "user": user,
+        "resource": resource,
+        "request": {
+            "ip": request.ip,
+            "device_id": request.device_id,
+            "user_agent": request.user_agent,
+            "time": time,
+        },
+    }))
+}
+
+/// The outcome of a policy applying to an action.
+#[derive(Debug, Clone, Serialize)]
+pub struct Evaluation {
+    pub name: String,
+    pub version: i32,
+    pub effect: Effect,
+    pub holds: bool,
+
+    /// Why the condition couldn't be evaluated. Deny policies failing this way deny anyway.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Evaluation {
+    pub fn denies(&self) -> bool {
+        self.effect == Effect::Deny && (self.holds || self.error.is_some())
+    }
+
+    pub fn allows(&self) -> bool {
+        self.effect == Effect::Allow && self.holds && self.error.is_none()
+    }
+}
+
+/// Evaluates the policies applying to `action` over `context`, in name order.
+pub fn evaluate(policies: &[Policy], action: &str, context: &Value) -> Vec<Evaluation> {
+    let mut evaluations: Vec<Evaluation> = policies
+        .iter()
+        .filter(|policy| policy.applies_to(action))
+        .map(|policy| {
+            let result = language::parse(&policy.condition)
+                .map_err(|e| format!("{} (at {})", e.message, e.position))
+                .and_then(|expr| language::holds(&expr, context));
+
+            Evaluation {
+                name: policy.name.clone(),
+                version: policy.version,
+                effect: policy.effect,
+                holds: result.as_ref().is_ok_and(|holds| *holds),
+                error: result.err(),
+            }
+        })
+        .collect();
+
+    evaluations.sort_by(|a, b| a.name.cmp(&b.name));
+    evaluations
+}
@@ -0,0 +1,669 @@
+use std::{collections::HashMap, time::Duration};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::Utc;
+use futures::StreamExt;
+use hmac::Mac;
+use scylla::{
+    frame::{response::result::CqlValue, value::CqlTimestamp},
+    query::Query,
+    transport::errors::QueryError,
+    Session,
+};
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use tracing::{event, Level};
+
+use crate::{
+    state::{AppState, State},
+    tenants,
+};
+
+/// How long a tenant stays suspended but restorable before its data is purged, unless another
+/// period is requested.
+pub const DEFAULT_GRACE_PERIOD: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// Longest grace period that can be requested.
+pub const MAX_GRACE_PERIOD: Duration = Duration::from_secs(90 * 24 * 60 * 60);
+
+/// How often the worker looks for purges that are due.
+const POLL_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How long a worker holds a purge without renewing its lease before another one can resume it.
+const LEASE: Duration = Duration::from_secs(300);
+
+/// Rows read per page while scanning a table for the tenant's partitions.
+const PAGE_SIZE: i32 = 1000;
+
+/// A table holding tenant data, along with the columns of its partition key.
+pub struct Table {
+    pub name: &'static str,
+    pub partition_key: &'static [&'static str],
+}
+
+/// Every table holding tenant data, in the order they're purged. Materialized views follow their
+/// base tables. The tenant registry, with its hosts, admins, settings and signing keys, is
+/// deleted last by `tenants::delete`.
+pub const TABLES: [Table; 40] = [
+    Table {
+        name: "sandbox_messages",
+        partition_key: &["tenant_id"],
+    },
+    Table {
+        name: "tenant_flag_changes",
+        partition_key: &["tenant_id"],
+    },
+    Table {
+        name: "tenant_flags",
+        partition_key: &["tenant_id"],
+    },
+    Table {
+        name: "tenant_active_users",
+        partition_key: &["tenant_id", "month"],
+    },
+    Table {
+        name: "tenant_usage",
+        partition_key: &["tenant_id"],
+    },
+    Table {
+        name: "tenant_quotas",
+        partition_key: &["tenant_id"],
+    },
+    Table {
+        name: "tenant_imports",
+        partition_key: &["tenant_id"],
+    },
+    Table {
+        name: "api_tokens",
+        partition_key: &["tenant_id", "api_token"],
+    },
+    Table {
+        name: "api_clients",
+        partition_key: &["tenant_id", "client_id"],
+    },
+    Table {
+        name: "devices",
+        partition_key: &["tenant_id", "user_id"],
+    },
+    Table {
+        name: "mfa_codes",
+        partition_key: &["tenant_id", "user_id", "code_type"],
+    },
+    Table {
+        name: "notification_recipients",
+        partition_key: &["tenant_id", "user_id"],
+    },
+    Table {
+        name: "notifications",
+        partition_key: &["tenant_id"],
+    },
+    Table {
+        name: "activity_logs",
+        partition_key: &["tenant_id"],
+    },
+    Table {
+        name: "hooks",
+        partition_key: &["tenant_id"],
+    },
+    Table {
+        name: "policy_versions",
+        partition_key: &["tenant_id", "name"],
+    },
+    Table {
+        name: "policies",
+        partition_key: &["tenant_id"],
+    },
+    Table {
+        name: "relation_tuples",
+        partition_key: &["tenant_id", "namespace"],
+    },
+    Table {
+        name: "relation_namespaces",
+        partition_key: &["tenant_id"],
+    },
+    Table {
+        name: "group_children",
+        partition_key: &["tenant_id", "group_id"],
+    },
+    Table {
+        name: "users_by_group",
+        partition_key: &["tenant_id", "group_id"],
+    },
+    Table {
+        name: "groups",
+        partition_key: &["tenant_id", "group_id"],
+    },
+    Table {
+        name: "roles",
+        partition_key: &["tenant_id"],
+    },
+    Table {
+        name: "organization_invitations",
+        partition_key: &["tenant_id", "organization_id"],
+    },
+    Table {
+        name: "organization_domains",
+        partition_key: &["tenant_id", "organization_id"],
+    },
+    Table {
+        name: "organizations_by_domain",
+        partition_key: &["tenant_id", "domain"],
+    },
+    Table {
+        name: "organizations_by_user",
+        partition_key: &["tenant_id", "user_id"],
+    },
+    Table {
+        name: "organizations",
+        partition_key: &["tenant_id", "organization_id"],
+    },
+    Table {
+        name: "oauth_accounts",
+        partition_key: &["tenant_id", "user_id"],
+    },
+    Table {
+        name: "oauth_provider_settings",
+        partition_key: &["tenant_id"],
+    },
+    Table {
+        name: "passwords",
+        partition_key: &["tenant_id", "user_id"],
+    },
+    Table {
+        name: "phone_numbers",
+        partition_key: &["tenant_id", "user_id"],
+    },
+    Table {
+        name: "email_rollbacks",
+        partition_key: &["tenant_id", "rollback_token"],
+    },
+    Table {
+        name: "email_changes",
+        partition_key: &["tenant_id", "user_id"],
+    },
+    Table {
+        name: "email_claims",
+        partition_key: &["tenant_id", "email"],
+    },
+    Table {
+        name: "emails",
+        partition_key: &["tenant_id", "user_id"],
+    },
+    Table {
+        name: "username_reservations",
+        partition_key: &["tenant_id", "username"],
+    },
+    Table {
+        name: "username_history",
+        partition_key: &["tenant_id", "user_id"],
+    },
+    Table {
+        name: "username_keys",
+        partition_key: &["tenant_id", "key"],
+    },
+    Table {
+        name: "users",
+        partition_key: &["user_id", "tenant_id"],
+    },
+];
+
+/// The name of the last step, which deletes the tenant itself.
+const REGISTRY_STEP: &str = "tenants";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    /// Waiting for the grace period to end. The tenant can still be restored.
+    Scheduled,
+    Running,
+    Completed,
+}
+
+impl Status {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Scheduled => "scheduled",
+            Self::Running => "running",
+            Self::Completed => "completed",
+        }
+    }
+
+    fn parse(status: &str) -> Option<Self> {
+        match status {
+            "scheduled" => Some(Self::Scheduled),
+            "running" => Some(Self::Running),
+            "completed" => Some(Self::Completed),
+            _ => None,
+        }
+    }
+}
+
+/// How far a purge went.
+#[derive(Debug, Serialize)]
+pub struct Progress {
+    pub completed_steps: usize,
+    pub total_steps: usize,
+
+    /// The table being purged, while the purge is running.
+    pub current: Option<&'static str>,
+}
+
+/// The purge of a tenant's data. It's kept once completed, as the record of the deletion.
+#[derive(Debug, Serialize)]
+pub struct Purge {
+    pub tenant_id: String,
+    pub status: Status,
+    pub requested_by: Option<String>,
+    pub requested_at: Option<i64>,
+
+    /// When the grace period ends and the data starts being purged.
+    pub purge_after: i64,
+    pub started_at: Option<i64>,
+    pub completed_at: Option<i64>,
+    pub progress: Progress,
+
+    /// The rows deleted from each table so far.
+    pub deleted: Map<String, Value>,
+
+    /// The signed deletion receipt, once completed.
+    pub receipt: Option<Value>,
+    #[serde(skip)]
+    lease_until: Option<CqlTimestamp>,
+}
+
+type PurgeRow = (
+    String,
+    Option<String>,
+    Option<String>,
+    Option<CqlTimestamp>,
+    Option<CqlTimestamp>,
+    Option<CqlTimestamp>,
+    Option<CqlTimestamp>,
+    Option<CqlTimestamp>,
+    Option<i32>,
+    Option<HashMap<String, i64>>,
+    Option<String>,
+);
+
+const COLUMNS: &str = "tenant_id, status, requested_by, requested_at, purge_after, started_at, completed_at, lease_until, step, deleted, receipt";
+
+fn total_steps() -> usize {
+    TABLES.len() + 1
+}
+
+fn step_name(step: usize) -> Option<&'static str> {
+    match TABLES.get(step) {
+        Some(table) => Some(table.name),
+        None if step == TABLES.len() => Some(REGISTRY_STEP),
+        None => None,
+    }
+}
+
+fn from_row(
+    (
+        tenant_id,
+        status,
+        requested_by,
+        requested_at,
+        purge_after,
+        started_at,
+        completed_at,
+        lease_until,
+        step,
+        deleted,
+        receipt,
+    ): PurgeRow,
+) -> Option<Purge> {
+    let status = Status::parse(status.as_deref()?)?;
+    let step = step.unwrap_or(0).max(0) as usize;
+
+    Some(Purge {
+        tenant_id,
+        status,
+        requested_by,
+        requested_at: requested_at.map(|CqlTimestamp(t)| t),
+        purge_after: purge_after.map_or(0, |CqlTimestamp(t)| t),
+        started_at: started_at.map(|CqlTimestamp(t)| t),
+        completed_at: completed_at.map(|CqlTimestamp(t)| t),
+        progress: Progress {
+            completed_steps: step.min(total_steps()),
+            total_steps: total_steps(),
+            current: (status == Status::Running)
+                .then(|| step_name(step))
+                .flatten(),
+        },
+        deleted: deleted
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(table, count)| (table, json!(count)))
+            .collect(),
+        receipt: receipt.and_then(|receipt| serde_json::from_str(&receipt).ok()),
+        lease_until,
+    })
+}
+
+pub async fn get(db: &Session, tenant_id: &str) -> Result<Option<Purge>, QueryError> {
+    let result = db
+        .query_unpaged(
+            format!("SELECT {COLUMNS} FROM tenant_purges WHERE tenant_id = ?"),
+            (tenant_id,),
+        )
+        .await?;
+
+    Ok(result
+        .maybe_first_row_typed::<PurgeRow>()
+        .ok()
+        .flatten()
+        .and_then(from_row))
+}
+
+/// Schedules the purge of a tenant, suspending it for the grace period. Scheduling it again
+/// restarts the grace period.
+pub async fn schedule(
+    state: &State,
+    tenant_id: &str,
+    requested_by: &str,
+    grace_period: Duration,
+) -> Result<Purge, QueryError> {
+    let now = Utc::now().timestamp_millis();
+    let purge_after = now + grace_period.as_millis() as i64;
+
+    tenants::set_suspended(state, tenant_id, true).await?;
+
+    state
+        .db
+        .query_unpaged(
+            "
+                INSERT INTO tenant_purges (
+                    tenant_id, status, requested_by, requested_at, purge_after, started_at, completed_at, lease_until, step, deleted, receipt
+                ) VALUES (
+                    ?, ?, ?, ?, ?, null, null, null, 0, null, null
+                )
+            ",
+            (
+                tenant_id,
+                Status::Scheduled.as_str(),
+                requested_by,
+                CqlTimestamp(now),
+                CqlTimestamp(purge_after),
+            ),
+        )
+        .await?;
+
+    Ok(Purge {
+        tenant_id: tenant_id.to_string(),
+        status: Status::Scheduled,
+        requested_by: Some(requested_by.to_string()),
+        requested_at: Some(now),
+        purge_after,
+        started_at: None,
+        completed_at: None,
+        progress: Progress {
+            completed_steps: 0,
+            total_steps: total_steps(),
+            current: None,
+        },
+        deleted: Map::new(),
+        receipt: None,
+        lease_until: None,
+    })
+}
+
+/// Cancels a purge still in its grace period and resumes the tenant. Returns `false` if the
+/// purge already started.
+pub async fn restore(state: &State, tenant_id: &str) -> Result<bool, QueryError> {
+    let result = state
+        .db
+        .query_unpaged(
+            "DELETE FROM tenant_purges WHERE tenant_id = ? IF status = ?",
+            (tenant_id, Status::Scheduled.as_str()),
+        )
+        .await?;
+
+    let applied = result
+        .first_row()
+        .ok()
+        .and_then(|row| row.columns.into_iter().next().flatten())
+        .and_then(|applied| applied.as_boolean())
+        .unwrap_or(false);
+
+    if applied {
+        tenants::set_suspended(state, tenant_id, false).await?;
+    }
+
+    Ok(applied)
+}
+
+/// Deletes every row of the tenant from a table, returning how many there were. Tables
+/// partitioned by tenant are deleted at once, while the others are scanned for the tenant's
+/// partitions, renewing the lease after every page. Returns `None` if the lease is lost, leaving
+/// the table to the worker holding it.
+async fn purge_table(
+    db: &Session,
+    table: &Table,
+    purge: &mut Purge,
+) -> Result<Option<i64>, QueryError> {
+    let tenant_id = purge.tenant_id.clone();
+
+    if table.partition_key == ["tenant_id"] {
+        let result = db
+            .query_unpaged(
+                format!("SELECT COUNT(*) FROM {} WHERE tenant_id = ?", table.name),
+                (&tenant_id,),
+            )
+            .await?;
+
+        let (count,) = result.first_row_typed::<(i64,)>().unwrap_or((0,));
+
+        db.query_unpaged(
+            format!("DELETE FROM {} WHERE tenant_id = ?", table.name),
+            (&tenant_id,),
+        )
+        .await?;
+
+        return Ok(Some(count));
+    }
+
+    let columns = table.partition_key.join(", ");
+    let conditions = table
+        .partition_key
+        .iter()
+        .map(|column| format!("{column} = ?"))
+        .collect::<Vec<String>>()
+        .join(" AND ");
+
+    let mut query = Query::new(format!(
+        "SELECT {columns} FROM {} WHERE tenant_id = ? ALLOW FILTERING",
+        table.name
+    ));
+    query.set_page_size(PAGE_SIZE);
+
+    let delete = db
+        .prepare(format!("DELETE FROM {} WHERE {conditions}", table.name))
+        .await?;
+
+    let mut rows = db.query_iter(query, (&tenant_id,)).await?;
+    let mut previous: Option<Vec<CqlValue>> = None;
+    let mut count = 0;
+
+    // Rows come grouped by partition, so each partition is deleted once, on its first row.
+    while let Some(row) = rows.next().await {
+        let key: Vec<CqlValue> = row?.columns.into_iter().flatten().collect();
+        count += 1;
+
+        if previous.as_ref() != Some(&key) {
+            db.execute_unpaged(&delete, &key).await?;
+            previous = Some(key);
+        }
+
+        if count % i64::from(PAGE_SIZE) == 0 && !lease(db, purge).await? {
+            return Ok(None);
+        }
+    }
+
+    Ok(Some(count))
+}
+
+/// Signs a receipt with the instance's key, so it can be shown to come from AccessCore.
+fn sign(state: &State, receipt: &Value) -> String {
+    let mut mac = state.hmac.clone();
+    mac.update(receipt.to_string().as_bytes());
+
+    URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes())
+}
+
+/// Takes or renews the lease of a purge, returning `false` if another worker holds it or the
+/// purge was restored in the meantime.
+async fn lease(db: &Session, purge: &mut Purge) -> Result<bool, QueryError> {
+    let lease_until = CqlTimestamp(Utc::now().timestamp_millis() + LEASE.as_millis() as i64);
+    let started_at = purge
+        .started_at
+        .unwrap_or_else(|| Utc::now().timestamp_millis());
+
+    let result = db
+        .query_unpaged(
+            "UPDATE tenant_purges SET status = ?, started_at = ?, lease_until = ? WHERE tenant_id = ? IF status = ? AND lease_until = ?",
+            (
+                Status::Running.as_str(),
+                CqlTimestamp(started_at),
+                lease_until,
+                &purge.tenant_id,
+                purge.status.as_str(),
+                purge.lease_until,
+            ),
+        )
+        .await?;
+
+    let applied = result
+        .first_row()
+        .ok()
+        .and_then(|row| row.columns.into_iter().next().flatten())
+        .and_then(|applied| applied.as_boolean())
+        .unwrap_or(false);
+
+    if applied {
+        purge.status = Status::Running;
+        purge.started_at = Some(started_at);
+        purge.lease_until = Some(lease_until);
+    }
+
+    Ok(applied)
+}
+
+/// Runs a due purge from the step it stopped at, recording progress after each table.
+async fn process(state: &State, mut purge: Purge) -> Result<(), QueryError> {
+    if !lease(&state.db, &mut purge).await? {
+        return Ok(());
+    }
+
+    for step in purge.progress.completed_steps..total_steps() {
+        let (name, count) = match TABLES.get(step) {
+            Some(table) => match purge_table(&state.db, table, &mut purge).await? {
+                Some(count) => (table.name, count),
+                None => return Ok(()),
+            },
+            None => {
+                tenants::delete(state, &purge.tenant_id).await?;
+                (REGISTRY_STEP, 1)
+            }
+        };
+
+        state
+            .db
+            .query_unpaged(
+                "UPDATE tenant_purges SET step = ?, deleted[?] = ? WHERE tenant_id = ?",
+                ((step + 1) as i32, name, count, &purge.tenant_id),
+            )
+            .await?;
+
+        purge.deleted.insert(name.to_string(), json!(count));
+        purge.progress.completed_steps = step + 1;
+
+        event!(
+            Level::INFO,
+            tenant_id = purge.tenant_id,
+            table = name,
+            deleted = count,
+            "Purged table."
+        );
+
+        if !lease(&state.db, &mut purge).await? {
+            return Ok(());
+        }
+    }
+
+    let completed_at = Utc::now().timestamp_millis();
+
+    let mut receipt = json!({
+        "tenant_id": purge.tenant_id,
+        "requested_by": purge.requested_by,
+        "requested_at": purge.requested_at,
+        "started_at": purge.started_at,
+        "completed_at": completed_at,
+        "deleted": purge.deleted,
+    });
+    receipt["signature"] = json!(sign(state, &receipt));
+
+    state
+        .db
+        .query_unpaged(
+            "UPDATE tenant_purges SET status = ?, completed_at = ?, lease_until = null, receipt = ? WHERE tenant_id = ?",
+            (
+                Status::Completed.as_str(),
+                CqlTimestamp(completed_at),
+                receipt.to_string(),
+                &purge.tenant_id,
+            ),
+        )
+        .await?;
+
+    event!(Level::INFO, tenant_id = purge.tenant_id, "Purged tenant.");
+
+    Ok(())
+}
+
+/// Lists the purges whose grace period is over and that aren't completed, including the ones
+/// whose worker stopped. There are few enough of them for a full scan.
+async fn due(db: &Session) -> Result<Vec<Purge>, QueryError> {
+    let result = db
+        .query_unpaged(format!("SELECT {COLUMNS} FROM tenant_purges"), &[])
+        .await?;
+
+    let now = Utc::now().timestamp_millis();
+
+    Ok(result
+        .rows_typed_or_empty::<PurgeRow>()
+        .filter_map(|row| row.ok())
+        .filter_map(from_row)
+        .filter(|purge| purge.status != Status::Completed && purge.purge_after <= now)
+        .filter(|purge| purge.lease_until.is_none_or(|CqlTimestamp(t)| t <= now))
+        .collect())
+}
+
+/// Runs due purges in the background. Any instance can run a purge, and one that stops midway
+/// is resumed once its lease expires.
+pub async fn run(state: AppState) {
+    let mut interval = tokio::time::interval(POLL_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        let state = state.read().await;
+
+        let purges = match due(&state.db).await {
+            Ok(purges) => purges,
+            Err(e) => {
+                event!(Level::WARN, error = format!("{e}"));
+                continue;
+            }
+        };
+
+        for purge in purges {
+            let tenant_id = purge.tenant_id.clone();
+
+            if let Err(e) = process(&state, purge).await {
+                event!(Level::WARN, error = format!("{e}"), tenant_id);
+            }
+        }
+    }
+}
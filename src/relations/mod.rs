@@ -0,0 +1,783 @@
+pub mod namespaces;
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+};
+
+use base64::engine::{general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use chrono::Utc;
+use redis::AsyncCommands;
+use scylla::{
+    batch::Batch,
+    frame::value::CqlTimestamp,
+    query::Query,
+    statement::Consistency,
+    transport::{errors::QueryError, retry_policy::DefaultRetryPolicy},
+    Session,
+};
+use serde::{Deserialize, Serialize};
+use tracing::{event, Level};
+
+use crate::state::State;
+use namespaces::Rewrite;
+
+/// Tenant-wide permission required to manage namespaces and write relation tuples.
+pub const MANAGE_PERMISSION: &str = "relations.manage";
+
+/// Tenant-wide permission required to check, expand and list relations of other users.
+pub const READ_PERMISSION: &str = "relations.read";
+
+/// How many relations deep a check or an expansion follows usersets before giving up.
+const MAX_DEPTH: usize = 16;
+
+/// Seconds a check result is cached for. Requests carrying a newer consistency token skip it.
+const CACHE_TTL: u64 = 60;
+
+/// An object, written `namespace:object_id`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Object {
+    pub namespace: String,
+    pub object_id: String,
+}
+
+impl Object {
+    pub fn parse(object: &str) -> Option<Self> {
+        let (namespace, object_id) = object.split_once(':')?;
+
+        (namespaces::is_valid_name(namespace) && is_valid_id(object_id)).then(|| Self {
+            namespace: namespace.to_string(),
+            object_id: object_id.to_string(),
+        })
+    }
+}
+
+impl fmt::Display for Object {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.namespace, self.object_id)
+    }
+}
+
+/// Who a tuple relates an object to.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Subject {
+    /// A user, written as their ID.
+    User(String),
+
+    /// Another object, written `namespace:object_id`, which is what `->` follows.
+    Object(Object),
+
+    /// Every subject of a relation of another object, written `namespace:object_id#relation`.
+    Userset(Object, String),
+}
+
+impl Subject {
+    pub fn parse(subject: &str) -> Option<Self> {
+        if !subject.contains(':') {
+            return is_valid_id(subject).then(|| Self::User(subject.to_string()));
+        }
+
+        match subject.split_once('#') {
+            Some((object, relation)) => {
+                let object = Object::parse(object)?;
+
+                namespaces::is_valid_name(relation)
+                    .then(|| Self::Userset(object, relation.to_string()))
+            }
+            None => Object::parse(subject).map(Self::Object),
+        }
+    }
+}
+
+impl fmt::Display for Subject {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::User(user_id) => write!(f, "{user_id}"),
+            Self::Object(object) => write!(f, "{object}"),
+            Self::Userset(object, relation) => write!(f, "{object}#{relation}"),
+        }
+    }
+}
+
+/// A relation tuple, written `namespace:object_id#relation@subject`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Tuple {
+    pub object: Object,
+    pub relation: String,
+    pub subject: Subject,
+}
+
+impl Tuple {
+    pub fn parse(tuple: &str) -> Option<Self> {
+        let (object, rest) = tuple.split_once('#')?;
+        let (relation, subject) = rest.split_once('@')?;
+
+        Some(Self {
+            object: Object::parse(object)?,
+            relation: namespaces::is_valid_name(relation).then(|| relation.to_string())?,
+            subject: Subject::parse(subject)?,
+        })
+    }
+}
+
+impl fmt::Display for Tuple {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}#{}@{}", self.object, self.relation, self.subject)
+    }
+}
+
+impl Serialize for Tuple {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// Object and user IDs can't contain the characters separating the parts of a tuple.
+fn is_valid_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= 128
+        && id
+            .chars()
+            .all(|c| c.is_ascii_graphic() && !matches!(c, ':' | '#' | '@'))
+}
+
+/// Marks the time of a write, in microseconds. Reads given a token are guaranteed to see that
+/// write and everything before it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ConsistencyToken(pub i64);
+
+impl ConsistencyToken {
+    pub fn encode(&self) -> String {
+        URL_SAFE_NO_PAD.encode(self.0.to_be_bytes())
+    }
+
+    pub fn decode(token: &str) -> Option<Self> {
+        let bytes: [u8; 8] = URL_SAFE_NO_PAD.decode(token).ok()?.try_into().ok()?;
+
+        Some(Self(i64::from_be_bytes(bytes)))
+    }
+}
+
+#[derive(Serialize)]
+pub struct NamespaceConfig {
+    pub namespace: String,
+    pub config: String,
+    pub updated_at: Option<i64>,
+}
+
+pub async fn namespace(
+    db: &Session,
+    tenant_id: &str,
+    namespace: &str,
+) -> Result<Option<NamespaceConfig>, QueryError> {
+    let result = db
+        .query_unpaged(
+            "SELECT config, updated_at FROM relation_namespaces WHERE tenant_id = ? AND namespace = ?",
+            (tenant_id, namespace),
+        )
+        .await?;
+
+    Ok(
+        match result.maybe_first_row_typed::<(Option<String>, Option<CqlTimestamp>)>() {
+            Ok(Some((config, updated_at))) => Some(NamespaceConfig {
+                namespace: namespace.to_string(),
+                config: config.unwrap_or_default(),
+                updated_at: updated_at.map(|CqlTimestamp(t)| t),
+            }),
+            _ => None,
+        },
+    )
+}
+
+pub async fn list_namespaces(
+    db: &Session,
+    tenant_id: &str,
+) -> Result<Vec<NamespaceConfig>, QueryError> {
+    let result = db
+        .query_unpaged(
+            "SELECT namespace, config, updated_at FROM relation_namespaces WHERE tenant_id = ?",
+            (tenant_id,),
+        )
+        .await?;
+
+    Ok(result
+        .rows_typed_or_empty::<(String, Option<String>, Option<CqlTimestamp>)>()
+        .filter_map(|row| row.ok())
+        .map(|(namespace, config, updated_at)| NamespaceConfig {
+            namespace,
+            config: config.unwrap_or_default(),
+            updated_at: updated_at.map(|CqlTimestamp(t)| t),
+        })
+        .collect())
+}
+
+/// Saves a namespace config, which must have been parsed successfully.
+pub async fn save_namespace(
+    db: &Session,
+    tenant_id: &str,
+    namespace: &str,
+    config: &str,
+) -> Result<(), QueryError> {
+    db.query_unpaged(
+        "INSERT INTO relation_namespaces (tenant_id, namespace, config, updated_at) VALUES (?, ?, ?, ?)",
+        (
+            tenant_id,
+            namespace,
+            config,
+            CqlTimestamp(Utc::now().timestamp_millis()),
+        ),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Deletes a namespace along with all of its tuples.
+pub async fn delete_namespace(
+    db: &Session,
+    tenant_id: &str,
+    namespace: &str,
+) -> Result<(), QueryError> {
+    let mut batch = Batch::default();
+
+    batch.append_statement("DELETE FROM relation_namespaces WHERE tenant_id = ? AND namespace = ?");
+    batch.append_statement("DELETE FROM relation_tuples WHERE tenant_id = ? AND namespace = ?");
+
+    db.batch(&batch, ((tenant_id, namespace), (tenant_id, namespace)))
+        .await?;
+
+    Ok(())
+}
+
+/// Writes and deletes tuples atomically, returning a token for the write. Writes go to a
+/// quorum of replicas without downgrading, so quorum reads given the token see them.
+pub async fn write(
+    db: &Session,
+    tenant_id: &str,
+    writes: &[Tuple],
+    deletes: &[Tuple],
+) -> Result<ConsistencyToken, QueryError> {
+    let timestamp = Utc::now().timestamp_micros();
+    let mut batch = Batch::default();
+    let mut values: Vec<(&str, &str, &str, &str, String)> = vec![];
+
+    for tuple in writes {
+        batch.append_statement(
+            "INSERT INTO relation_tuples (tenant_id, namespace, object_id, relation, subject) VALUES (?, ?, ?, ?, ?)",
+        );
+        values.push((
+            tenant_id,
+            &tuple.object.namespace,
+            &tuple.object.object_id,
+            &tuple.relation,
+            tuple.subject.to_string(),
+        ));
+    }
+
+    for tuple in deletes {
+        batch.append_statement(
+            "DELETE FROM relation_tuples WHERE tenant_id = ? AND namespace = ? AND object_id = ? AND relation = ? AND subject = ?",
+        );
+        values.push((
+            tenant_id,
+            &tuple.object.namespace,
+            &tuple.object.object_id,
+            &tuple.relation,
+            tuple.subject.to_string(),
+        ));
+    }
+
+    batch.set_timestamp(Some(timestamp));
+    batch.set_consistency(Consistency::Quorum);
+    batch.set_retry_policy(Some(Arc::new(DefaultRetryPolicy)));
+
+    db.batch(&batch, values).await?;
+
+    Ok(ConsistencyToken(timestamp))
+}
+
+/// Lists the tuples of a namespace, optionally of a single object and relation.
+pub async fn tuples(
+    db: &Session,
+    tenant_id: &str,
+    namespace: &str,
+    object_id: Option<&str>,
+    relation: Option<&str>,
+) -> Result<Vec<Tuple>, QueryError> {
+    let result = match (object_id, relation) {
+        (Some(object_id), Some(relation)) => db.query_unpaged(
+            "SELECT object_id, relation, subject FROM relation_tuples WHERE tenant_id = ? AND namespace = ? AND object_id = ? AND relation = ?",
+            (tenant_id, namespace, object_id, relation),
+        ).await?,
+        (Some(object_id), None) => db.query_unpaged(
+            "SELECT object_id, relation, subject FROM relation_tuples WHERE tenant_id = ? AND namespace = ? AND object_id = ?",
+            (tenant_id, namespace, object_id),
+        ).await?,
+        _ => db.query_unpaged(
+            "SELECT object_id, relation, subject FROM relation_tuples WHERE tenant_id = ? AND namespace = ?",
+            (tenant_id, namespace),
+        ).await?,
+    };
+
+    Ok(result
+        .rows_typed_or_empty::<(String, String, String)>()
+        .filter_map(|row| row.ok())
+        .filter(|(_, r, _)| relation.is_none_or(|relation| relation == r))
+        .filter_map(|(object_id, relation, subject)| {
+            Some(Tuple {
+                object: Object {
+                    namespace: namespace.to_string(),
+                    object_id,
+                },
+                relation,
+                subject: Subject::parse(&subject)?,
+            })
+        })
+        .collect())
+}
+
+/// How a relation's subjects were found, as returned by the expand API.
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Expansion {
+    /// The subjects of `userset`, computed by `rewrite`.
+    Userset {
+        userset: String,
+        rewrite: String,
+        children: Vec<Expansion>,
+    },
+
+    /// Users stored directly, plus the expansions of the usersets stored.
+    Direct {
+        users: Vec<String>,
+        children: Vec<Expansion>,
+    },
+    Union {
+        children: Vec<Expansion>,
+    },
+    Intersection {
+        children: Vec<Expansion>,
+    },
+
+    /// A userset already being expanded higher up, or too deep to follow.
+    Truncated {
+        userset: String,
+    },
+}
+
+#[derive(Serialize, Deserialize)]
+struct CachedCheck {
+    allowed: bool,
+    computed_at: i64,
+}
+
+type BoxedFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T, QueryError>> + Send + 'a>>;
+
+/// Evaluates relations of a tenant against its namespace configs, which are loaded once per
+/// evaluator.
+pub struct Evaluator<'a> {
+    state: &'a State,
+    tenant_id: &'a str,
+    token: Option<ConsistencyToken>,
+    configs: HashMap<String, Option<BTreeMap<String, Rewrite>>>,
+}
+
+impl<'a> Evaluator<'a> {
+    pub fn new(state: &'a State, tenant_id: &'a str, token: Option<ConsistencyToken>) -> Self {
+        Self {
+            state,
+            tenant_id,
+            token,
+            configs: HashMap::new(),
+        }
+    }
+
+    /// Returns how `relation` is computed in `namespace`, if both are defined.
+    async fn rewrite(
+        &mut self,
+        namespace: &str,
+        relation: &str,
+    ) -> Result<Option<Rewrite>, QueryError> {
+        if !self.configs.contains_key(namespace) {
+            let config = self::namespace(&self.state.db, self.tenant_id, namespace)
+                .await?
+                .and_then(|config| namespaces::parse(&config.config).ok());
+
+            self.configs.insert(namespace.to_string(), config);
+        }
+
+        Ok(self
+            .configs
+            .get(namespace)
+            .and_then(|config| config.as_ref())
+            .and_then(|config| config.get(relation))
+            .cloned())
+    }
+
+    /// Reads the subjects stored for a relation of an object. Given a consistency token, the
+    /// read goes to a quorum of replicas without downgrading, so it sees the token's write.
+    async fn direct(&self, object: &Object, relation: &str) -> Result<Vec<Subject>, QueryError> {
+        let mut query = Query::new(
+            "SELECT subject FROM relation_tuples WHERE tenant_id = ? AND namespace = ? AND object_id = ? AND relation = ?",
+        );
+
+        if self.token.is_some() {
+            query.set_consistency(Consistency::Quorum);
+            query.set_retry_policy(Some(Arc::new(DefaultRetryPolicy)));
+        }
+
+        let result = self
+            .state
+            .db
+            .query_unpaged(
+                query,
+                (
+                    self.tenant_id,
+                    &object.namespace,
+                    &object.object_id,
+                    relation,
+                ),
+            )
+            .await?;
+
+        Ok(result
+            .rows_typed_or_empty::<(String,)>()
+            .filter_map(|row| row.ok())
+            .filter_map(|(subject,)| Subject::parse(&subject))
+            .collect())
+    }
+
+    /// Checks whether `user_id` is a subject of `relation` on `object`. Results are cached
+    /// briefly, unless the evaluator's consistency token is newer than the cached result.
+    pub async fn check(
+        &mut self,
+        object: &Object,
+        relation: &str,
+        user_id: &str,
+    ) -> Result<bool, QueryError> {
+        let key = format!("relations:{}:{object}#{relation}@{user_id}", self.tenant_id);
+        let mut redis = match self.state.redis.get_multiplexed_async_connection().await {
+            Ok(conn) => Some(conn),
+            Err(e) => {
+                event!(Level::WARN, error = format!("{e}"));
+                None
+            }
+        };
+
+        if let Some(conn) = redis.as_mut() {
+            let cached: Option<String> = conn.get(&key).await.unwrap_or(None);
+
+            if let Some(cached) = cached.and_then(|c| serde_json::from_str::<CachedCheck>(&c).ok())
+            {
+                if self
+                    .token
+                    .is_none_or(|ConsistencyToken(t)| cached.computed_at >= t)
+                {
+                    return Ok(cached.allowed);
+                }
+            }
+        }
+
+        let computed_at = Utc::now().timestamp_micros();
+        let allowed = self
+            .check_relation(object.clone(), relation.to_string(), user_id, MAX_DEPTH)
+            .await?;
+
+        if let Some(conn) = redis.as_mut() {
+            let cached = CachedCheck {
+                allowed,
+                computed_at,
+            };
+            let result: redis::RedisResult<()> = conn
+                .set_ex(
+                    &key,
+                    serde_json::to_string(&cached).unwrap_or_default(),
+                    CACHE_TTL,
+                )
+                .await;
+
+            if let Err(e) = result {
+                event!(Level::WARN, error = format!("{e}"));
+            }
+        }
+
+        Ok(allowed)
+    }
+
+    fn check_relation<'b>(
+        &'b mut self,
+        object: Object,
+        relation: String,
+        user_id: &'b str,
+        depth: usize,
+    ) -> BoxedFuture<'b, bool> {
+        Box::pin(async move {
+            if depth == 0 {
+                return Ok(false);
+            }
+
+            match self.rewrite(&object.namespace, &relation).await? {
+                Some(rewrite) => {
+                    self.evaluate(rewrite, object, relation, user_id, depth)
+                        .await
+                }
+                None => Ok(false),
+            }
+        })
+    }
+
+    fn evaluate<'b>(
+        &'b mut self,
+        rewrite: Rewrite,
+        object: Object,
+        relation: String,
+        user_id: &'b str,
+        depth: usize,
+    ) -> BoxedFuture<'b, bool> {
+        Box::pin(async move {
+            match rewrite {
+                Rewrite::This => {
+                    for subject in self.direct(&object, &relation).await? {
+                        let found = match subject {
+                            Subject::User(id) => id == user_id,
+                            Subject::Userset(object, relation) => {
+                                self.check_relation(object, relation, user_id, depth - 1)
+                                    .await?
+                            }
+                            Subject::Object(_) => false,
+                        };
+
+                        if found {
+                            return Ok(true);
+                        }
+                    }
+
+                    Ok(false)
+                }
+                Rewrite::Computed(computed) => {
+                    self.check_relation(object, computed, user_id, depth - 1)
+                        .await
+                }
+                Rewrite::TupleToUserset { tupleset, computed } => {
+                    for subject in self.direct(&object, &tupleset).await? {
+                        let (Subject::Object(related) | Subject::Userset(related, _)) = subject
+                        else {
+                            continue;
+                        };
+
+                        if self
+                            .check_relation(related, computed.clone(), user_id, depth - 1)
+                            .await?
+                        {
+                            return Ok(true);
+                        }
+                    }
+
+                    Ok(false)
+                }
+                Rewrite::Union(children) => {
+                    for child in children {
+                        if self
+                            .evaluate(child, object.clone(), relation.clone(), user_id, depth)
+                            .await?
+                        {
+                            return Ok(true);
+                        }
+                    }
+
+                    Ok(false)
+                }
+                Rewrite::Intersection(children) => {
+                    for child in children {
+                        if !self
+                            .evaluate(child, object.clone(), relation.clone(), user_id, depth)
+                            .await?
+                        {
+                            return Ok(false);
+                        }
+                    }
+
+                    Ok(true)
+                }
+            }
+        })
+    }
+
+    /// Expands `relation` on `object` into the tree of subjects it's made of.
+    pub async fn expand(
+        &mut self,
+        object: Object,
+        relation: String,
+    ) -> Result<Expansion, QueryError> {
+        self.expand_userset(object, relation, MAX_DEPTH, vec![])
+            .await
+    }
+
+    fn expand_userset<'b>(
+        &'b mut self,
+        object: Object,
+        relation: String,
+        depth: usize,
+        path: Vec<String>,
+    ) -> BoxedFuture<'b, Expansion> {
+        Box::pin(async move {
+            let userset = format!("{object}#{relation}");
+
+            if depth == 0 || path.contains(&userset) {
+                return Ok(Expansion::Truncated { userset });
+            }
+
+            let Some(rewrite) = self.rewrite(&object.namespace, &relation).await? else {
+                return Ok(Expansion::Userset {
+                    userset,
+                    rewrite: String::new(),
+                    children: vec![],
+                });
+            };
+
+            let mut path = path;
+            path.push(userset.clone());
+
+            let child = self
+                .expand_rewrite(rewrite.clone(), object, relation, depth, path)
+                .await?;
+
+            Ok(Expansion::Userset {
+                userset,
+                rewrite: rewrite.to_string(),
+                children: vec![child],
+            })
+        })
+    }
+
+    fn expand_rewrite<'b>(
+        &'b mut self,
+        rewrite: Rewrite,
+        object: Object,
+        relation: String,
+        depth: usize,
+        path: Vec<String>,
+    ) -> BoxedFuture<'b, Expansion> {
+        Box::pin(async move {
+            Ok(match rewrite {
+                Rewrite::This => {
+                    let mut users: Vec<String> = vec![];
+                    let mut children: Vec<Expansion> = vec![];
+
+                    for subject in self.direct(&object, &relation).await? {
+                        match subject {
+                            Subject::User(id) => users.push(id),
+                            Subject::Userset(object, relation) => children.push(
+                                self.expand_userset(object, relation, depth - 1, path.clone())
+                                    .await?,
+                            ),
+                            Subject::Object(_) => {}
+                        }
+                    }
+
+                    users.sort();
+
+                    Expansion::Direct { users, children }
+                }
+                Rewrite::Computed(computed) => {
+                    self.expand_userset(object, computed, depth - 1, path)
+                        .await?
+                }
+                Rewrite::TupleToUserset { tupleset, computed } => {
+                    let mut children: Vec<Expansion> = vec![];
+
+                    for subject in self.direct(&object, &tupleset).await? {
+                        if let Subject::Object(related) | Subject::Userset(related, _) = subject {
+                            children.push(
+                                self.expand_userset(
+                                    related,
+                                    computed.clone(),
+                                    depth - 1,
+                                    path.clone(),
+                                )
+                                .await?,
+                            );
+                        }
+                    }
+
+                    Expansion::Union { children }
+                }
+                Rewrite::Union(rewrites) => Expansion::Union {
+                    children: self
+                        .expand_all(rewrites, object, relation, depth, path)
+                        .await?,
+                },
+                Rewrite::Intersection(rewrites) => Expansion::Intersection {
+                    children: self
+                        .expand_all(rewrites, object, relation, depth, path)
+                        .await?,
+                },
+            })
+        })
+    }
+
+    async fn expand_all(
+        &mut self,
+        rewrites: Vec<Rewrite>,
+        object: Object,
+        relation: String,
+        depth: usize,
+        path: Vec<String>,
+    ) -> Result<Vec<Expansion>, QueryError> {
+        let mut children: Vec<Expansion> = vec![];
+
+        for rewrite in rewrites {
+            children.push(
+                self.expand_rewrite(
+                    rewrite,
+                    object.clone(),
+                    relation.clone(),
+                    depth,
+                    path.clone(),
+                )
+                .await?,
+            );
+        }
+
+        Ok(children)
+    }
+}
+
+/// Lists the objects of `namespace` on which `user_id` is a subject of `relation`, checking
+/// every object that has tuples in the namespace.
+pub async fn list_objects(
+    evaluator: &mut Evaluator<'_>,
+    namespace: &str,
+    relation: &str,
+    user_id: &str,
+) -> Result<BTreeSet<String>, QueryError> {
+    let candidates: BTreeSet<String> = tuples(
+        &evaluator.state.db,
+        evaluator.tenant_id,
+        namespace,
+        None,
+        None,
+    )
+    .await?
+    .into_iter()
+    .map(|tuple| tuple.object.object_id)
+    .collect();
+
+    let mut objects: BTreeSet<String> = BTreeSet::new();
+
+    for object_id in candidates {
+        let object = Object {
+            namespace: namespace.to_string(),
+            object_id,
+        };
+
+        if evaluator.check(&object, relation, user_id).await? {
+            objects.insert(object.object_id);
+        }
+    }
+
+    Ok(objects)
+}
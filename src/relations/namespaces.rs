@@ -0,0 +1,309 @@
+use std::{collections::BTreeMap, fmt};
+
+use serde::Serialize;
+
+/// How a relation's subjects are computed, as written in a namespace config.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Rewrite {
+    /// The subjects stored in tuples for the relation itself.
+    This,
+
+    /// The subjects of another relation of the same object, e.g. `editor`.
+    Computed(String),
+
+    /// The subjects of `computed` on every object referenced by the object's `tupleset`
+    /// relation, e.g. `parent->viewer`.
+    TupleToUserset { tupleset: String, computed: String },
+
+    /// Subjects of any of the rewrites, written with `|`.
+    Union(Vec<Rewrite>),
+
+    /// Subjects of all of the rewrites, written with `&`.
+    Intersection(Vec<Rewrite>),
+}
+
+impl fmt::Display for Rewrite {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let join = |f: &mut fmt::Formatter<'_>, children: &[Rewrite], operator: &str| {
+            write!(f, "(")?;
+
+            for (i, child) in children.iter().enumerate() {
+                if i > 0 {
+                    write!(f, " {operator} ")?;
+                }
+
+                write!(f, "{child}")?;
+            }
+
+            write!(f, ")")
+        };
+
+        match self {
+            Self::This => write!(f, "this"),
+            Self::Computed(relation) => write!(f, "{relation}"),
+            Self::TupleToUserset { tupleset, computed } => write!(f, "{tupleset}->{computed}"),
+            Self::Union(children) => join(f, children, "|"),
+            Self::Intersection(children) => join(f, children, "&"),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ParseError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl ParseError {
+    fn new(line: usize, message: impl Into<String>) -> Self {
+        Self {
+            line,
+            message: message.into(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Name(String),
+    Arrow,
+    Pipe,
+    Ampersand,
+    Open,
+    Close,
+}
+
+/// Checks whether `name` can name a namespace or a relation: 1 to 64 lowercase ASCII letters,
+/// digits or underscores, starting with a letter.
+pub fn is_valid_name(name: &str) -> bool {
+    name.len() <= 64
+        && name.starts_with(|c: char| c.is_ascii_lowercase())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+}
+
+fn tokenize(expression: &str, line: usize) -> Result<Vec<Token>, ParseError> {
+    let mut tokens: Vec<Token> = vec![];
+    let mut chars = expression.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {}
+            '|' => tokens.push(Token::Pipe),
+            '&' => tokens.push(Token::Ampersand),
+            '(' => tokens.push(Token::Open),
+            ')' => tokens.push(Token::Close),
+            '-' if chars.peek() == Some(&'>') => {
+                chars.next();
+                tokens.push(Token::Arrow);
+            }
+            c if c.is_ascii_alphanumeric() || c == '_' => {
+                let mut name = c.to_string();
+
+                while let Some(&next) = chars.peek() {
+                    if !(next.is_ascii_alphanumeric() || next == '_') {
+                        break;
+                    }
+
+                    name.push(next);
+                    chars.next();
+                }
+
+                tokens.push(Token::Name(name));
+            }
+            c => {
+                return Err(ParseError::new(
+                    line,
+                    format!("Unexpected character '{c}'."),
+                ))
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// A recursive descent parser over a relation's expression, where `&` binds tighter than `|`.
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+    line: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn union(&mut self) -> Result<Rewrite, ParseError> {
+        let mut children = vec![self.intersection()?];
+
+        while self.peek() == Some(&Token::Pipe) {
+            self.next();
+            children.push(self.intersection()?);
+        }
+
+        Ok(match children.len() {
+            1 => children.remove(0),
+            _ => Rewrite::Union(children),
+        })
+    }
+
+    fn intersection(&mut self) -> Result<Rewrite, ParseError> {
+        let mut children = vec![self.operand()?];
+
+        while self.peek() == Some(&Token::Ampersand) {
+            self.next();
+            children.push(self.operand()?);
+        }
+
+        Ok(match children.len() {
+            1 => children.remove(0),
+            _ => Rewrite::Intersection(children),
+        })
+    }
+
+    fn operand(&mut self) -> Result<Rewrite, ParseError> {
+        match self.next() {
+            Some(Token::Open) => {
+                let rewrite = self.union()?;
+
+                match self.next() {
+                    Some(Token::Close) => Ok(rewrite),
+                    _ => Err(ParseError::new(self.line, "Expected ')'.")),
+                }
+            }
+            Some(Token::Name(name)) if name == "this" => Ok(Rewrite::This),
+            Some(Token::Name(name)) => {
+                if self.peek() != Some(&Token::Arrow) {
+                    return Ok(Rewrite::Computed(name));
+                }
+
+                self.next();
+
+                match self.next() {
+                    Some(Token::Name(computed)) if computed != "this" => {
+                        Ok(Rewrite::TupleToUserset {
+                            tupleset: name,
+                            computed,
+                        })
+                    }
+                    _ => Err(ParseError::new(
+                        self.line,
+                        "Expected a relation name after '->'.",
+                    )),
+                }
+            }
+            _ => Err(ParseError::new(
+                self.line,
+                "Expected 'this', a relation name or '('.",
+            )),
+        }
+    }
+}
+
+/// Collects the relations of the namespace a rewrite refers to, which must be defined.
+fn references<'a>(rewrite: &'a Rewrite, found: &mut Vec<&'a str>) {
+    match rewrite {
+        Rewrite::This => {}
+        Rewrite::Computed(relation) => found.push(relation),
+        Rewrite::TupleToUserset { tupleset, .. } => found.push(tupleset),
+        Rewrite::Union(children) | Rewrite::Intersection(children) => {
+            for child in children {
+                references(child, found);
+            }
+        }
+    }
+}
+
+/// Parses a namespace config, made of one relation per line, such as:
+///
+/// ```text
+/// # Comments start with a hash.
+/// owner
+/// parent
+/// editor = this | owner
+/// viewer = (this | editor | parent->viewer) & member
+/// ```
+///
+/// A relation without an expression only has the subjects stored for it, like `= this`.
+/// Relations referenced by a computed userset or on the left of `->` must be defined in the
+/// same namespace, while the right of `->` refers to the namespace of the related object.
+pub fn parse(config: &str) -> Result<BTreeMap<String, Rewrite>, ParseError> {
+    let mut relations: BTreeMap<String, Rewrite> = BTreeMap::new();
+    let mut lines: BTreeMap<String, usize> = BTreeMap::new();
+
+    for (i, line) in config.lines().enumerate() {
+        let number = i + 1;
+        let line = line.split('#').next().unwrap_or_default().trim();
+
+        if line.is_empty() {
+            continue;
+        }
+
+        let (name, expression) = match line.split_once('=') {
+            Some((name, expression)) => (name.trim(), Some(expression)),
+            None => (line, None),
+        };
+
+        if !is_valid_name(name) || name == "this" {
+            return Err(ParseError::new(
+                number,
+                format!("'{name}' is not a valid relation name."),
+            ));
+        }
+
+        if relations.contains_key(name) {
+            return Err(ParseError::new(
+                number,
+                format!("The relation '{name}' is defined more than once."),
+            ));
+        }
+
+        let rewrite = match expression {
+            None => Rewrite::This,
+            Some(expression) => {
+                let mut parser = Parser {
+                    tokens: tokenize(expression, number)?,
+                    position: 0,
+                    line: number,
+                };
+                let rewrite = parser.union()?;
+
+                if parser.peek().is_some() {
+                    return Err(ParseError::new(number, "Unexpected tokens at the end."));
+                }
+
+                rewrite
+            }
+        };
+
+        relations.insert(name.to_string(), rewrite);
+        lines.insert(name.to_string(), number);
+    }
+
+    if relations.is_empty() {
+        return Err(ParseError::new(1, "The namespace defines no relations."));
+    }
+
+    for (name, rewrite) in &relations {
+        let mut found = vec![];
+        references(rewrite, &mut found);
+
+        if let Some(unknown) = found.iter().find(|r| !relations.contains_key(**r)) {
+            return Err(ParseError::new(
+                lines[name],
+                format!("The relation '{unknown}' is not defined."),
+            ));
+        }
+    }
+
+    Ok(relations)
+}
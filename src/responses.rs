@@ -94,6 +94,14 @@ pub enum CommonError {
         request_id: String,
         tenant_id: Option<String>,
     },
+    Unauthorized {
+        request_id: String,
+        tenant_id: Option<String>,
+    },
+    Forbidden {
+        request_id: String,
+        tenant_id: Option<String>,
+    },
 }
 
 impl response::IntoResponse for CommonError {
@@ -162,6 +170,30 @@ impl response::IntoResponse for CommonError {
                 request_id,
                 tenant_id,
             ),
+            Self::Unauthorized {
+                request_id,
+                tenant_id,
+            } => error_response(
+                StatusCode::UNAUTHORIZED,
+                "Unauthorized",
+                "This endpoint requires an access token.",
+                Some("headers.authorization"),
+                HashMap::new(),
+                request_id,
+                tenant_id,
+            ),
+            Self::Forbidden {
+                request_id,
+                tenant_id,
+            } => error_response(
+                StatusCode::FORBIDDEN,
+                "Forbidden",
+                "You don't have the permissions required to do this.",
+                None,
+                HashMap::new(),
+                request_id,
+                tenant_id,
+            ),
         };
 
         data.into_response()
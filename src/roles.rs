@@ -0,0 +1,311 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use scylla::{transport::errors::QueryError, Session};
+use serde::Serialize;
+
+use crate::{authz, state::State};
+
+/// Tenant-wide permission required to manage roles and assign them.
+pub const MANAGE_PERMISSION: &str = "roles.manage";
+
+/// A tenant-defined role, bundling permissions and the permissions of the roles it inherits
+/// from. Roles are referenced by name from `users.roles`, `groups.roles` and
+/// `organizations_by_user.roles`.
+#[derive(Clone, Serialize)]
+pub struct Role {
+    pub name: String,
+    pub description: Option<String>,
+
+    /// Granted permissions, plus denied ones prefixed with `-`.
+    pub permissions: HashSet<String>,
+    pub inherits: HashSet<String>,
+}
+
+type RoleRow = (
+    String,
+    Option<String>,
+    Option<HashSet<String>>,
+    Option<HashSet<String>>,
+);
+
+fn from_row((name, description, permissions, inherits): RoleRow) -> Role {
+    Role {
+        name,
+        description,
+        permissions: permissions.unwrap_or_default(),
+        inherits: inherits.unwrap_or_default(),
+    }
+}
+
+pub async fn get(db: &Session, tenant_id: &str, name: &str) -> Result<Option<Role>, QueryError> {
+    let result = db
+        .query_unpaged(
+            "SELECT role, description, permissions, inherits FROM roles WHERE tenant_id = ? AND role = ?",
+            (tenant_id, name),
+        )
+        .await?;
+
+    Ok(result
+        .maybe_first_row_typed::<RoleRow>()
+        .ok()
+        .flatten()
+        .map(from_row))
+}
+
+/// Returns all the roles of the tenant by name.
+pub async fn all(db: &Session, tenant_id: &str) -> Result<HashMap<String, Role>, QueryError> {
+    let result = db
+        .query_unpaged(
+            "SELECT role, description, permissions, inherits FROM roles WHERE tenant_id = ?",
+            (tenant_id,),
+        )
+        .await?;
+
+    Ok(result
+        .rows_typed_or_empty::<RoleRow>()
+        .filter_map(|row| row.ok())
+        .map(|row| {
+            let role = from_row(row);
+            (role.name.clone(), role)
+        })
+        .collect())
+}
+
+pub async fn save(state: &State, tenant_id: &str, role: &Role) -> Result<(), QueryError> {
+    let db = &state.db;
+    db.query_unpaged(
+        "INSERT INTO roles (tenant_id, role, description, permissions, inherits) VALUES (?, ?, ?, ?, ?)",
+        (
+            tenant_id,
+            &role.name,
+            &role.description,
+            &role.permissions,
+            &role.inherits,
+        ),
+    )
+    .await?;
+
+    authz::invalidate(state, tenant_id).await;
+
+    Ok(())
+}
+
+/// Deletes a role. Assignments and inheritances of the role are left in place and ignored until
+/// a role with the same name is created again.
+pub async fn delete(state: &State, tenant_id: &str, name: &str) -> Result<(), QueryError> {
+    let db = &state.db;
+    db.query_unpaged(
+        "DELETE FROM roles WHERE tenant_id = ? AND role = ?",
+        (tenant_id, name),
+    )
+    .await?;
+
+    authz::invalidate(state, tenant_id).await;
+
+    Ok(())
+}
+
+/// Returns the names of all the roles `name` inherits from, directly or through other roles.
+pub fn ancestors(roles: &HashMap<String, Role>, name: &str) -> HashSet<String> {
+    let mut found: HashSet<String> = HashSet::new();
+    let mut stack: Vec<&str> = vec![name];
+
+    while let Some(current) = stack.pop() {
+        if let Some(role) = roles.get(current) {
+            for parent in &role.inherits {
+                if found.insert(parent.clone()) {
+                    stack.push(parent);
+                }
+            }
+        }
+    }
+
+    found
+}
+
+/// Checks whether making `name` inherit from `inherits` would make it inherit from itself.
+pub fn creates_cycle(
+    roles: &HashMap<String, Role>,
+    name: &str,
+    inherits: &HashSet<String>,
+) -> bool {
+    inherits
+        .iter()
+        .any(|parent| parent == name || ancestors(roles, parent).contains(name))
+}
+
+/// Returns the permissions a role grants and denies, including the inherited ones. Unknown roles
+/// grant nothing.
+pub fn permissions(roles: &HashMap<String, Role>, name: &str) -> BTreeSet<String> {
+    let Some(role) = roles.get(name) else {
+        return BTreeSet::new();
+    };
+
+    let mut permissions: BTreeSet<String> = role.permissions.iter().cloned().collect();
+
+    for parent in ancestors(roles, name) {
+        if let Some(parent) = roles.get(&parent) {
+            permissions.extend(parent.permissions.iter().cloned());
+        }
+    }
+
+    permissions
+}
+
+/// Assigns a role to a user.
+pub async fn assign_to_user(
+    state: &State,
+    tenant_id: &str,
+    user_id: &str,
+    name: &str,
+) -> Result<(), QueryError> {
+    let db = &state.db;
+    db.query_unpaged(
+        "UPDATE users SET roles = roles + ? WHERE tenant_id = ? AND user_id = ?",
+        (HashSet::from([name]), tenant_id, user_id),
+    )
+    .await?;
+
+    authz::invalidate(state, tenant_id).await;
+
+    Ok(())
+}
+
+pub async fn unassign_from_user(
+    state: &State,
+    tenant_id: &str,
+    user_id: &str,
+    name: &str,
+) -> Result<(), QueryError> {
+    let db = &state.db;
+    db.query_unpaged(
+        "UPDATE users SET roles = roles - ? WHERE tenant_id = ? AND user_id = ?",
+        (HashSet::from([name]), tenant_id, user_id),
+    )
+    .await?;
+
+    authz::invalidate(state, tenant_id).await;
+
+    Ok(())
+}
+
+/// Returns the roles assigned directly to a user.
+pub async fn user_roles(
+    db: &Session,
+    tenant_id: &str,
+    user_id: &str,
+) -> Result<HashSet<String>, QueryError> {
+    let result = db
+        .query_unpaged(
+            "SELECT roles FROM users WHERE tenant_id = ? AND user_id = ?",
+            (tenant_id, user_id),
+        )
+        .await?;
+
+    Ok(
+        match result.maybe_first_row_typed::<(Option<HashSet<String>>,)>() {
+            Ok(Some((Some(roles),))) => roles,
+            _ => HashSet::new(),
+        },
+    )
+}
+
+/// Assigns a role to a group, whose row is keyed by its priority too.
+pub async fn assign_to_group(
+    state: &State,
+    tenant_id: &str,
+    group_id: &str,
+    priority: i16,
+    name: &str,
+) -> Result<(), QueryError> {
+    let db = &state.db;
+    db.query_unpaged(
+        "UPDATE groups SET roles = roles + ? WHERE tenant_id = ? AND group_id = ? AND priority = ?",
+        (HashSet::from([name]), tenant_id, group_id, priority),
+    )
+    .await?;
+
+    authz::invalidate(state, tenant_id).await;
+
+    Ok(())
+}
+
+pub async fn unassign_from_group(
+    state: &State,
+    tenant_id: &str,
+    group_id: &str,
+    priority: i16,
+    name: &str,
+) -> Result<(), QueryError> {
+    let db = &state.db;
+    db.query_unpaged(
+        "UPDATE groups SET roles = roles - ? WHERE tenant_id = ? AND group_id = ? AND priority = ?",
+        (HashSet::from([name]), tenant_id, group_id, priority),
+    )
+    .await?;
+
+    authz::invalidate(state, tenant_id).await;
+
+    Ok(())
+}
+
+/// Assigns a role to an organization membership, which only applies within the organization.
+pub async fn assign_to_membership(
+    state: &State,
+    tenant_id: &str,
+    organization_id: &str,
+    user_id: &str,
+    name: &str,
+) -> Result<(), QueryError> {
+    let db = &state.db;
+    db.query_unpaged(
+        "UPDATE organizations_by_user SET roles = roles + ? WHERE tenant_id = ? AND user_id = ? AND organization_id = ?",
+        (HashSet::from([name]), tenant_id, user_id, organization_id),
+    )
+    .await?;
+
+    authz::invalidate(state, tenant_id).await;
+
+    Ok(())
+}
+
+pub async fn unassign_from_membership(
+    state: &State,
+    tenant_id: &str,
+    organization_id: &str,
+    user_id: &str,
+    name: &str,
+) -> Result<(), QueryError> {
+    let db = &state.db;
+    db.query_unpaged(
+        "UPDATE organizations_by_user SET roles = roles - ? WHERE tenant_id = ? AND user_id = ? AND organization_id = ?",
+        (HashSet::from([name]), tenant_id, user_id, organization_id),
+    )
+    .await?;
+
+    authz::invalidate(state, tenant_id).await;
+
+    Ok(())
+}
+
+/// Returns the roles assigned to a user's membership of an organization.
+pub async fn membership_roles(
+    db: &Session,
+    tenant_id: &str,
+    organization_id: &str,
+    user_id: &str,
+) -> Result<HashSet<String>, QueryError> {
+    let result = db
+        .query_unpaged(
+            "SELECT roles FROM organizations_by_user WHERE tenant_id = ? AND user_id = ? AND organization_id = ?",
+            (tenant_id, user_id, organization_id),
+        )
+        .await?;
+
+    Ok(
+        match result.maybe_first_row_typed::<(Option<HashSet<String>>,)>() {
+            Ok(Some((Some(roles),))) => roles,
+            _ => HashSet::new(),
+        },
+    )
+}
@@ -1,19 +1,26 @@
-use super::requests::{RefreshTokenPayload, SignInPayload, SignUpPayload};
+use super::requests::{EmailRollbackPayload, RefreshTokenPayload, SignInPayload, SignUpPayload};
 use crate::{
     constants::BCRYPT_PASSWORD_COST,
+    emails,
     error_handlers::error_response,
+    hooks::{self, Outcome, Trigger, Verdict},
+    organizations, permissions,
+    policies::RequestContext,
     requests::Request,
     responses::{CommonError, Error, Response, ResponseMeta},
     routes::auth::responses::TokenResponse,
-    state::AppState,
-    tokens::{token, Flow, FlowToken, TokenType},
+    settings,
+    state::{self, AppState},
+    tokens::{self, token, Flow, FlowToken, TokenType},
     types::{RequestID, TenantID},
+    usage::{self, Metric},
+    usernames::{self, Availability},
     utils::{id::gen_id, text::trim},
 };
 use axum::{
     body::Body,
-    extract::{rejection::JsonRejection, State},
-    http::StatusCode,
+    extract::{rejection::JsonRejection, ConnectInfo, State},
+    http::{HeaderMap, StatusCode},
     response,
     response::IntoResponse,
     Extension, Json,
@@ -23,26 +30,69 @@ use chrono::{Duration, Utc};
 use jwt::{Header, SignWithKey, Token};
 use scylla::{
     batch::Batch,
+    frame::value::CqlTimestamp,
     query::Query,
     serialize::{row::SerializeRow, value::SerializeValue},
     transport::errors::QueryError,
     QueryResult, Session,
 };
-use serde_json::{json, Value};
-use std::collections::HashMap;
+use serde_json::{json, Map, Value};
+use std::{collections::HashMap, net::SocketAddr};
 use tracing::{event, Level};
 use validator::{ValidateEmail, ValidateLength};
-use zxcvbn::Score;
+
+/// Runs the tenant's hooks for a trigger, turning a denial into the response to return.
+async fn run_hooks(
+    state: &state::State,
+    tenant_id: &str,
+    trigger: Trigger,
+    event: Value,
+    request_id: &str,
+) -> Result<Outcome, response::Response<Body>> {
+    match hooks::run(state, tenant_id, trigger, event).await {
+        Ok(Verdict::Continue(outcome)) => Ok(outcome),
+        Ok(Verdict::Deny { hook_id, denial }) => {
+            let status = denial
+                .status
+                .and_then(|status| StatusCode::from_u16(status).ok())
+                .filter(|status| {
+                    status.is_client_error() || *status == StatusCode::SERVICE_UNAVAILABLE
+                })
+                .unwrap_or(StatusCode::FORBIDDEN);
+
+            Err(error_response(
+                status,
+                &trim(&denial.title, 100),
+                &trim(&denial.detail, 500),
+                None,
+                HashMap::from([("hook_id", json!(hook_id))]),
+                request_id.to_string(),
+                Some(tenant_id.to_string()),
+            )
+            .into_response())
+        }
+        Err(e) => {
+            event!(Level::ERROR, error = format!("{e}"));
+
+            Err(CommonError::InternalServerError {
+                request_id: request_id.to_string(),
+                tenant_id: Some(tenant_id.to_string()),
+            }
+            .into_response())
+        }
+    }
+}
 
 // TODO: Add rate limit.
 // TODO: Add risk-based security.
 // TODO: Add MFA.
-// TODO: Add rules checks.
 // TODO: Add activity logs.
 pub async fn sign_up(
     Extension(TenantID(tenant_id)): Extension<TenantID>,
     Extension(RequestID(request_id)): Extension<RequestID>,
     Extension(response_meta): Extension<ResponseMeta<'_>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
     State(state): State<AppState>,
     payload: Result<Json<Request<SignUpPayload>>, JsonRejection>,
 ) -> response::Response<Body> {
@@ -58,19 +108,49 @@ pub async fn sign_up(
         }
     };
 
+    let state = state.read().await;
+
+    let username_policy = match usernames::Policy::load(&state, &tenant_id).await {
+        Ok(p) => p,
+        Err(e) => {
+            event!(Level::ERROR, error = format!("{e}"));
+
+            return CommonError::InternalServerError {
+                request_id,
+                tenant_id: Some(tenant_id),
+            }
+            .into_response();
+        }
+    };
+
+    let email_policy = match emails::Policy::load(&state, &tenant_id).await {
+        Ok(p) => p,
+        Err(e) => {
+            event!(Level::ERROR, error = format!("{e}"));
+
+            return CommonError::InternalServerError {
+                request_id,
+                tenant_id: Some(tenant_id),
+            }
+            .into_response();
+        }
+    };
+
     let mut errors: Vec<Error> = vec![];
 
     if let Some(username) = &payload.username {
-        if !ValidateLength::validate_length(&username, Some(4), Some(32), None) {
+        for violation in username_policy.validate(username) {
+            let (message, detail, mut meta) =
+                usernames::violation_details(&username_policy, &violation);
+
+            meta.insert("input", json!(trim(username, 20)));
+
             errors.push(Error::new(
                 StatusCode::UNPROCESSABLE_ENTITY.as_u16(),
-                "Invalid Username",
-                "The username must be from 4 to 32 characters long.",
+                message,
+                &detail,
                 Some("body.data.username"),
-                HashMap::from([
-                    ("input", json!(trim(&username, 20))),
-                    ("length", json!(username.len())),
-                ]),
+                meta,
             ));
         }
     }
@@ -83,13 +163,107 @@ pub async fn sign_up(
             Some("body.data.email"),
             HashMap::from([("input", json!(trim(&payload.email, 20)))]),
         ));
+    } else if let Err(violation) = email_policy.check_domain(&payload.email) {
+        let (message, detail) = violation.details();
+
+        errors.push(Error::new(
+            StatusCode::UNPROCESSABLE_ENTITY.as_u16(),
+            message,
+            detail,
+            Some("body.data.email"),
+            HashMap::from([
+                ("input", json!(trim(&payload.email, 20))),
+                ("domain", json!(emails::domain(&payload.email))),
+            ]),
+        ));
     }
 
-    if !ValidateLength::validate_length(&payload.password, None, Some(32), None) {
+    let email = email_policy.normalize(&payload.email);
+
+    let invitation = match &payload.invitation_token {
+        Some(token) => {
+            match organizations::pending_invitation(&state.db, &state.hmac, &tenant_id, token).await
+            {
+                Ok(Some(invitation)) if invitation.email == email => Some(invitation),
+                Ok(_) => {
+                    errors.push(Error::new(
+                        StatusCode::UNPROCESSABLE_ENTITY.as_u16(),
+                        "Invalid Invitation",
+                        "The invitation is invalid, has expired, was revoked or was sent to another email.",
+                        Some("body.data.invitation_token"),
+                        HashMap::new(),
+                    ));
+
+                    None
+                }
+                Err(e) => {
+                    event!(Level::ERROR, error = format!("{e}"));
+
+                    return CommonError::InternalServerError {
+                        request_id,
+                        tenant_id: Some(tenant_id),
+                    }
+                    .into_response();
+                }
+            }
+        }
+        None => None,
+    };
+
+    // Invited users already proved they own the email, so they don't expire unverified.
+    let (is_verified, ttl) = if invitation.is_some() {
+        (true, 0)
+    } else {
+        (false, 172800)
+    };
+
+    let (password_min_length, password_max_length, password_min_score) = match tokio::try_join!(
+        settings::read(&state, &tenant_id, settings::PASSWORD_MIN_LENGTH),
+        settings::read(&state, &tenant_id, settings::PASSWORD_MAX_LENGTH),
+        settings::read(&state, &tenant_id, settings::PASSWORD_MIN_SCORE),
+    ) {
+        Ok(policy) => policy,
+        Err(e) => {
+            event!(Level::ERROR, error = format!("{e}"));
+
+            return CommonError::InternalServerError {
+                request_id,
+                tenant_id: Some(tenant_id),
+            }
+            .into_response();
+        }
+    };
+
+    if !ValidateLength::validate_length(
+        &payload.password,
+        Some(password_min_length as u64),
+        None,
+        None,
+    ) {
+        errors.push(Error::new(
+            StatusCode::UNPROCESSABLE_ENTITY.as_u16(),
+            "Password Too Short",
+            &format!("The password must be at least {password_min_length} characters in length."),
+            Some("body.data.password"),
+            HashMap::from([
+                ("input", json!(trim(&payload.password, 20))),
+                ("length", json!(payload.password.len())),
+            ]),
+        ));
+    }
+
+    if !ValidateLength::validate_length(
+        &payload.password,
+        None,
+        Some(password_max_length as u64),
+        None,
+    ) {
         errors.push(Error::new(
             StatusCode::UNPROCESSABLE_ENTITY.as_u16(),
             "Password Too Long",
-            "The password must not be more than 32 characters in length.",
+            &format!(
+                "The password must not be more than {password_max_length} characters in length."
+            ),
             Some("body.data.password"),
             HashMap::from([
                 ("input", json!(trim(&payload.password, 20))),
@@ -110,7 +284,7 @@ pub async fn sign_up(
 
     let password_strength = zxcvbn::zxcvbn(&payload.password, &user_inputs[..]);
 
-    if password_strength.score() <= Score::Two {
+    if u8::from(password_strength.score()) < password_min_score {
         errors.push(Error::new(
             StatusCode::UNPROCESSABLE_ENTITY.as_u16(),
             "Password Too Weak",
@@ -122,11 +296,12 @@ pub async fn sign_up(
                     "suggestions",
                     json!(password_strength
                         .feedback()
-                        .unwrap()
-                        .suggestions()
-                        .iter()
-                        .map(|suggestion| { suggestion.to_string() })
-                        .collect::<Vec<String>>()),
+                        .map(|feedback| feedback
+                            .suggestions()
+                            .iter()
+                            .map(|suggestion| { suggestion.to_string() })
+                            .collect::<Vec<String>>())
+                        .unwrap_or_default()),
                 ),
                 (
                     "score",
@@ -143,8 +318,6 @@ pub async fn sign_up(
         return (StatusCode::UNPROCESSABLE_ENTITY, Json(response)).into_response();
     }
 
-    let state = state.read().await;
-
     /// Checks if theres a row that satisfies `check(row)` in the query.
     async fn exists(
         db: &Session,
@@ -173,41 +346,57 @@ pub async fn sign_up(
     }
 
     if let Some(username) = &payload.username {
-        let username_exists = exists(
-            &state.db,
-            "SELECT user_id FROM users_by_username WHERE tenant_id = ? AND username = ?",
-            (tenant_id.clone(), &username),
-            request_id.clone(),
-            Some(tenant_id.clone()),
-        )
-        .await;
+        match usernames::availability(&state.db, &tenant_id, &username_policy, username, None).await
+        {
+            Err(e) => {
+                event!(Level::ERROR, error = format!("{e}"));
 
-        if let Err(err) = username_exists {
-            return err.into_response();
-        } else if let Ok(res) = username_exists {
-            if res {
+                return CommonError::InternalServerError {
+                    request_id,
+                    tenant_id: Some(tenant_id),
+                }
+                .into_response();
+            }
+            Ok(Availability::Taken) | Ok(Availability::Reserved) => {
                 return error_response(
                     StatusCode::CONFLICT,
                     "Username Already In Use",
                     "There's already a user with this username.",
                     Some("body.data.username"),
-                    HashMap::from([("input", json!(trim(&username, 20)))]),
+                    HashMap::from([("input", json!(trim(username, 20)))]),
                     request_id.clone(),
                     Some(tenant_id.clone()),
                 )
                 .into_response();
             }
+            Ok(Availability::Available) => {}
         }
     }
 
-    let email_exists = exists(
-        &state.db,
-        "SELECT email FROM users_by_email WHERE tenant_id = ? AND email = ? LIMIT 1",
-        (tenant_id.clone(), &payload.email),
-        request_id.clone(),
-        Some(tenant_id.clone()),
-    )
-    .await;
+    let user_id = gen_id(None);
+
+    // The email is claimed first, so a concurrent sign-up or email change can't link it too.
+    let email_exists = match emails::claim(&state.db, &tenant_id, &email, &user_id).await {
+        Ok(true) => {
+            exists(
+                &state.db,
+                "SELECT email FROM users_by_email WHERE tenant_id = ? AND email = ? LIMIT 1",
+                (tenant_id.clone(), &email),
+                request_id.clone(),
+                Some(tenant_id.clone()),
+            )
+            .await
+        }
+        Ok(false) => Ok(true),
+        Err(e) => {
+            event!(Level::ERROR, error = format!("{e}"));
+
+            Err(CommonError::InternalServerError {
+                request_id: request_id.clone(),
+                tenant_id: Some(tenant_id.clone()),
+            })
+        }
+    };
 
     if let Err(err) = email_exists {
         return err.into_response();
@@ -254,6 +443,84 @@ pub async fn sign_up(
         }
     }
 
+    // New users are usually active this month too, so its limit applies as well.
+    match usage::check(
+        &state.db,
+        &tenant_id,
+        &[Metric::SignUps, Metric::ActiveUsers, Metric::Users],
+    )
+    .await
+    {
+        Ok(exceeded) => {
+            if let Some(hard) = exceeded.iter().find(|exceeded| exceeded.is_hard) {
+                let detail = if hard.metric == Metric::Users {
+                    "The tenant reached its limit of users, so no more users can sign up."
+                } else {
+                    "The tenant reached its monthly limit, so no more users can sign up until next month."
+                };
+
+                return error_response(
+                    StatusCode::FORBIDDEN,
+                    "Quota Exceeded",
+                    detail,
+                    None,
+                    HashMap::from([("metric", json!(hard.metric)), ("limit", json!(hard.limit))]),
+                    request_id,
+                    Some(tenant_id),
+                )
+                .into_response();
+            }
+
+            for soft in exceeded {
+                event!(
+                    Level::WARN,
+                    tenant_id,
+                    metric = soft.metric.as_str(),
+                    limit = soft.limit,
+                    usage = soft.usage,
+                    "Soft limit exceeded."
+                );
+            }
+        }
+        Err(e) => {
+            event!(Level::ERROR, error = format!("{e}"));
+
+            return CommonError::InternalServerError {
+                request_id,
+                tenant_id: Some(tenant_id),
+            }
+            .into_response();
+        }
+    }
+
+    let request = RequestContext::new(addr.ip(), &headers, None);
+
+    let hook_event = hooks::event(
+        Trigger::PreSignUp,
+        &tenant_id,
+        &request,
+        json!({
+            "email": email,
+            "address": payload.email,
+            "username": payload.username,
+            "phone_number": payload.phone_number,
+            "organization_id": invitation.as_ref().map(|i| &i.organization_id),
+        }),
+    );
+
+    let metadata = match run_hooks(
+        &state,
+        &tenant_id,
+        Trigger::PreSignUp,
+        hook_event,
+        &request_id,
+    )
+    .await
+    {
+        Ok(outcome) => outcome.metadata,
+        Err(response) => return response,
+    };
+
     let password = bcrypt::hash(&payload.password, (&*BCRYPT_PASSWORD_COST).clone() as u32);
 
     if let Err(e) = password {
@@ -266,36 +533,68 @@ pub async fn sign_up(
         .into_response();
     }
 
-    let user_id = gen_id(None);
+    // Availability was only read, so the username's key is claimed before anything is written.
+    if let Some(username) = &payload.username {
+        let key = username_policy.key(username);
+
+        match usernames::claim(&state.db, &tenant_id, &key, &user_id, username, ttl).await {
+            Ok(true) => {}
+            Ok(false) => {
+                return error_response(
+                    StatusCode::CONFLICT,
+                    "Username Already In Use",
+                    "There's already a user with this username.",
+                    Some("body.data.username"),
+                    HashMap::from([("input", json!(trim(username, 20)))]),
+                    request_id,
+                    Some(tenant_id),
+                )
+                .into_response();
+            }
+            Err(e) => {
+                event!(Level::ERROR, error = format!("{e}"));
+
+                return CommonError::InternalServerError {
+                    request_id,
+                    tenant_id: Some(tenant_id),
+                }
+                .into_response();
+            }
+        }
+    }
+
+    let now = CqlTimestamp(Utc::now().timestamp_millis());
 
     let mut execution_results: Vec<Result<QueryResult, QueryError>> = vec![
         state.db.query_unpaged(
-            "
+            format!("
                 INSERT INTO users (
                     tenant_id, user_id, username, is_verified, is_locked, is_suspended, roles, login_count, metadata, permissions, password, created_at
                 ) VALUES (
-                    ?, ?, ?, false, false, false, {}, 0, {}, {}, ?, toTimestamp(now())
-                ) USING TTL 172800
-            ",
+                    ?, ?, ?, ?, false, false, {{}}, 0, ?, {{}}, ?, toTimestamp(now())
+                ) USING TTL {ttl}
+            "),
             (
                 &tenant_id,
                 &user_id,
                 &payload.username,
+                is_verified,
+                &metadata,
                 password.unwrap(),
             )
         ).await,
         state.db.query_unpaged(
-            "
-                INSERT INTO emails (
-                    tenant_id, user_id, email, is_main, is_work, is_verified, created_at
-                ) VALUES (
-                    ?, ?, ?, true, false, false, toTimestamp(now())
-                ) USING TTL 172800
-            ",
+            emails::insert_statement(ttl),
             (
                 &tenant_id,
                 &user_id,
+                &email,
                 &payload.email,
+                true,
+                false,
+                is_verified,
+                now,
+                is_verified.then_some(now),
             )
         ).await
     ];
@@ -305,13 +604,15 @@ pub async fn sign_up(
             state
                 .db
                 .query_unpaged(
-                    "
+                    format!(
+                        "
                         INSERT INTO phone_numbers (
                             tenant_id, user_id, number, is_main, is_work, is_verified, created_at
                         ) VALUES (
                             ?, ?, ?, true, false, false, toTimestamp(now())
-                        ) USING TTL 172800
-                    ",
+                        ) USING TTL {ttl}
+                    "
+                    ),
                     (&tenant_id, &user_id, phone_number),
                 )
                 .await,
@@ -332,6 +633,104 @@ pub async fn sign_up(
         .into_response();
     }
 
+    // Unverified users expire, so they only count toward the users limit once verified.
+    usage::record_sign_up(&tenant_id, is_verified);
+
+    let hook_event = hooks::event(
+        Trigger::PostSignUp,
+        &tenant_id,
+        &request,
+        json!({
+            "user_id": user_id,
+            "email": email,
+            "username": payload.username,
+            "phone_number": payload.phone_number,
+            "is_verified": is_verified,
+        }),
+    );
+
+    // Post sign-up hooks can't deny, so only their metadata matters.
+    if let Ok(outcome) = run_hooks(
+        &state,
+        &tenant_id,
+        Trigger::PostSignUp,
+        hook_event,
+        &request_id,
+    )
+    .await
+    {
+        if !outcome.metadata.is_empty() {
+            let result = state
+                .db
+                .query_unpaged(
+                    format!("UPDATE users USING TTL {ttl} SET metadata = metadata + ? WHERE tenant_id = ? AND user_id = ?"),
+                    (&outcome.metadata, &tenant_id, &user_id),
+                )
+                .await;
+
+            if let Err(e) = result {
+                event!(Level::ERROR, error = format!("{e}"));
+            }
+        }
+    }
+
+    if let Some(invitation) = invitation {
+        let result = organizations::add_member(
+            &state,
+            &tenant_id,
+            &invitation.organization_id,
+            &user_id,
+            invitation.role,
+        )
+        .await;
+
+        let result = match result {
+            Ok(()) => {
+                organizations::delete_invitation(
+                    &state.db,
+                    &tenant_id,
+                    &invitation.organization_id,
+                    &invitation.invitation_id,
+                )
+                .await
+            }
+            Err(e) => Err(e),
+        };
+
+        // The invitation verified the email, so the user also joins the domain's organization.
+        let result = match result {
+            Ok(()) => organizations::auto_join(&state, &tenant_id, &user_id, &email)
+                .await
+                .map(|_| ()),
+            Err(e) => Err(e),
+        };
+
+        if let Err(e) = result {
+            event!(Level::ERROR, error = format!("{e}"));
+
+            return CommonError::InternalServerError {
+                request_id,
+                tenant_id: Some(tenant_id),
+            }
+            .into_response();
+        }
+
+        return (
+            StatusCode::CREATED,
+            Response::new(
+                Some(HashMap::from([
+                    ("user_id", json!(user_id)),
+                    ("organization_id", json!(invitation.organization_id)),
+                    ("role", json!(invitation.role)),
+                ])),
+                None,
+                Some(response_meta),
+                Some(HashMap::from([("sign_in", "/auth/sign-in")])),
+            ),
+        )
+            .into_response();
+    }
+
     let claims = FlowToken {
         token_type: TokenType::Flow,
         flow: Flow::SignUpEmailVerification,
@@ -377,6 +776,8 @@ pub async fn sign_in(
     Extension(TenantID(tenant_id)): Extension<TenantID>,
     Extension(RequestID(request_id)): Extension<RequestID>,
     Extension(response_meta): Extension<HashMap<&str, Value>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
     State(state): State<AppState>,
     payload: Result<Json<Request<SignInPayload>>, JsonRejection>,
 ) -> response::Response<Body> {
@@ -437,11 +838,26 @@ pub async fn sign_in(
     let mut user_id: Option<String> = None;
 
     if payload.login.validate_email() {
+        let email_policy = match emails::Policy::load(&state, &tenant_id).await {
+            Ok(p) => p,
+            Err(e) => {
+                event!(Level::ERROR, error = format!("{e}"));
+
+                return CommonError::InternalServerError {
+                    request_id,
+                    tenant_id: Some(tenant_id),
+                }
+                .into_response();
+            }
+        };
+
+        let email = email_policy.normalize(&payload.login);
+
         user_id = match query_user(
             &state.db,
             &tenant_id,
             &request_id,
-            &payload.login,
+            &email,
             "users_by_email",
             "email",
         )
@@ -449,6 +865,23 @@ pub async fn sign_in(
         {
             Err(e) => return e.into_response(),
             Ok(id) => id,
+        };
+
+        // Emails stored before normalization was introduced are kept as provided.
+        if user_id.is_none() && email != payload.login {
+            user_id = match query_user(
+                &state.db,
+                &tenant_id,
+                &request_id,
+                &payload.login,
+                "users_by_email",
+                "email",
+            )
+            .await
+            {
+                Err(e) => return e.into_response(),
+                Ok(id) => id,
+            }
         }
     }
 
@@ -468,6 +901,36 @@ pub async fn sign_in(
         }
     }
 
+    // Usernames are unique by their policy key, so "Alice" can sign in as "alice".
+    if user_id.is_none() {
+        let username_policy = match usernames::Policy::load(&state, &tenant_id).await {
+            Ok(p) => p,
+            Err(e) => {
+                event!(Level::ERROR, error = format!("{e}"));
+
+                return CommonError::InternalServerError {
+                    request_id,
+                    tenant_id: Some(tenant_id),
+                }
+                .into_response();
+            }
+        };
+
+        user_id = match query_user(
+            &state.db,
+            &tenant_id,
+            &request_id,
+            username_policy.key(&payload.login),
+            "username_keys",
+            "key",
+        )
+        .await
+        {
+            Err(e) => return e.into_response(),
+            Ok(id) => id,
+        }
+    }
+
     if user_id == None {
         user_id = match query_user(
             &state.db,
@@ -552,28 +1015,137 @@ pub async fn sign_in(
                 },
             }
 
+            match organizations::required_sso(&state.db, &tenant_id, &user_id).await {
+                Ok(None) => {}
+                Ok(Some(organization_id)) => {
+                    return error_response(
+                        StatusCode::FORBIDDEN,
+                        "SSO Required",
+                        "Your organization requires you to sign in with its single sign-on.",
+                        Some("body.data.login"),
+                        HashMap::from([("organization_id", json!(organization_id))]),
+                        request_id,
+                        Some(tenant_id),
+                    )
+                    .into_response()
+                }
+                Err(e) => {
+                    event!(Level::ERROR, error = format!("{e}"));
+
+                    return CommonError::InternalServerError {
+                        request_id,
+                        tenant_id: Some(tenant_id),
+                    }
+                    .into_response();
+                }
+            }
+
+            let (mfa_required, access_token_expires_in, refresh_token_expires_in) = match tokio::try_join!(
+                settings::read(&state, &tenant_id, settings::MFA_REQUIRED),
+                settings::read(&state, &tenant_id, settings::ACCESS_TOKEN_LIFETIME),
+                settings::read(&state, &tenant_id, settings::REFRESH_TOKEN_LIFETIME),
+            ) {
+                Ok(s) => s,
+                Err(e) => {
+                    event!(Level::ERROR, error = format!("{e}"));
+
+                    return CommonError::InternalServerError {
+                        request_id,
+                        tenant_id: Some(tenant_id),
+                    }
+                    .into_response();
+                }
+            };
+
+            // There's no second factor to complete yet, so password sign-ins are refused.
+            if mfa_required {
+                return error_response(
+                    StatusCode::FORBIDDEN,
+                    "MFA Required",
+                    "The tenant requires a second factor to sign in, which this sign-in method doesn't support.",
+                    Some("body.data.login"),
+                    HashMap::new(),
+                    request_id,
+                    Some(tenant_id),
+                )
+                .into_response();
+            }
+
+            let request = RequestContext::new(addr.ip(), &headers, None);
+
+            let hook_event = hooks::event(
+                Trigger::PreSignIn,
+                &tenant_id,
+                &request,
+                json!({ "user_id": user_id, "login": payload.login }),
+            );
+
+            let sign_in_outcome = match run_hooks(
+                &state,
+                &tenant_id,
+                Trigger::PreSignIn,
+                hook_event,
+                &request_id,
+            )
+            .await
+            {
+                Ok(outcome) => outcome,
+                Err(response) => return response,
+            };
+
+            let mut scopes = vec![permissions::WILDCARD.to_string()];
+
+            let hook_event = hooks::event(
+                Trigger::PreTokenIssue,
+                &tenant_id,
+                &request,
+                json!({ "user_id": user_id, "grant": "sign_in", "scopes": scopes }),
+            );
+
+            let issue_outcome = match run_hooks(
+                &state,
+                &tenant_id,
+                Trigger::PreTokenIssue,
+                hook_event,
+                &request_id,
+            )
+            .await
+            {
+                Ok(outcome) => outcome,
+                Err(response) => return response,
+            };
+
+            let mut metadata = sign_in_outcome.metadata;
+            metadata.extend(issue_outcome.metadata);
+            scopes.extend(issue_outcome.scopes);
+
+            let claims = issue_outcome.claims;
+            let stored_claims =
+                (!claims.is_empty()).then(|| Value::Object(claims.clone()).to_string());
+
             let access_token = token(None);
             let refresh_token = token(None);
 
             let mut batch = Batch::default();
 
-            // TODO: Make the expiry times configurable by tenant.
-
-            let access_token_expires_in = 3600;
-            let refresh_token_expires_in = 2628288;
-
-            batch.append_statement(format!("INSERT INTO api_tokens (tenant_id, user_id, api_token, is_refresh, scopes, created_at) VALUES (?, ?, ?, false, {{'all'}}, toTimestamp(now())) USING TTL {access_token_expires_in}").as_str());
-            batch.append_statement(format!("INSERT INTO api_tokens (tenant_id, user_id, api_token, is_refresh, scopes, created_at) VALUES (?, ?, ?, true, {{'all'}}, toTimestamp(now())) USING TTL {refresh_token_expires_in}").as_str());
-            batch.append_statement("UPDATE users SET last_login = toTimestamp(now()), login_count = ? WHERE tenant_id = ? AND user_id = ?");
+            batch.append_statement(format!("INSERT INTO api_tokens (tenant_id, user_id, api_token, is_refresh, scopes, claims, created_at) VALUES (?, ?, ?, false, ?, ?, toTimestamp(now())) USING TTL {access_token_expires_in}").as_str());
+            batch.append_statement(format!("INSERT INTO api_tokens (tenant_id, user_id, api_token, is_refresh, scopes, claims, created_at) VALUES (?, ?, ?, true, ?, ?, toTimestamp(now())) USING TTL {refresh_token_expires_in}").as_str());
+            batch.append_statement("UPDATE users SET last_login = toTimestamp(now()), login_count = ?, metadata = metadata + ? WHERE tenant_id = ? AND user_id = ?");
 
             let batch_result = state
                 .db
                 .batch(
                     &batch,
                     (
-                        (&tenant_id, &user_id, &access_token),
-                        (&tenant_id, &user_id, &refresh_token),
-                        (login_count + 1, &tenant_id, &user_id),
+                        (&tenant_id, &user_id, &access_token, &scopes, &stored_claims),
+                        (
+                            &tenant_id,
+                            &user_id,
+                            &refresh_token,
+                            &scopes,
+                            &stored_claims,
+                        ),
+                        (login_count + 1, &metadata, &tenant_id, &user_id),
                     ),
                 )
                 .await;
@@ -589,6 +1161,8 @@ pub async fn sign_in(
                     .into_response();
                 }
                 Ok(_) => {
+                    usage::record_issuance(&state, &tenant_id, &user_id).await;
+
                     return Json(Response::new(
                         Some(TokenResponse {
                             user_id,
@@ -596,7 +1170,8 @@ pub async fn sign_in(
                             refresh_token: URL_SAFE_NO_PAD.encode(refresh_token),
                             access_token_expires_in,
                             refresh_token_expires_in,
-                            scopes: vec!["all".to_string()],
+                            scopes,
+                            claims,
                             token_type: "Bearer".to_string(),
                         }),
                         None,
@@ -606,7 +1181,7 @@ pub async fn sign_in(
                             ("token", "/auth/token"),
                         ])),
                     ))
-                    .into_response()
+                    .into_response();
                 }
             }
         }
@@ -617,6 +1192,8 @@ pub async fn token_refresh(
     Extension(TenantID(tenant_id)): Extension<TenantID>,
     Extension(RequestID(request_id)): Extension<RequestID>,
     Extension(response_meta): Extension<HashMap<&str, Value>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
     State(state): State<AppState>,
     payload: Result<Json<Request<RefreshTokenPayload>>, JsonRejection>,
 ) -> response::Response<Body> {
@@ -667,7 +1244,7 @@ pub async fn token_refresh(
         .db
         .query_unpaged(
             "
-            SELECT user_id, scopes, device_id, client_id, TTL(created_at) FROM api_tokens
+            SELECT user_id, scopes, device_id, client_id, TTL(created_at), claims FROM api_tokens
             WHERE tenant_id = ?
                 AND api_token = ?
                 AND is_refresh = true
@@ -688,24 +1265,83 @@ pub async fn token_refresh(
         }
     };
 
-    let (user_id, scopes, device_id, client_id, refresh_token_expires_in) =
-        match row.first_row_typed::<(String, Vec<String>, Option<String>, Option<String>, i32)>() {
-            Ok(r) => r,
+    let (user_id, mut scopes, device_id, client_id, refresh_token_expires_in, claims) = match row
+        .first_row_typed::<(
+            String,
+            Vec<String>,
+            Option<String>,
+            Option<String>,
+            i32,
+            Option<String>,
+        )>() {
+        Ok(r) => r,
+        Err(e) => {
+            event!(Level::ERROR, error = format!("{e}"));
+            return invalid_token_response;
+        }
+    };
+
+    let mut claims: Map<String, Value> = claims
+        .and_then(|claims| serde_json::from_str(&claims).ok())
+        .unwrap_or_default();
+
+    let hook_event = hooks::event(
+        Trigger::PreTokenIssue,
+        &tenant_id,
+        &RequestContext::new(addr.ip(), &headers, device_id.clone()),
+        json!({
+            "user_id": user_id,
+            "grant": "refresh_token",
+            "scopes": scopes,
+            "claims": claims,
+        }),
+    );
+
+    let outcome = match run_hooks(
+        &state,
+        &tenant_id,
+        Trigger::PreTokenIssue,
+        hook_event,
+        &request_id,
+    )
+    .await
+    {
+        Ok(outcome) => outcome,
+        Err(response) => return response,
+    };
+
+    for scope in outcome.scopes {
+        if !scopes.contains(&scope) {
+            scopes.push(scope);
+        }
+    }
+
+    claims.extend(outcome.claims);
+
+    let stored_claims = (!claims.is_empty()).then(|| Value::Object(claims.clone()).to_string());
+
+    let access_token = token(None);
+    let refresh_token = token(None);
+
+    let access_token_expires_in =
+        match settings::read(&state, &tenant_id, settings::ACCESS_TOKEN_LIFETIME).await {
+            Ok(lifetime) => lifetime,
             Err(e) => {
                 event!(Level::ERROR, error = format!("{e}"));
-                return invalid_token_response;
+
+                return CommonError::InternalServerError {
+                    request_id,
+                    tenant_id: Some(tenant_id),
+                }
+                .into_response();
             }
         };
 
-    let access_token = token(None);
-    let refresh_token = token(None);
-    let access_token_expires_in = 3600;
-
     let mut batch = Batch::default();
 
     batch.append_statement("DELETE FROM api_tokens WHERE tenant_id = ? AND api_token = ?");
-    batch.append_statement(format!("INSERT INTO api_tokens (tenant_id, user_id, api_token, is_refresh, scopes, device_id, client_id, created_at) VALUES (?, ?, ?, false, ?, ?, ?, toTimestamp(now())) USING TTL {access_token_expires_in}").as_str());
-    batch.append_statement(format!("INSERT INTO api_tokens (tenant_id, user_id, api_token, is_refresh, scopes, device_id, client_id, created_at) VALUES (?, ?, ?, true, ?, ?, ?, toTimestamp(now())) USING TTL {refresh_token_expires_in}").as_str());
+    batch.append_statement(format!("INSERT INTO api_tokens (tenant_id, user_id, api_token, is_refresh, scopes, claims, device_id, client_id, created_at) VALUES (?, ?, ?, false, ?, ?, ?, ?, toTimestamp(now())) USING TTL {access_token_expires_in}").as_str());
+    batch.append_statement(format!("INSERT INTO api_tokens (tenant_id, user_id, api_token, is_refresh, scopes, claims, device_id, client_id, created_at) VALUES (?, ?, ?, true, ?, ?, ?, ?, toTimestamp(now())) USING TTL {refresh_token_expires_in}").as_str());
 
     let inserts_result = state
         .db
@@ -718,6 +1354,7 @@ pub async fn token_refresh(
                     &user_id,
                     &access_token,
                     &scopes,
+                    &stored_claims,
                     &device_id,
                     &client_id,
                 ),
@@ -726,6 +1363,7 @@ pub async fn token_refresh(
                     &user_id,
                     &refresh_token,
                     &scopes,
+                    &stored_claims,
                     &device_id,
                     &client_id,
                 ),
@@ -742,6 +1380,8 @@ pub async fn token_refresh(
         .into_response();
     }
 
+    usage::record_issuance(&state, &tenant_id, &user_id).await;
+
     (
         StatusCode::OK,
         Response::new(
@@ -752,6 +1392,7 @@ pub async fn token_refresh(
                 access_token_expires_in,
                 refresh_token_expires_in: refresh_token_expires_in as u64,
                 scopes,
+                claims,
                 token_type: "Bearer".to_string(),
             }),
             None,
@@ -761,3 +1402,202 @@ pub async fn token_refresh(
     )
         .into_response()
 }
+
+/// Rolls back a main email change with the token sent to the previous address. Since the change
+/// may not have been made by the account owner, every token of the user is revoked.
+pub async fn email_rollback(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(response_meta): Extension<ResponseMeta<'_>>,
+    State(state): State<AppState>,
+    payload: Result<Json<Request<EmailRollbackPayload>>, JsonRejection>,
+) -> response::Response<Body> {
+    let Json(Request { data: payload, .. }) = match payload {
+        Ok(p) => p,
+        Err(err) => {
+            return CommonError::JsonRejection {
+                err,
+                request_id,
+                tenant_id: Some(tenant_id),
+            }
+            .into_response()
+        }
+    };
+
+    let invalid_token_response = error_response(
+        StatusCode::BAD_REQUEST,
+        "Invalid Rollback Token",
+        "The rollback token is not a valid token. Check that the rollback window hasn't passed.",
+        Some("body.data.rollback_token"),
+        HashMap::from([("input", json!(trim(&payload.rollback_token, 20)))]),
+        request_id.clone(),
+        Some(tenant_id.clone()),
+    )
+    .into_response();
+
+    let rollback_token = match URL_SAFE_NO_PAD.decode(&payload.rollback_token) {
+        Ok(b) if b.len() == 64 => b,
+        _ => return invalid_token_response,
+    };
+
+    let state = state.read().await;
+
+    let rollback = match state
+        .db
+        .query_unpaged(
+            "SELECT user_id, old_email, new_email FROM email_rollbacks WHERE tenant_id = ? AND rollback_token = ?",
+            (&tenant_id, &rollback_token),
+        )
+        .await
+    {
+        Ok(r) => r.maybe_first_row_typed::<(String, String, String)>(),
+        Err(e) => {
+            event!(Level::ERROR, error = format!("{e}"));
+            return CommonError::InternalServerError {
+                request_id,
+                tenant_id: Some(tenant_id),
+            }
+            .into_response();
+        }
+    };
+
+    let (user_id, old_email, new_email) = match rollback {
+        Ok(Some(r)) => r,
+        _ => return invalid_token_response,
+    };
+
+    let rows = match emails::list(&state.db, &tenant_id, &user_id).await {
+        Ok(rows) => emails::rollback(rows, &old_email, &new_email),
+        Err(e) => {
+            event!(Level::ERROR, error = format!("{e}"));
+            return CommonError::InternalServerError {
+                request_id,
+                tenant_id: Some(tenant_id),
+            }
+            .into_response();
+        }
+    };
+
+    let Some(rows) = rows else {
+        return invalid_token_response;
+    };
+
+    let tokens = match state
+        .db
+        .query_unpaged(
+            "SELECT api_token FROM api_tokens_by_user WHERE tenant_id = ? AND user_id = ?",
+            (&tenant_id, &user_id),
+        )
+        .await
+    {
+        Ok(r) => r
+            .rows_typed_or_empty::<(Vec<u8>,)>()
+            .filter_map(|row| row.ok())
+            .map(|(api_token,)| api_token)
+            .collect::<Vec<Vec<u8>>>(),
+        Err(e) => {
+            event!(Level::ERROR, error = format!("{e}"));
+            return CommonError::InternalServerError {
+                request_id,
+                tenant_id: Some(tenant_id),
+            }
+            .into_response();
+        }
+    };
+
+    let mut batch = Batch::default();
+
+    for _ in &rows {
+        batch.append_statement(emails::insert_statement(0).as_str());
+    }
+
+    let emails_result = state
+        .db
+        .batch(
+            &batch,
+            rows.iter()
+                .map(|row| {
+                    (
+                        &tenant_id,
+                        &user_id,
+                        &row.email,
+                        &row.address,
+                        row.is_main,
+                        row.is_work,
+                        row.is_verified,
+                        row.created_at,
+                        row.verified_at,
+                    )
+                })
+                .collect::<Vec<_>>(),
+        )
+        .await;
+
+    if let Err(e) = emails_result {
+        event!(Level::ERROR, error = format!("{e}"));
+        return CommonError::InternalServerError {
+            request_id,
+            tenant_id: Some(tenant_id),
+        }
+        .into_response();
+    }
+
+    let mut batch = Batch::default();
+
+    batch.append_statement("DELETE FROM emails WHERE tenant_id = ? AND user_id = ? AND email = ?");
+    batch
+        .append_statement("DELETE FROM email_rollbacks WHERE tenant_id = ? AND rollback_token = ?");
+
+    let batch_result = state
+        .db
+        .batch(
+            &batch,
+            (
+                (&tenant_id, &user_id, &new_email),
+                (&tenant_id, &rollback_token),
+            ),
+        )
+        .await;
+
+    if let Err(e) = batch_result {
+        event!(Level::ERROR, error = format!("{e}"));
+        return CommonError::InternalServerError {
+            request_id,
+            tenant_id: Some(tenant_id),
+        }
+        .into_response();
+    }
+
+    for api_token in tokens {
+        if let Err(e) = state
+            .db
+            .query_unpaged(
+                "DELETE FROM api_tokens WHERE tenant_id = ? AND api_token = ?",
+                (&tenant_id, &api_token),
+            )
+            .await
+        {
+            event!(Level::ERROR, error = format!("{e}"));
+            continue;
+        }
+
+        tokens::invalidate(&state, &tenant_id, &api_token).await;
+    }
+
+    (
+        StatusCode::OK,
+        Response::new(
+            Some(HashMap::from([
+                ("user_id", json!(user_id)),
+                (
+                    "email",
+                    json!(rows.iter().find(|row| row.is_main).map(|row| &row.address)),
+                ),
+            ])),
+            None,
+            Some(response_meta),
+            Some(HashMap::from([("sign_in", "/auth/sign-in")])),
+        ),
+    )
+        .into_response()
+}
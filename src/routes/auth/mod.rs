@@ -10,4 +10,5 @@ pub fn router() -> Router<AppState> {
         .route("/sign-up", post(handlers::sign_up))
         .route("/sign-in", post(handlers::sign_in))
         .route("/token", post(handlers::token_refresh))
+        .route("/email/rollback", post(handlers::email_rollback))
 }
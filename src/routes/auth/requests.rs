@@ -6,6 +6,10 @@ pub struct SignUpPayload {
     pub phone_number: Option<String>,
     pub username: Option<String>,
     pub password: String,
+
+    /// An organization invitation sent to `email`, which skips the email verification and adds
+    /// the user to the organization.
+    pub invitation_token: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -16,5 +20,10 @@ pub struct SignInPayload {
 
 #[derive(Debug, Deserialize)]
 pub struct RefreshTokenPayload {
-    pub refresh_token: String
+    pub refresh_token: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EmailRollbackPayload {
+    pub rollback_token: String,
 }
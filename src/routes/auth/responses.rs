@@ -1,4 +1,5 @@
 use serde::Serialize;
+use serde_json::{Map, Value};
 
 #[derive(Serialize)]
 pub struct TokenResponse {
@@ -8,5 +9,9 @@ pub struct TokenResponse {
     pub access_token_expires_in: u64,
     pub refresh_token_expires_in: u64,
     pub scopes: Vec<String>,
+
+    /// Custom claims added by the tenant's hooks.
+    #[serde(skip_serializing_if = "Map::is_empty")]
+    pub claims: Map<String, Value>,
     pub token_type: String,
 }
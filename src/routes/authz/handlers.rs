@@ -0,0 +1,275 @@
+use super::{
+    requests::{BatchCheckPayload, CheckPayload},
+    responses::CheckResponse,
+};
+use crate::{
+    auth::Auth,
+    authz::{self, Resource, CHECK_PERMISSION, MAX_BATCH_SIZE},
+    error_handlers::{self, internal_error},
+    permissions,
+    policies::RequestContext,
+    requests::Request,
+    responses::{CommonError, Error, Response, ResponseMeta},
+    state::{self, AppState},
+    types::{RequestID, TenantID},
+    utils::text::trim,
+};
+use axum::{
+    body::Body,
+    extract::{rejection::JsonRejection, ConnectInfo, State},
+    http::{HeaderMap, StatusCode},
+    response::{self, IntoResponse},
+    Extension, Json,
+};
+use serde_json::{json, Value};
+use std::{collections::HashMap, net::SocketAddr};
+
+/// Validates the action and resource of a check, reporting errors under `location`.
+fn validate(action: &str, resource: Option<&str>, location: &str) -> Vec<Error> {
+    let mut errors: Vec<Error> = vec![];
+
+    if !permissions::is_concrete(action) {
+        errors.push(Error::new(
+            StatusCode::UNPROCESSABLE_ENTITY.as_u16(),
+            "Invalid Action",
+            "The action must be a permission name without wildcards, like billing.invoices.read.",
+            Some(&format!("{location}.action")),
+            HashMap::from([("input", json!(trim(action, 20)))]),
+        ));
+    }
+
+    if Resource::parse(resource).is_none() {
+        errors.push(Error::new(
+            StatusCode::UNPROCESSABLE_ENTITY.as_u16(),
+            "Invalid Resource",
+            "The resource must be omitted, or reference an organization as organization:{organization_id} or a user as user:{user_id}.",
+            Some(&format!("{location}.resource")),
+            HashMap::from([("input", json!(resource.map(|r| trim(r, 20))))]),
+        ));
+    }
+
+    errors
+}
+
+/// Checks that the user can check what `subjects` can do. Anyone can check themselves, while
+/// checking others requires the `authz.check` permission.
+async fn authorize<'a>(
+    state: &state::State,
+    tenant_id: &str,
+    user_id: &str,
+    scopes: &[String],
+    mut subjects: impl Iterator<Item = &'a str>,
+    request_id: &str,
+) -> Result<(), response::Response<Body>> {
+    if subjects.all(|subject| subject == user_id) {
+        return Ok(());
+    }
+
+    error_handlers::authorize(
+        state,
+        tenant_id,
+        Some(user_id.to_string()),
+        scopes,
+        CHECK_PERMISSION,
+        request_id,
+    )
+    .await
+    .map(|_| ())
+}
+
+/// Decides whether a user can perform an action, optionally on a resource. With `debug` set,
+/// the decision comes with the rules that led to it.
+#[allow(clippy::too_many_arguments)]
+pub async fn check(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(response_meta): Extension<ResponseMeta<'_>>,
+    Extension(auth): Extension<Auth>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    State(state): State<AppState>,
+    payload: Result<Json<Request<CheckPayload>>, JsonRejection>,
+) -> response::Response<Body> {
+    let Json(Request { data: payload, .. }) = match payload {
+        Ok(p) => p,
+        Err(err) => {
+            return CommonError::JsonRejection {
+                err,
+                request_id,
+                tenant_id: Some(tenant_id),
+            }
+            .into_response()
+        }
+    };
+
+    let Some(user_id) = auth.user_id else {
+        return CommonError::Unauthorized {
+            request_id,
+            tenant_id: Some(tenant_id),
+        }
+        .into_response();
+    };
+
+    let errors = validate(&payload.action, payload.resource.as_deref(), "body.data");
+
+    if !errors.is_empty() {
+        let response: Response<Value> =
+            Response::new(None, Some(errors), Some(response_meta), None);
+
+        return (StatusCode::UNPROCESSABLE_ENTITY, Json(response)).into_response();
+    }
+
+    let subject = payload.subject.unwrap_or_else(|| user_id.clone());
+    let state = state.read().await;
+
+    if let Err(response) = authorize(
+        &state,
+        &tenant_id,
+        &user_id,
+        &auth.scopes,
+        [subject.as_str()].into_iter(),
+        &request_id,
+    )
+    .await
+    {
+        return response;
+    }
+
+    let resource = Resource::parse(payload.resource.as_deref()).unwrap_or(Resource::Tenant);
+    let request = RequestContext::new(addr.ip(), &headers, auth.device_id);
+
+    match authz::check(
+        &state,
+        &tenant_id,
+        &subject,
+        &payload.action,
+        &resource,
+        &request,
+        payload.debug.unwrap_or(false),
+    )
+    .await
+    {
+        Ok(decision) => Response::new(
+            Some(CheckResponse {
+                subject,
+                action: payload.action,
+                resource: payload.resource,
+                decision,
+            }),
+            None,
+            Some(response_meta),
+            None,
+        )
+        .into_response(),
+        Err(e) => internal_error(e, request_id, tenant_id),
+    }
+}
+
+/// Runs up to 100 checks at once, answering them in the same order.
+#[allow(clippy::too_many_arguments)]
+pub async fn batch_check(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(response_meta): Extension<ResponseMeta<'_>>,
+    Extension(auth): Extension<Auth>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    State(state): State<AppState>,
+    payload: Result<Json<Request<BatchCheckPayload>>, JsonRejection>,
+) -> response::Response<Body> {
+    let Json(Request { data: payload, .. }) = match payload {
+        Ok(p) => p,
+        Err(err) => {
+            return CommonError::JsonRejection {
+                err,
+                request_id,
+                tenant_id: Some(tenant_id),
+            }
+            .into_response()
+        }
+    };
+
+    let Some(user_id) = auth.user_id else {
+        return CommonError::Unauthorized {
+            request_id,
+            tenant_id: Some(tenant_id),
+        }
+        .into_response();
+    };
+
+    let mut errors: Vec<Error> = vec![];
+
+    if payload.checks.is_empty() || payload.checks.len() > MAX_BATCH_SIZE {
+        errors.push(Error::new(
+            StatusCode::UNPROCESSABLE_ENTITY.as_u16(),
+            "Invalid Batch Size",
+            "A batch must contain from 1 to 100 checks.",
+            Some("body.data.checks"),
+            HashMap::from([("count", json!(payload.checks.len()))]),
+        ));
+    }
+
+    for (i, check) in payload.checks.iter().enumerate() {
+        errors.extend(validate(
+            &check.action,
+            check.resource.as_deref(),
+            &format!("body.data.checks[{i}]"),
+        ));
+    }
+
+    if !errors.is_empty() {
+        let response: Response<Value> =
+            Response::new(None, Some(errors), Some(response_meta), None);
+
+        return (StatusCode::UNPROCESSABLE_ENTITY, Json(response)).into_response();
+    }
+
+    let state = state.read().await;
+
+    if let Err(response) = authorize(
+        &state,
+        &tenant_id,
+        &user_id,
+        &auth.scopes,
+        payload
+            .checks
+            .iter()
+            .map(|check| check.subject.as_deref().unwrap_or(&user_id)),
+        &request_id,
+    )
+    .await
+    {
+        return response;
+    }
+
+    let debug = payload.debug.unwrap_or(false);
+    let request = RequestContext::new(addr.ip(), &headers, auth.device_id);
+    let mut results: Vec<CheckResponse> = Vec::with_capacity(payload.checks.len());
+
+    for check in payload.checks {
+        let subject = check.subject.unwrap_or_else(|| user_id.clone());
+        let resource = Resource::parse(check.resource.as_deref()).unwrap_or(Resource::Tenant);
+
+        match authz::check(
+            &state,
+            &tenant_id,
+            &subject,
+            &check.action,
+            &resource,
+            &request,
+            debug,
+        )
+        .await
+        {
+            Ok(decision) => results.push(CheckResponse {
+                subject,
+                action: check.action,
+                resource: check.resource,
+                decision,
+            }),
+            Err(e) => return internal_error(e, request_id, tenant_id),
+        }
+    }
+
+    Response::new(Some(results), None, Some(response_meta), None).into_response()
+}
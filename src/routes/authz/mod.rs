@@ -0,0 +1,13 @@
+mod handlers;
+mod requests;
+mod responses;
+
+use axum::{routing::post, Router};
+
+use crate::state::AppState;
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/check", post(handlers::check))
+        .route("/check/batch", post(handlers::batch_check))
+}
@@ -0,0 +1,25 @@
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize)]
+pub struct CheckPayload {
+    /// The user to check, which defaults to the authenticated user.
+    pub subject: Option<String>,
+    pub action: String,
+    pub resource: Option<String>,
+
+    /// Whether to explain the decision, bypassing the cache.
+    pub debug: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BatchCheck {
+    pub subject: Option<String>,
+    pub action: String,
+    pub resource: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BatchCheckPayload {
+    pub checks: Vec<BatchCheck>,
+    pub debug: Option<bool>,
+}
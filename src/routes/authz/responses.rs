@@ -0,0 +1,13 @@
+use serde::Serialize;
+
+use crate::authz::Decision;
+
+#[derive(Serialize)]
+pub struct CheckResponse {
+    pub subject: String,
+    pub action: String,
+    pub resource: Option<String>,
+
+    #[serde(flatten)]
+    pub decision: Decision,
+}
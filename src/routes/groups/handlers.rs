@@ -0,0 +1,607 @@
+use super::requests::{CreateGroupPayload, UpdateGroupPayload};
+use crate::{
+    auth::Auth,
+    error_handlers::{authorize, error_response, internal_error},
+    groups::{self, Group, MANAGE_PERMISSION},
+    permissions,
+    requests::Request,
+    responses::{CommonError, Error, Response, ResponseMeta},
+    state::AppState,
+    types::{RequestID, TenantID},
+    users,
+    utils::{id::gen_id, text::trim},
+};
+use axum::{
+    body::Body,
+    extract::{rejection::JsonRejection, Path, State},
+    http::StatusCode,
+    response::{self, IntoResponse},
+    Extension, Json,
+};
+use scylla::Session;
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+use validator::ValidateLength;
+
+async fn find(
+    db: &Session,
+    tenant_id: &str,
+    group_id: &str,
+    request_id: &str,
+) -> Result<Group, response::Response<Body>> {
+    match groups::get(db, tenant_id, group_id).await {
+        Ok(Some(group)) => Ok(group),
+        Ok(None) => Err(error_response(
+            StatusCode::NOT_FOUND,
+            "Group Not Found",
+            "There's no group with this ID.",
+            Some("path.group_id"),
+            HashMap::from([("input", json!(trim(group_id, 20)))]),
+            request_id.to_string(),
+            Some(tenant_id.to_string()),
+        )
+        .into_response()),
+        Err(e) => Err(internal_error(
+            e,
+            request_id.to_string(),
+            tenant_id.to_string(),
+        )),
+    }
+}
+
+/// Validates the fields of a group, returning every error found.
+fn validate(
+    name: Option<&str>,
+    permissions: Option<&HashSet<String>>,
+    color: Option<i32>,
+) -> Vec<Error> {
+    let mut errors: Vec<Error> = vec![];
+
+    if let Some(name) = name {
+        if !ValidateLength::validate_length(&name.trim(), Some(1), Some(64), None) {
+            errors.push(Error::new(
+                StatusCode::UNPROCESSABLE_ENTITY.as_u16(),
+                "Invalid Group Name",
+                "The group name must be from 1 to 64 characters long.",
+                Some("body.data.name"),
+                HashMap::from([("input", json!(trim(name, 20)))]),
+            ));
+        }
+    }
+
+    if let Some(permissions) = permissions {
+        let invalid: Vec<&String> = permissions
+            .iter()
+            .filter(|permission| !permissions::is_valid(permission))
+            .collect();
+
+        if !invalid.is_empty() {
+            errors.push(Error::new(
+                StatusCode::UNPROCESSABLE_ENTITY.as_u16(),
+                "Invalid Permissions",
+                "Permissions must be dot-separated ASCII segments without spaces, where * matches any segment, optionally prefixed with - to deny them.",
+                Some("body.data.permissions"),
+                HashMap::from([("invalid", json!(invalid))]),
+            ));
+        }
+    }
+
+    if let Some(color) = color {
+        if !(0..=0xFFFFFF).contains(&color) {
+            errors.push(Error::new(
+                StatusCode::UNPROCESSABLE_ENTITY.as_u16(),
+                "Invalid Color",
+                "The color must be an RGB value from 0 to 16777215.",
+                Some("body.data.color"),
+                HashMap::from([("input", json!(color))]),
+            ));
+        }
+    }
+
+    errors
+}
+
+/// Creates a group. Requires the `groups.manage` permission.
+pub async fn create_group(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(response_meta): Extension<ResponseMeta<'_>>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    payload: Result<Json<Request<CreateGroupPayload>>, JsonRejection>,
+) -> response::Response<Body> {
+    let Json(Request { data: payload, .. }) = match payload {
+        Ok(p) => p,
+        Err(err) => {
+            return CommonError::JsonRejection {
+                err,
+                request_id,
+                tenant_id: Some(tenant_id),
+            }
+            .into_response()
+        }
+    };
+
+    let state = state.read().await;
+
+    if let Err(response) = authorize(
+        &state,
+        &tenant_id,
+        auth.user_id,
+        &auth.scopes,
+        MANAGE_PERMISSION,
+        &request_id,
+    )
+    .await
+    {
+        return response;
+    }
+
+    let errors = validate(
+        Some(&payload.name),
+        payload.permissions.as_ref(),
+        payload.color,
+    );
+
+    if !errors.is_empty() {
+        let response: Response<Value> =
+            Response::new(None, Some(errors), Some(response_meta), None);
+
+        return (StatusCode::UNPROCESSABLE_ENTITY, Json(response)).into_response();
+    }
+
+    let group = Group {
+        group_id: gen_id(None),
+        name: payload.name.trim().to_string(),
+        description: payload.description,
+        permissions: payload.permissions.unwrap_or_default(),
+        roles: HashSet::new(),
+        priority: payload.priority.unwrap_or(0),
+        color: payload.color,
+    };
+
+    if let Err(e) = groups::save(&state, &tenant_id, &group, None).await {
+        return internal_error(e, request_id, tenant_id);
+    }
+
+    (
+        StatusCode::CREATED,
+        Response::new(Some(group), None, Some(response_meta), None),
+    )
+        .into_response()
+}
+
+/// Lists the tenant's groups from the highest priority to the lowest. Requires the
+/// `groups.manage` permission.
+pub async fn list_groups(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(response_meta): Extension<ResponseMeta<'_>>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+) -> response::Response<Body> {
+    let state = state.read().await;
+
+    if let Err(response) = authorize(
+        &state,
+        &tenant_id,
+        auth.user_id,
+        &auth.scopes,
+        MANAGE_PERMISSION,
+        &request_id,
+    )
+    .await
+    {
+        return response;
+    }
+
+    match groups::list(&state.db, &tenant_id).await {
+        Ok(groups) => Response::new(Some(groups), None, Some(response_meta), None).into_response(),
+        Err(e) => internal_error(e, request_id, tenant_id),
+    }
+}
+
+pub async fn get_group(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(response_meta): Extension<ResponseMeta<'_>>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    Path(group_id): Path<String>,
+) -> response::Response<Body> {
+    let state = state.read().await;
+
+    if let Err(response) = authorize(
+        &state,
+        &tenant_id,
+        auth.user_id,
+        &auth.scopes,
+        MANAGE_PERMISSION,
+        &request_id,
+    )
+    .await
+    {
+        return response;
+    }
+
+    match find(&state.db, &tenant_id, &group_id, &request_id).await {
+        Ok(group) => Response::new(Some(group), None, Some(response_meta), None).into_response(),
+        Err(response) => response,
+    }
+}
+
+pub async fn update_group(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(response_meta): Extension<ResponseMeta<'_>>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    Path(group_id): Path<String>,
+    payload: Result<Json<Request<UpdateGroupPayload>>, JsonRejection>,
+) -> response::Response<Body> {
+    let Json(Request { data: payload, .. }) = match payload {
+        Ok(p) => p,
+        Err(err) => {
+            return CommonError::JsonRejection {
+                err,
+                request_id,
+                tenant_id: Some(tenant_id),
+            }
+            .into_response()
+        }
+    };
+
+    let state = state.read().await;
+
+    if let Err(response) = authorize(
+        &state,
+        &tenant_id,
+        auth.user_id,
+        &auth.scopes,
+        MANAGE_PERMISSION,
+        &request_id,
+    )
+    .await
+    {
+        return response;
+    }
+
+    let errors = validate(
+        payload.name.as_deref(),
+        payload.permissions.as_ref(),
+        payload.color,
+    );
+
+    if !errors.is_empty() {
+        let response: Response<Value> =
+            Response::new(None, Some(errors), Some(response_meta), None);
+
+        return (StatusCode::UNPROCESSABLE_ENTITY, Json(response)).into_response();
+    }
+
+    let mut group = match find(&state.db, &tenant_id, &group_id, &request_id).await {
+        Ok(g) => g,
+        Err(response) => return response,
+    };
+
+    let previous_priority = group.priority;
+
+    if let Some(name) = payload.name {
+        group.name = name.trim().to_string();
+    }
+
+    if let Some(description) = payload.description {
+        group.description = Some(description);
+    }
+
+    if let Some(permissions) = payload.permissions {
+        group.permissions = permissions;
+    }
+
+    if let Some(priority) = payload.priority {
+        group.priority = priority;
+    }
+
+    if let Some(color) = payload.color {
+        group.color = Some(color);
+    }
+
+    if let Err(e) = groups::save(&state, &tenant_id, &group, Some(previous_priority)).await {
+        return internal_error(e, request_id, tenant_id);
+    }
+
+    Response::new(Some(group), None, Some(response_meta), None).into_response()
+}
+
+/// Deletes a group along with its memberships.
+pub async fn delete_group(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    Path(group_id): Path<String>,
+) -> response::Response<Body> {
+    let state = state.read().await;
+
+    if let Err(response) = authorize(
+        &state,
+        &tenant_id,
+        auth.user_id,
+        &auth.scopes,
+        MANAGE_PERMISSION,
+        &request_id,
+    )
+    .await
+    {
+        return response;
+    }
+
+    if let Err(response) = find(&state.db, &tenant_id, &group_id, &request_id).await {
+        return response;
+    }
+
+    if let Err(e) = groups::delete(&state, &tenant_id, &group_id).await {
+        return internal_error(e, request_id, tenant_id);
+    }
+
+    StatusCode::NO_CONTENT.into_response()
+}
+
+pub async fn list_members(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(response_meta): Extension<ResponseMeta<'_>>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    Path(group_id): Path<String>,
+) -> response::Response<Body> {
+    let state = state.read().await;
+
+    if let Err(response) = authorize(
+        &state,
+        &tenant_id,
+        auth.user_id,
+        &auth.scopes,
+        MANAGE_PERMISSION,
+        &request_id,
+    )
+    .await
+    {
+        return response;
+    }
+
+    if let Err(response) = find(&state.db, &tenant_id, &group_id, &request_id).await {
+        return response;
+    }
+
+    match groups::members(&state.db, &tenant_id, &group_id).await {
+        Ok(members) => {
+            Response::new(Some(members), None, Some(response_meta), None).into_response()
+        }
+        Err(e) => internal_error(e, request_id, tenant_id),
+    }
+}
+
+pub async fn add_member(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    Path((group_id, user_id)): Path<(String, String)>,
+) -> response::Response<Body> {
+    let state = state.read().await;
+
+    if let Err(response) = authorize(
+        &state,
+        &tenant_id,
+        auth.user_id,
+        &auth.scopes,
+        MANAGE_PERMISSION,
+        &request_id,
+    )
+    .await
+    {
+        return response;
+    }
+
+    if let Err(response) = find(&state.db, &tenant_id, &group_id, &request_id).await {
+        return response;
+    }
+
+    match users::exists(&state.db, &tenant_id, &user_id).await {
+        Ok(true) => {}
+        Ok(false) => {
+            return error_response(
+                StatusCode::NOT_FOUND,
+                "User Not Found",
+                "There's no user with this ID.",
+                Some("path.user_id"),
+                HashMap::from([("input", json!(trim(&user_id, 20)))]),
+                request_id,
+                Some(tenant_id),
+            )
+            .into_response()
+        }
+        Err(e) => return internal_error(e, request_id, tenant_id),
+    }
+
+    if let Err(e) = groups::add_member(&state, &tenant_id, &group_id, &user_id).await {
+        return internal_error(e, request_id, tenant_id);
+    }
+
+    StatusCode::NO_CONTENT.into_response()
+}
+
+pub async fn remove_member(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    Path((group_id, user_id)): Path<(String, String)>,
+) -> response::Response<Body> {
+    let state = state.read().await;
+
+    if let Err(response) = authorize(
+        &state,
+        &tenant_id,
+        auth.user_id,
+        &auth.scopes,
+        MANAGE_PERMISSION,
+        &request_id,
+    )
+    .await
+    {
+        return response;
+    }
+
+    if let Err(e) = groups::remove_member(&state, &tenant_id, &group_id, &user_id).await {
+        return internal_error(e, request_id, tenant_id);
+    }
+
+    StatusCode::NO_CONTENT.into_response()
+}
+
+pub async fn list_children(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(response_meta): Extension<ResponseMeta<'_>>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    Path(group_id): Path<String>,
+) -> response::Response<Body> {
+    let state = state.read().await;
+
+    if let Err(response) = authorize(
+        &state,
+        &tenant_id,
+        auth.user_id,
+        &auth.scopes,
+        MANAGE_PERMISSION,
+        &request_id,
+    )
+    .await
+    {
+        return response;
+    }
+
+    if let Err(response) = find(&state.db, &tenant_id, &group_id, &request_id).await {
+        return response;
+    }
+
+    match groups::children(&state.db, &tenant_id, &group_id).await {
+        Ok(children) => {
+            Response::new(Some(children), None, Some(response_meta), None).into_response()
+        }
+        Err(e) => internal_error(e, request_id, tenant_id),
+    }
+}
+
+/// Nests a group inside another, so the child's members become members of the parent too.
+pub async fn add_child(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    Path((group_id, child_group_id)): Path<(String, String)>,
+) -> response::Response<Body> {
+    let state = state.read().await;
+
+    if let Err(response) = authorize(
+        &state,
+        &tenant_id,
+        auth.user_id,
+        &auth.scopes,
+        MANAGE_PERMISSION,
+        &request_id,
+    )
+    .await
+    {
+        return response;
+    }
+
+    if let Err(response) = find(&state.db, &tenant_id, &group_id, &request_id).await {
+        return response;
+    }
+
+    if let Err(response) = find(&state.db, &tenant_id, &child_group_id, &request_id).await {
+        return response;
+    }
+
+    match groups::add_child(&state, &tenant_id, &group_id, &child_group_id).await {
+        Ok(true) => StatusCode::NO_CONTENT.into_response(),
+        Ok(false) => error_response(
+            StatusCode::CONFLICT,
+            "Group Cycle",
+            "The group already contains this group, directly or through other groups.",
+            Some("path.child_group_id"),
+            HashMap::from([("input", json!(trim(&child_group_id, 20)))]),
+            request_id,
+            Some(tenant_id),
+        )
+        .into_response(),
+        Err(e) => internal_error(e, request_id, tenant_id),
+    }
+}
+
+pub async fn remove_child(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    Path((group_id, child_group_id)): Path<(String, String)>,
+) -> response::Response<Body> {
+    let state = state.read().await;
+
+    if let Err(response) = authorize(
+        &state,
+        &tenant_id,
+        auth.user_id,
+        &auth.scopes,
+        MANAGE_PERMISSION,
+        &request_id,
+    )
+    .await
+    {
+        return response;
+    }
+
+    if let Err(e) = groups::remove_child(&state, &tenant_id, &group_id, &child_group_id).await {
+        return internal_error(e, request_id, tenant_id);
+    }
+
+    StatusCode::NO_CONTENT.into_response()
+}
+
+/// Returns the group's members along with the members of its nested groups, as a tree.
+pub async fn member_tree(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(response_meta): Extension<ResponseMeta<'_>>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    Path(group_id): Path<String>,
+) -> response::Response<Body> {
+    let state = state.read().await;
+
+    if let Err(response) = authorize(
+        &state,
+        &tenant_id,
+        auth.user_id,
+        &auth.scopes,
+        MANAGE_PERMISSION,
+        &request_id,
+    )
+    .await
+    {
+        return response;
+    }
+
+    if let Err(response) = find(&state.db, &tenant_id, &group_id, &request_id).await {
+        return response;
+    }
+
+    match groups::tree(&state.db, &tenant_id, &group_id).await {
+        Ok(tree) => Response::new(Some(tree), None, Some(response_meta), None).into_response(),
+        Err(e) => internal_error(e, request_id, tenant_id),
+    }
+}
@@ -0,0 +1,31 @@
+mod handlers;
+mod requests;
+
+use axum::{
+    routing::{get, put},
+    Router,
+};
+
+use crate::state::AppState;
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/", get(handlers::list_groups).post(handlers::create_group))
+        .route(
+            "/:group_id",
+            get(handlers::get_group)
+                .patch(handlers::update_group)
+                .delete(handlers::delete_group),
+        )
+        .route("/:group_id/members", get(handlers::list_members))
+        .route(
+            "/:group_id/members/:user_id",
+            put(handlers::add_member).delete(handlers::remove_member),
+        )
+        .route("/:group_id/children", get(handlers::list_children))
+        .route(
+            "/:group_id/children/:child_group_id",
+            put(handlers::add_child).delete(handlers::remove_child),
+        )
+        .route("/:group_id/tree", get(handlers::member_tree))
+}
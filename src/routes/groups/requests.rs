@@ -0,0 +1,21 @@
+use std::collections::HashSet;
+
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize)]
+pub struct CreateGroupPayload {
+    pub name: String,
+    pub description: Option<String>,
+    pub permissions: Option<HashSet<String>>,
+    pub priority: Option<i16>,
+    pub color: Option<i32>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateGroupPayload {
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub permissions: Option<HashSet<String>>,
+    pub priority: Option<i16>,
+    pub color: Option<i32>,
+}
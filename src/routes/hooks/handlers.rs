@@ -0,0 +1,504 @@
+use super::requests::{CreateHookPayload, TestHookPayload, UpdateHookPayload};
+use crate::{
+    auth::Auth,
+    error_handlers::{authorize, error_response, internal_error},
+    hooks::{
+        self, Action, Hook, DEFAULT_TIMEOUT_MS, MANAGE_PERMISSION, MAX_HOOKS_PER_TRIGGER,
+        MAX_TIMEOUT_MS, MAX_TRIGGER_BUDGET_MS,
+    },
+    policies::RequestContext,
+    requests::Request,
+    responses::{CommonError, Error, Response, ResponseMeta},
+    state::AppState,
+    types::{RequestID, TenantID},
+    utils::{id::gen_id, text::trim},
+};
+use axum::{
+    body::Body,
+    extract::{rejection::JsonRejection, ConnectInfo, Path, State},
+    http::{HeaderMap, StatusCode},
+    response::{self, IntoResponse},
+    Extension, Json,
+};
+use chrono::Utc;
+use scylla::Session;
+use serde_json::{json, Value};
+use std::{collections::HashMap, net::SocketAddr};
+
+fn unprocessable(errors: Vec<Error>, response_meta: ResponseMeta<'_>) -> response::Response<Body> {
+    let response: Response<Value> = Response::new(None, Some(errors), Some(response_meta), None);
+
+    (StatusCode::UNPROCESSABLE_ENTITY, Json(response)).into_response()
+}
+
+async fn find_hook(
+    db: &Session,
+    tenant_id: &str,
+    hook_id: &str,
+    request_id: &str,
+) -> Result<Hook, response::Response<Body>> {
+    match hooks::get(db, tenant_id, hook_id).await {
+        Ok(Some(hook)) => Ok(hook),
+        Ok(None) => Err(error_response(
+            StatusCode::NOT_FOUND,
+            "Hook Not Found",
+            "There's no hook with this ID.",
+            Some("path.hook_id"),
+            HashMap::from([("input", json!(trim(hook_id, 20)))]),
+            request_id.to_string(),
+            Some(tenant_id.to_string()),
+        )
+        .into_response()),
+        Err(e) => Err(internal_error(
+            e,
+            request_id.to_string(),
+            tenant_id.to_string(),
+        )),
+    }
+}
+
+/// Validates the fields shared by new and updated hooks, given the tenant's current hooks.
+fn validate(hook: &Hook, hooks: &[Hook]) -> Vec<Error> {
+    let mut errors: Vec<Error> = vec![];
+
+    if hook.name.trim().is_empty() || hook.name.chars().count() > 64 {
+        errors.push(Error::new(
+            StatusCode::UNPROCESSABLE_ENTITY.as_u16(),
+            "Invalid Name",
+            "The name must be from 1 to 64 characters long.",
+            Some("body.data.name"),
+            HashMap::new(),
+        ));
+    }
+
+    if !(1..=MAX_TIMEOUT_MS).contains(&hook.timeout_ms) {
+        errors.push(Error::new(
+            StatusCode::UNPROCESSABLE_ENTITY.as_u16(),
+            "Invalid Timeout",
+            &format!("The timeout must be from 1 to {MAX_TIMEOUT_MS} milliseconds."),
+            Some("body.data.timeout_ms"),
+            HashMap::from([("input", json!(hook.timeout_ms))]),
+        ));
+    } else if !hooks::fits_budget(hooks, hook) {
+        errors.push(Error::new(
+            StatusCode::UNPROCESSABLE_ENTITY.as_u16(),
+            "Hook Budget Exceeded",
+            &format!("The timeouts of the enabled hooks of a trigger can add up to at most {MAX_TRIGGER_BUDGET_MS} milliseconds."),
+            Some("body.data.timeout_ms"),
+            HashMap::from([("input", json!(hook.timeout_ms))]),
+        ));
+    }
+
+    if let Err(e) = hooks::validate_action(&hook.action) {
+        let location = match hook.action {
+            Action::Script { .. } => "body.data.source",
+            Action::Callout { .. } => "body.data.url",
+        };
+
+        errors.push(Error::new(
+            StatusCode::UNPROCESSABLE_ENTITY.as_u16(),
+            "Invalid Action",
+            &e,
+            Some(location),
+            HashMap::new(),
+        ));
+    }
+
+    errors
+}
+
+/// Adds the secret to a hook, which is only shown when it's created or rotated.
+fn with_secret(hook: &Hook) -> Value {
+    let mut value = json!(hook);
+    value["secret"] = json!(hook.secret);
+
+    value
+}
+
+/// Lists the tenant's hooks in the order they run. Requires the `hooks.manage` permission.
+pub async fn list_hooks(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(response_meta): Extension<ResponseMeta<'_>>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+) -> response::Response<Body> {
+    let state = state.read().await;
+
+    if let Err(response) = authorize(
+        &state,
+        &tenant_id,
+        auth.user_id,
+        &auth.scopes,
+        MANAGE_PERMISSION,
+        &request_id,
+    )
+    .await
+    {
+        return response;
+    }
+
+    match hooks::all(&state.db, &tenant_id).await {
+        Ok(all) => Response::new(Some(all), None, Some(response_meta), None).into_response(),
+        Err(e) => internal_error(e, request_id, tenant_id),
+    }
+}
+
+/// Creates a hook. The response is the only time its secret is shown, until it's rotated.
+pub async fn create_hook(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(response_meta): Extension<ResponseMeta<'_>>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    payload: Result<Json<Request<CreateHookPayload>>, JsonRejection>,
+) -> response::Response<Body> {
+    let Json(Request { data: payload, .. }) = match payload {
+        Ok(p) => p,
+        Err(err) => {
+            return CommonError::JsonRejection {
+                err,
+                request_id,
+                tenant_id: Some(tenant_id),
+            }
+            .into_response()
+        }
+    };
+
+    let state = state.read().await;
+
+    if let Err(response) = authorize(
+        &state,
+        &tenant_id,
+        auth.user_id,
+        &auth.scopes,
+        MANAGE_PERMISSION,
+        &request_id,
+    )
+    .await
+    {
+        return response;
+    }
+
+    let existing = match hooks::all(&state.db, &tenant_id).await {
+        Ok(all) => all
+            .into_iter()
+            .filter(|hook| hook.trigger == payload.trigger)
+            .collect::<Vec<Hook>>(),
+        Err(e) => return internal_error(e, request_id, tenant_id),
+    };
+
+    let hook = Hook {
+        hook_id: gen_id(None),
+        trigger: payload.trigger,
+        name: payload.name,
+        action: payload.action,
+        position: payload
+            .position
+            .unwrap_or_else(|| existing.last().map_or(0, |hook| hook.position + 1)),
+        timeout_ms: payload.timeout_ms.unwrap_or(DEFAULT_TIMEOUT_MS),
+        fail_open: payload.fail_open.unwrap_or(false),
+        is_enabled: payload.is_enabled.unwrap_or(true),
+        secret: hooks::gen_secret(),
+        created_at: Some(Utc::now().timestamp_millis()),
+        updated_at: None,
+    };
+
+    let mut errors = validate(&hook, &existing);
+
+    if existing.len() >= MAX_HOOKS_PER_TRIGGER {
+        errors.push(Error::new(
+            StatusCode::UNPROCESSABLE_ENTITY.as_u16(),
+            "Too Many Hooks",
+            "A trigger can have at most 10 hooks.",
+            Some("body.data.trigger"),
+            HashMap::from([("input", json!(hook.trigger))]),
+        ));
+    }
+
+    if !errors.is_empty() {
+        return unprocessable(errors, response_meta);
+    }
+
+    if let Err(e) = hooks::save(&state.db, &tenant_id, &hook).await {
+        return internal_error(e, request_id, tenant_id);
+    }
+
+    (
+        StatusCode::CREATED,
+        Response::new(Some(with_secret(&hook)), None, Some(response_meta), None),
+    )
+        .into_response()
+}
+
+pub async fn get_hook(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(response_meta): Extension<ResponseMeta<'_>>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    Path(hook_id): Path<String>,
+) -> response::Response<Body> {
+    let state = state.read().await;
+
+    if let Err(response) = authorize(
+        &state,
+        &tenant_id,
+        auth.user_id,
+        &auth.scopes,
+        MANAGE_PERMISSION,
+        &request_id,
+    )
+    .await
+    {
+        return response;
+    }
+
+    match find_hook(&state.db, &tenant_id, &hook_id, &request_id).await {
+        Ok(hook) => Response::new(Some(hook), None, Some(response_meta), None).into_response(),
+        Err(response) => response,
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub async fn update_hook(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(response_meta): Extension<ResponseMeta<'_>>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    Path(hook_id): Path<String>,
+    payload: Result<Json<Request<UpdateHookPayload>>, JsonRejection>,
+) -> response::Response<Body> {
+    let Json(Request { data: payload, .. }) = match payload {
+        Ok(p) => p,
+        Err(err) => {
+            return CommonError::JsonRejection {
+                err,
+                request_id,
+                tenant_id: Some(tenant_id),
+            }
+            .into_response()
+        }
+    };
+
+    let state = state.read().await;
+
+    if let Err(response) = authorize(
+        &state,
+        &tenant_id,
+        auth.user_id,
+        &auth.scopes,
+        MANAGE_PERMISSION,
+        &request_id,
+    )
+    .await
+    {
+        return response;
+    }
+
+    let mut hook = match find_hook(&state.db, &tenant_id, &hook_id, &request_id).await {
+        Ok(hook) => hook,
+        Err(response) => return response,
+    };
+
+    let mut errors: Vec<Error> = vec![];
+
+    match (&mut hook.action, payload.source, payload.url) {
+        (Action::Script { source }, Some(new_source), None) => *source = new_source,
+        (Action::Callout { url }, None, Some(new_url)) => *url = new_url,
+        (_, None, None) => {}
+        _ => errors.push(Error::new(
+            StatusCode::UNPROCESSABLE_ENTITY.as_u16(),
+            "Invalid Action",
+            "Only the source of a script or the URL of a callout can be changed.",
+            Some("body.data"),
+            HashMap::new(),
+        )),
+    }
+
+    if let Some(name) = payload.name {
+        hook.name = name;
+    }
+
+    if let Some(position) = payload.position {
+        hook.position = position;
+    }
+
+    if let Some(timeout_ms) = payload.timeout_ms {
+        hook.timeout_ms = timeout_ms;
+    }
+
+    if let Some(fail_open) = payload.fail_open {
+        hook.fail_open = fail_open;
+    }
+
+    if let Some(is_enabled) = payload.is_enabled {
+        hook.is_enabled = is_enabled;
+    }
+
+    let existing = match hooks::all(&state.db, &tenant_id).await {
+        Ok(all) => all,
+        Err(e) => return internal_error(e, request_id, tenant_id),
+    };
+
+    errors.extend(validate(&hook, &existing));
+
+    if !errors.is_empty() {
+        return unprocessable(errors, response_meta);
+    }
+
+    if let Err(e) = hooks::save(&state.db, &tenant_id, &hook).await {
+        return internal_error(e, request_id, tenant_id);
+    }
+
+    hook.updated_at = Some(Utc::now().timestamp_millis());
+
+    Response::new(Some(hook), None, Some(response_meta), None).into_response()
+}
+
+pub async fn delete_hook(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    Path(hook_id): Path<String>,
+) -> response::Response<Body> {
+    let state = state.read().await;
+
+    if let Err(response) = authorize(
+        &state,
+        &tenant_id,
+        auth.user_id,
+        &auth.scopes,
+        MANAGE_PERMISSION,
+        &request_id,
+    )
+    .await
+    {
+        return response;
+    }
+
+    if let Err(response) = find_hook(&state.db, &tenant_id, &hook_id, &request_id).await {
+        return response;
+    }
+
+    if let Err(e) = hooks::delete(&state.db, &tenant_id, &hook_id).await {
+        return internal_error(e, request_id, tenant_id);
+    }
+
+    StatusCode::NO_CONTENT.into_response()
+}
+
+/// Replaces the secret signing a hook's callouts, returning the new one.
+pub async fn rotate_secret(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(response_meta): Extension<ResponseMeta<'_>>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    Path(hook_id): Path<String>,
+) -> response::Response<Body> {
+    let state = state.read().await;
+
+    if let Err(response) = authorize(
+        &state,
+        &tenant_id,
+        auth.user_id,
+        &auth.scopes,
+        MANAGE_PERMISSION,
+        &request_id,
+    )
+    .await
+    {
+        return response;
+    }
+
+    let mut hook = match find_hook(&state.db, &tenant_id, &hook_id, &request_id).await {
+        Ok(hook) => hook,
+        Err(response) => return response,
+    };
+
+    hook.secret = hooks::gen_secret();
+
+    if let Err(e) = hooks::save(&state.db, &tenant_id, &hook).await {
+        return internal_error(e, request_id, tenant_id);
+    }
+
+    hook.updated_at = Some(Utc::now().timestamp_millis());
+
+    Response::new(Some(with_secret(&hook)), None, Some(response_meta), None).into_response()
+}
+
+/// Runs a hook, enabled or not, over an event built from `data` and the request, and reports
+/// how it would deny or what it would change. Nothing is applied.
+#[allow(clippy::too_many_arguments)]
+pub async fn test_hook(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(response_meta): Extension<ResponseMeta<'_>>,
+    Extension(auth): Extension<Auth>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    State(state): State<AppState>,
+    Path(hook_id): Path<String>,
+    payload: Result<Json<Request<TestHookPayload>>, JsonRejection>,
+) -> response::Response<Body> {
+    let Json(Request { data: payload, .. }) = match payload {
+        Ok(p) => p,
+        Err(err) => {
+            return CommonError::JsonRejection {
+                err,
+                request_id,
+                tenant_id: Some(tenant_id),
+            }
+            .into_response()
+        }
+    };
+
+    let state = state.read().await;
+
+    if let Err(response) = authorize(
+        &state,
+        &tenant_id,
+        auth.user_id,
+        &auth.scopes,
+        MANAGE_PERMISSION,
+        &request_id,
+    )
+    .await
+    {
+        return response;
+    }
+
+    let hook = match find_hook(&state.db, &tenant_id, &hook_id, &request_id).await {
+        Ok(hook) => hook,
+        Err(response) => return response,
+    };
+
+    let event = hooks::event(
+        hook.trigger,
+        &tenant_id,
+        &RequestContext::new(addr.ip(), &headers, auth.device_id),
+        payload.data,
+    );
+
+    let result = match hooks::test(&hook, &event).await {
+        Ok((denial, outcome)) => json!({
+            "succeeded": true,
+            "deny": denial,
+            "metadata": outcome.metadata,
+            "claims": outcome.claims,
+            "scopes": outcome.scopes,
+        }),
+        Err(e) => json!({ "succeeded": false, "error": e }),
+    };
+
+    Response::new(
+        Some(json!({ "event": event, "result": result })),
+        None,
+        Some(response_meta),
+        None,
+    )
+    .into_response()
+}
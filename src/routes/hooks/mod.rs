@@ -0,0 +1,22 @@
+mod handlers;
+mod requests;
+
+use axum::{
+    routing::{get, post},
+    Router,
+};
+
+use crate::state::AppState;
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/", get(handlers::list_hooks).post(handlers::create_hook))
+        .route(
+            "/:hook_id",
+            get(handlers::get_hook)
+                .patch(handlers::update_hook)
+                .delete(handlers::delete_hook),
+        )
+        .route("/:hook_id/secret", post(handlers::rotate_secret))
+        .route("/:hook_id/test", post(handlers::test_hook))
+}
@@ -0,0 +1,40 @@
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::hooks::{Action, Trigger};
+
+#[derive(Debug, Deserialize)]
+pub struct CreateHookPayload {
+    pub name: String,
+    pub trigger: Trigger,
+
+    /// `{"kind": "script", "source": "..."}` or `{"kind": "callout", "url": "..."}`.
+    #[serde(flatten)]
+    pub action: Action,
+
+    /// Where the hook runs among the hooks of its trigger, lowest first.
+    pub position: Option<i32>,
+    pub timeout_ms: Option<i32>,
+    pub fail_open: Option<bool>,
+    pub is_enabled: Option<bool>,
+}
+
+/// Changes to a hook. Its trigger and kind can't change, so `source` only applies to scripts
+/// and `url` to callouts.
+#[derive(Debug, Deserialize)]
+pub struct UpdateHookPayload {
+    pub name: Option<String>,
+    pub source: Option<String>,
+    pub url: Option<String>,
+    pub position: Option<i32>,
+    pub timeout_ms: Option<i32>,
+    pub fail_open: Option<bool>,
+    pub is_enabled: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TestHookPayload {
+    /// The trigger-specific part of the event, as the hook would receive it under `event.data`.
+    #[serde(default)]
+    pub data: Value,
+}
@@ -0,0 +1,381 @@
+use super::requests::AddHostPayload;
+use crate::{
+    auth::Auth,
+    error_handlers::{authorize, error_response, internal_error},
+    hosts::{self, Host, Method, MANAGE_PERMISSION},
+    requests::Request,
+    responses::{CommonError, Response, ResponseMeta},
+    state::{self, AppState},
+    types::{RequestID, TenantID},
+    utils::text::trim,
+};
+use axum::{
+    body::Body,
+    extract::{rejection::JsonRejection, Path, State},
+    http::StatusCode,
+    response::{self, IntoResponse},
+    Extension, Json,
+};
+use chrono::Utc;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+/// Describes a host along with how to prove ownership of it, while it's pending.
+fn host_data(host: &Host) -> Value {
+    let mut data = json!(host);
+
+    if host.is_custom && !host.is_verified {
+        data["challenge"] = match host.method {
+            Some(Method::Http) => json!({
+                "url": host.challenge_url(),
+                "body": host.record_value(),
+            }),
+            _ => json!({
+                "name": host.record_name(),
+                "value": host.record_value(),
+            }),
+        };
+    }
+
+    data
+}
+
+fn host_taken_response(
+    host: &str,
+    request_id: String,
+    tenant_id: String,
+) -> response::Response<Body> {
+    error_response(
+        StatusCode::CONFLICT,
+        "Host Taken",
+        "The host is already linked to another tenant.",
+        Some("body.data.host"),
+        HashMap::from([("input", json!(trim(host, 64)))]),
+        request_id,
+        Some(tenant_id),
+    )
+    .into_response()
+}
+
+/// Loads a host of the tenant.
+async fn find_host(
+    state: &state::State,
+    tenant_id: &str,
+    host: &str,
+    request_id: &str,
+) -> Result<Host, response::Response<Body>> {
+    match hosts::get(&state.db, tenant_id, &hosts::normalize(host)).await {
+        Ok(Some(host)) => Ok(host),
+        Ok(None) => Err(error_response(
+            StatusCode::NOT_FOUND,
+            "Host Not Found",
+            "The host isn't linked to this tenant.",
+            Some("path.host"),
+            HashMap::from([("input", json!(trim(host, 64)))]),
+            request_id.to_string(),
+            Some(tenant_id.to_string()),
+        )
+        .into_response()),
+        Err(e) => Err(internal_error(
+            e,
+            request_id.to_string(),
+            tenant_id.to_string(),
+        )),
+    }
+}
+
+/// Lists the tenant's hosts, including pending custom domains. Requires the `hosts.manage`
+/// permission.
+pub async fn list_hosts(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(response_meta): Extension<ResponseMeta<'_>>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+) -> response::Response<Body> {
+    let state = state.read().await;
+
+    if let Err(response) = authorize(
+        &state,
+        &tenant_id,
+        auth.user_id,
+        &auth.scopes,
+        MANAGE_PERMISSION,
+        &request_id,
+    )
+    .await
+    {
+        return response;
+    }
+
+    match hosts::list(&state.db, &tenant_id).await {
+        Ok(hosts) => Response::new(
+            Some(hosts.iter().map(host_data).collect::<Vec<Value>>()),
+            None,
+            Some(response_meta),
+            None,
+        )
+        .into_response(),
+        Err(e) => internal_error(e, request_id, tenant_id),
+    }
+}
+
+/// Adds a custom domain, which only resolves to the tenant once verified.
+pub async fn add_host(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(response_meta): Extension<ResponseMeta<'_>>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    payload: Result<Json<Request<AddHostPayload>>, JsonRejection>,
+) -> response::Response<Body> {
+    let Json(Request { data: payload, .. }) = match payload {
+        Ok(p) => p,
+        Err(err) => {
+            return CommonError::JsonRejection {
+                err,
+                request_id,
+                tenant_id: Some(tenant_id),
+            }
+            .into_response()
+        }
+    };
+
+    let host = hosts::normalize(&payload.host);
+    let method = payload.method.unwrap_or(Method::Dns);
+
+    if !hosts::is_valid(&host) {
+        return error_response(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "Invalid Host",
+            "The host must be a public domain name or a wildcard like *.example.com, without a port.",
+            Some("body.data.host"),
+            HashMap::from([("input", json!(trim(&payload.host, 64)))]),
+            request_id,
+            Some(tenant_id),
+        )
+        .into_response();
+    }
+
+    if hosts::is_wildcard(&host) && method == Method::Http {
+        return error_response(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "Unsupported Method",
+            "Wildcards can only be verified with a DNS TXT record.",
+            Some("body.data.method"),
+            HashMap::from([("input", json!(method))]),
+            request_id,
+            Some(tenant_id),
+        )
+        .into_response();
+    }
+
+    let state = state.read().await;
+
+    if let Err(response) = authorize(
+        &state,
+        &tenant_id,
+        auth.user_id,
+        &auth.scopes,
+        MANAGE_PERMISSION,
+        &request_id,
+    )
+    .await
+    {
+        return response;
+    }
+
+    match hosts::owner(&state.db, &host).await {
+        Ok(Some(owner)) if owner != tenant_id => {
+            return host_taken_response(&host, request_id, tenant_id)
+        }
+        Ok(Some(_)) => {
+            return error_response(
+                StatusCode::CONFLICT,
+                "Host Already Active",
+                "The host is already linked to this tenant.",
+                Some("body.data.host"),
+                HashMap::from([("input", json!(trim(&host, 64)))]),
+                request_id,
+                Some(tenant_id),
+            )
+            .into_response()
+        }
+        Ok(None) => {}
+        Err(e) => return internal_error(e, request_id, tenant_id),
+    }
+
+    match hosts::request(&state.db, &tenant_id, &host, method).await {
+        Ok(host) => {
+            let links = format!("/hosts/{}/verify", host.host);
+
+            (
+                StatusCode::CREATED,
+                Response::new(
+                    Some(host_data(&host)),
+                    None,
+                    Some(response_meta),
+                    Some(HashMap::from([("verify", links.as_str())])),
+                ),
+            )
+                .into_response()
+        }
+        Err(e) => internal_error(e, request_id, tenant_id),
+    }
+}
+
+pub async fn get_host(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(response_meta): Extension<ResponseMeta<'_>>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    Path(host): Path<String>,
+) -> response::Response<Body> {
+    let state = state.read().await;
+
+    if let Err(response) = authorize(
+        &state,
+        &tenant_id,
+        auth.user_id,
+        &auth.scopes,
+        MANAGE_PERMISSION,
+        &request_id,
+    )
+    .await
+    {
+        return response;
+    }
+
+    match find_host(&state, &tenant_id, &host, &request_id).await {
+        Ok(host) => {
+            Response::new(Some(host_data(&host)), None, Some(response_meta), None).into_response()
+        }
+        Err(response) => response,
+    }
+}
+
+/// Verifies a custom domain through its DNS or HTTP challenge, activating it.
+pub async fn verify_host(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(response_meta): Extension<ResponseMeta<'_>>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    Path(host): Path<String>,
+) -> response::Response<Body> {
+    let state = state.read().await;
+
+    if let Err(response) = authorize(
+        &state,
+        &tenant_id,
+        auth.user_id,
+        &auth.scopes,
+        MANAGE_PERMISSION,
+        &request_id,
+    )
+    .await
+    {
+        return response;
+    }
+
+    let mut host = match find_host(&state, &tenant_id, &host, &request_id).await {
+        Ok(host) => host,
+        Err(response) => return response,
+    };
+
+    // Hosts linked by the platform and verified domains are already active.
+    if !host.is_custom || host.is_verified {
+        return Response::new(Some(host_data(&host)), None, Some(response_meta), None)
+            .into_response();
+    }
+
+    if !hosts::check(&state.dns, &host).await {
+        let (detail, meta) = match host.method {
+            Some(Method::Http) => (
+                "The challenge wasn't served at its URL. Make sure the host answers over HTTP, then try again.",
+                HashMap::from([
+                    ("url", json!(host.challenge_url())),
+                    ("body", json!(host.record_value())),
+                ]),
+            ),
+            _ => (
+                "The TXT record wasn't found. DNS changes can take a while to propagate, so try again later.",
+                HashMap::from([
+                    ("name", json!(host.record_name())),
+                    ("value", json!(host.record_value())),
+                ]),
+            ),
+        };
+
+        return error_response(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "Host Verification Failed",
+            detail,
+            Some("path.host"),
+            meta,
+            request_id,
+            Some(tenant_id),
+        )
+        .into_response();
+    }
+
+    match hosts::verify(&state, &host).await {
+        Ok(true) => {}
+        Ok(false) => return host_taken_response(&host.host, request_id, tenant_id),
+        Err(e) => return internal_error(e, request_id, tenant_id),
+    }
+
+    host.is_verified = true;
+    host.verified_at = Some(Utc::now().timestamp_millis());
+
+    Response::new(Some(host_data(&host)), None, Some(response_meta), None).into_response()
+}
+
+/// Removes a custom domain. Hosts linked by the platform can only be removed by its admins.
+pub async fn remove_host(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    Path(host): Path<String>,
+) -> response::Response<Body> {
+    let state = state.read().await;
+
+    if let Err(response) = authorize(
+        &state,
+        &tenant_id,
+        auth.user_id,
+        &auth.scopes,
+        MANAGE_PERMISSION,
+        &request_id,
+    )
+    .await
+    {
+        return response;
+    }
+
+    let host = match find_host(&state, &tenant_id, &host, &request_id).await {
+        Ok(host) => host,
+        Err(response) => return response,
+    };
+
+    if !host.is_custom {
+        return error_response(
+            StatusCode::CONFLICT,
+            "Platform Host",
+            "Hosts linked by the platform can't be removed by the tenant.",
+            Some("path.host"),
+            HashMap::from([("input", json!(trim(&host.host, 64)))]),
+            request_id,
+            Some(tenant_id),
+        )
+        .into_response();
+    }
+
+    if let Err(e) = hosts::remove(&state, &host).await {
+        return internal_error(e, request_id, tenant_id);
+    }
+
+    StatusCode::NO_CONTENT.into_response()
+}
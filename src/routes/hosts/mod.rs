@@ -0,0 +1,19 @@
+mod handlers;
+mod requests;
+
+use axum::{
+    routing::{get, post},
+    Router,
+};
+
+use crate::state::AppState;
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/", get(handlers::list_hosts).post(handlers::add_host))
+        .route(
+            "/:host",
+            get(handlers::get_host).delete(handlers::remove_host),
+        )
+        .route("/:host/verify", post(handlers::verify_host))
+}
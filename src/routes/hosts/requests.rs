@@ -0,0 +1,12 @@
+use serde::Deserialize;
+
+use crate::hosts::Method;
+
+#[derive(Debug, Deserialize)]
+pub struct AddHostPayload {
+    /// A host name or a wildcard like `*.auth.example.com`, optionally followed by a port.
+    pub host: String,
+
+    /// How ownership is proven, which defaults to a DNS TXT record.
+    pub method: Option<Method>,
+}
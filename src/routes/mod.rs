@@ -1,2 +1,13 @@
 pub mod auth;
+pub mod authz;
+pub mod groups;
+pub mod hooks;
+pub mod hosts;
+pub mod organizations;
+pub mod platform;
+pub mod policies;
+pub mod relations;
+pub mod roles;
+pub mod sandbox;
+pub mod settings;
 pub mod users;
@@ -0,0 +1,1438 @@
+use super::requests::{
+    AcceptInvitationPayload, ClaimDomainPayload, CreateOrganizationPayload, InvitePayload,
+    SetMemberPayload, UpdateDomainPayload, UpdateOrganizationPayload,
+};
+use crate::{
+    auth::Auth,
+    emails,
+    error_handlers::{error_response, internal_error},
+    notifications::{self, Event, Priority},
+    organizations::{
+        self, Access, DomainClaim, Invitation, Organization, Role, INVITATION_EXPIRES_IN,
+    },
+    requests::Request,
+    responses::{CommonError, Response, ResponseMeta},
+    state::{self, AppState},
+    tokens::{InvitationToken, TokenType},
+    types::{RequestID, TenantID},
+    users,
+    utils::{id::gen_id, text::trim},
+};
+use axum::{
+    body::Body,
+    extract::{rejection::JsonRejection, Path, State},
+    http::StatusCode,
+    response::{self, IntoResponse},
+    Extension, Json,
+};
+use chrono::{Duration, Utc};
+use scylla::{batch::Batch, Session};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use validator::{ValidateEmail, ValidateLength};
+
+/// Loads an organization and what the user can do in it. Organizations the user can't view are
+/// reported as not found.
+async fn authorize(
+    state: &state::State,
+    tenant_id: &str,
+    organization_id: &str,
+    user_id: &str,
+    request_id: &str,
+) -> Result<(Organization, Access), response::Response<Body>> {
+    let not_found = || {
+        error_response(
+            StatusCode::NOT_FOUND,
+            "Organization Not Found",
+            "There's no organization with this ID.",
+            Some("path.organization_id"),
+            HashMap::from([("input", json!(trim(organization_id, 20)))]),
+            request_id.to_string(),
+            Some(tenant_id.to_string()),
+        )
+        .into_response()
+    };
+
+    let organization = match organizations::get(&state.db, tenant_id, organization_id).await {
+        Ok(Some(o)) => o,
+        Ok(None) => return Err(not_found()),
+        Err(e) => {
+            return Err(internal_error(
+                e,
+                request_id.to_string(),
+                tenant_id.to_string(),
+            ))
+        }
+    };
+
+    let access = match organizations::access(state, tenant_id, organization_id, user_id).await {
+        Ok(a) => a,
+        Err(e) => {
+            return Err(internal_error(
+                e,
+                request_id.to_string(),
+                tenant_id.to_string(),
+            ))
+        }
+    };
+
+    if !access.can_view() {
+        return Err(not_found());
+    }
+
+    Ok((organization, access))
+}
+
+fn validate_name(name: &str, request_id: &str, tenant_id: &str) -> Option<response::Response> {
+    if ValidateLength::validate_length(&name.trim(), Some(1), Some(64), None) {
+        return None;
+    }
+
+    Some(
+        error_response(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "Invalid Organization Name",
+            "The organization name must be from 1 to 64 characters long.",
+            Some("body.data.name"),
+            HashMap::from([("input", json!(trim(name, 20)))]),
+            request_id.to_string(),
+            Some(tenant_id.to_string()),
+        )
+        .into_response(),
+    )
+}
+
+/// Creates an organization owned by the user.
+pub async fn create_organization(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(response_meta): Extension<ResponseMeta<'_>>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    payload: Result<Json<Request<CreateOrganizationPayload>>, JsonRejection>,
+) -> response::Response<Body> {
+    let Json(Request { data: payload, .. }) = match payload {
+        Ok(p) => p,
+        Err(err) => {
+            return CommonError::JsonRejection {
+                err,
+                request_id,
+                tenant_id: Some(tenant_id),
+            }
+            .into_response()
+        }
+    };
+
+    let Some(user_id) = auth.user_id else {
+        return CommonError::Unauthorized {
+            request_id,
+            tenant_id: Some(tenant_id),
+        }
+        .into_response();
+    };
+
+    if let Some(response) = validate_name(&payload.name, &request_id, &tenant_id) {
+        return response;
+    }
+
+    let state = state.read().await;
+
+    let organization_id = gen_id(None);
+    let metadata = payload.metadata.unwrap_or_default();
+
+    let mut batch = Batch::default();
+
+    batch.append_statement("INSERT INTO organizations (tenant_id, organization_id, name, metadata, created_at) VALUES (?, ?, ?, ?, toTimestamp(now()))");
+    batch.append_statement("INSERT INTO organizations_by_user (tenant_id, user_id, organization_id, role, joined_at) VALUES (?, ?, ?, ?, toTimestamp(now()))");
+
+    if let Err(e) = state
+        .db
+        .batch(
+            &batch,
+            (
+                (&tenant_id, &organization_id, payload.name.trim(), &metadata),
+                (&tenant_id, &user_id, &organization_id, Role::Owner as i8),
+            ),
+        )
+        .await
+    {
+        return internal_error(e, request_id, tenant_id);
+    }
+
+    let links = format!("/organizations/{organization_id}/members");
+
+    (
+        StatusCode::CREATED,
+        Response::new(
+            Some(HashMap::from([
+                ("organization_id", json!(organization_id)),
+                ("name", json!(payload.name.trim())),
+                ("metadata", json!(metadata)),
+                ("role", json!(Role::Owner)),
+            ])),
+            None,
+            Some(response_meta),
+            Some(HashMap::from([("members", links.as_str())])),
+        ),
+    )
+        .into_response()
+}
+
+pub async fn get_organization(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(response_meta): Extension<ResponseMeta<'_>>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    Path(organization_id): Path<String>,
+) -> response::Response<Body> {
+    let Some(user_id) = auth.user_id else {
+        return CommonError::Unauthorized {
+            request_id,
+            tenant_id: Some(tenant_id),
+        }
+        .into_response();
+    };
+
+    let state = state.read().await;
+
+    let (organization, _) =
+        match authorize(&state, &tenant_id, &organization_id, &user_id, &request_id).await {
+            Ok(o) => o,
+            Err(response) => return response,
+        };
+
+    Response::new(Some(organization), None, Some(response_meta), None).into_response()
+}
+
+/// Renames the organization or replaces its metadata. Requires the admin or owner role.
+pub async fn update_organization(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(response_meta): Extension<ResponseMeta<'_>>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    Path(organization_id): Path<String>,
+    payload: Result<Json<Request<UpdateOrganizationPayload>>, JsonRejection>,
+) -> response::Response<Body> {
+    let Json(Request { data: payload, .. }) = match payload {
+        Ok(p) => p,
+        Err(err) => {
+            return CommonError::JsonRejection {
+                err,
+                request_id,
+                tenant_id: Some(tenant_id),
+            }
+            .into_response()
+        }
+    };
+
+    let Some(user_id) = auth.user_id else {
+        return CommonError::Unauthorized {
+            request_id,
+            tenant_id: Some(tenant_id),
+        }
+        .into_response();
+    };
+
+    if let Some(name) = &payload.name {
+        if let Some(response) = validate_name(name, &request_id, &tenant_id) {
+            return response;
+        }
+    }
+
+    let state = state.read().await;
+
+    let (mut organization, access) =
+        match authorize(&state, &tenant_id, &organization_id, &user_id, &request_id).await {
+            Ok(o) => o,
+            Err(response) => return response,
+        };
+
+    if !access.can_manage() {
+        return CommonError::Forbidden {
+            request_id,
+            tenant_id: Some(tenant_id),
+        }
+        .into_response();
+    }
+
+    if let Some(name) = payload.name {
+        organization.name = name.trim().to_string();
+    }
+
+    if let Some(metadata) = payload.metadata {
+        organization.metadata = metadata;
+    }
+
+    if let Err(e) = state
+        .db
+        .query_unpaged(
+            "UPDATE organizations SET name = ?, metadata = ? WHERE tenant_id = ? AND organization_id = ?",
+            (
+                &organization.name,
+                &organization.metadata,
+                &tenant_id,
+                &organization_id,
+            ),
+        )
+        .await
+    {
+        return internal_error(e, request_id, tenant_id);
+    }
+
+    Response::new(Some(organization), None, Some(response_meta), None).into_response()
+}
+
+/// Deletes the organization and all its memberships. Requires the owner role.
+pub async fn delete_organization(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    Path(organization_id): Path<String>,
+) -> response::Response<Body> {
+    let Some(user_id) = auth.user_id else {
+        return CommonError::Unauthorized {
+            request_id,
+            tenant_id: Some(tenant_id),
+        }
+        .into_response();
+    };
+
+    let state = state.read().await;
+
+    let (_, access) =
+        match authorize(&state, &tenant_id, &organization_id, &user_id, &request_id).await {
+            Ok(o) => o,
+            Err(response) => return response,
+        };
+
+    if !access.is_owner() {
+        return CommonError::Forbidden {
+            request_id,
+            tenant_id: Some(tenant_id),
+        }
+        .into_response();
+    }
+
+    let members = match organizations::members(&state.db, &tenant_id, &organization_id).await {
+        Ok(m) => m,
+        Err(e) => return internal_error(e, request_id, tenant_id),
+    };
+
+    for member in members {
+        if let Err(e) =
+            organizations::remove_member(&state, &tenant_id, &organization_id, &member.user_id)
+                .await
+        {
+            return internal_error(e, request_id, tenant_id);
+        }
+    }
+
+    if let Err(e) = state
+        .db
+        .query_unpaged(
+            "DELETE FROM organizations WHERE tenant_id = ? AND organization_id = ?",
+            (&tenant_id, &organization_id),
+        )
+        .await
+    {
+        return internal_error(e, request_id, tenant_id);
+    }
+
+    StatusCode::NO_CONTENT.into_response()
+}
+
+pub async fn list_members(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(response_meta): Extension<ResponseMeta<'_>>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    Path(organization_id): Path<String>,
+) -> response::Response<Body> {
+    let Some(user_id) = auth.user_id else {
+        return CommonError::Unauthorized {
+            request_id,
+            tenant_id: Some(tenant_id),
+        }
+        .into_response();
+    };
+
+    let state = state.read().await;
+
+    if let Err(response) =
+        authorize(&state, &tenant_id, &organization_id, &user_id, &request_id).await
+    {
+        return response;
+    }
+
+    match organizations::members(&state.db, &tenant_id, &organization_id).await {
+        Ok(members) => {
+            Response::new(Some(members), None, Some(response_meta), None).into_response()
+        }
+        Err(e) => internal_error(e, request_id, tenant_id),
+    }
+}
+
+/// Adds a member to the organization or changes their role. Admins can manage members and
+/// admins, while only owners can grant or revoke the owner role.
+pub async fn set_member(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(response_meta): Extension<ResponseMeta<'_>>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    Path((organization_id, member_id)): Path<(String, String)>,
+    payload: Result<Json<Request<SetMemberPayload>>, JsonRejection>,
+) -> response::Response<Body> {
+    let Json(Request { data: payload, .. }) = match payload {
+        Ok(p) => p,
+        Err(err) => {
+            return CommonError::JsonRejection {
+                err,
+                request_id,
+                tenant_id: Some(tenant_id),
+            }
+            .into_response()
+        }
+    };
+
+    let Some(user_id) = auth.user_id else {
+        return CommonError::Unauthorized {
+            request_id,
+            tenant_id: Some(tenant_id),
+        }
+        .into_response();
+    };
+
+    let state = state.read().await;
+
+    let (_, access) =
+        match authorize(&state, &tenant_id, &organization_id, &user_id, &request_id).await {
+            Ok(o) => o,
+            Err(response) => return response,
+        };
+
+    let current_role =
+        match organizations::role(&state.db, &tenant_id, &organization_id, &member_id).await {
+            Ok(r) => r,
+            Err(e) => return internal_error(e, request_id, tenant_id),
+        };
+
+    if !access.can_change_role(current_role, Some(payload.role)) {
+        return CommonError::Forbidden {
+            request_id,
+            tenant_id: Some(tenant_id),
+        }
+        .into_response();
+    }
+
+    let result = match current_role {
+        Some(Role::Owner) if payload.role != Role::Owner => {
+            match last_owner(&state.db, &tenant_id, &organization_id, &member_id).await {
+                Ok(true) => return last_owner_response(request_id, tenant_id),
+                Ok(false) => {}
+                Err(e) => return internal_error(e, request_id, tenant_id),
+            }
+
+            organizations::set_role(
+                &state,
+                &tenant_id,
+                &organization_id,
+                &member_id,
+                payload.role,
+            )
+            .await
+        }
+        Some(_) => {
+            organizations::set_role(
+                &state,
+                &tenant_id,
+                &organization_id,
+                &member_id,
+                payload.role,
+            )
+            .await
+        }
+        None => {
+            match users::exists(&state.db, &tenant_id, &member_id).await {
+                Ok(true) => {}
+                Ok(false) => {
+                    return error_response(
+                        StatusCode::NOT_FOUND,
+                        "User Not Found",
+                        "There's no user with this ID.",
+                        Some("path.user_id"),
+                        HashMap::from([("input", json!(trim(&member_id, 20)))]),
+                        request_id,
+                        Some(tenant_id),
+                    )
+                    .into_response()
+                }
+                Err(e) => return internal_error(e, request_id, tenant_id),
+            }
+
+            organizations::add_member(
+                &state,
+                &tenant_id,
+                &organization_id,
+                &member_id,
+                payload.role,
+            )
+            .await
+        }
+    };
+
+    if let Err(e) = result {
+        return internal_error(e, request_id, tenant_id);
+    }
+
+    (
+        if current_role.is_some() {
+            StatusCode::OK
+        } else {
+            StatusCode::CREATED
+        },
+        Response::new(
+            Some(HashMap::from([
+                ("organization_id", json!(organization_id)),
+                ("user_id", json!(member_id)),
+                ("role", json!(payload.role)),
+            ])),
+            None,
+            Some(response_meta),
+            None,
+        ),
+    )
+        .into_response()
+}
+
+/// Removes a member from the organization. Members can always remove themselves, except for the
+/// last owner.
+pub async fn remove_member(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    Path((organization_id, member_id)): Path<(String, String)>,
+) -> response::Response<Body> {
+    let Some(user_id) = auth.user_id else {
+        return CommonError::Unauthorized {
+            request_id,
+            tenant_id: Some(tenant_id),
+        }
+        .into_response();
+    };
+
+    let state = state.read().await;
+
+    let (_, access) =
+        match authorize(&state, &tenant_id, &organization_id, &user_id, &request_id).await {
+            Ok(o) => o,
+            Err(response) => return response,
+        };
+
+    let current_role =
+        match organizations::role(&state.db, &tenant_id, &organization_id, &member_id).await {
+            Ok(Some(r)) => r,
+            Ok(None) => {
+                return error_response(
+                    StatusCode::NOT_FOUND,
+                    "Member Not Found",
+                    "The user is not a member of this organization.",
+                    Some("path.user_id"),
+                    HashMap::from([("input", json!(trim(&member_id, 20)))]),
+                    request_id,
+                    Some(tenant_id),
+                )
+                .into_response()
+            }
+            Err(e) => return internal_error(e, request_id, tenant_id),
+        };
+
+    if member_id != user_id && !access.can_change_role(Some(current_role), None) {
+        return CommonError::Forbidden {
+            request_id,
+            tenant_id: Some(tenant_id),
+        }
+        .into_response();
+    }
+
+    if current_role == Role::Owner {
+        match last_owner(&state.db, &tenant_id, &organization_id, &member_id).await {
+            Ok(true) => return last_owner_response(request_id, tenant_id),
+            Ok(false) => {}
+            Err(e) => return internal_error(e, request_id, tenant_id),
+        }
+    }
+
+    if let Err(e) =
+        organizations::remove_member(&state, &tenant_id, &organization_id, &member_id).await
+    {
+        return internal_error(e, request_id, tenant_id);
+    }
+
+    StatusCode::NO_CONTENT.into_response()
+}
+
+/// Checks whether `user_id` is the only owner of the organization.
+async fn last_owner(
+    db: &Session,
+    tenant_id: &str,
+    organization_id: &str,
+    user_id: &str,
+) -> Result<bool, scylla::transport::errors::QueryError> {
+    Ok(organizations::members(db, tenant_id, organization_id)
+        .await?
+        .iter()
+        .all(|member| member.role != Role::Owner || member.user_id == user_id))
+}
+
+fn last_owner_response(request_id: String, tenant_id: String) -> response::Response<Body> {
+    error_response(
+        StatusCode::CONFLICT,
+        "Last Owner",
+        "The organization must have at least one owner. Make another member an owner first.",
+        Some("path.user_id"),
+        HashMap::new(),
+        request_id,
+        Some(tenant_id),
+    )
+    .into_response()
+}
+
+/// Invites an email to join the organization with a role. The invitee gets a signed token by
+/// email, which existing users accept through `/organizations/invitations/accept` and new users
+/// pass to `/auth/sign-up`.
+pub async fn invite(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(response_meta): Extension<ResponseMeta<'_>>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    Path(organization_id): Path<String>,
+    payload: Result<Json<Request<InvitePayload>>, JsonRejection>,
+) -> response::Response<Body> {
+    let Json(Request { data: payload, .. }) = match payload {
+        Ok(p) => p,
+        Err(err) => {
+            return CommonError::JsonRejection {
+                err,
+                request_id,
+                tenant_id: Some(tenant_id),
+            }
+            .into_response()
+        }
+    };
+
+    let Some(user_id) = auth.user_id else {
+        return CommonError::Unauthorized {
+            request_id,
+            tenant_id: Some(tenant_id),
+        }
+        .into_response();
+    };
+
+    if !ValidateEmail::validate_email(&payload.email) {
+        return error_response(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "Invalid Email",
+            "The email field requires a valid email.",
+            Some("body.data.email"),
+            HashMap::from([("input", json!(trim(&payload.email, 20)))]),
+            request_id,
+            Some(tenant_id),
+        )
+        .into_response();
+    }
+
+    let state = state.read().await;
+
+    let (organization, access) =
+        match authorize(&state, &tenant_id, &organization_id, &user_id, &request_id).await {
+            Ok(o) => o,
+            Err(response) => return response,
+        };
+
+    if !access.can_change_role(None, Some(payload.role)) {
+        return CommonError::Forbidden {
+            request_id,
+            tenant_id: Some(tenant_id),
+        }
+        .into_response();
+    }
+
+    let email_policy = match emails::Policy::load(&state, &tenant_id).await {
+        Ok(p) => p,
+        Err(e) => return internal_error(e, request_id, tenant_id),
+    };
+
+    if let Err(violation) = email_policy.check_domain(&payload.email) {
+        let (message, detail) = violation.details();
+
+        return error_response(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            message,
+            detail,
+            Some("body.data.email"),
+            HashMap::from([
+                ("input", json!(trim(&payload.email, 20))),
+                ("domain", json!(emails::domain(&payload.email))),
+            ]),
+            request_id,
+            Some(tenant_id),
+        )
+        .into_response();
+    }
+
+    let email = email_policy.normalize(&payload.email);
+
+    let invitee = match emails::owner(&state.db, &tenant_id, &email).await {
+        Ok(o) => o,
+        Err(e) => return internal_error(e, request_id, tenant_id),
+    };
+
+    if let Some(invitee) = &invitee {
+        match organizations::role(&state.db, &tenant_id, &organization_id, invitee).await {
+            Ok(None) => {}
+            Ok(Some(_)) => {
+                return error_response(
+                    StatusCode::CONFLICT,
+                    "Already A Member",
+                    "The user with this email is already a member of the organization.",
+                    Some("body.data.email"),
+                    HashMap::from([("input", json!(trim(&payload.email, 20)))]),
+                    request_id,
+                    Some(tenant_id),
+                )
+                .into_response()
+            }
+            Err(e) => return internal_error(e, request_id, tenant_id),
+        }
+    }
+
+    let now = Utc::now();
+    let expires_at = now + Duration::seconds(INVITATION_EXPIRES_IN as i64);
+
+    let invitation = Invitation {
+        invitation_id: gen_id(None),
+        organization_id: organization_id.clone(),
+        email: email.clone(),
+        address: payload.email.clone(),
+        role: payload.role,
+        invited_by: Some(user_id),
+        created_at: Some(now.timestamp_millis()),
+        expires_at: Some(expires_at.timestamp_millis()),
+    };
+
+    if let Err(e) = organizations::create_invitation(&state.db, &tenant_id, &invitation).await {
+        return internal_error(e, request_id, tenant_id);
+    }
+
+    let token = InvitationToken {
+        token_type: TokenType::Invitation,
+        tenant_id: tenant_id.clone(),
+        organization_id: organization_id.clone(),
+        invitation_id: invitation.invitation_id.clone(),
+        email,
+        expires_at: expires_at.timestamp(),
+    }
+    .sign(&state.hmac);
+
+    let token = match token {
+        Ok(t) => t,
+        Err(e) => return internal_error(e, request_id, tenant_id),
+    };
+
+    let accept = if invitee.is_some() {
+        "/organizations/invitations/accept"
+    } else {
+        "/auth/sign-up"
+    };
+
+    if let Err(e) = notifications::send_email(
+        &state,
+        &tenant_id,
+        invitee.as_deref(),
+        &payload.email,
+        Event::OrganizationInvitation,
+        Priority::Medium,
+        "You're invited to join an organization",
+        "You were invited to join an organization. Use the invitation token to accept it.",
+        HashMap::from([
+            ("invitation_token".to_string(), token),
+            ("organization_id".to_string(), organization_id),
+            ("organization_name".to_string(), organization.name),
+            ("role".to_string(), payload.role.name().to_string()),
+            ("accept".to_string(), accept.to_string()),
+        ]),
+    )
+    .await
+    {
+        return internal_error(e, request_id, tenant_id);
+    }
+
+    (
+        StatusCode::CREATED,
+        Response::new(Some(invitation), None, Some(response_meta), None),
+    )
+        .into_response()
+}
+
+/// Lists the organization's pending invitations. Requires the admin or owner role.
+pub async fn list_invitations(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(response_meta): Extension<ResponseMeta<'_>>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    Path(organization_id): Path<String>,
+) -> response::Response<Body> {
+    let Some(user_id) = auth.user_id else {
+        return CommonError::Unauthorized {
+            request_id,
+            tenant_id: Some(tenant_id),
+        }
+        .into_response();
+    };
+
+    let state = state.read().await;
+
+    let (_, access) =
+        match authorize(&state, &tenant_id, &organization_id, &user_id, &request_id).await {
+            Ok(o) => o,
+            Err(response) => return response,
+        };
+
+    if !access.can_manage() {
+        return CommonError::Forbidden {
+            request_id,
+            tenant_id: Some(tenant_id),
+        }
+        .into_response();
+    }
+
+    match organizations::invitations(&state.db, &tenant_id, &organization_id).await {
+        Ok(invitations) => {
+            Response::new(Some(invitations), None, Some(response_meta), None).into_response()
+        }
+        Err(e) => internal_error(e, request_id, tenant_id),
+    }
+}
+
+/// Revokes a pending invitation, which invalidates its token.
+pub async fn revoke_invitation(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    Path((organization_id, invitation_id)): Path<(String, String)>,
+) -> response::Response<Body> {
+    let Some(user_id) = auth.user_id else {
+        return CommonError::Unauthorized {
+            request_id,
+            tenant_id: Some(tenant_id),
+        }
+        .into_response();
+    };
+
+    let state = state.read().await;
+
+    let (_, access) =
+        match authorize(&state, &tenant_id, &organization_id, &user_id, &request_id).await {
+            Ok(o) => o,
+            Err(response) => return response,
+        };
+
+    let invitation =
+        match organizations::invitation(&state.db, &tenant_id, &organization_id, &invitation_id)
+            .await
+        {
+            Ok(Some(i)) => i,
+            Ok(None) => {
+                return error_response(
+                    StatusCode::NOT_FOUND,
+                    "Invitation Not Found",
+                    "There's no pending invitation with this ID.",
+                    Some("path.invitation_id"),
+                    HashMap::from([("input", json!(trim(&invitation_id, 20)))]),
+                    request_id,
+                    Some(tenant_id),
+                )
+                .into_response()
+            }
+            Err(e) => return internal_error(e, request_id, tenant_id),
+        };
+
+    if !access.can_change_role(None, Some(invitation.role)) {
+        return CommonError::Forbidden {
+            request_id,
+            tenant_id: Some(tenant_id),
+        }
+        .into_response();
+    }
+
+    if let Err(e) =
+        organizations::delete_invitation(&state.db, &tenant_id, &organization_id, &invitation_id)
+            .await
+    {
+        return internal_error(e, request_id, tenant_id);
+    }
+
+    StatusCode::NO_CONTENT.into_response()
+}
+
+/// Accepts an invitation as an existing user. The invitation's email must be one of the user's
+/// verified emails.
+pub async fn accept_invitation(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(response_meta): Extension<ResponseMeta<'_>>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    payload: Result<Json<Request<AcceptInvitationPayload>>, JsonRejection>,
+) -> response::Response<Body> {
+    let Json(Request { data: payload, .. }) = match payload {
+        Ok(p) => p,
+        Err(err) => {
+            return CommonError::JsonRejection {
+                err,
+                request_id,
+                tenant_id: Some(tenant_id),
+            }
+            .into_response()
+        }
+    };
+
+    let Some(user_id) = auth.user_id else {
+        return CommonError::Unauthorized {
+            request_id,
+            tenant_id: Some(tenant_id),
+        }
+        .into_response();
+    };
+
+    let state = state.read().await;
+
+    let invitation = match organizations::pending_invitation(
+        &state.db,
+        &state.hmac,
+        &tenant_id,
+        &payload.invitation_token,
+    )
+    .await
+    {
+        Ok(Some(i)) => i,
+        Ok(None) => return invalid_invitation_response(request_id, tenant_id),
+        Err(e) => return internal_error(e, request_id, tenant_id),
+    };
+
+    let user_emails = match emails::list(&state.db, &tenant_id, &user_id).await {
+        Ok(e) => e,
+        Err(e) => return internal_error(e, request_id, tenant_id),
+    };
+
+    if !user_emails
+        .iter()
+        .any(|row| row.is_verified && row.email == invitation.email)
+    {
+        return error_response(
+            StatusCode::FORBIDDEN,
+            "Invitation Email Mismatch",
+            "The invitation was sent to an email that isn't verified on your account.",
+            Some("body.data.invitation_token"),
+            HashMap::from([("email", json!(emails::mask(&invitation.address)))]),
+            request_id,
+            Some(tenant_id),
+        )
+        .into_response();
+    }
+
+    let organization_id = invitation.organization_id;
+
+    match organizations::role(&state.db, &tenant_id, &organization_id, &user_id).await {
+        Ok(None) => {}
+        Ok(Some(_)) => {
+            if let Err(e) = organizations::delete_invitation(
+                &state.db,
+                &tenant_id,
+                &organization_id,
+                &invitation.invitation_id,
+            )
+            .await
+            {
+                return internal_error(e, request_id, tenant_id);
+            }
+
+            return error_response(
+                StatusCode::CONFLICT,
+                "Already A Member",
+                "You're already a member of this organization.",
+                Some("body.data.invitation_token"),
+                HashMap::new(),
+                request_id,
+                Some(tenant_id),
+            )
+            .into_response();
+        }
+        Err(e) => return internal_error(e, request_id, tenant_id),
+    }
+
+    let result = organizations::add_member(
+        &state,
+        &tenant_id,
+        &organization_id,
+        &user_id,
+        invitation.role,
+    )
+    .await;
+
+    let result = match result {
+        Ok(()) => {
+            organizations::delete_invitation(
+                &state.db,
+                &tenant_id,
+                &organization_id,
+                &invitation.invitation_id,
+            )
+            .await
+        }
+        Err(e) => Err(e),
+    };
+
+    if let Err(e) = result {
+        return internal_error(e, request_id, tenant_id);
+    }
+
+    let links = format!("/organizations/{organization_id}");
+
+    (
+        StatusCode::CREATED,
+        Response::new(
+            Some(HashMap::from([
+                ("organization_id", json!(organization_id)),
+                ("user_id", json!(user_id)),
+                ("role", json!(invitation.role)),
+            ])),
+            None,
+            Some(response_meta),
+            Some(HashMap::from([("organization", links.as_str())])),
+        ),
+    )
+        .into_response()
+}
+
+fn invalid_invitation_response(request_id: String, tenant_id: String) -> response::Response {
+    error_response(
+        StatusCode::NOT_FOUND,
+        "Invitation Not Found",
+        "The invitation is invalid, has expired or was revoked.",
+        Some("body.data.invitation_token"),
+        HashMap::new(),
+        request_id,
+        Some(tenant_id),
+    )
+    .into_response()
+}
+
+/// Serializes a domain claim along with the TXT record that proves it.
+fn domain_claim_data(claim: &DomainClaim) -> Value {
+    let mut data = json!(claim);
+
+    data["txt_record"] = json!({
+        "name": claim.record_name(),
+        "value": claim.record_value(),
+    });
+
+    data
+}
+
+/// Loads a domain claim of an organization the user owns.
+async fn owned_domain_claim(
+    state: &state::State,
+    tenant_id: &str,
+    organization_id: &str,
+    user_id: &str,
+    domain: &str,
+    request_id: &str,
+) -> Result<DomainClaim, response::Response<Body>> {
+    let (_, access) = authorize(state, tenant_id, organization_id, user_id, request_id).await?;
+
+    if !access.is_owner() {
+        return Err(CommonError::Forbidden {
+            request_id: request_id.to_string(),
+            tenant_id: Some(tenant_id.to_string()),
+        }
+        .into_response());
+    }
+
+    match organizations::domain_claim(
+        &state.db,
+        tenant_id,
+        organization_id,
+        &domain.to_lowercase(),
+    )
+    .await
+    {
+        Ok(Some(claim)) => Ok(claim),
+        Ok(None) => Err(error_response(
+            StatusCode::NOT_FOUND,
+            "Domain Not Found",
+            "The organization hasn't claimed this domain.",
+            Some("path.domain"),
+            HashMap::from([("input", json!(trim(domain, 20)))]),
+            request_id.to_string(),
+            Some(tenant_id.to_string()),
+        )
+        .into_response()),
+        Err(e) => Err(internal_error(
+            e,
+            request_id.to_string(),
+            tenant_id.to_string(),
+        )),
+    }
+}
+
+fn domain_claimed_response(
+    domain: &str,
+    request_id: String,
+    tenant_id: String,
+) -> response::Response {
+    error_response(
+        StatusCode::CONFLICT,
+        "Domain Already Claimed",
+        "Another organization already verified this domain.",
+        Some("body.data.domain"),
+        HashMap::from([("input", json!(trim(domain, 20)))]),
+        request_id,
+        Some(tenant_id),
+    )
+    .into_response()
+}
+
+/// Claims an email domain for the organization. The claim must be verified by publishing the
+/// returned TXT record before users on the domain join automatically. Requires the owner role.
+pub async fn claim_domain(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(response_meta): Extension<ResponseMeta<'_>>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    Path(organization_id): Path<String>,
+    payload: Result<Json<Request<ClaimDomainPayload>>, JsonRejection>,
+) -> response::Response<Body> {
+    let Json(Request { data: payload, .. }) = match payload {
+        Ok(p) => p,
+        Err(err) => {
+            return CommonError::JsonRejection {
+                err,
+                request_id,
+                tenant_id: Some(tenant_id),
+            }
+            .into_response()
+        }
+    };
+
+    let Some(user_id) = auth.user_id else {
+        return CommonError::Unauthorized {
+            request_id,
+            tenant_id: Some(tenant_id),
+        }
+        .into_response();
+    };
+
+    let domain = payload.domain.trim().trim_end_matches('.').to_lowercase();
+
+    // A domain is valid if it makes a valid email.
+    if !domain.contains('.') || !ValidateEmail::validate_email(&format!("user@{domain}")) {
+        return error_response(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "Invalid Domain",
+            "The domain field requires a valid domain, like example.com.",
+            Some("body.data.domain"),
+            HashMap::from([("input", json!(trim(&payload.domain, 20)))]),
+            request_id,
+            Some(tenant_id),
+        )
+        .into_response();
+    }
+
+    if emails::is_disposable(&domain) {
+        return error_response(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "Disposable Domain",
+            "Domains of disposable email providers can't be claimed.",
+            Some("body.data.domain"),
+            HashMap::from([("input", json!(trim(&payload.domain, 20)))]),
+            request_id,
+            Some(tenant_id),
+        )
+        .into_response();
+    }
+
+    let state = state.read().await;
+
+    let (_, access) =
+        match authorize(&state, &tenant_id, &organization_id, &user_id, &request_id).await {
+            Ok(o) => o,
+            Err(response) => return response,
+        };
+
+    if !access.is_owner() {
+        return CommonError::Forbidden {
+            request_id,
+            tenant_id: Some(tenant_id),
+        }
+        .into_response();
+    }
+
+    match organizations::domain_owner(&state.db, &tenant_id, &domain).await {
+        Ok(Some((owner, _))) if owner != organization_id => {
+            return domain_claimed_response(&domain, request_id, tenant_id)
+        }
+        Ok(_) => {}
+        Err(e) => return internal_error(e, request_id, tenant_id),
+    }
+
+    match organizations::claim_domain(
+        &state.db,
+        &tenant_id,
+        &organization_id,
+        &domain,
+        payload.require_sso.unwrap_or(false),
+    )
+    .await
+    {
+        Ok(claim) => {
+            let links = format!("/organizations/{organization_id}/domains/{domain}/verify");
+
+            (
+                StatusCode::CREATED,
+                Response::new(
+                    Some(domain_claim_data(&claim)),
+                    None,
+                    Some(response_meta),
+                    Some(HashMap::from([("verify", links.as_str())])),
+                ),
+            )
+                .into_response()
+        }
+        Err(e) => internal_error(e, request_id, tenant_id),
+    }
+}
+
+/// Lists the organization's domain claims. Requires the admin or owner role.
+pub async fn list_domains(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(response_meta): Extension<ResponseMeta<'_>>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    Path(organization_id): Path<String>,
+) -> response::Response<Body> {
+    let Some(user_id) = auth.user_id else {
+        return CommonError::Unauthorized {
+            request_id,
+            tenant_id: Some(tenant_id),
+        }
+        .into_response();
+    };
+
+    let state = state.read().await;
+
+    let (_, access) =
+        match authorize(&state, &tenant_id, &organization_id, &user_id, &request_id).await {
+            Ok(o) => o,
+            Err(response) => return response,
+        };
+
+    if !access.can_manage() {
+        return CommonError::Forbidden {
+            request_id,
+            tenant_id: Some(tenant_id),
+        }
+        .into_response();
+    }
+
+    match organizations::domain_claims(&state.db, &tenant_id, &organization_id).await {
+        Ok(claims) => Response::new(
+            Some(claims.iter().map(domain_claim_data).collect::<Vec<Value>>()),
+            None,
+            Some(response_meta),
+            None,
+        )
+        .into_response(),
+        Err(e) => internal_error(e, request_id, tenant_id),
+    }
+}
+
+/// Verifies a domain claim by looking up its TXT record.
+pub async fn verify_domain(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(response_meta): Extension<ResponseMeta<'_>>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    Path((organization_id, domain)): Path<(String, String)>,
+) -> response::Response<Body> {
+    let Some(user_id) = auth.user_id else {
+        return CommonError::Unauthorized {
+            request_id,
+            tenant_id: Some(tenant_id),
+        }
+        .into_response();
+    };
+
+    let state = state.read().await;
+
+    let mut claim = match owned_domain_claim(
+        &state,
+        &tenant_id,
+        &organization_id,
+        &user_id,
+        &domain,
+        &request_id,
+    )
+    .await
+    {
+        Ok(c) => c,
+        Err(response) => return response,
+    };
+
+    if !organizations::is_proven(&state.dns, &claim).await {
+        return error_response(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "Domain Verification Failed",
+            "The TXT record wasn't found. DNS changes can take a while to propagate, so try again later.",
+            Some("path.domain"),
+            HashMap::from([
+                ("name", json!(claim.record_name())),
+                ("value", json!(claim.record_value())),
+            ]),
+            request_id,
+            Some(tenant_id),
+        )
+        .into_response();
+    }
+
+    match organizations::verify_domain(&state.db, &tenant_id, &claim).await {
+        Ok(true) => {}
+        Ok(false) => return domain_claimed_response(&claim.domain, request_id, tenant_id),
+        Err(e) => return internal_error(e, request_id, tenant_id),
+    }
+
+    claim.is_verified = true;
+    claim.verified_at = Some(Utc::now().timestamp_millis());
+
+    Response::new(
+        Some(domain_claim_data(&claim)),
+        None,
+        Some(response_meta),
+        None,
+    )
+    .into_response()
+}
+
+/// Changes whether members on the domain must sign in with the organization's SSO.
+pub async fn update_domain(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(response_meta): Extension<ResponseMeta<'_>>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    Path((organization_id, domain)): Path<(String, String)>,
+    payload: Result<Json<Request<UpdateDomainPayload>>, JsonRejection>,
+) -> response::Response<Body> {
+    let Json(Request { data: payload, .. }) = match payload {
+        Ok(p) => p,
+        Err(err) => {
+            return CommonError::JsonRejection {
+                err,
+                request_id,
+                tenant_id: Some(tenant_id),
+            }
+            .into_response()
+        }
+    };
+
+    let Some(user_id) = auth.user_id else {
+        return CommonError::Unauthorized {
+            request_id,
+            tenant_id: Some(tenant_id),
+        }
+        .into_response();
+    };
+
+    let state = state.read().await;
+
+    let mut claim = match owned_domain_claim(
+        &state,
+        &tenant_id,
+        &organization_id,
+        &user_id,
+        &domain,
+        &request_id,
+    )
+    .await
+    {
+        Ok(c) => c,
+        Err(response) => return response,
+    };
+
+    if let Err(e) =
+        organizations::set_require_sso(&state.db, &tenant_id, &claim, payload.require_sso).await
+    {
+        return internal_error(e, request_id, tenant_id);
+    }
+
+    claim.require_sso = payload.require_sso;
+
+    Response::new(
+        Some(domain_claim_data(&claim)),
+        None,
+        Some(response_meta),
+        None,
+    )
+    .into_response()
+}
+
+/// Removes a domain claim. Existing members who joined through it stay in the organization.
+pub async fn remove_domain(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    Path((organization_id, domain)): Path<(String, String)>,
+) -> response::Response<Body> {
+    let Some(user_id) = auth.user_id else {
+        return CommonError::Unauthorized {
+            request_id,
+            tenant_id: Some(tenant_id),
+        }
+        .into_response();
+    };
+
+    let state = state.read().await;
+
+    let claim = match owned_domain_claim(
+        &state,
+        &tenant_id,
+        &organization_id,
+        &user_id,
+        &domain,
+        &request_id,
+    )
+    .await
+    {
+        Ok(c) => c,
+        Err(response) => return response,
+    };
+
+    if let Err(e) = organizations::remove_domain(&state.db, &tenant_id, &claim).await {
+        return internal_error(e, request_id, tenant_id);
+    }
+
+    StatusCode::NO_CONTENT.into_response()
+}
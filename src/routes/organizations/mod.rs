@@ -0,0 +1,46 @@
+mod handlers;
+mod requests;
+
+use axum::{
+    routing::{delete, get, patch, post, put},
+    Router,
+};
+
+use crate::state::AppState;
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/", post(handlers::create_organization))
+        .route("/invitations/accept", post(handlers::accept_invitation))
+        .route(
+            "/:organization_id",
+            get(handlers::get_organization)
+                .patch(handlers::update_organization)
+                .delete(handlers::delete_organization),
+        )
+        .route("/:organization_id/members", get(handlers::list_members))
+        .route(
+            "/:organization_id/members/:user_id",
+            put(handlers::set_member).delete(handlers::remove_member),
+        )
+        .route(
+            "/:organization_id/invitations",
+            get(handlers::list_invitations).post(handlers::invite),
+        )
+        .route(
+            "/:organization_id/invitations/:invitation_id",
+            delete(handlers::revoke_invitation),
+        )
+        .route(
+            "/:organization_id/domains",
+            get(handlers::list_domains).post(handlers::claim_domain),
+        )
+        .route(
+            "/:organization_id/domains/:domain",
+            patch(handlers::update_domain).delete(handlers::remove_domain),
+        )
+        .route(
+            "/:organization_id/domains/:domain/verify",
+            post(handlers::verify_domain),
+        )
+}
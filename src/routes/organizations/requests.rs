@@ -0,0 +1,44 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+use crate::organizations::Role;
+
+#[derive(Debug, Deserialize)]
+pub struct CreateOrganizationPayload {
+    pub name: String,
+    pub metadata: Option<HashMap<String, String>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateOrganizationPayload {
+    pub name: Option<String>,
+    pub metadata: Option<HashMap<String, String>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SetMemberPayload {
+    pub role: Role,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct InvitePayload {
+    pub email: String,
+    pub role: Role,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AcceptInvitationPayload {
+    pub invitation_token: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ClaimDomainPayload {
+    pub domain: String,
+    pub require_sso: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateDomainPayload {
+    pub require_sso: bool,
+}
@@ -0,0 +1,1608 @@
+use super::requests::{
+    CreateTenantPayload, FlagChangesQuery, ImportTenantPayload, PurgeTenantPayload, SetFlagPayload,
+    SetQuotaPayload, UpdateTenantPayload, UsageQuery,
+};
+use crate::{
+    archive,
+    auth::Auth,
+    cache,
+    error_handlers::{error_response, internal_error},
+    flags, hosts, permissions, purge,
+    requests::Request,
+    responses::{CommonError, Error, Response, ResponseMeta},
+    state::{self, AppState},
+    tenants::{self, Tenant, MANAGE_PERMISSION, PLATFORM_TENANT_ID},
+    types::{RequestID, TenantID},
+    usage,
+    utils::text::trim,
+};
+use axum::{
+    body::Body,
+    extract::{rejection::JsonRejection, Path, Query, State},
+    http::{header, StatusCode},
+    response::{self, IntoResponse},
+    Extension, Json,
+};
+use chrono::{NaiveDate, Utc};
+use scylla::Session;
+use serde_json::{json, Value};
+use std::{collections::HashMap, time::Duration};
+
+/// Maximum number of admins assigned when creating a tenant.
+const MAX_INITIAL_ADMINS: usize = 10;
+
+/// Checks that the request is made to the platform tenant by one of its admins, with a token
+/// allowing tenant management.
+async fn authorize(
+    state: &state::State,
+    tenant_id: &str,
+    user_id: Option<String>,
+    scopes: &[String],
+    request_id: &str,
+) -> Result<String, response::Response<Body>> {
+    let Some(user_id) = user_id else {
+        return Err(CommonError::Unauthorized {
+            request_id: request_id.to_string(),
+            tenant_id: Some(tenant_id.to_string()),
+        }
+        .into_response());
+    };
+
+    let forbidden = CommonError::Forbidden {
+        request_id: request_id.to_string(),
+        tenant_id: Some(tenant_id.to_string()),
+    };
+
+    if tenant_id != PLATFORM_TENANT_ID || !permissions::scopes_allow(scopes, MANAGE_PERMISSION) {
+        return Err(forbidden.into_response());
+    }
+
+    match tenants::is_admin(&state.db, PLATFORM_TENANT_ID, &user_id).await {
+        Ok(true) => Ok(user_id),
+        Ok(false) => Err(forbidden.into_response()),
+        Err(e) => Err(internal_error(
+            e,
+            request_id.to_string(),
+            tenant_id.to_string(),
+        )),
+    }
+}
+
+fn unprocessable(errors: Vec<Error>, response_meta: ResponseMeta<'_>) -> response::Response<Body> {
+    let response: Response<Value> = Response::new(None, Some(errors), Some(response_meta), None);
+
+    (StatusCode::UNPROCESSABLE_ENTITY, Json(response)).into_response()
+}
+
+fn conflict_response(
+    title: &str,
+    detail: &str,
+    location: &str,
+    input: &str,
+    request_id: String,
+) -> response::Response<Body> {
+    error_response(
+        StatusCode::CONFLICT,
+        title,
+        detail,
+        Some(location),
+        HashMap::from([("input", json!(trim(input, 64)))]),
+        request_id,
+        Some(PLATFORM_TENANT_ID.to_string()),
+    )
+    .into_response()
+}
+
+async fn find_tenant(
+    db: &Session,
+    tenant_id: &str,
+    request_id: &str,
+) -> Result<Tenant, response::Response<Body>> {
+    match tenants::get(db, tenant_id).await {
+        Ok(Some(tenant)) => Ok(tenant),
+        Ok(None) => Err(error_response(
+            StatusCode::NOT_FOUND,
+            "Tenant Not Found",
+            "There's no tenant with this ID.",
+            Some("path.tenant_id"),
+            HashMap::from([("input", json!(trim(tenant_id, 32)))]),
+            request_id.to_string(),
+            Some(PLATFORM_TENANT_ID.to_string()),
+        )
+        .into_response()),
+        Err(e) => Err(internal_error(
+            e,
+            request_id.to_string(),
+            PLATFORM_TENANT_ID.to_string(),
+        )),
+    }
+}
+
+/// Reads a tenant along with its hosts, admins and signing keys.
+async fn details(
+    db: &Session,
+    tenant_id: &str,
+    request_id: &str,
+) -> Result<Value, response::Response<Body>> {
+    let tenant = find_tenant(db, tenant_id, request_id).await?;
+
+    match tokio::try_join!(
+        hosts::list(db, tenant_id),
+        tenants::admins(db, tenant_id),
+        tenants::signing_keys(db, tenant_id),
+    ) {
+        Ok((hosts, admins, signing_keys)) => {
+            let mut value = json!(tenant);
+            value["hosts"] = json!(hosts);
+            value["admins"] = json!(admins);
+            value["signing_keys"] = json!(signing_keys);
+
+            Ok(value)
+        }
+        Err(e) => Err(internal_error(
+            e,
+            request_id.to_string(),
+            PLATFORM_TENANT_ID.to_string(),
+        )),
+    }
+}
+
+fn validate_name(name: &str) -> Option<Error> {
+    (name.trim().is_empty() || name.chars().count() > 64).then(|| {
+        Error::new(
+            StatusCode::UNPROCESSABLE_ENTITY.as_u16(),
+            "Invalid Name",
+            "The name must be from 1 to 64 characters long.",
+            Some("body.data.name"),
+            HashMap::new(),
+        )
+    })
+}
+
+/// Validates a host, which mustn't be linked to another tenant.
+async fn validate_host(
+    db: &Session,
+    host: &str,
+    location: &str,
+    tenant_id: Option<&str>,
+) -> Result<Option<Error>, scylla::transport::errors::QueryError> {
+    if !hosts::is_valid_for_platform(host) {
+        return Ok(Some(Error::new(
+            StatusCode::UNPROCESSABLE_ENTITY.as_u16(),
+            "Invalid Host",
+            "The host must be a lowercase host name or a wildcard like *.example.com, optionally followed by a port.",
+            Some(location),
+            HashMap::from([("input", json!(trim(host, 64)))]),
+        )));
+    }
+
+    Ok(match hosts::owner(db, host).await? {
+        Some(owner) if Some(owner.as_str()) != tenant_id => Some(Error::new(
+            StatusCode::UNPROCESSABLE_ENTITY.as_u16(),
+            "Host Taken",
+            "The host is already linked to another tenant.",
+            Some(location),
+            HashMap::from([("input", json!(trim(host, 64)))]),
+        )),
+        _ => None,
+    })
+}
+
+/// Lists every tenant. Only the platform's super-admins can manage tenants.
+pub async fn list_tenants(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(response_meta): Extension<ResponseMeta<'_>>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+) -> response::Response<Body> {
+    let state = state.read().await;
+
+    if let Err(response) =
+        authorize(&state, &tenant_id, auth.user_id, &auth.scopes, &request_id).await
+    {
+        return response;
+    }
+
+    match tenants::all(&state.db).await {
+        Ok(all) => Response::new(Some(all), None, Some(response_meta), None).into_response(),
+        Err(e) => internal_error(e, request_id, tenant_id),
+    }
+}
+
+/// Creates a tenant linked to its first host, with its initial admins, default settings and a signing
+/// key.
+pub async fn create_tenant(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(response_meta): Extension<ResponseMeta<'_>>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    payload: Result<Json<Request<CreateTenantPayload>>, JsonRejection>,
+) -> response::Response<Body> {
+    let Json(Request { data: payload, .. }) = match payload {
+        Ok(p) => p,
+        Err(err) => {
+            return CommonError::JsonRejection {
+                err,
+                request_id,
+                tenant_id: Some(tenant_id),
+            }
+            .into_response()
+        }
+    };
+
+    let state = state.read().await;
+
+    if let Err(response) =
+        authorize(&state, &tenant_id, auth.user_id, &auth.scopes, &request_id).await
+    {
+        return response;
+    }
+
+    let new_tenant_id = payload
+        .tenant_id
+        .clone()
+        .unwrap_or_else(tenants::gen_tenant_id);
+
+    let mut errors: Vec<Error> = validate_name(&payload.name).into_iter().collect();
+
+    if !tenants::is_valid_id(&new_tenant_id) {
+        errors.push(Error::new(
+            StatusCode::UNPROCESSABLE_ENTITY.as_u16(),
+            "Invalid Tenant ID",
+            "The tenant ID must be from 3 to 32 lowercase letters, digits or hyphens, not starting or ending with a hyphen.",
+            Some("body.data.tenant_id"),
+            HashMap::from([("input", json!(trim(&new_tenant_id, 32)))]),
+        ));
+    }
+
+    let host = hosts::normalize(&payload.host);
+
+    match validate_host(&state.db, &host, "body.data.host", None).await {
+        Ok(error) => errors.extend(error),
+        Err(e) => return internal_error(e, request_id, tenant_id),
+    }
+
+    let mut admins = payload.admins.clone();
+    admins.sort();
+    admins.dedup();
+
+    if admins.len() > MAX_INITIAL_ADMINS {
+        errors.push(Error::new(
+            StatusCode::UNPROCESSABLE_ENTITY.as_u16(),
+            "Too Many Admins",
+            "A tenant can be created with at most 10 admins.",
+            Some("body.data.admins"),
+            HashMap::new(),
+        ));
+    } else {
+        let mut unknown: Vec<&String> = vec![];
+
+        for user_id in &admins {
+            match tenants::is_platform_user(&state.db, user_id).await {
+                Ok(true) => {}
+                Ok(false) => unknown.push(user_id),
+                Err(e) => return internal_error(e, request_id, tenant_id),
+            }
+        }
+
+        if !unknown.is_empty() {
+            errors.push(Error::new(
+                StatusCode::UNPROCESSABLE_ENTITY.as_u16(),
+                "Unknown Admins",
+                "Admins must be users of the platform tenant.",
+                Some("body.data.admins"),
+                HashMap::from([("invalid", json!(unknown))]),
+            ));
+        }
+    }
+
+    if !errors.is_empty() {
+        return unprocessable(errors, response_meta);
+    }
+
+    match tenants::create(
+        &state,
+        &new_tenant_id,
+        payload.name.trim(),
+        payload.is_sandbox,
+    )
+    .await
+    {
+        Ok(true) => {}
+        Ok(false) => {
+            return conflict_response(
+                "Tenant ID Taken",
+                "There's already a tenant with this ID.",
+                "body.data.tenant_id",
+                &new_tenant_id,
+                request_id,
+            )
+        }
+        Err(e) => return internal_error(e, request_id, tenant_id),
+    }
+
+    match hosts::add(&state, &new_tenant_id, &host).await {
+        Ok(true) => {}
+        Ok(false) => {
+            return conflict_response(
+                "Host Taken",
+                "The host is already linked to another tenant.",
+                "body.data.host",
+                &host,
+                request_id,
+            )
+        }
+        Err(e) => return internal_error(e, request_id, tenant_id),
+    }
+
+    if let Err(e) = tenants::bootstrap(&state.db, &new_tenant_id).await {
+        return internal_error(e, request_id, tenant_id);
+    }
+
+    for user_id in &admins {
+        if let Err(e) = tenants::add_admin(&state.db, &new_tenant_id, user_id).await {
+            return internal_error(e, request_id, tenant_id);
+        }
+    }
+
+    match details(&state.db, &new_tenant_id, &request_id).await {
+        Ok(tenant) => (
+            StatusCode::CREATED,
+            Response::new(Some(tenant), None, Some(response_meta), None),
+        )
+            .into_response(),
+        Err(response) => response,
+    }
+}
+
+pub async fn get_tenant(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(response_meta): Extension<ResponseMeta<'_>>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    Path(target): Path<String>,
+) -> response::Response<Body> {
+    let state = state.read().await;
+
+    if let Err(response) =
+        authorize(&state, &tenant_id, auth.user_id, &auth.scopes, &request_id).await
+    {
+        return response;
+    }
+
+    match details(&state.db, &target, &request_id).await {
+        Ok(tenant) => Response::new(Some(tenant), None, Some(response_meta), None).into_response(),
+        Err(response) => response,
+    }
+}
+
+/// Renames a tenant, or turns it into a sandbox or back. The platform tenant can't be a
+/// sandbox.
+#[allow(clippy::too_many_arguments)]
+pub async fn update_tenant(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(response_meta): Extension<ResponseMeta<'_>>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    Path(target): Path<String>,
+    payload: Result<Json<Request<UpdateTenantPayload>>, JsonRejection>,
+) -> response::Response<Body> {
+    let Json(Request { data: payload, .. }) = match payload {
+        Ok(p) => p,
+        Err(err) => {
+            return CommonError::JsonRejection {
+                err,
+                request_id,
+                tenant_id: Some(tenant_id),
+            }
+            .into_response()
+        }
+    };
+
+    let state = state.read().await;
+
+    if let Err(response) =
+        authorize(&state, &tenant_id, auth.user_id, &auth.scopes, &request_id).await
+    {
+        return response;
+    }
+
+    if let Err(response) = find_tenant(&state.db, &target, &request_id).await {
+        return response;
+    }
+
+    if let Some(error) = payload.name.as_deref().and_then(validate_name) {
+        return unprocessable(vec![error], response_meta);
+    }
+
+    if target == PLATFORM_TENANT_ID && payload.is_sandbox == Some(true) {
+        return conflict_response(
+            "Platform Tenant",
+            "The platform tenant can't be a sandbox.",
+            "body.data.is_sandbox",
+            &target,
+            request_id,
+        );
+    }
+
+    if let Some(name) = &payload.name {
+        if let Err(e) = tenants::rename(&state.db, &target, name.trim()).await {
+            return internal_error(e, request_id, tenant_id);
+        }
+    }
+
+    if let Some(is_sandbox) = payload.is_sandbox {
+        if let Err(e) = tenants::set_sandbox(&state, &target, is_sandbox).await {
+            return internal_error(e, request_id, tenant_id);
+        }
+    }
+
+    match details(&state.db, &target, &request_id).await {
+        Ok(tenant) => Response::new(Some(tenant), None, Some(response_meta), None).into_response(),
+        Err(response) => response,
+    }
+}
+
+/// Suspends or resumes a tenant. The platform tenant can't be suspended.
+async fn set_suspended(
+    state: &state::State,
+    target: &str,
+    is_suspended: bool,
+    request_id: &str,
+    response_meta: ResponseMeta<'_>,
+) -> response::Response<Body> {
+    if let Err(response) = find_tenant(&state.db, target, request_id).await {
+        return response;
+    }
+
+    if target == PLATFORM_TENANT_ID && is_suspended {
+        return conflict_response(
+            "Platform Tenant",
+            "The platform tenant can't be suspended.",
+            "path.tenant_id",
+            target,
+            request_id.to_string(),
+        );
+    }
+
+    if !is_suspended {
+        match purge::get(&state.db, target).await {
+            Ok(Some(_)) => {
+                return conflict_response(
+                    "Tenant Deleted",
+                    "The tenant is scheduled for deletion. Restore it instead.",
+                    "path.tenant_id",
+                    target,
+                    request_id.to_string(),
+                )
+            }
+            Ok(None) => {}
+            Err(e) => {
+                return internal_error(e, request_id.to_string(), PLATFORM_TENANT_ID.to_string())
+            }
+        }
+    }
+
+    if let Err(e) = tenants::set_suspended(state, target, is_suspended).await {
+        return internal_error(e, request_id.to_string(), PLATFORM_TENANT_ID.to_string());
+    }
+
+    match details(&state.db, target, request_id).await {
+        Ok(tenant) => Response::new(Some(tenant), None, Some(response_meta), None).into_response(),
+        Err(response) => response,
+    }
+}
+
+/// Suspends a tenant, refusing every request made to it until it's resumed.
+pub async fn suspend_tenant(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(response_meta): Extension<ResponseMeta<'_>>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    Path(target): Path<String>,
+) -> response::Response<Body> {
+    let state = state.read().await;
+
+    if let Err(response) =
+        authorize(&state, &tenant_id, auth.user_id, &auth.scopes, &request_id).await
+    {
+        return response;
+    }
+
+    set_suspended(&state, &target, true, &request_id, response_meta).await
+}
+
+pub async fn resume_tenant(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(response_meta): Extension<ResponseMeta<'_>>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    Path(target): Path<String>,
+) -> response::Response<Body> {
+    let state = state.read().await;
+
+    if let Err(response) =
+        authorize(&state, &tenant_id, auth.user_id, &auth.scopes, &request_id).await
+    {
+        return response;
+    }
+
+    set_suspended(&state, &target, false, &request_id, response_meta).await
+}
+
+/// Suspends a tenant and schedules the purge of all its data once the grace period ends. The
+/// platform tenant can't be deleted.
+async fn schedule_purge(
+    state: &state::State,
+    target: &str,
+    user_id: &str,
+    grace_period: Duration,
+    request_id: &str,
+    response_meta: ResponseMeta<'_>,
+) -> response::Response<Body> {
+    if let Err(response) = find_tenant(&state.db, target, request_id).await {
+        return response;
+    }
+
+    if target == PLATFORM_TENANT_ID {
+        return conflict_response(
+            "Platform Tenant",
+            "The platform tenant can't be deleted.",
+            "path.tenant_id",
+            target,
+            request_id.to_string(),
+        );
+    }
+
+    match purge::get(&state.db, target).await {
+        Ok(Some(purge)) if purge.status != purge::Status::Scheduled => {
+            return conflict_response(
+                "Purge Started",
+                "The tenant's data is already being purged.",
+                "path.tenant_id",
+                target,
+                request_id.to_string(),
+            )
+        }
+        Ok(_) => {}
+        Err(e) => return internal_error(e, request_id.to_string(), PLATFORM_TENANT_ID.to_string()),
+    }
+
+    match purge::schedule(state, target, user_id, grace_period).await {
+        Ok(purge) => (
+            StatusCode::ACCEPTED,
+            Response::new(Some(purge), None, Some(response_meta), None),
+        )
+            .into_response(),
+        Err(e) => internal_error(e, request_id.to_string(), PLATFORM_TENANT_ID.to_string()),
+    }
+}
+
+/// Deletes a tenant with the default grace period, during which it's suspended but can be
+/// restored. Its data is then purged in the background.
+pub async fn delete_tenant(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(response_meta): Extension<ResponseMeta<'_>>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    Path(target): Path<String>,
+) -> response::Response<Body> {
+    let state = state.read().await;
+
+    let user_id = match authorize(&state, &tenant_id, auth.user_id, &auth.scopes, &request_id).await
+    {
+        Ok(user_id) => user_id,
+        Err(response) => return response,
+    };
+
+    schedule_purge(
+        &state,
+        &target,
+        &user_id,
+        purge::DEFAULT_GRACE_PERIOD,
+        &request_id,
+        response_meta,
+    )
+    .await
+}
+
+/// Schedules the purge of a tenant with a chosen grace period, which can be zero.
+#[allow(clippy::too_many_arguments)]
+pub async fn purge_tenant(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(response_meta): Extension<ResponseMeta<'_>>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    Path(target): Path<String>,
+    payload: Result<Json<Request<PurgeTenantPayload>>, JsonRejection>,
+) -> response::Response<Body> {
+    let Json(Request { data: payload, .. }) = match payload {
+        Ok(p) => p,
+        Err(err) => {
+            return CommonError::JsonRejection {
+                err,
+                request_id,
+                tenant_id: Some(tenant_id),
+            }
+            .into_response()
+        }
+    };
+
+    let grace_period = payload
+        .grace_period
+        .map_or(purge::DEFAULT_GRACE_PERIOD, Duration::from_secs);
+
+    if grace_period > purge::MAX_GRACE_PERIOD {
+        return unprocessable(
+            vec![Error::new(
+                StatusCode::UNPROCESSABLE_ENTITY.as_u16(),
+                "Invalid Grace Period",
+                "The grace period can be at most 90 days long.",
+                Some("body.data.grace_period"),
+                HashMap::from([("max", json!(purge::MAX_GRACE_PERIOD.as_secs()))]),
+            )],
+            response_meta,
+        );
+    }
+
+    let state = state.read().await;
+
+    let user_id = match authorize(&state, &tenant_id, auth.user_id, &auth.scopes, &request_id).await
+    {
+        Ok(user_id) => user_id,
+        Err(response) => return response,
+    };
+
+    schedule_purge(
+        &state,
+        &target,
+        &user_id,
+        grace_period,
+        &request_id,
+        response_meta,
+    )
+    .await
+}
+
+/// Reports the progress of a tenant's purge, along with its deletion receipt once completed.
+/// Available after the tenant itself is gone.
+pub async fn get_purge(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(response_meta): Extension<ResponseMeta<'_>>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    Path(target): Path<String>,
+) -> response::Response<Body> {
+    let state = state.read().await;
+
+    if let Err(response) =
+        authorize(&state, &tenant_id, auth.user_id, &auth.scopes, &request_id).await
+    {
+        return response;
+    }
+
+    match purge::get(&state.db, &target).await {
+        Ok(Some(purge)) => {
+            Response::new(Some(purge), None, Some(response_meta), None).into_response()
+        }
+        Ok(None) => error_response(
+            StatusCode::NOT_FOUND,
+            "Purge Not Found",
+            "There's no purge of a tenant with this ID.",
+            Some("path.tenant_id"),
+            HashMap::from([("input", json!(trim(&target, 32)))]),
+            request_id,
+            Some(tenant_id),
+        )
+        .into_response(),
+        Err(e) => internal_error(e, request_id, tenant_id),
+    }
+}
+
+/// Cancels the purge of a tenant during its grace period, resuming the tenant.
+pub async fn restore_tenant(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(response_meta): Extension<ResponseMeta<'_>>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    Path(target): Path<String>,
+) -> response::Response<Body> {
+    let state = state.read().await;
+
+    if let Err(response) =
+        authorize(&state, &tenant_id, auth.user_id, &auth.scopes, &request_id).await
+    {
+        return response;
+    }
+
+    match purge::restore(&state, &target).await {
+        Ok(true) => {}
+        Ok(false) => {
+            return conflict_response(
+                "Not Restorable",
+                "The tenant isn't scheduled for deletion, or its purge already started.",
+                "path.tenant_id",
+                &target,
+                request_id,
+            )
+        }
+        Err(e) => return internal_error(e, request_id, tenant_id),
+    }
+
+    match details(&state.db, &target, &request_id).await {
+        Ok(tenant) => Response::new(Some(tenant), None, Some(response_meta), None).into_response(),
+        Err(response) => response,
+    }
+}
+
+/// Streams an archive of every row of a tenant, to back it up or move it to another cluster.
+pub async fn export_tenant(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(auth): Extension<Auth>,
+    State(app_state): State<AppState>,
+    Path(target): Path<String>,
+) -> response::Response<Body> {
+    let state = app_state.read().await;
+
+    if let Err(response) =
+        authorize(&state, &tenant_id, auth.user_id, &auth.scopes, &request_id).await
+    {
+        return response;
+    }
+
+    if let Err(response) = find_tenant(&state.db, &target, &request_id).await {
+        return response;
+    }
+
+    let filename = format!(
+        "attachment; filename=\"{target}-{}.tar.gz\"",
+        Utc::now().format("%Y%m%d%H%M%S")
+    );
+
+    (
+        [
+            (header::CONTENT_TYPE, "application/gzip".to_string()),
+            (header::CONTENT_DISPOSITION, filename),
+        ],
+        Body::from_stream(archive::export(app_state.clone(), target)),
+    )
+        .into_response()
+}
+
+/// Creates a tenant from an archive downloaded from a URL, under the same or another ID. The
+/// tenant stays suspended while the archive is imported in the background.
+#[allow(clippy::too_many_arguments)]
+pub async fn import_tenant(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(response_meta): Extension<ResponseMeta<'_>>,
+    Extension(auth): Extension<Auth>,
+    State(app_state): State<AppState>,
+    payload: Result<Json<Request<ImportTenantPayload>>, JsonRejection>,
+) -> response::Response<Body> {
+    let Json(Request { data: payload, .. }) = match payload {
+        Ok(p) => p,
+        Err(err) => {
+            return CommonError::JsonRejection {
+                err,
+                request_id,
+                tenant_id: Some(tenant_id),
+            }
+            .into_response()
+        }
+    };
+
+    let mut errors: Vec<Error> = vec![];
+
+    if !tenants::is_valid_id(&payload.tenant_id) {
+        errors.push(Error::new(
+            StatusCode::UNPROCESSABLE_ENTITY.as_u16(),
+            "Invalid Tenant ID",
+            "The tenant ID must be from 3 to 32 lowercase letters, digits or hyphens, not starting or ending with a hyphen.",
+            Some("body.data.tenant_id"),
+            HashMap::from([("input", json!(trim(&payload.tenant_id, 32)))]),
+        ));
+    }
+
+    if !archive::is_valid_url(&payload.url) {
+        errors.push(Error::new(
+            StatusCode::UNPROCESSABLE_ENTITY.as_u16(),
+            "Invalid URL",
+            "The archive must be downloadable from an HTTP or HTTPS URL on a public host.",
+            Some("body.data.url"),
+            HashMap::new(),
+        ));
+    }
+
+    if !errors.is_empty() {
+        return unprocessable(errors, response_meta);
+    }
+
+    let state = app_state.read().await;
+
+    let user_id = match authorize(&state, &tenant_id, auth.user_id, &auth.scopes, &request_id).await
+    {
+        Ok(user_id) => user_id,
+        Err(response) => return response,
+    };
+
+    let import = match archive::start_import(&state, &payload.tenant_id, &user_id).await {
+        Ok(Some(import)) => import,
+        Ok(None) => {
+            return conflict_response(
+                "Tenant ID Taken",
+                "There's already a tenant with this ID.",
+                "body.data.tenant_id",
+                &payload.tenant_id,
+                request_id,
+            )
+        }
+        Err(e) => return internal_error(e, request_id, tenant_id),
+    };
+
+    tokio::spawn(archive::import(
+        app_state.clone(),
+        payload.tenant_id.clone(),
+        payload.url,
+    ));
+
+    let links = format!("/tenants/{}/import", payload.tenant_id);
+
+    (
+        StatusCode::ACCEPTED,
+        Response::new(
+            Some(import),
+            None,
+            Some(response_meta),
+            Some(HashMap::from([("self", links.as_str())])),
+        ),
+    )
+        .into_response()
+}
+
+/// Reports the progress of the import that created a tenant.
+pub async fn get_import(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(response_meta): Extension<ResponseMeta<'_>>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    Path(target): Path<String>,
+) -> response::Response<Body> {
+    let state = state.read().await;
+
+    if let Err(response) =
+        authorize(&state, &tenant_id, auth.user_id, &auth.scopes, &request_id).await
+    {
+        return response;
+    }
+
+    match archive::get_import(&state.db, &target).await {
+        Ok(Some(import)) => {
+            Response::new(Some(import), None, Some(response_meta), None).into_response()
+        }
+        Ok(None) => error_response(
+            StatusCode::NOT_FOUND,
+            "Import Not Found",
+            "The tenant wasn't created from an archive.",
+            Some("path.tenant_id"),
+            HashMap::from([("input", json!(trim(&target, 32)))]),
+            request_id,
+            Some(tenant_id),
+        )
+        .into_response(),
+        Err(e) => internal_error(e, request_id, tenant_id),
+    }
+}
+
+pub async fn list_admins(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(response_meta): Extension<ResponseMeta<'_>>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    Path(target): Path<String>,
+) -> response::Response<Body> {
+    let state = state.read().await;
+
+    if let Err(response) =
+        authorize(&state, &tenant_id, auth.user_id, &auth.scopes, &request_id).await
+    {
+        return response;
+    }
+
+    if let Err(response) = find_tenant(&state.db, &target, &request_id).await {
+        return response;
+    }
+
+    match tenants::admins(&state.db, &target).await {
+        Ok(admins) => Response::new(Some(admins), None, Some(response_meta), None).into_response(),
+        Err(e) => internal_error(e, request_id, tenant_id),
+    }
+}
+
+/// Makes a user of the platform tenant an admin of a tenant.
+pub async fn add_admin(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    Path((target, user_id)): Path<(String, String)>,
+) -> response::Response<Body> {
+    let state = state.read().await;
+
+    if let Err(response) =
+        authorize(&state, &tenant_id, auth.user_id, &auth.scopes, &request_id).await
+    {
+        return response;
+    }
+
+    if let Err(response) = find_tenant(&state.db, &target, &request_id).await {
+        return response;
+    }
+
+    match tenants::is_platform_user(&state.db, &user_id).await {
+        Ok(true) => {}
+        Ok(false) => {
+            return error_response(
+                StatusCode::NOT_FOUND,
+                "User Not Found",
+                "Admins must be users of the platform tenant.",
+                Some("path.user_id"),
+                HashMap::from([("input", json!(trim(&user_id, 64)))]),
+                request_id,
+                Some(tenant_id),
+            )
+            .into_response()
+        }
+        Err(e) => return internal_error(e, request_id, tenant_id),
+    }
+
+    if let Err(e) = tenants::add_admin(&state.db, &target, &user_id).await {
+        return internal_error(e, request_id, tenant_id);
+    }
+
+    StatusCode::NO_CONTENT.into_response()
+}
+
+/// Removes an admin from a tenant. The platform tenant always keeps at least one admin.
+pub async fn remove_admin(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    Path((target, user_id)): Path<(String, String)>,
+) -> response::Response<Body> {
+    let state = state.read().await;
+
+    if let Err(response) =
+        authorize(&state, &tenant_id, auth.user_id, &auth.scopes, &request_id).await
+    {
+        return response;
+    }
+
+    if let Err(response) = find_tenant(&state.db, &target, &request_id).await {
+        return response;
+    }
+
+    let admins = match tenants::admins(&state.db, &target).await {
+        Ok(admins) => admins,
+        Err(e) => return internal_error(e, request_id, tenant_id),
+    };
+
+    if !admins.contains(&user_id) {
+        return error_response(
+            StatusCode::NOT_FOUND,
+            "Admin Not Found",
+            "The user isn't an admin of this tenant.",
+            Some("path.user_id"),
+            HashMap::from([("input", json!(trim(&user_id, 64)))]),
+            request_id,
+            Some(tenant_id),
+        )
+        .into_response();
+    }
+
+    if target == PLATFORM_TENANT_ID && admins.len() == 1 {
+        return conflict_response(
+            "Last Platform Admin",
+            "The platform tenant must keep at least one admin.",
+            "path.user_id",
+            &user_id,
+            request_id,
+        );
+    }
+
+    if let Err(e) = tenants::remove_admin(&state.db, &target, &user_id).await {
+        return internal_error(e, request_id, tenant_id);
+    }
+
+    StatusCode::NO_CONTENT.into_response()
+}
+
+pub async fn list_hosts(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(response_meta): Extension<ResponseMeta<'_>>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    Path(target): Path<String>,
+) -> response::Response<Body> {
+    let state = state.read().await;
+
+    if let Err(response) =
+        authorize(&state, &tenant_id, auth.user_id, &auth.scopes, &request_id).await
+    {
+        return response;
+    }
+
+    if let Err(response) = find_tenant(&state.db, &target, &request_id).await {
+        return response;
+    }
+
+    match hosts::list(&state.db, &target).await {
+        Ok(hosts) => Response::new(Some(hosts), None, Some(response_meta), None).into_response(),
+        Err(e) => internal_error(e, request_id, tenant_id),
+    }
+}
+
+/// Links a host or a wildcard to a tenant. Unlike the tenant's own custom domains, it's active
+/// right away.
+pub async fn add_host(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(response_meta): Extension<ResponseMeta<'_>>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    Path((target, host)): Path<(String, String)>,
+) -> response::Response<Body> {
+    let state = state.read().await;
+
+    if let Err(response) =
+        authorize(&state, &tenant_id, auth.user_id, &auth.scopes, &request_id).await
+    {
+        return response;
+    }
+
+    if let Err(response) = find_tenant(&state.db, &target, &request_id).await {
+        return response;
+    }
+
+    let host = hosts::normalize(&host);
+
+    match validate_host(&state.db, &host, "path.host", Some(&target)).await {
+        Ok(Some(error)) => return unprocessable(vec![error], response_meta),
+        Ok(None) => {}
+        Err(e) => return internal_error(e, request_id, tenant_id),
+    }
+
+    match hosts::add(&state, &target, &host).await {
+        Ok(true) => StatusCode::NO_CONTENT.into_response(),
+        Ok(false) => conflict_response(
+            "Host Taken",
+            "The host is already linked to another tenant.",
+            "path.host",
+            &host,
+            request_id,
+        ),
+        Err(e) => internal_error(e, request_id, tenant_id),
+    }
+}
+
+/// Unlinks a host from a tenant, including custom domains the tenant added.
+pub async fn remove_host(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    Path((target, host)): Path<(String, String)>,
+) -> response::Response<Body> {
+    let state = state.read().await;
+
+    if let Err(response) =
+        authorize(&state, &tenant_id, auth.user_id, &auth.scopes, &request_id).await
+    {
+        return response;
+    }
+
+    let host = match hosts::get(&state.db, &target, &hosts::normalize(&host)).await {
+        Ok(Some(host)) => host,
+        Ok(None) => {
+            return error_response(
+                StatusCode::NOT_FOUND,
+                "Host Not Found",
+                "The host isn't linked to this tenant.",
+                Some("path.host"),
+                HashMap::from([("input", json!(trim(&host, 64)))]),
+                request_id,
+                Some(tenant_id),
+            )
+            .into_response()
+        }
+        Err(e) => return internal_error(e, request_id, tenant_id),
+    };
+
+    if let Err(e) = hosts::remove(&state, &host).await {
+        return internal_error(e, request_id, tenant_id);
+    }
+
+    StatusCode::NO_CONTENT.into_response()
+}
+
+fn metric_not_found(
+    metric: &str,
+    request_id: String,
+    tenant_id: String,
+) -> response::Response<Body> {
+    error_response(
+        StatusCode::NOT_FOUND,
+        "Metric Not Found",
+        "There's no usage metric with this name.",
+        Some("path.metric"),
+        HashMap::from([
+            ("input", json!(trim(metric, 32))),
+            ("metrics", json!(usage::Metric::ALL)),
+        ]),
+        request_id,
+        Some(tenant_id),
+    )
+    .into_response()
+}
+
+/// Lists the quotas of a tenant along with the usage they apply to.
+pub async fn list_quotas(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(response_meta): Extension<ResponseMeta<'_>>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    Path(target): Path<String>,
+) -> response::Response<Body> {
+    let state = state.read().await;
+
+    if let Err(response) =
+        authorize(&state, &tenant_id, auth.user_id, &auth.scopes, &request_id).await
+    {
+        return response;
+    }
+
+    if let Err(response) = find_tenant(&state.db, &target, &request_id).await {
+        return response;
+    }
+
+    let quotas = match usage::quotas(&state.db, &target).await {
+        Ok(quotas) => quotas,
+        Err(e) => return internal_error(e, request_id, tenant_id),
+    };
+
+    let mut data = vec![];
+
+    for quota in quotas {
+        match usage::current(&state.db, &target, quota.metric).await {
+            Ok(usage) => {
+                let mut quota = json!(quota);
+                quota["usage"] = json!(usage);
+                data.push(quota);
+            }
+            Err(e) => return internal_error(e, request_id, tenant_id),
+        }
+    }
+
+    Response::new(Some(data), None, Some(response_meta), None).into_response()
+}
+
+/// Sets the limits of a tenant on a metric. Hard limits on sign-ups, active users and users are
+/// enforced when users sign up.
+#[allow(clippy::too_many_arguments)]
+pub async fn set_quota(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(response_meta): Extension<ResponseMeta<'_>>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    Path((target, metric)): Path<(String, String)>,
+    payload: Result<Json<Request<SetQuotaPayload>>, JsonRejection>,
+) -> response::Response<Body> {
+    let Json(Request { data: payload, .. }) = match payload {
+        Ok(p) => p,
+        Err(err) => {
+            return CommonError::JsonRejection {
+                err,
+                request_id,
+                tenant_id: Some(tenant_id),
+            }
+            .into_response()
+        }
+    };
+
+    let Some(metric) = usage::Metric::parse(&metric) else {
+        return metric_not_found(&metric, request_id, tenant_id);
+    };
+
+    let mut errors: Vec<Error> = vec![];
+
+    for (limit, location) in [
+        (payload.soft_limit, "body.data.soft_limit"),
+        (payload.hard_limit, "body.data.hard_limit"),
+    ] {
+        if limit.is_some_and(|limit| limit < 0) {
+            errors.push(Error::new(
+                StatusCode::UNPROCESSABLE_ENTITY.as_u16(),
+                "Invalid Limit",
+                "Limits can't be negative.",
+                Some(location),
+                HashMap::from([("input", json!(limit))]),
+            ));
+        }
+    }
+
+    if let (Some(soft_limit), Some(hard_limit)) = (payload.soft_limit, payload.hard_limit) {
+        if soft_limit > hard_limit {
+            errors.push(Error::new(
+                StatusCode::UNPROCESSABLE_ENTITY.as_u16(),
+                "Invalid Limit",
+                "The soft limit can't be above the hard limit.",
+                Some("body.data.soft_limit"),
+                HashMap::from([("hard_limit", json!(hard_limit))]),
+            ));
+        }
+    }
+
+    if payload.soft_limit.is_none() && payload.hard_limit.is_none() {
+        errors.push(Error::new(
+            StatusCode::UNPROCESSABLE_ENTITY.as_u16(),
+            "Missing Limit",
+            "A quota needs a soft limit, a hard limit or both.",
+            Some("body.data"),
+            HashMap::new(),
+        ));
+    }
+
+    if !errors.is_empty() {
+        return unprocessable(errors, response_meta);
+    }
+
+    let state = state.read().await;
+
+    if let Err(response) =
+        authorize(&state, &tenant_id, auth.user_id, &auth.scopes, &request_id).await
+    {
+        return response;
+    }
+
+    if let Err(response) = find_tenant(&state.db, &target, &request_id).await {
+        return response;
+    }
+
+    let quota = usage::Quota {
+        metric,
+        soft_limit: payload.soft_limit,
+        hard_limit: payload.hard_limit,
+    };
+
+    match usage::set_quota(&state.db, &target, &quota).await {
+        Ok(()) => Response::new(Some(quota), None, Some(response_meta), None).into_response(),
+        Err(e) => internal_error(e, request_id, tenant_id),
+    }
+}
+
+pub async fn remove_quota(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    Path((target, metric)): Path<(String, String)>,
+) -> response::Response<Body> {
+    let Some(metric) = usage::Metric::parse(&metric) else {
+        return metric_not_found(&metric, request_id, tenant_id);
+    };
+
+    let state = state.read().await;
+
+    if let Err(response) =
+        authorize(&state, &tenant_id, auth.user_id, &auth.scopes, &request_id).await
+    {
+        return response;
+    }
+
+    if let Err(e) = usage::remove_quota(&state.db, &target, metric).await {
+        return internal_error(e, request_id, tenant_id);
+    }
+
+    StatusCode::NO_CONTENT.into_response()
+}
+
+/// Reports the usage of a tenant per day, over the last 30 days unless a range is given.
+#[allow(clippy::too_many_arguments)]
+pub async fn usage_report(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(response_meta): Extension<ResponseMeta<'_>>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    Path(target): Path<String>,
+    Query(query): Query<UsageQuery>,
+) -> response::Response<Body> {
+    let today = Utc::now().date_naive();
+    let mut errors: Vec<Error> = vec![];
+
+    let mut parse = |date: &Option<String>, default: NaiveDate, location: &str| match date {
+        Some(date) => NaiveDate::parse_from_str(date, "%Y-%m-%d")
+            .inspect_err(|_| {
+                errors.push(Error::new(
+                    StatusCode::UNPROCESSABLE_ENTITY.as_u16(),
+                    "Invalid Date",
+                    "Dates must be written as YYYY-MM-DD.",
+                    Some(location),
+                    HashMap::from([("input", json!(trim(date, 32)))]),
+                ))
+            })
+            .unwrap_or(default),
+        None => default,
+    };
+
+    let to = parse(&query.to, today, "query.to");
+    let from = parse(&query.from, to - chrono::Duration::days(29), "query.from");
+
+    if from > to {
+        errors.push(Error::new(
+            StatusCode::UNPROCESSABLE_ENTITY.as_u16(),
+            "Invalid Range",
+            "The range can't start after it ends.",
+            Some("query.from"),
+            HashMap::new(),
+        ));
+    } else if (to - from).num_days() >= usage::MAX_REPORT_DAYS {
+        errors.push(Error::new(
+            StatusCode::UNPROCESSABLE_ENTITY.as_u16(),
+            "Invalid Range",
+            "A report can cover at most 366 days.",
+            Some("query.from"),
+            HashMap::from([("max_days", json!(usage::MAX_REPORT_DAYS))]),
+        ));
+    }
+
+    if !errors.is_empty() {
+        return unprocessable(errors, response_meta);
+    }
+
+    let state = state.read().await;
+
+    if let Err(response) =
+        authorize(&state, &tenant_id, auth.user_id, &auth.scopes, &request_id).await
+    {
+        return response;
+    }
+
+    match usage::report(&state.db, &target, from, to).await {
+        Ok(days) => Response::new(Some(days), None, Some(response_meta), None).into_response(),
+        Err(e) => internal_error(e, request_id, tenant_id),
+    }
+}
+
+fn invalid_flag(flag: &str, response_meta: ResponseMeta<'_>) -> response::Response<Body> {
+    unprocessable(
+        vec![Error::new(
+            StatusCode::UNPROCESSABLE_ENTITY.as_u16(),
+            "Invalid Flag",
+            "Flag names must be from 1 to 64 lowercase letters, digits, dots, hyphens or underscores.",
+            Some("path.flag"),
+            HashMap::from([("input", json!(trim(flag, 64)))]),
+        )],
+        response_meta,
+    )
+}
+
+/// Lists the feature flags set for a tenant.
+pub async fn list_flags(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(response_meta): Extension<ResponseMeta<'_>>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    Path(target): Path<String>,
+) -> response::Response<Body> {
+    let state = state.read().await;
+
+    if let Err(response) =
+        authorize(&state, &tenant_id, auth.user_id, &auth.scopes, &request_id).await
+    {
+        return response;
+    }
+
+    if let Err(response) = find_tenant(&state.db, &target, &request_id).await {
+        return response;
+    }
+
+    match flags::all(&state, &target).await {
+        Ok(flags) => Response::new(Some(flags), None, Some(response_meta), None).into_response(),
+        Err(e) => internal_error(e, request_id, tenant_id),
+    }
+}
+
+/// Turns a feature flag of a tenant on or off, optionally for a percentage of its users. The
+/// change is recorded in the flag's audit trail.
+#[allow(clippy::too_many_arguments)]
+pub async fn set_flag(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(response_meta): Extension<ResponseMeta<'_>>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    Path((target, flag)): Path<(String, String)>,
+    payload: Result<Json<Request<SetFlagPayload>>, JsonRejection>,
+) -> response::Response<Body> {
+    let Json(Request { data: payload, .. }) = match payload {
+        Ok(p) => p,
+        Err(err) => {
+            return CommonError::JsonRejection {
+                err,
+                request_id,
+                tenant_id: Some(tenant_id),
+            }
+            .into_response()
+        }
+    };
+
+    if !flags::is_valid_name(&flag) {
+        return invalid_flag(&flag, response_meta);
+    }
+
+    let mut errors: Vec<Error> = vec![];
+
+    if payload.rollout.is_some_and(|rollout| rollout > 100) {
+        errors.push(Error::new(
+            StatusCode::UNPROCESSABLE_ENTITY.as_u16(),
+            "Invalid Rollout",
+            "The rollout is a percentage of users, from 0 to 100.",
+            Some("body.data.rollout"),
+            HashMap::from([("input", json!(payload.rollout))]),
+        ));
+    }
+
+    let description = payload
+        .description
+        .as_deref()
+        .map(str::trim)
+        .filter(|description| !description.is_empty());
+
+    if description.is_some_and(|description| description.chars().count() > 500) {
+        errors.push(Error::new(
+            StatusCode::UNPROCESSABLE_ENTITY.as_u16(),
+            "Description Too Long",
+            "The description must not be more than 500 characters in length.",
+            Some("body.data.description"),
+            HashMap::new(),
+        ));
+    }
+
+    if !errors.is_empty() {
+        return unprocessable(errors, response_meta);
+    }
+
+    let state = state.read().await;
+
+    let user_id = match authorize(&state, &tenant_id, auth.user_id, &auth.scopes, &request_id).await
+    {
+        Ok(user_id) => user_id,
+        Err(response) => return response,
+    };
+
+    if let Err(response) = find_tenant(&state.db, &target, &request_id).await {
+        return response;
+    }
+
+    match flags::set(
+        &state,
+        &target,
+        &flag,
+        payload.is_enabled,
+        payload.rollout,
+        description.map(str::to_string),
+        &user_id,
+    )
+    .await
+    {
+        Ok(flag) => Response::new(Some(flag), None, Some(response_meta), None).into_response(),
+        Err(e) => internal_error(e, request_id, tenant_id),
+    }
+}
+
+/// Removes a feature flag of a tenant, which turns it off. The removal is recorded in the
+/// flag's audit trail.
+pub async fn remove_flag(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    Path((target, flag)): Path<(String, String)>,
+) -> response::Response<Body> {
+    let state = state.read().await;
+
+    let user_id = match authorize(&state, &tenant_id, auth.user_id, &auth.scopes, &request_id).await
+    {
+        Ok(user_id) => user_id,
+        Err(response) => return response,
+    };
+
+    match flags::get(&state, &target, &flag).await {
+        Ok(Some(_)) => {}
+        Ok(None) => {
+            return error_response(
+                StatusCode::NOT_FOUND,
+                "Flag Not Found",
+                "The flag isn't set for this tenant.",
+                Some("path.flag"),
+                HashMap::from([("input", json!(trim(&flag, 64)))]),
+                request_id,
+                Some(tenant_id),
+            )
+            .into_response()
+        }
+        Err(e) => return internal_error(e, request_id, tenant_id),
+    }
+
+    if let Err(e) = flags::remove(&state, &target, &flag, &user_id).await {
+        return internal_error(e, request_id, tenant_id);
+    }
+
+    StatusCode::NO_CONTENT.into_response()
+}
+
+/// Lists the latest changes of a tenant's feature flags, newest first, optionally of a single
+/// flag.
+#[allow(clippy::too_many_arguments)]
+pub async fn list_flag_changes(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(response_meta): Extension<ResponseMeta<'_>>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    Path(target): Path<String>,
+    Query(query): Query<FlagChangesQuery>,
+) -> response::Response<Body> {
+    let state = state.read().await;
+
+    if let Err(response) =
+        authorize(&state, &tenant_id, auth.user_id, &auth.scopes, &request_id).await
+    {
+        return response;
+    }
+
+    match flags::changes(&state.db, &target, query.flag.as_deref()).await {
+        Ok(changes) => {
+            Response::new(Some(changes), None, Some(response_meta), None).into_response()
+        }
+        Err(e) => internal_error(e, request_id, tenant_id),
+    }
+}
+
+/// Reports the hit rates of this instance's caches.
+pub async fn cache_stats(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(response_meta): Extension<ResponseMeta<'_>>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+) -> response::Response<Body> {
+    let state = state.read().await;
+
+    if let Err(response) =
+        authorize(&state, &tenant_id, auth.user_id, &auth.scopes, &request_id).await
+    {
+        return response;
+    }
+
+    Response::new(Some(cache::stats()), None, Some(response_meta), None).into_response()
+}
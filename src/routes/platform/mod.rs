@@ -0,0 +1,65 @@
+mod handlers;
+mod requests;
+
+use axum::{
+    routing::{get, post, put},
+    Router,
+};
+
+use crate::state::AppState;
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/cache", get(handlers::cache_stats))
+        .route(
+            "/tenants",
+            get(handlers::list_tenants).post(handlers::create_tenant),
+        )
+        .route("/tenants/import", post(handlers::import_tenant))
+        .route(
+            "/tenants/:tenant_id",
+            get(handlers::get_tenant)
+                .patch(handlers::update_tenant)
+                .delete(handlers::delete_tenant),
+        )
+        .route(
+            "/tenants/:tenant_id/suspend",
+            post(handlers::suspend_tenant),
+        )
+        .route("/tenants/:tenant_id/resume", post(handlers::resume_tenant))
+        .route(
+            "/tenants/:tenant_id/purge",
+            get(handlers::get_purge).post(handlers::purge_tenant),
+        )
+        .route(
+            "/tenants/:tenant_id/restore",
+            post(handlers::restore_tenant),
+        )
+        .route("/tenants/:tenant_id/export", get(handlers::export_tenant))
+        .route("/tenants/:tenant_id/import", get(handlers::get_import))
+        .route("/tenants/:tenant_id/hosts", get(handlers::list_hosts))
+        .route(
+            "/tenants/:tenant_id/hosts/:host",
+            put(handlers::add_host).delete(handlers::remove_host),
+        )
+        .route("/tenants/:tenant_id/quotas", get(handlers::list_quotas))
+        .route(
+            "/tenants/:tenant_id/quotas/:metric",
+            put(handlers::set_quota).delete(handlers::remove_quota),
+        )
+        .route("/tenants/:tenant_id/usage", get(handlers::usage_report))
+        .route("/tenants/:tenant_id/flags", get(handlers::list_flags))
+        .route(
+            "/tenants/:tenant_id/flags/:flag",
+            put(handlers::set_flag).delete(handlers::remove_flag),
+        )
+        .route(
+            "/tenants/:tenant_id/flag-changes",
+            get(handlers::list_flag_changes),
+        )
+        .route("/tenants/:tenant_id/admins", get(handlers::list_admins))
+        .route(
+            "/tenants/:tenant_id/admins/:user_id",
+            put(handlers::add_admin).delete(handlers::remove_admin),
+        )
+}
@@ -0,0 +1,71 @@
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize)]
+pub struct CreateTenantPayload {
+    /// The ID of the tenant, which is generated if missing.
+    pub tenant_id: Option<String>,
+    pub name: String,
+
+    /// The first host of the tenant, which is active right away.
+    pub host: String,
+
+    /// Users of the platform tenant to make admins of the new tenant.
+    #[serde(default)]
+    pub admins: Vec<String>,
+
+    /// Whether the tenant is for testing, where codes are fixed and messages aren't sent.
+    #[serde(default)]
+    pub is_sandbox: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateTenantPayload {
+    pub name: Option<String>,
+    pub is_sandbox: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PurgeTenantPayload {
+    /// Seconds during which the tenant stays suspended but restorable, which defaults to 30
+    /// days.
+    pub grace_period: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ImportTenantPayload {
+    /// The ID of the new tenant, which can differ from the one in the archive.
+    pub tenant_id: String,
+
+    /// Where to download the archive from, such as a presigned URL.
+    pub url: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SetQuotaPayload {
+    pub soft_limit: Option<i64>,
+    pub hard_limit: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UsageQuery {
+    /// The first day of the report, as YYYY-MM-DD.
+    pub from: Option<String>,
+
+    /// The last day of the report, as YYYY-MM-DD.
+    pub to: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SetFlagPayload {
+    pub is_enabled: bool,
+
+    /// The percentage of users to turn the flag on for, or everyone if missing.
+    pub rollout: Option<u8>,
+    pub description: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FlagChangesQuery {
+    /// Only lists the changes of this flag.
+    pub flag: Option<String>,
+}
@@ -0,0 +1,607 @@
+use super::requests::{DryRunPayload, SetPolicyPayload};
+use crate::{
+    auth::Auth,
+    authz::{self, Resource},
+    error_handlers::{authorize, error_response, internal_error},
+    permissions,
+    policies::{self, language, Policy, RequestContext, MANAGE_PERMISSION, MAX_CONDITION_LENGTH},
+    requests::Request,
+    responses::{CommonError, Error, Response, ResponseMeta},
+    state::AppState,
+    types::{RequestID, TenantID},
+    utils::text::trim,
+};
+use axum::{
+    body::Body,
+    extract::{rejection::JsonRejection, ConnectInfo, Path, State},
+    http::{HeaderMap, StatusCode},
+    response::{self, IntoResponse},
+    Extension, Json,
+};
+use chrono::{DateTime, Utc};
+use scylla::Session;
+use serde_json::{json, Value};
+use std::{collections::HashMap, net::SocketAddr};
+
+/// Maximum number of action patterns in a policy.
+const MAX_ACTIONS: usize = 50;
+
+/// Name of the draft policy of a dry-run, unless it stands in for a saved one.
+const DRAFT_NAME: &str = "draft";
+
+fn unprocessable(errors: Vec<Error>, response_meta: ResponseMeta<'_>) -> response::Response<Body> {
+    let response: Response<Value> = Response::new(None, Some(errors), Some(response_meta), None);
+
+    (StatusCode::UNPROCESSABLE_ENTITY, Json(response)).into_response()
+}
+
+fn not_found_response(
+    name: &str,
+    request_id: String,
+    tenant_id: String,
+) -> response::Response<Body> {
+    error_response(
+        StatusCode::NOT_FOUND,
+        "Policy Not Found",
+        "There's no policy with this name.",
+        Some("path.policy"),
+        HashMap::from([("input", json!(trim(name, 20)))]),
+        request_id,
+        Some(tenant_id),
+    )
+    .into_response()
+}
+
+fn version_not_found_response(
+    version: i32,
+    request_id: String,
+    tenant_id: String,
+) -> response::Response<Body> {
+    error_response(
+        StatusCode::NOT_FOUND,
+        "Policy Version Not Found",
+        "The policy has no version with this number.",
+        Some("path.version"),
+        HashMap::from([("input", json!(version))]),
+        request_id,
+        Some(tenant_id),
+    )
+    .into_response()
+}
+
+fn conflict_response(
+    name: &str,
+    request_id: String,
+    tenant_id: String,
+) -> response::Response<Body> {
+    error_response(
+        StatusCode::CONFLICT,
+        "Concurrent Policy Update",
+        "Another version of the policy was saved at the same time. Try again.",
+        Some("path.policy"),
+        HashMap::from([("input", json!(trim(name, 20)))]),
+        request_id,
+        Some(tenant_id),
+    )
+    .into_response()
+}
+
+/// Validates a policy, reporting errors under `location`.
+fn validate(policy: &SetPolicyPayload, location: &str) -> Vec<Error> {
+    let mut errors: Vec<Error> = vec![];
+
+    if policy
+        .description
+        .as_ref()
+        .is_some_and(|description| description.chars().count() > 256)
+    {
+        errors.push(Error::new(
+            StatusCode::UNPROCESSABLE_ENTITY.as_u16(),
+            "Invalid Description",
+            "The description must be at most 256 characters long.",
+            Some(&format!("{location}.description")),
+            HashMap::new(),
+        ));
+    }
+
+    let invalid: Vec<&String> = policy
+        .actions
+        .iter()
+        .filter(|action| {
+            !permissions::is_valid(action) || action.starts_with(permissions::DENY_PREFIX)
+        })
+        .collect();
+
+    if policy.actions.is_empty() || policy.actions.len() > MAX_ACTIONS || !invalid.is_empty() {
+        errors.push(Error::new(
+            StatusCode::UNPROCESSABLE_ENTITY.as_u16(),
+            "Invalid Actions",
+            "A policy must apply to 1 to 50 actions, written as permissions where * matches any segment. Use the deny effect rather than the - prefix.",
+            Some(&format!("{location}.actions")),
+            HashMap::from([("invalid", json!(invalid))]),
+        ));
+    }
+
+    if policy.condition.chars().count() > MAX_CONDITION_LENGTH {
+        errors.push(Error::new(
+            StatusCode::UNPROCESSABLE_ENTITY.as_u16(),
+            "Invalid Condition",
+            "The condition must be at most 4096 characters long.",
+            Some(&format!("{location}.condition")),
+            HashMap::new(),
+        ));
+    } else if let Err(e) = language::parse(&policy.condition) {
+        errors.push(Error::new(
+            StatusCode::UNPROCESSABLE_ENTITY.as_u16(),
+            "Invalid Condition",
+            &e.message,
+            Some(&format!("{location}.condition")),
+            HashMap::from([("position", json!(e.position))]),
+        ));
+    }
+
+    errors
+}
+
+fn invalid_name_error(name: &str, location: &str) -> Error {
+    Error::new(
+        StatusCode::UNPROCESSABLE_ENTITY.as_u16(),
+        "Invalid Policy Name",
+        "The policy name must be from 1 to 64 lowercase letters, digits, dots, underscores or hyphens.",
+        Some(location),
+        HashMap::from([("input", json!(trim(name, 20)))]),
+    )
+}
+
+async fn find_version(
+    db: &Session,
+    tenant_id: &str,
+    name: &str,
+    version: i32,
+    request_id: &str,
+) -> Result<Policy, response::Response<Body>> {
+    match policies::version(db, tenant_id, name, version).await {
+        Ok(Some(policy)) => Ok(policy),
+        Ok(None) => Err(version_not_found_response(
+            version,
+            request_id.to_string(),
+            tenant_id.to_string(),
+        )),
+        Err(e) => Err(internal_error(
+            e,
+            request_id.to_string(),
+            tenant_id.to_string(),
+        )),
+    }
+}
+
+/// Lists the tenant's policies. Requires the `policies.manage` permission.
+pub async fn list_policies(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(response_meta): Extension<ResponseMeta<'_>>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+) -> response::Response<Body> {
+    let state = state.read().await;
+
+    if let Err(response) = authorize(
+        &state,
+        &tenant_id,
+        auth.user_id,
+        &auth.scopes,
+        MANAGE_PERMISSION,
+        &request_id,
+    )
+    .await
+    {
+        return response;
+    }
+
+    match policies::all(&state.db, &tenant_id).await {
+        Ok(all) => Response::new(Some(all), None, Some(response_meta), None).into_response(),
+        Err(e) => internal_error(e, request_id, tenant_id),
+    }
+}
+
+pub async fn get_policy(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(response_meta): Extension<ResponseMeta<'_>>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> response::Response<Body> {
+    let state = state.read().await;
+
+    if let Err(response) = authorize(
+        &state,
+        &tenant_id,
+        auth.user_id,
+        &auth.scopes,
+        MANAGE_PERMISSION,
+        &request_id,
+    )
+    .await
+    {
+        return response;
+    }
+
+    match policies::get(&state.db, &tenant_id, &name).await {
+        Ok(Some(policy)) => {
+            Response::new(Some(policy), None, Some(response_meta), None).into_response()
+        }
+        Ok(None) => not_found_response(&name, request_id, tenant_id),
+        Err(e) => internal_error(e, request_id, tenant_id),
+    }
+}
+
+/// Creates or updates a policy, saving it as a new version. The condition is parsed up front,
+/// so policies in effect always parse.
+pub async fn set_policy(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(response_meta): Extension<ResponseMeta<'_>>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    payload: Result<Json<Request<SetPolicyPayload>>, JsonRejection>,
+) -> response::Response<Body> {
+    let Json(Request { data: payload, .. }) = match payload {
+        Ok(p) => p,
+        Err(err) => {
+            return CommonError::JsonRejection {
+                err,
+                request_id,
+                tenant_id: Some(tenant_id),
+            }
+            .into_response()
+        }
+    };
+
+    let state = state.read().await;
+
+    let user_id = match authorize(
+        &state,
+        &tenant_id,
+        auth.user_id,
+        &auth.scopes,
+        MANAGE_PERMISSION,
+        &request_id,
+    )
+    .await
+    {
+        Ok(user_id) => user_id,
+        Err(response) => return response,
+    };
+
+    let mut errors = validate(&payload, "body.data");
+
+    // The dry-run route shadows a policy of the same name.
+    if !policies::is_valid_name(&name) || name == "dry-run" {
+        errors.push(invalid_name_error(&name, "path.policy"));
+    }
+
+    if !errors.is_empty() {
+        return unprocessable(errors, response_meta);
+    }
+
+    let policy = Policy {
+        name,
+        version: 0,
+        description: payload.description,
+        effect: payload.effect,
+        actions: payload.actions,
+        condition: payload.condition,
+        updated_by: Some(user_id),
+        updated_at: None,
+    };
+
+    match policies::save(&state, &tenant_id, policy.clone()).await {
+        Ok(Some(policy)) => {
+            Response::new(Some(policy), None, Some(response_meta), None).into_response()
+        }
+        Ok(None) => conflict_response(&policy.name, request_id, tenant_id),
+        Err(e) => internal_error(e, request_id, tenant_id),
+    }
+}
+
+/// Deletes a policy. Its versions are kept, so it can be restored later.
+pub async fn delete_policy(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> response::Response<Body> {
+    let state = state.read().await;
+
+    if let Err(response) = authorize(
+        &state,
+        &tenant_id,
+        auth.user_id,
+        &auth.scopes,
+        MANAGE_PERMISSION,
+        &request_id,
+    )
+    .await
+    {
+        return response;
+    }
+
+    match policies::get(&state.db, &tenant_id, &name).await {
+        Ok(Some(_)) => {}
+        Ok(None) => return not_found_response(&name, request_id, tenant_id),
+        Err(e) => return internal_error(e, request_id, tenant_id),
+    }
+
+    if let Err(e) = policies::delete(&state, &tenant_id, &name).await {
+        return internal_error(e, request_id, tenant_id);
+    }
+
+    StatusCode::NO_CONTENT.into_response()
+}
+
+/// Lists every version of a policy, newest first.
+pub async fn list_versions(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(response_meta): Extension<ResponseMeta<'_>>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> response::Response<Body> {
+    let state = state.read().await;
+
+    if let Err(response) = authorize(
+        &state,
+        &tenant_id,
+        auth.user_id,
+        &auth.scopes,
+        MANAGE_PERMISSION,
+        &request_id,
+    )
+    .await
+    {
+        return response;
+    }
+
+    match policies::versions(&state.db, &tenant_id, &name).await {
+        Ok(versions) if versions.is_empty() => not_found_response(&name, request_id, tenant_id),
+        Ok(versions) => {
+            Response::new(Some(versions), None, Some(response_meta), None).into_response()
+        }
+        Err(e) => internal_error(e, request_id, tenant_id),
+    }
+}
+
+pub async fn get_version(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(response_meta): Extension<ResponseMeta<'_>>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    Path((name, version)): Path<(String, i32)>,
+) -> response::Response<Body> {
+    let state = state.read().await;
+
+    if let Err(response) = authorize(
+        &state,
+        &tenant_id,
+        auth.user_id,
+        &auth.scopes,
+        MANAGE_PERMISSION,
+        &request_id,
+    )
+    .await
+    {
+        return response;
+    }
+
+    match find_version(&state.db, &tenant_id, &name, version, &request_id).await {
+        Ok(policy) => Response::new(Some(policy), None, Some(response_meta), None).into_response(),
+        Err(response) => response,
+    }
+}
+
+/// Puts a previous version of a policy back in effect, saving it as the newest version.
+pub async fn restore_version(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(response_meta): Extension<ResponseMeta<'_>>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    Path((name, version)): Path<(String, i32)>,
+) -> response::Response<Body> {
+    let state = state.read().await;
+
+    let user_id = match authorize(
+        &state,
+        &tenant_id,
+        auth.user_id,
+        &auth.scopes,
+        MANAGE_PERMISSION,
+        &request_id,
+    )
+    .await
+    {
+        Ok(user_id) => user_id,
+        Err(response) => return response,
+    };
+
+    let policy = match find_version(&state.db, &tenant_id, &name, version, &request_id).await {
+        Ok(policy) => policy,
+        Err(response) => return response,
+    };
+
+    let policy = Policy {
+        updated_by: Some(user_id),
+        ..policy
+    };
+
+    match policies::save(&state, &tenant_id, policy).await {
+        Ok(Some(policy)) => {
+            Response::new(Some(policy), None, Some(response_meta), None).into_response()
+        }
+        Ok(None) => conflict_response(&name, request_id, tenant_id),
+        Err(e) => internal_error(e, request_id, tenant_id),
+    }
+}
+
+/// Decides a check the way `/authz/check` would, optionally with an unsaved policy and in
+/// other circumstances than the current request. Returns the decision, explained, along with
+/// the context policies were evaluated over. Nothing is saved or cached.
+#[allow(clippy::too_many_arguments)]
+pub async fn dry_run(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(response_meta): Extension<ResponseMeta<'_>>,
+    Extension(auth): Extension<Auth>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    State(state): State<AppState>,
+    payload: Result<Json<Request<DryRunPayload>>, JsonRejection>,
+) -> response::Response<Body> {
+    let Json(Request { data: payload, .. }) = match payload {
+        Ok(p) => p,
+        Err(err) => {
+            return CommonError::JsonRejection {
+                err,
+                request_id,
+                tenant_id: Some(tenant_id),
+            }
+            .into_response()
+        }
+    };
+
+    let state = state.read().await;
+
+    let user_id = match authorize(
+        &state,
+        &tenant_id,
+        auth.user_id,
+        &auth.scopes,
+        MANAGE_PERMISSION,
+        &request_id,
+    )
+    .await
+    {
+        Ok(user_id) => user_id,
+        Err(response) => return response,
+    };
+
+    let mut errors: Vec<Error> = vec![];
+
+    if !permissions::is_concrete(&payload.action) {
+        errors.push(Error::new(
+            StatusCode::UNPROCESSABLE_ENTITY.as_u16(),
+            "Invalid Action",
+            "The action must be a permission name without wildcards, like billing.invoices.read.",
+            Some("body.data.action"),
+            HashMap::from([("input", json!(trim(&payload.action, 20)))]),
+        ));
+    }
+
+    let resource = Resource::parse(payload.resource.as_deref());
+
+    if resource.is_none() {
+        errors.push(Error::new(
+            StatusCode::UNPROCESSABLE_ENTITY.as_u16(),
+            "Invalid Resource",
+            "The resource must be omitted, or reference an organization as organization:{organization_id} or a user as user:{user_id}.",
+            Some("body.data.resource"),
+            HashMap::from([("input", json!(payload.resource.as_deref().map(|r| trim(r, 20))))]),
+        ));
+    }
+
+    if let Some(draft) = &payload.policy {
+        errors.extend(validate(&draft.policy, "body.data.policy"));
+
+        if let Some(name) = draft
+            .name
+            .as_deref()
+            .filter(|n| !policies::is_valid_name(n))
+        {
+            errors.push(invalid_name_error(name, "body.data.policy.name"));
+        }
+    }
+
+    let overrides = payload.context.unwrap_or_default();
+    let mut request = RequestContext::new(addr.ip(), &headers, auth.device_id);
+
+    if let Some(time) = overrides.time {
+        match DateTime::parse_from_rfc3339(&time) {
+            Ok(time) => request.time = time.with_timezone(&Utc),
+            Err(_) => errors.push(Error::new(
+                StatusCode::UNPROCESSABLE_ENTITY.as_u16(),
+                "Invalid Time",
+                "The time must be an RFC 3339 date and time, like 2024-01-31T09:30:00Z.",
+                Some("body.data.context.time"),
+                HashMap::from([("input", json!(trim(&time, 40)))]),
+            )),
+        }
+    }
+
+    if !errors.is_empty() {
+        return unprocessable(errors, response_meta);
+    }
+
+    request.ip = overrides.ip.or(request.ip);
+    request.device_id = overrides.device_id.or(request.device_id);
+    request.user_agent = overrides.user_agent.or(request.user_agent);
+
+    let mut saved = match policies::all(&state.db, &tenant_id).await {
+        Ok(saved) => saved,
+        Err(e) => return internal_error(e, request_id, tenant_id),
+    };
+
+    if let Some(draft) = payload.policy {
+        let name = draft.name.unwrap_or_else(|| DRAFT_NAME.to_string());
+
+        saved.retain(|policy| policy.name != name);
+        saved.push(Policy {
+            name,
+            version: 0,
+            description: draft.policy.description,
+            effect: draft.policy.effect,
+            actions: draft.policy.actions,
+            condition: draft.policy.condition,
+            updated_by: Some(user_id.clone()),
+            updated_at: None,
+        });
+    }
+
+    let subject = payload.subject.unwrap_or(user_id);
+    let resource = resource.unwrap_or(Resource::Tenant);
+
+    let context = match policies::context(&state, &tenant_id, &subject, &resource, &request).await {
+        Ok(context) => context,
+        Err(e) => return internal_error(e, request_id, tenant_id),
+    };
+
+    match authz::decide(
+        &state,
+        &tenant_id,
+        &subject,
+        &payload.action,
+        &resource,
+        &request,
+        &saved,
+        true,
+    )
+    .await
+    {
+        Ok(decision) => {
+            let data = json!({
+                "subject": subject,
+                "action": payload.action,
+                "resource": payload.resource,
+                "decision": decision,
+                "context": context,
+            });
+
+            Response::new(Some(data), None, Some(response_meta), None).into_response()
+        }
+        Err(e) => internal_error(e, request_id, tenant_id),
+    }
+}
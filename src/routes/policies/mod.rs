@@ -0,0 +1,27 @@
+mod handlers;
+mod requests;
+
+use axum::{
+    routing::{get, post},
+    Router,
+};
+
+use crate::state::AppState;
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/", get(handlers::list_policies))
+        .route("/dry-run", post(handlers::dry_run))
+        .route(
+            "/:policy",
+            get(handlers::get_policy)
+                .put(handlers::set_policy)
+                .delete(handlers::delete_policy),
+        )
+        .route("/:policy/versions", get(handlers::list_versions))
+        .route("/:policy/versions/:version", get(handlers::get_version))
+        .route(
+            "/:policy/versions/:version/restore",
+            post(handlers::restore_version),
+        )
+}
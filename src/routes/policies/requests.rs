@@ -0,0 +1,47 @@
+use std::collections::BTreeSet;
+
+use serde::Deserialize;
+
+use crate::policies::Effect;
+
+#[derive(Debug, Deserialize)]
+pub struct SetPolicyPayload {
+    pub description: Option<String>,
+    pub effect: Effect,
+
+    /// Permission patterns of the actions the policy applies to, like `users.profiles.*`.
+    pub actions: BTreeSet<String>,
+    pub condition: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DraftPolicy {
+    /// The name of the saved policy the draft stands in for, if any.
+    pub name: Option<String>,
+
+    #[serde(flatten)]
+    pub policy: SetPolicyPayload,
+}
+
+/// Overrides of what's known about the request, to try decisions in other circumstances.
+#[derive(Debug, Default, Deserialize)]
+pub struct DryRunContext {
+    pub ip: Option<String>,
+    pub device_id: Option<String>,
+    pub user_agent: Option<String>,
+
+    /// An RFC 3339 date and time.
+    pub time: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DryRunPayload {
+    /// The user to decide for, which defaults to the authenticated user.
+    pub subject: Option<String>,
+    pub action: String,
+    pub resource: Option<String>,
+
+    /// An unsaved policy to evaluate along with the saved ones.
+    pub policy: Option<DraftPolicy>,
+    pub context: Option<DryRunContext>,
+}
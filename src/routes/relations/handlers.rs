@@ -0,0 +1,720 @@
+use super::requests::{
+    CheckPayload, ExpandPayload, ListObjectsPayload, SetNamespacePayload, WriteTuplesPayload,
+};
+use crate::{
+    auth::Auth,
+    error_handlers::{self, error_response, internal_error},
+    relations::{
+        self, namespaces, ConsistencyToken, Evaluator, Object, Tuple, MANAGE_PERMISSION,
+        READ_PERMISSION,
+    },
+    requests::Request,
+    responses::{CommonError, Error, Response, ResponseMeta},
+    state::{self, AppState},
+    types::{RequestID, TenantID},
+    utils::text::trim,
+};
+use axum::{
+    body::Body,
+    extract::{rejection::JsonRejection, Path, State},
+    http::StatusCode,
+    response::{self, IntoResponse},
+    Extension, Json,
+};
+use serde_json::{json, Value};
+use std::collections::{BTreeSet, HashMap};
+
+/// Maximum number of writes and deletes in a single tuple write.
+const MAX_WRITE_SIZE: usize = 100;
+
+/// Checks that the user is authenticated and, unless `subject` is themselves, that they have
+/// `permission`.
+async fn authorize(
+    state: &state::State,
+    tenant_id: &str,
+    user_id: Option<String>,
+    scopes: &[String],
+    subject: Option<&str>,
+    permission: &str,
+    request_id: &str,
+) -> Result<(), response::Response<Body>> {
+    if user_id.is_some() && subject == user_id.as_deref() {
+        return Ok(());
+    }
+
+    error_handlers::authorize(state, tenant_id, user_id, scopes, permission, request_id)
+        .await
+        .map(|_| ())
+}
+
+fn unprocessable(errors: Vec<Error>, response_meta: ResponseMeta<'_>) -> response::Response<Body> {
+    let response: Response<Value> = Response::new(None, Some(errors), Some(response_meta), None);
+
+    (StatusCode::UNPROCESSABLE_ENTITY, Json(response)).into_response()
+}
+
+fn invalid_error(title: &str, detail: &str, location: &str, input: &str) -> Error {
+    Error::new(
+        StatusCode::UNPROCESSABLE_ENTITY.as_u16(),
+        title,
+        detail,
+        Some(location),
+        HashMap::from([("input", json!(trim(input, 40)))]),
+    )
+}
+
+fn namespace_not_found(
+    namespace: &str,
+    request_id: String,
+    tenant_id: String,
+) -> response::Response<Body> {
+    error_response(
+        StatusCode::NOT_FOUND,
+        "Namespace Not Found",
+        "There's no namespace with this name.",
+        Some("path.namespace"),
+        HashMap::from([("input", json!(trim(namespace, 20)))]),
+        request_id,
+        Some(tenant_id),
+    )
+    .into_response()
+}
+
+/// Parses an optional consistency token, adding an error to `errors` if it's malformed.
+fn consistency_token(token: Option<&str>, errors: &mut Vec<Error>) -> Option<ConsistencyToken> {
+    let token = token?;
+    let decoded = ConsistencyToken::decode(token);
+
+    if decoded.is_none() {
+        errors.push(invalid_error(
+            "Invalid Consistency Token",
+            "The consistency token must be one returned by a tuple write.",
+            "body.data.consistency_token",
+            token,
+        ));
+    }
+
+    decoded
+}
+
+pub async fn list_namespaces(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(response_meta): Extension<ResponseMeta<'_>>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+) -> response::Response<Body> {
+    let state = state.read().await;
+
+    if let Err(response) = authorize(
+        &state,
+        &tenant_id,
+        auth.user_id,
+        &auth.scopes,
+        None,
+        MANAGE_PERMISSION,
+        &request_id,
+    )
+    .await
+    {
+        return response;
+    }
+
+    match relations::list_namespaces(&state.db, &tenant_id).await {
+        Ok(namespaces) => {
+            Response::new(Some(namespaces), None, Some(response_meta), None).into_response()
+        }
+        Err(e) => internal_error(e, request_id, tenant_id),
+    }
+}
+
+pub async fn get_namespace(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(response_meta): Extension<ResponseMeta<'_>>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    Path(namespace): Path<String>,
+) -> response::Response<Body> {
+    let state = state.read().await;
+
+    if let Err(response) = authorize(
+        &state,
+        &tenant_id,
+        auth.user_id,
+        &auth.scopes,
+        None,
+        MANAGE_PERMISSION,
+        &request_id,
+    )
+    .await
+    {
+        return response;
+    }
+
+    match relations::namespace(&state.db, &tenant_id, &namespace).await {
+        Ok(Some(config)) => {
+            Response::new(Some(config), None, Some(response_meta), None).into_response()
+        }
+        Ok(None) => namespace_not_found(&namespace, request_id, tenant_id),
+        Err(e) => internal_error(e, request_id, tenant_id),
+    }
+}
+
+/// Creates or replaces a namespace config. Tuples of relations the new config no longer defines
+/// are kept but ignored.
+pub async fn set_namespace(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(response_meta): Extension<ResponseMeta<'_>>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    Path(namespace): Path<String>,
+    payload: Result<Json<Request<SetNamespacePayload>>, JsonRejection>,
+) -> response::Response<Body> {
+    let Json(Request { data: payload, .. }) = match payload {
+        Ok(p) => p,
+        Err(err) => {
+            return CommonError::JsonRejection {
+                err,
+                request_id,
+                tenant_id: Some(tenant_id),
+            }
+            .into_response()
+        }
+    };
+
+    let state = state.read().await;
+
+    if let Err(response) = authorize(
+        &state,
+        &tenant_id,
+        auth.user_id,
+        &auth.scopes,
+        None,
+        MANAGE_PERMISSION,
+        &request_id,
+    )
+    .await
+    {
+        return response;
+    }
+
+    let mut errors: Vec<Error> = vec![];
+
+    if !namespaces::is_valid_name(&namespace) {
+        errors.push(invalid_error(
+            "Invalid Namespace Name",
+            "The namespace name must be from 1 to 64 lowercase letters, digits or underscores, starting with a letter.",
+            "path.namespace",
+            &namespace,
+        ));
+    }
+
+    let relations = match namespaces::parse(&payload.config) {
+        Ok(relations) => Some(relations),
+        Err(e) => {
+            errors.push(Error::new(
+                StatusCode::UNPROCESSABLE_ENTITY.as_u16(),
+                "Invalid Namespace Config",
+                &e.message,
+                Some("body.data.config"),
+                HashMap::from([("line", json!(e.line))]),
+            ));
+            None
+        }
+    };
+
+    if !errors.is_empty() {
+        return unprocessable(errors, response_meta);
+    }
+
+    if let Err(e) =
+        relations::save_namespace(&state.db, &tenant_id, &namespace, &payload.config).await
+    {
+        return internal_error(e, request_id, tenant_id);
+    }
+
+    let data = json!({
+        "namespace": namespace,
+        "config": payload.config,
+        "relations": relations.map(|relations| {
+            relations
+                .into_iter()
+                .map(|(name, rewrite)| (name, rewrite.to_string()))
+                .collect::<HashMap<String, String>>()
+        }),
+    });
+
+    Response::new(Some(data), None, Some(response_meta), None).into_response()
+}
+
+/// Deletes a namespace along with all of its tuples.
+pub async fn delete_namespace(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    Path(namespace): Path<String>,
+) -> response::Response<Body> {
+    let state = state.read().await;
+
+    if let Err(response) = authorize(
+        &state,
+        &tenant_id,
+        auth.user_id,
+        &auth.scopes,
+        None,
+        MANAGE_PERMISSION,
+        &request_id,
+    )
+    .await
+    {
+        return response;
+    }
+
+    match relations::namespace(&state.db, &tenant_id, &namespace).await {
+        Ok(Some(_)) => {}
+        Ok(None) => return namespace_not_found(&namespace, request_id, tenant_id),
+        Err(e) => return internal_error(e, request_id, tenant_id),
+    }
+
+    if let Err(e) = relations::delete_namespace(&state.db, &tenant_id, &namespace).await {
+        return internal_error(e, request_id, tenant_id);
+    }
+
+    StatusCode::NO_CONTENT.into_response()
+}
+
+pub async fn list_tuples(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(response_meta): Extension<ResponseMeta<'_>>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    Path(namespace): Path<String>,
+) -> response::Response<Body> {
+    let state = state.read().await;
+
+    if let Err(response) = authorize(
+        &state,
+        &tenant_id,
+        auth.user_id,
+        &auth.scopes,
+        None,
+        READ_PERMISSION,
+        &request_id,
+    )
+    .await
+    {
+        return response;
+    }
+
+    match relations::tuples(&state.db, &tenant_id, &namespace, None, None).await {
+        Ok(tuples) => Response::new(Some(tuples), None, Some(response_meta), None).into_response(),
+        Err(e) => internal_error(e, request_id, tenant_id),
+    }
+}
+
+pub async fn list_object_tuples(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(response_meta): Extension<ResponseMeta<'_>>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    Path((namespace, object_id)): Path<(String, String)>,
+) -> response::Response<Body> {
+    let state = state.read().await;
+
+    if let Err(response) = authorize(
+        &state,
+        &tenant_id,
+        auth.user_id,
+        &auth.scopes,
+        None,
+        READ_PERMISSION,
+        &request_id,
+    )
+    .await
+    {
+        return response;
+    }
+
+    match relations::tuples(&state.db, &tenant_id, &namespace, Some(&object_id), None).await {
+        Ok(tuples) => Response::new(Some(tuples), None, Some(response_meta), None).into_response(),
+        Err(e) => internal_error(e, request_id, tenant_id),
+    }
+}
+
+/// Writes and deletes up to 100 tuples atomically. The response carries a consistency token
+/// that checks can be given to see this write.
+pub async fn write_tuples(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(response_meta): Extension<ResponseMeta<'_>>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    payload: Result<Json<Request<WriteTuplesPayload>>, JsonRejection>,
+) -> response::Response<Body> {
+    let Json(Request { data: payload, .. }) = match payload {
+        Ok(p) => p,
+        Err(err) => {
+            return CommonError::JsonRejection {
+                err,
+                request_id,
+                tenant_id: Some(tenant_id),
+            }
+            .into_response()
+        }
+    };
+
+    let state = state.read().await;
+
+    if let Err(response) = authorize(
+        &state,
+        &tenant_id,
+        auth.user_id,
+        &auth.scopes,
+        None,
+        MANAGE_PERMISSION,
+        &request_id,
+    )
+    .await
+    {
+        return response;
+    }
+
+    let writes = payload.writes.unwrap_or_default();
+    let deletes = payload.deletes.unwrap_or_default();
+    let mut errors: Vec<Error> = vec![];
+
+    if writes.len() + deletes.len() > MAX_WRITE_SIZE {
+        errors.push(Error::new(
+            StatusCode::UNPROCESSABLE_ENTITY.as_u16(),
+            "Too Many Tuples",
+            "A write can contain up to 100 writes and deletes in total.",
+            Some("body.data"),
+            HashMap::from([("count", json!(writes.len() + deletes.len()))]),
+        ));
+    }
+
+    let mut parse = |tuples: &[String], field: &str| -> Vec<Tuple> {
+        tuples
+            .iter()
+            .enumerate()
+            .filter_map(|(i, tuple)| match Tuple::parse(tuple) {
+                Some(parsed) => Some(parsed),
+                None => {
+                    errors.push(invalid_error(
+                        "Invalid Tuple",
+                        "Tuples must be written as namespace:object_id#relation@subject, where the subject is a user ID, an object or an object#relation userset.",
+                        &format!("body.data.{field}[{i}]"),
+                        tuple,
+                    ));
+                    None
+                }
+            })
+            .collect()
+    };
+
+    let writes = parse(&writes, "writes");
+    let deletes = parse(&deletes, "deletes");
+
+    if deletes.iter().any(|tuple| writes.contains(tuple)) {
+        errors.push(Error::new(
+            StatusCode::UNPROCESSABLE_ENTITY.as_u16(),
+            "Conflicting Tuples",
+            "A tuple can't be written and deleted in the same write.",
+            Some("body.data.deletes"),
+            HashMap::new(),
+        ));
+    }
+
+    // Tuples can only be written for relations their namespace defines, while deleting is
+    // always allowed so stale tuples can be cleaned up.
+    let mut defined: HashMap<String, BTreeSet<String>> = HashMap::new();
+
+    for (i, tuple) in writes.iter().enumerate() {
+        let namespace = &tuple.object.namespace;
+
+        if !defined.contains_key(namespace) {
+            let relations = match relations::namespace(&state.db, &tenant_id, namespace).await {
+                Ok(config) => config
+                    .and_then(|config| namespaces::parse(&config.config).ok())
+                    .map(|relations| relations.into_keys().collect())
+                    .unwrap_or_default(),
+                Err(e) => return internal_error(e, request_id, tenant_id),
+            };
+
+            defined.insert(namespace.clone(), relations);
+        }
+
+        if !defined[namespace].contains(&tuple.relation) {
+            errors.push(invalid_error(
+                "Unknown Relation",
+                "The namespace doesn't exist or doesn't define this relation.",
+                &format!("body.data.writes[{i}]"),
+                &tuple.to_string(),
+            ));
+        }
+    }
+
+    if !errors.is_empty() {
+        return unprocessable(errors, response_meta);
+    }
+
+    match relations::write(&state.db, &tenant_id, &writes, &deletes).await {
+        Ok(token) => Response::new(
+            Some(json!({ "consistency_token": token.encode() })),
+            None,
+            Some(response_meta),
+            None,
+        )
+        .into_response(),
+        Err(e) => internal_error(e, request_id, tenant_id),
+    }
+}
+
+/// Checks whether a user is a subject of a relation on an object, following the namespace
+/// configs. Anyone can check themselves, while checking others requires `relations.read`.
+pub async fn check(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(response_meta): Extension<ResponseMeta<'_>>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    payload: Result<Json<Request<CheckPayload>>, JsonRejection>,
+) -> response::Response<Body> {
+    let Json(Request { data: payload, .. }) = match payload {
+        Ok(p) => p,
+        Err(err) => {
+            return CommonError::JsonRejection {
+                err,
+                request_id,
+                tenant_id: Some(tenant_id),
+            }
+            .into_response()
+        }
+    };
+
+    let state = state.read().await;
+    let subject = payload.subject.or_else(|| auth.user_id.clone());
+
+    if let Err(response) = authorize(
+        &state,
+        &tenant_id,
+        auth.user_id,
+        &auth.scopes,
+        subject.as_deref(),
+        READ_PERMISSION,
+        &request_id,
+    )
+    .await
+    {
+        return response;
+    }
+
+    let subject = subject.unwrap_or_default();
+    let mut errors: Vec<Error> = vec![];
+    let object = Object::parse(&payload.object);
+
+    if object.is_none() {
+        errors.push(invalid_error(
+            "Invalid Object",
+            "The object must be written as namespace:object_id.",
+            "body.data.object",
+            &payload.object,
+        ));
+    }
+
+    if !namespaces::is_valid_name(&payload.relation) {
+        errors.push(invalid_error(
+            "Invalid Relation",
+            "The relation must be a relation name.",
+            "body.data.relation",
+            &payload.relation,
+        ));
+    }
+
+    let token = consistency_token(payload.consistency_token.as_deref(), &mut errors);
+
+    let Some(object) = object.filter(|_| errors.is_empty()) else {
+        return unprocessable(errors, response_meta);
+    };
+
+    let mut evaluator = Evaluator::new(&state, &tenant_id, token);
+
+    match evaluator.check(&object, &payload.relation, &subject).await {
+        Ok(allowed) => Response::new(
+            Some(json!({
+                "object": object.to_string(),
+                "relation": payload.relation,
+                "subject": subject,
+                "allowed": allowed,
+            })),
+            None,
+            Some(response_meta),
+            None,
+        )
+        .into_response(),
+        Err(e) => internal_error(e, request_id, tenant_id),
+    }
+}
+
+/// Expands a relation on an object into the tree of users and usersets it's made of. Requires
+/// the `relations.read` permission.
+pub async fn expand(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(response_meta): Extension<ResponseMeta<'_>>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    payload: Result<Json<Request<ExpandPayload>>, JsonRejection>,
+) -> response::Response<Body> {
+    let Json(Request { data: payload, .. }) = match payload {
+        Ok(p) => p,
+        Err(err) => {
+            return CommonError::JsonRejection {
+                err,
+                request_id,
+                tenant_id: Some(tenant_id),
+            }
+            .into_response()
+        }
+    };
+
+    let state = state.read().await;
+
+    if let Err(response) = authorize(
+        &state,
+        &tenant_id,
+        auth.user_id,
+        &auth.scopes,
+        None,
+        READ_PERMISSION,
+        &request_id,
+    )
+    .await
+    {
+        return response;
+    }
+
+    let mut errors: Vec<Error> = vec![];
+    let object = Object::parse(&payload.object);
+
+    if object.is_none() {
+        errors.push(invalid_error(
+            "Invalid Object",
+            "The object must be written as namespace:object_id.",
+            "body.data.object",
+            &payload.object,
+        ));
+    }
+
+    if !namespaces::is_valid_name(&payload.relation) {
+        errors.push(invalid_error(
+            "Invalid Relation",
+            "The relation must be a relation name.",
+            "body.data.relation",
+            &payload.relation,
+        ));
+    }
+
+    let token = consistency_token(payload.consistency_token.as_deref(), &mut errors);
+
+    let Some(object) = object.filter(|_| errors.is_empty()) else {
+        return unprocessable(errors, response_meta);
+    };
+
+    let mut evaluator = Evaluator::new(&state, &tenant_id, token);
+
+    match evaluator.expand(object, payload.relation).await {
+        Ok(expansion) => {
+            Response::new(Some(expansion), None, Some(response_meta), None).into_response()
+        }
+        Err(e) => internal_error(e, request_id, tenant_id),
+    }
+}
+
+/// Lists the objects of a namespace on which a user is a subject of a relation. Anyone can
+/// list their own objects, while listing others' requires `relations.read`.
+pub async fn list_objects(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(response_meta): Extension<ResponseMeta<'_>>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    payload: Result<Json<Request<ListObjectsPayload>>, JsonRejection>,
+) -> response::Response<Body> {
+    let Json(Request { data: payload, .. }) = match payload {
+        Ok(p) => p,
+        Err(err) => {
+            return CommonError::JsonRejection {
+                err,
+                request_id,
+                tenant_id: Some(tenant_id),
+            }
+            .into_response()
+        }
+    };
+
+    let state = state.read().await;
+    let subject = payload.subject.or_else(|| auth.user_id.clone());
+
+    if let Err(response) = authorize(
+        &state,
+        &tenant_id,
+        auth.user_id,
+        &auth.scopes,
+        subject.as_deref(),
+        READ_PERMISSION,
+        &request_id,
+    )
+    .await
+    {
+        return response;
+    }
+
+    let subject = subject.unwrap_or_default();
+    let mut errors: Vec<Error> = vec![];
+
+    if !namespaces::is_valid_name(&payload.namespace) {
+        errors.push(invalid_error(
+            "Invalid Namespace",
+            "The namespace must be a namespace name.",
+            "body.data.namespace",
+            &payload.namespace,
+        ));
+    }
+
+    if !namespaces::is_valid_name(&payload.relation) {
+        errors.push(invalid_error(
+            "Invalid Relation",
+            "The relation must be a relation name.",
+            "body.data.relation",
+            &payload.relation,
+        ));
+    }
+
+    let token = consistency_token(payload.consistency_token.as_deref(), &mut errors);
+
+    if !errors.is_empty() {
+        return unprocessable(errors, response_meta);
+    }
+
+    let mut evaluator = Evaluator::new(&state, &tenant_id, token);
+
+    match relations::list_objects(
+        &mut evaluator,
+        &payload.namespace,
+        &payload.relation,
+        &subject,
+    )
+    .await
+    {
+        Ok(objects) => {
+            Response::new(Some(objects), None, Some(response_meta), None).into_response()
+        }
+        Err(e) => internal_error(e, request_id, tenant_id),
+    }
+}
@@ -0,0 +1,29 @@
+mod handlers;
+mod requests;
+
+use axum::{
+    routing::{get, post},
+    Router,
+};
+
+use crate::state::AppState;
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/namespaces", get(handlers::list_namespaces))
+        .route(
+            "/namespaces/:namespace",
+            get(handlers::get_namespace)
+                .put(handlers::set_namespace)
+                .delete(handlers::delete_namespace),
+        )
+        .route("/namespaces/:namespace/tuples", get(handlers::list_tuples))
+        .route(
+            "/namespaces/:namespace/tuples/:object_id",
+            get(handlers::list_object_tuples),
+        )
+        .route("/tuples", post(handlers::write_tuples))
+        .route("/check", post(handlers::check))
+        .route("/expand", post(handlers::expand))
+        .route("/list-objects", post(handlers::list_objects))
+}
@@ -0,0 +1,39 @@
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize)]
+pub struct SetNamespacePayload {
+    pub config: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct WriteTuplesPayload {
+    /// Tuples to write, as `namespace:object_id#relation@subject`.
+    pub writes: Option<Vec<String>>,
+    pub deletes: Option<Vec<String>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CheckPayload {
+    /// The object, as `namespace:object_id`.
+    pub object: String,
+    pub relation: String,
+
+    /// The user to check, which defaults to the authenticated user.
+    pub subject: Option<String>,
+    pub consistency_token: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ExpandPayload {
+    pub object: String,
+    pub relation: String,
+    pub consistency_token: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ListObjectsPayload {
+    pub namespace: String,
+    pub relation: String,
+    pub subject: Option<String>,
+    pub consistency_token: Option<String>,
+}
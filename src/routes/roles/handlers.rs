@@ -0,0 +1,747 @@
+use super::requests::{CreateRolePayload, UpdateRolePayload};
+use crate::{
+    auth::Auth,
+    error_handlers::{authorize, error_response, internal_error},
+    groups, organizations, permissions,
+    requests::Request,
+    responses::{CommonError, Error, Response, ResponseMeta},
+    roles::{self, Role, MANAGE_PERMISSION},
+    state::{self, AppState},
+    types::{RequestID, TenantID},
+    users,
+    utils::text::trim,
+};
+use axum::{
+    body::Body,
+    extract::{rejection::JsonRejection, Path, State},
+    http::StatusCode,
+    response::{self, IntoResponse},
+    Extension, Json,
+};
+use scylla::Session;
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+
+fn not_found_response(
+    name: &str,
+    request_id: String,
+    tenant_id: String,
+) -> response::Response<Body> {
+    error_response(
+        StatusCode::NOT_FOUND,
+        "Role Not Found",
+        "There's no role with this name.",
+        Some("path.role"),
+        HashMap::from([("input", json!(trim(name, 20)))]),
+        request_id,
+        Some(tenant_id),
+    )
+    .into_response()
+}
+
+async fn find(
+    db: &Session,
+    tenant_id: &str,
+    name: &str,
+    request_id: &str,
+) -> Result<Role, response::Response<Body>> {
+    match roles::get(db, tenant_id, name).await {
+        Ok(Some(role)) => Ok(role),
+        Ok(None) => Err(not_found_response(
+            name,
+            request_id.to_string(),
+            tenant_id.to_string(),
+        )),
+        Err(e) => Err(internal_error(
+            e,
+            request_id.to_string(),
+            tenant_id.to_string(),
+        )),
+    }
+}
+
+/// Checks whether a role name is made of 1 to 64 lowercase ASCII letters, digits, `.`, `_`
+/// or `-`, so it can be used in paths as is.
+fn is_valid_name(name: &str) -> bool {
+    (1..=64).contains(&name.len())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || "._-".contains(c))
+}
+
+/// Validates the fields of a role against the tenant's other roles, returning every error found.
+fn validate(
+    name: Option<&str>,
+    permissions: Option<&HashSet<String>>,
+    inherits: Option<&HashSet<String>>,
+    existing: &HashMap<String, Role>,
+) -> Vec<Error> {
+    let mut errors: Vec<Error> = vec![];
+
+    if let Some(name) = name {
+        if !is_valid_name(name) {
+            errors.push(Error::new(
+                StatusCode::UNPROCESSABLE_ENTITY.as_u16(),
+                "Invalid Role Name",
+                "The role name must be from 1 to 64 lowercase letters, digits, dots, underscores or hyphens.",
+                Some("body.data.name"),
+                HashMap::from([("input", json!(trim(name, 20)))]),
+            ));
+        }
+    }
+
+    if let Some(permissions) = permissions {
+        let invalid: Vec<&String> = permissions
+            .iter()
+            .filter(|permission| !permissions::is_valid(permission))
+            .collect();
+
+        if !invalid.is_empty() {
+            errors.push(Error::new(
+                StatusCode::UNPROCESSABLE_ENTITY.as_u16(),
+                "Invalid Permissions",
+                "Permissions must be dot-separated ASCII segments without spaces, where * matches any segment, optionally prefixed with - to deny them.",
+                Some("body.data.permissions"),
+                HashMap::from([("invalid", json!(invalid))]),
+            ));
+        }
+    }
+
+    if let Some(inherits) = inherits {
+        let unknown: Vec<&String> = inherits
+            .iter()
+            .filter(|parent| !existing.contains_key(*parent))
+            .collect();
+
+        if !unknown.is_empty() {
+            errors.push(Error::new(
+                StatusCode::UNPROCESSABLE_ENTITY.as_u16(),
+                "Unknown Roles",
+                "Roles can only inherit from existing roles.",
+                Some("body.data.inherits"),
+                HashMap::from([("unknown", json!(unknown))]),
+            ));
+        }
+    }
+
+    errors
+}
+
+fn cycle_response(name: &str, request_id: String, tenant_id: String) -> response::Response<Body> {
+    error_response(
+        StatusCode::CONFLICT,
+        "Role Cycle",
+        "The role would inherit from itself, directly or through other roles.",
+        Some("body.data.inherits"),
+        HashMap::from([("role", json!(trim(name, 20)))]),
+        request_id,
+        Some(tenant_id),
+    )
+    .into_response()
+}
+
+/// Creates a role. Requires the `roles.manage` permission.
+pub async fn create_role(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(response_meta): Extension<ResponseMeta<'_>>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    payload: Result<Json<Request<CreateRolePayload>>, JsonRejection>,
+) -> response::Response<Body> {
+    let Json(Request { data: payload, .. }) = match payload {
+        Ok(p) => p,
+        Err(err) => {
+            return CommonError::JsonRejection {
+                err,
+                request_id,
+                tenant_id: Some(tenant_id),
+            }
+            .into_response()
+        }
+    };
+
+    let state = state.read().await;
+
+    if let Err(response) = authorize(
+        &state,
+        &tenant_id,
+        auth.user_id,
+        &auth.scopes,
+        MANAGE_PERMISSION,
+        &request_id,
+    )
+    .await
+    {
+        return response;
+    }
+
+    let existing = match roles::all(&state.db, &tenant_id).await {
+        Ok(existing) => existing,
+        Err(e) => return internal_error(e, request_id, tenant_id),
+    };
+
+    let errors = validate(
+        Some(&payload.name),
+        payload.permissions.as_ref(),
+        payload.inherits.as_ref(),
+        &existing,
+    );
+
+    if !errors.is_empty() {
+        let response: Response<Value> =
+            Response::new(None, Some(errors), Some(response_meta), None);
+
+        return (StatusCode::UNPROCESSABLE_ENTITY, Json(response)).into_response();
+    }
+
+    if existing.contains_key(&payload.name) {
+        return error_response(
+            StatusCode::CONFLICT,
+            "Role Already Exists",
+            "There's already a role with this name.",
+            Some("body.data.name"),
+            HashMap::from([("input", json!(trim(&payload.name, 20)))]),
+            request_id,
+            Some(tenant_id),
+        )
+        .into_response();
+    }
+
+    let role = Role {
+        name: payload.name,
+        description: payload.description,
+        permissions: payload.permissions.unwrap_or_default(),
+        inherits: payload.inherits.unwrap_or_default(),
+    };
+
+    if roles::creates_cycle(&existing, &role.name, &role.inherits) {
+        return cycle_response(&role.name, request_id, tenant_id);
+    }
+
+    if let Err(e) = roles::save(&state, &tenant_id, &role).await {
+        return internal_error(e, request_id, tenant_id);
+    }
+
+    (
+        StatusCode::CREATED,
+        Response::new(Some(role), None, Some(response_meta), None),
+    )
+        .into_response()
+}
+
+/// Lists the tenant's roles. Requires the `roles.manage` permission.
+pub async fn list_roles(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(response_meta): Extension<ResponseMeta<'_>>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+) -> response::Response<Body> {
+    let state = state.read().await;
+
+    if let Err(response) = authorize(
+        &state,
+        &tenant_id,
+        auth.user_id,
+        &auth.scopes,
+        MANAGE_PERMISSION,
+        &request_id,
+    )
+    .await
+    {
+        return response;
+    }
+
+    match roles::all(&state.db, &tenant_id).await {
+        Ok(all) => {
+            let mut all: Vec<Role> = all.into_values().collect();
+            all.sort_by(|a, b| a.name.cmp(&b.name));
+
+            Response::new(Some(all), None, Some(response_meta), None).into_response()
+        }
+        Err(e) => internal_error(e, request_id, tenant_id),
+    }
+}
+
+/// Returns a role along with every permission it grants or denies, including inherited ones.
+pub async fn get_role(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(response_meta): Extension<ResponseMeta<'_>>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> response::Response<Body> {
+    let state = state.read().await;
+
+    if let Err(response) = authorize(
+        &state,
+        &tenant_id,
+        auth.user_id,
+        &auth.scopes,
+        MANAGE_PERMISSION,
+        &request_id,
+    )
+    .await
+    {
+        return response;
+    }
+
+    let existing = match roles::all(&state.db, &tenant_id).await {
+        Ok(existing) => existing,
+        Err(e) => return internal_error(e, request_id, tenant_id),
+    };
+
+    let Some(role) = existing.get(&name) else {
+        return not_found_response(&name, request_id, tenant_id);
+    };
+
+    let data = json!({
+        "name": role.name,
+        "description": role.description,
+        "permissions": role.permissions,
+        "inherits": role.inherits,
+        "effective_permissions": roles::permissions(&existing, &name),
+    });
+
+    Response::new(Some(data), None, Some(response_meta), None).into_response()
+}
+
+pub async fn update_role(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(response_meta): Extension<ResponseMeta<'_>>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    payload: Result<Json<Request<UpdateRolePayload>>, JsonRejection>,
+) -> response::Response<Body> {
+    let Json(Request { data: payload, .. }) = match payload {
+        Ok(p) => p,
+        Err(err) => {
+            return CommonError::JsonRejection {
+                err,
+                request_id,
+                tenant_id: Some(tenant_id),
+            }
+            .into_response()
+        }
+    };
+
+    let state = state.read().await;
+
+    if let Err(response) = authorize(
+        &state,
+        &tenant_id,
+        auth.user_id,
+        &auth.scopes,
+        MANAGE_PERMISSION,
+        &request_id,
+    )
+    .await
+    {
+        return response;
+    }
+
+    let existing = match roles::all(&state.db, &tenant_id).await {
+        Ok(existing) => existing,
+        Err(e) => return internal_error(e, request_id, tenant_id),
+    };
+
+    let errors = validate(
+        None,
+        payload.permissions.as_ref(),
+        payload.inherits.as_ref(),
+        &existing,
+    );
+
+    if !errors.is_empty() {
+        let response: Response<Value> =
+            Response::new(None, Some(errors), Some(response_meta), None);
+
+        return (StatusCode::UNPROCESSABLE_ENTITY, Json(response)).into_response();
+    }
+
+    let Some(mut role) = existing.get(&name).cloned() else {
+        return not_found_response(&name, request_id, tenant_id);
+    };
+
+    if let Some(description) = payload.description {
+        role.description = Some(description);
+    }
+
+    if let Some(permissions) = payload.permissions {
+        role.permissions = permissions;
+    }
+
+    if let Some(inherits) = payload.inherits {
+        if roles::creates_cycle(&existing, &name, &inherits) {
+            return cycle_response(&name, request_id, tenant_id);
+        }
+
+        role.inherits = inherits;
+    }
+
+    if let Err(e) = roles::save(&state, &tenant_id, &role).await {
+        return internal_error(e, request_id, tenant_id);
+    }
+
+    Response::new(Some(role), None, Some(response_meta), None).into_response()
+}
+
+/// Deletes a role. Its assignments stop granting anything but are kept.
+pub async fn delete_role(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> response::Response<Body> {
+    let state = state.read().await;
+
+    if let Err(response) = authorize(
+        &state,
+        &tenant_id,
+        auth.user_id,
+        &auth.scopes,
+        MANAGE_PERMISSION,
+        &request_id,
+    )
+    .await
+    {
+        return response;
+    }
+
+    if let Err(response) = find(&state.db, &tenant_id, &name, &request_id).await {
+        return response;
+    }
+
+    if let Err(e) = roles::delete(&state, &tenant_id, &name).await {
+        return internal_error(e, request_id, tenant_id);
+    }
+
+    StatusCode::NO_CONTENT.into_response()
+}
+
+pub async fn assign_to_user(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    Path((name, user_id)): Path<(String, String)>,
+) -> response::Response<Body> {
+    let state = state.read().await;
+
+    if let Err(response) = authorize(
+        &state,
+        &tenant_id,
+        auth.user_id,
+        &auth.scopes,
+        MANAGE_PERMISSION,
+        &request_id,
+    )
+    .await
+    {
+        return response;
+    }
+
+    if let Err(response) = find(&state.db, &tenant_id, &name, &request_id).await {
+        return response;
+    }
+
+    match users::exists(&state.db, &tenant_id, &user_id).await {
+        Ok(true) => {}
+        Ok(false) => {
+            return error_response(
+                StatusCode::NOT_FOUND,
+                "User Not Found",
+                "There's no user with this ID.",
+                Some("path.user_id"),
+                HashMap::from([("input", json!(trim(&user_id, 20)))]),
+                request_id,
+                Some(tenant_id),
+            )
+            .into_response()
+        }
+        Err(e) => return internal_error(e, request_id, tenant_id),
+    }
+
+    if let Err(e) = roles::assign_to_user(&state, &tenant_id, &user_id, &name).await {
+        return internal_error(e, request_id, tenant_id);
+    }
+
+    StatusCode::NO_CONTENT.into_response()
+}
+
+pub async fn unassign_from_user(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    Path((name, user_id)): Path<(String, String)>,
+) -> response::Response<Body> {
+    let state = state.read().await;
+
+    if let Err(response) = authorize(
+        &state,
+        &tenant_id,
+        auth.user_id,
+        &auth.scopes,
+        MANAGE_PERMISSION,
+        &request_id,
+    )
+    .await
+    {
+        return response;
+    }
+
+    match users::exists(&state.db, &tenant_id, &user_id).await {
+        Ok(true) => {}
+        Ok(false) => return StatusCode::NO_CONTENT.into_response(),
+        Err(e) => return internal_error(e, request_id, tenant_id),
+    }
+
+    if let Err(e) = roles::unassign_from_user(&state, &tenant_id, &user_id, &name).await {
+        return internal_error(e, request_id, tenant_id);
+    }
+
+    StatusCode::NO_CONTENT.into_response()
+}
+
+/// Finds a group, whose priority is needed to update its row.
+async fn find_group(
+    db: &Session,
+    tenant_id: &str,
+    group_id: &str,
+    request_id: &str,
+) -> Result<Option<groups::Group>, response::Response<Body>> {
+    groups::get(db, tenant_id, group_id)
+        .await
+        .map_err(|e| internal_error(e, request_id.to_string(), tenant_id.to_string()))
+}
+
+pub async fn assign_to_group(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    Path((name, group_id)): Path<(String, String)>,
+) -> response::Response<Body> {
+    let state = state.read().await;
+
+    if let Err(response) = authorize(
+        &state,
+        &tenant_id,
+        auth.user_id,
+        &auth.scopes,
+        MANAGE_PERMISSION,
+        &request_id,
+    )
+    .await
+    {
+        return response;
+    }
+
+    if let Err(response) = find(&state.db, &tenant_id, &name, &request_id).await {
+        return response;
+    }
+
+    let group = match find_group(&state.db, &tenant_id, &group_id, &request_id).await {
+        Ok(Some(group)) => group,
+        Ok(None) => {
+            return error_response(
+                StatusCode::NOT_FOUND,
+                "Group Not Found",
+                "There's no group with this ID.",
+                Some("path.group_id"),
+                HashMap::from([("input", json!(trim(&group_id, 20)))]),
+                request_id,
+                Some(tenant_id),
+            )
+            .into_response()
+        }
+        Err(response) => return response,
+    };
+
+    if let Err(e) =
+        roles::assign_to_group(&state, &tenant_id, &group_id, group.priority, &name).await
+    {
+        return internal_error(e, request_id, tenant_id);
+    }
+
+    StatusCode::NO_CONTENT.into_response()
+}
+
+pub async fn unassign_from_group(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    Path((name, group_id)): Path<(String, String)>,
+) -> response::Response<Body> {
+    let state = state.read().await;
+
+    if let Err(response) = authorize(
+        &state,
+        &tenant_id,
+        auth.user_id,
+        &auth.scopes,
+        MANAGE_PERMISSION,
+        &request_id,
+    )
+    .await
+    {
+        return response;
+    }
+
+    let group = match find_group(&state.db, &tenant_id, &group_id, &request_id).await {
+        Ok(Some(group)) => group,
+        Ok(None) => return StatusCode::NO_CONTENT.into_response(),
+        Err(response) => return response,
+    };
+
+    if let Err(e) =
+        roles::unassign_from_group(&state, &tenant_id, &group_id, group.priority, &name).await
+    {
+        return internal_error(e, request_id, tenant_id);
+    }
+
+    StatusCode::NO_CONTENT.into_response()
+}
+
+/// Checks that the user can manage the organization and that `member_id` belongs to it. Roles
+/// assigned to a membership only apply within the organization, so organization admins can
+/// manage them without the `roles.manage` permission.
+async fn authorize_membership(
+    state: &state::State,
+    tenant_id: &str,
+    organization_id: &str,
+    member_id: &str,
+    user_id: Option<String>,
+    request_id: &str,
+) -> Result<(), response::Response<Body>> {
+    let Some(user_id) = user_id else {
+        return Err(CommonError::Unauthorized {
+            request_id: request_id.to_string(),
+            tenant_id: Some(tenant_id.to_string()),
+        }
+        .into_response());
+    };
+
+    let access = organizations::access(state, tenant_id, organization_id, &user_id)
+        .await
+        .map_err(|e| internal_error(e, request_id.to_string(), tenant_id.to_string()))?;
+
+    let exists = organizations::get(&state.db, tenant_id, organization_id)
+        .await
+        .map_err(|e| internal_error(e, request_id.to_string(), tenant_id.to_string()))?
+        .is_some();
+
+    if !exists || !access.can_view() {
+        return Err(error_response(
+            StatusCode::NOT_FOUND,
+            "Organization Not Found",
+            "There's no organization with this ID.",
+            Some("path.organization_id"),
+            HashMap::from([("input", json!(trim(organization_id, 20)))]),
+            request_id.to_string(),
+            Some(tenant_id.to_string()),
+        )
+        .into_response());
+    }
+
+    if !access.can_manage() {
+        return Err(CommonError::Forbidden {
+            request_id: request_id.to_string(),
+            tenant_id: Some(tenant_id.to_string()),
+        }
+        .into_response());
+    }
+
+    match organizations::role(&state.db, tenant_id, organization_id, member_id).await {
+        Ok(Some(_)) => Ok(()),
+        Ok(None) => Err(error_response(
+            StatusCode::NOT_FOUND,
+            "Member Not Found",
+            "The user is not a member of this organization.",
+            Some("path.user_id"),
+            HashMap::from([("input", json!(trim(member_id, 20)))]),
+            request_id.to_string(),
+            Some(tenant_id.to_string()),
+        )
+        .into_response()),
+        Err(e) => Err(internal_error(
+            e,
+            request_id.to_string(),
+            tenant_id.to_string(),
+        )),
+    }
+}
+
+/// Assigns a role to an organization membership. Requires managing the organization.
+pub async fn assign_to_membership(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    Path((name, organization_id, user_id)): Path<(String, String, String)>,
+) -> response::Response<Body> {
+    let state = state.read().await;
+
+    if let Err(response) = authorize_membership(
+        &state,
+        &tenant_id,
+        &organization_id,
+        &user_id,
+        auth.user_id,
+        &request_id,
+    )
+    .await
+    {
+        return response;
+    }
+
+    if let Err(response) = find(&state.db, &tenant_id, &name, &request_id).await {
+        return response;
+    }
+
+    if let Err(e) =
+        roles::assign_to_membership(&state, &tenant_id, &organization_id, &user_id, &name).await
+    {
+        return internal_error(e, request_id, tenant_id);
+    }
+
+    StatusCode::NO_CONTENT.into_response()
+}
+
+pub async fn unassign_from_membership(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    Path((name, organization_id, user_id)): Path<(String, String, String)>,
+) -> response::Response<Body> {
+    let state = state.read().await;
+
+    if let Err(response) = authorize_membership(
+        &state,
+        &tenant_id,
+        &organization_id,
+        &user_id,
+        auth.user_id,
+        &request_id,
+    )
+    .await
+    {
+        return response;
+    }
+
+    if let Err(e) =
+        roles::unassign_from_membership(&state, &tenant_id, &organization_id, &user_id, &name).await
+    {
+        return internal_error(e, request_id, tenant_id);
+    }
+
+    StatusCode::NO_CONTENT.into_response()
+}
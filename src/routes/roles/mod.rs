@@ -0,0 +1,32 @@
+mod handlers;
+mod requests;
+
+use axum::{
+    routing::{get, put},
+    Router,
+};
+
+use crate::state::AppState;
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/", get(handlers::list_roles).post(handlers::create_role))
+        .route(
+            "/:role",
+            get(handlers::get_role)
+                .patch(handlers::update_role)
+                .delete(handlers::delete_role),
+        )
+        .route(
+            "/:role/users/:user_id",
+            put(handlers::assign_to_user).delete(handlers::unassign_from_user),
+        )
+        .route(
+            "/:role/groups/:group_id",
+            put(handlers::assign_to_group).delete(handlers::unassign_from_group),
+        )
+        .route(
+            "/:role/organizations/:organization_id/members/:user_id",
+            put(handlers::assign_to_membership).delete(handlers::unassign_from_membership),
+        )
+}
@@ -0,0 +1,18 @@
+use std::collections::HashSet;
+
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize)]
+pub struct CreateRolePayload {
+    pub name: String,
+    pub description: Option<String>,
+    pub permissions: Option<HashSet<String>>,
+    pub inherits: Option<HashSet<String>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateRolePayload {
+    pub description: Option<String>,
+    pub permissions: Option<HashSet<String>>,
+    pub inherits: Option<HashSet<String>>,
+}
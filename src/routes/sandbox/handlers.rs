@@ -0,0 +1,117 @@
+use super::requests::MessagesQuery;
+use crate::{
+    auth::Auth,
+    error_handlers::{self, error_response, internal_error},
+    responses::{Response, ResponseMeta},
+    sandbox::{self, READ_PERMISSION},
+    state::{self, AppState},
+    types::{RequestID, Sandbox, TenantID},
+};
+use axum::{
+    body::Body,
+    extract::{Query, State},
+    http::StatusCode,
+    response::{self, IntoResponse},
+    Extension,
+};
+use std::collections::HashMap;
+
+/// Checks that the tenant is a sandbox and that the user can read its messages. Other tenants
+/// don't have this API at all.
+async fn authorize(
+    state: &state::State,
+    tenant_id: &str,
+    is_sandbox: bool,
+    user_id: Option<String>,
+    scopes: &[String],
+    request_id: &str,
+) -> Result<String, response::Response<Body>> {
+    if !is_sandbox {
+        return Err(error_response(
+            StatusCode::NOT_FOUND,
+            "Not A Sandbox",
+            "This API is only available to sandbox tenants.",
+            None,
+            HashMap::new(),
+            request_id.to_string(),
+            Some(tenant_id.to_string()),
+        )
+        .into_response());
+    }
+
+    error_handlers::authorize(
+        state,
+        tenant_id,
+        user_id,
+        scopes,
+        READ_PERMISSION,
+        request_id,
+    )
+    .await
+}
+
+/// Lists the latest emails the sandbox tenant would have sent, newest first, along with their
+/// codes and tokens. Requires the `sandbox.messages.read` permission.
+#[allow(clippy::too_many_arguments)]
+pub async fn list_messages(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(response_meta): Extension<ResponseMeta<'_>>,
+    Extension(auth): Extension<Auth>,
+    Extension(Sandbox(is_sandbox)): Extension<Sandbox>,
+    State(state): State<AppState>,
+    Query(query): Query<MessagesQuery>,
+) -> response::Response<Body> {
+    let state = state.read().await;
+
+    if let Err(response) = authorize(
+        &state,
+        &tenant_id,
+        is_sandbox,
+        auth.user_id,
+        &auth.scopes,
+        &request_id,
+    )
+    .await
+    {
+        return response;
+    }
+
+    match sandbox::messages(&state.db, &tenant_id, query.to.as_deref()).await {
+        Ok(messages) => {
+            Response::new(Some(messages), None, Some(response_meta), None).into_response()
+        }
+        Err(e) => internal_error(e, request_id, tenant_id),
+    }
+}
+
+/// Deletes every message of the sandbox tenant, such as between test runs. Requires the
+/// `sandbox.messages.read` permission.
+pub async fn clear_messages(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(auth): Extension<Auth>,
+    Extension(Sandbox(is_sandbox)): Extension<Sandbox>,
+    State(state): State<AppState>,
+) -> response::Response<Body> {
+    let state = state.read().await;
+
+    if let Err(response) = authorize(
+        &state,
+        &tenant_id,
+        is_sandbox,
+        auth.user_id,
+        &auth.scopes,
+        &request_id,
+    )
+    .await
+    {
+        return response;
+    }
+
+    if let Err(e) = sandbox::clear(&state.db, &tenant_id).await {
+        return internal_error(e, request_id, tenant_id);
+    }
+
+    StatusCode::NO_CONTENT.into_response()
+}
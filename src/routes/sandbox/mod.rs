@@ -0,0 +1,13 @@
+mod handlers;
+mod requests;
+
+use axum::{routing::get, Router};
+
+use crate::state::AppState;
+
+pub fn router() -> Router<AppState> {
+    Router::new().route(
+        "/messages",
+        get(handlers::list_messages).delete(handlers::clear_messages),
+    )
+}
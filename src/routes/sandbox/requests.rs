@@ -0,0 +1,7 @@
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize)]
+pub struct MessagesQuery {
+    /// Only lists the messages sent to this address.
+    pub to: Option<String>,
+}
@@ -0,0 +1,305 @@
+use super::requests::SetSettingPayload;
+use crate::{
+    auth::Auth,
+    error_handlers::{authorize, error_response, internal_error},
+    requests::Request,
+    responses::{CommonError, Error, Response, ResponseMeta},
+    settings::{self, Category, Definition, MANAGE_PERMISSION, REGISTRY},
+    state::AppState,
+    types::{RequestID, TenantID},
+    utils::text::trim,
+};
+use axum::{
+    body::Body,
+    extract::{rejection::JsonRejection, Path, State},
+    http::StatusCode,
+    response::{self, IntoResponse},
+    Extension, Json,
+};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+fn not_found_response(
+    category: &str,
+    key: &str,
+    request_id: String,
+    tenant_id: String,
+) -> response::Response<Body> {
+    error_response(
+        StatusCode::NOT_FOUND,
+        "Setting Not Found",
+        "There's no setting with this category and key.",
+        Some("path.key"),
+        HashMap::from([
+            ("category", json!(trim(category, 20))),
+            ("key", json!(trim(key, 64))),
+        ]),
+        request_id,
+        Some(tenant_id),
+    )
+    .into_response()
+}
+
+fn find_definition(category: &str, key: &str) -> Option<&'static Definition> {
+    Category::parse(category).and_then(|category| settings::definition(category, key))
+}
+
+/// Describes a setting along with its value for the tenant.
+fn describe(definition: &Definition, stored: Option<&String>) -> Value {
+    let mut value = json!(definition);
+    value["default"] = definition
+        .default
+        .map_or(Value::Null, |default| definition.to_json(default));
+    value["value"] = match (stored, definition.default) {
+        (Some(stored), _) => definition.to_json(stored),
+        (None, Some(default)) => definition.to_json(default),
+        (None, None) => Value::Null,
+    };
+    value["is_default"] = json!(stored.is_none());
+
+    value
+}
+
+/// Lists every setting with its value for the tenant. Requires the `settings.manage` permission.
+pub async fn list_settings(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(response_meta): Extension<ResponseMeta<'_>>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+) -> response::Response<Body> {
+    let state = state.read().await;
+
+    if let Err(response) = authorize(
+        &state,
+        &tenant_id,
+        auth.user_id,
+        &auth.scopes,
+        MANAGE_PERMISSION,
+        &request_id,
+    )
+    .await
+    {
+        return response;
+    }
+
+    let (security, users) = match tokio::try_join!(
+        settings::get_all(&state, &tenant_id, Category::Security),
+        settings::get_all(&state, &tenant_id, Category::Users),
+    ) {
+        Ok(s) => s,
+        Err(e) => return internal_error(e, request_id, tenant_id),
+    };
+
+    let all: Vec<Value> = REGISTRY
+        .iter()
+        .map(|definition| {
+            let stored = match definition.category {
+                Category::Security => security.get(definition.key),
+                Category::Users => users.get(definition.key),
+            };
+
+            describe(definition, stored)
+        })
+        .collect();
+
+    Response::new(Some(all), None, Some(response_meta), None).into_response()
+}
+
+pub async fn get_setting(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(response_meta): Extension<ResponseMeta<'_>>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    Path((category, key)): Path<(String, String)>,
+) -> response::Response<Body> {
+    let state = state.read().await;
+
+    if let Err(response) = authorize(
+        &state,
+        &tenant_id,
+        auth.user_id,
+        &auth.scopes,
+        MANAGE_PERMISSION,
+        &request_id,
+    )
+    .await
+    {
+        return response;
+    }
+
+    let Some(definition) = find_definition(&category, &key) else {
+        return not_found_response(&category, &key, request_id, tenant_id);
+    };
+
+    match settings::stored(&state, &tenant_id, definition.category, definition.key).await {
+        Ok(stored) => Response::new(
+            Some(describe(definition, stored.as_ref())),
+            None,
+            Some(response_meta),
+            None,
+        )
+        .into_response(),
+        Err(e) => internal_error(e, request_id, tenant_id),
+    }
+}
+
+/// Changes a setting. Every instance drops its cached settings for the tenant, so the change
+/// applies right away.
+#[allow(clippy::too_many_arguments)]
+pub async fn set_setting(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(response_meta): Extension<ResponseMeta<'_>>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    Path((category, key)): Path<(String, String)>,
+    payload: Result<Json<Request<SetSettingPayload>>, JsonRejection>,
+) -> response::Response<Body> {
+    let Json(Request { data: payload, .. }) = match payload {
+        Ok(p) => p,
+        Err(err) => {
+            return CommonError::JsonRejection {
+                err,
+                request_id,
+                tenant_id: Some(tenant_id),
+            }
+            .into_response()
+        }
+    };
+
+    let state = state.read().await;
+
+    if let Err(response) = authorize(
+        &state,
+        &tenant_id,
+        auth.user_id,
+        &auth.scopes,
+        MANAGE_PERMISSION,
+        &request_id,
+    )
+    .await
+    {
+        return response;
+    }
+
+    let Some(definition) = find_definition(&category, &key) else {
+        return not_found_response(&category, &key, request_id, tenant_id);
+    };
+
+    let value = match definition.validate(&payload.value) {
+        Ok(value) => value,
+        Err(detail) => {
+            let response: Response<Value> = Response::new(
+                None,
+                Some(vec![Error::new(
+                    StatusCode::UNPROCESSABLE_ENTITY.as_u16(),
+                    "Invalid Value",
+                    &detail,
+                    Some("body.data.value"),
+                    HashMap::from([("kind", json!(definition.kind))]),
+                )]),
+                Some(response_meta),
+                None,
+            );
+
+            return (StatusCode::UNPROCESSABLE_ENTITY, Json(response)).into_response();
+        }
+    };
+
+    match settings::check_range(&state, &tenant_id, definition, &value).await {
+        Ok(Some(detail)) => {
+            let response: Response<Value> = Response::new(
+                None,
+                Some(vec![Error::new(
+                    StatusCode::UNPROCESSABLE_ENTITY.as_u16(),
+                    "Invalid Range",
+                    &detail,
+                    Some("body.data.value"),
+                    HashMap::from([("input", payload.value)]),
+                )]),
+                Some(response_meta),
+                None,
+            );
+
+            return (StatusCode::UNPROCESSABLE_ENTITY, Json(response)).into_response();
+        }
+        Ok(None) => {}
+        Err(e) => return internal_error(e, request_id, tenant_id),
+    }
+
+    if let Err(e) = settings::set(
+        &state,
+        &tenant_id,
+        definition.category,
+        definition.key,
+        &value,
+    )
+    .await
+    {
+        return internal_error(e, request_id, tenant_id);
+    }
+
+    Response::new(
+        Some(describe(definition, Some(&value))),
+        None,
+        Some(response_meta),
+        None,
+    )
+    .into_response()
+}
+
+/// Unsets a setting, so its default applies again.
+pub async fn reset_setting(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    Path((category, key)): Path<(String, String)>,
+) -> response::Response<Body> {
+    let state = state.read().await;
+
+    if let Err(response) = authorize(
+        &state,
+        &tenant_id,
+        auth.user_id,
+        &auth.scopes,
+        MANAGE_PERMISSION,
+        &request_id,
+    )
+    .await
+    {
+        return response;
+    }
+
+    let Some(definition) = find_definition(&category, &key) else {
+        return not_found_response(&category, &key, request_id, tenant_id);
+    };
+
+    // Its default may be out of order with the other bound of its range.
+    if let Some(default) = definition.default {
+        match settings::check_range(&state, &tenant_id, definition, default).await {
+            Ok(Some(detail)) => {
+                return error_response(
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    "Invalid Range",
+                    &format!("The setting can't be reset to its default. {detail}"),
+                    Some("path.key"),
+                    HashMap::from([("default", definition.to_json(default))]),
+                    request_id,
+                    Some(tenant_id),
+                )
+                .into_response();
+            }
+            Ok(None) => {}
+            Err(e) => return internal_error(e, request_id, tenant_id),
+        }
+    }
+
+    if let Err(e) = settings::reset(&state, &tenant_id, definition.category, definition.key).await {
+        return internal_error(e, request_id, tenant_id);
+    }
+
+    StatusCode::NO_CONTENT.into_response()
+}
@@ -0,0 +1,17 @@
+mod handlers;
+mod requests;
+
+use axum::{routing::get, Router};
+
+use crate::state::AppState;
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/", get(handlers::list_settings))
+        .route(
+            "/:category/:key",
+            get(handlers::get_setting)
+                .put(handlers::set_setting)
+                .delete(handlers::reset_setting),
+        )
+}
@@ -0,0 +1,8 @@
+use serde::Deserialize;
+use serde_json::Value;
+
+#[derive(Debug, Deserialize)]
+pub struct SetSettingPayload {
+    /// A boolean, an integer or a string, depending on the setting.
+    pub value: Value,
+}
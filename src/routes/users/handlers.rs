@@ -0,0 +1,1457 @@
+use super::{
+    requests::{ChangeEmailPayload, ChangeUsernamePayload, VerifyCodePayload},
+    responses::{EmailChangeResponse, UsernameHistoryEntry},
+};
+use crate::{
+    auth::Auth,
+    codes, emails,
+    error_handlers::{error_response, internal_error},
+    flags::{self, Flags},
+    groups,
+    notifications::{self, Event, Priority},
+    organizations, permissions,
+    requests::Request,
+    responses::{CommonError, Error, Response, ResponseMeta},
+    settings,
+    state::AppState,
+    tokens::{token, Flow, FlowToken, TokenType},
+    types::{RequestID, Sandbox, TenantID},
+    usernames::{self, Availability},
+    utils::text::trim,
+};
+use axum::{
+    body::Body,
+    extract::{rejection::JsonRejection, Path, State},
+    http::StatusCode,
+    response::{self, IntoResponse},
+    Extension, Json,
+};
+use base64::engine::{general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use chrono::{Duration, Utc};
+use scylla::{batch::Batch, frame::value::CqlTimestamp, transport::errors::QueryError, Session};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use tracing::{event, Level};
+use validator::ValidateEmail;
+
+/// Seconds the email change verification code and flow token are valid for.
+const EMAIL_CHANGE_EXPIRES_IN: u64 = 900;
+
+/// Starts a main email change. A verification code is sent to the new address and a notice to
+/// the current one; nothing changes until the code is confirmed at `/users/@me/email/verify`.
+#[allow(clippy::too_many_arguments)]
+pub async fn request_email_change(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(response_meta): Extension<ResponseMeta<'_>>,
+    Extension(auth): Extension<Auth>,
+    Extension(Sandbox(is_sandbox)): Extension<Sandbox>,
+    State(state): State<AppState>,
+    payload: Result<Json<Request<ChangeEmailPayload>>, JsonRejection>,
+) -> response::Response<Body> {
+    let Json(Request { data: payload, .. }) = match payload {
+        Ok(p) => p,
+        Err(err) => {
+            return CommonError::JsonRejection {
+                err,
+                request_id,
+                tenant_id: Some(tenant_id),
+            }
+            .into_response()
+        }
+    };
+
+    let Some(user_id) = auth.user_id else {
+        return CommonError::Unauthorized {
+            request_id,
+            tenant_id: Some(tenant_id),
+        }
+        .into_response();
+    };
+
+    if !payload.email.validate_email() {
+        return error_response(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "Invalid Email",
+            "The email field requires a valid email.",
+            Some("body.data.email"),
+            HashMap::from([("input", json!(trim(&payload.email, 20)))]),
+            request_id,
+            Some(tenant_id),
+        )
+        .into_response();
+    }
+
+    let state = state.read().await;
+
+    let email_policy = match emails::Policy::load(&state, &tenant_id).await {
+        Ok(p) => p,
+        Err(e) => {
+            event!(Level::ERROR, error = format!("{e}"));
+            return CommonError::InternalServerError {
+                request_id,
+                tenant_id: Some(tenant_id),
+            }
+            .into_response();
+        }
+    };
+
+    if let Err(violation) = email_policy.check_domain(&payload.email) {
+        let (message, detail) = violation.details();
+
+        return error_response(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            message,
+            detail,
+            Some("body.data.email"),
+            HashMap::from([
+                ("input", json!(trim(&payload.email, 20))),
+                ("domain", json!(emails::domain(&payload.email))),
+            ]),
+            request_id,
+            Some(tenant_id),
+        )
+        .into_response();
+    }
+
+    let new_email = email_policy.normalize(&payload.email);
+
+    let current_email = match emails::main_email(&state.db, &tenant_id, &user_id).await {
+        Ok(e) => e,
+        Err(e) => {
+            event!(Level::ERROR, error = format!("{e}"));
+            return CommonError::InternalServerError {
+                request_id,
+                tenant_id: Some(tenant_id),
+            }
+            .into_response();
+        }
+    };
+
+    if current_email
+        .as_ref()
+        .is_some_and(|current| current.email == new_email)
+    {
+        return error_response(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "Same Email",
+            "The email provided is already your main email.",
+            Some("body.data.email"),
+            HashMap::from([("input", json!(trim(&payload.email, 20)))]),
+            request_id,
+            Some(tenant_id),
+        )
+        .into_response();
+    }
+
+    match emails::in_use(&state.db, &tenant_id, &new_email).await {
+        Err(e) => {
+            event!(Level::ERROR, error = format!("{e}"));
+            return CommonError::InternalServerError {
+                request_id,
+                tenant_id: Some(tenant_id),
+            }
+            .into_response();
+        }
+        Ok(true) => {
+            return error_response(
+                StatusCode::CONFLICT,
+                "Email Already In Use",
+                "There's already a user with this email.",
+                Some("body.data.email"),
+                HashMap::from([("input", json!(trim(&payload.email, 20)))]),
+                request_id,
+                Some(tenant_id),
+            )
+            .into_response();
+        }
+        Ok(false) => {}
+    }
+
+    let mut redis_connection = match state.redis.get_multiplexed_async_connection().await {
+        Ok(conn) => conn,
+        Err(e) => {
+            event!(Level::ERROR, error = format!("{e}"));
+            return CommonError::InternalServerError {
+                request_id,
+                tenant_id: Some(tenant_id),
+            }
+            .into_response();
+        }
+    };
+
+    let code = match codes::issue(
+        &mut redis_connection,
+        &tenant_id,
+        "email_change",
+        &user_id,
+        EMAIL_CHANGE_EXPIRES_IN,
+        is_sandbox,
+    )
+    .await
+    {
+        Ok(c) => c,
+        Err(e) => {
+            event!(Level::ERROR, error = format!("{e}"));
+            return CommonError::InternalServerError {
+                request_id,
+                tenant_id: Some(tenant_id),
+            }
+            .into_response();
+        }
+    };
+
+    let old_email = current_email.as_ref().map(|current| &current.email);
+
+    if let Err(e) = state
+        .db
+        .query_unpaged(
+            format!("INSERT INTO email_changes (tenant_id, user_id, old_email, new_email, new_address, created_at) VALUES (?, ?, ?, ?, ?, toTimestamp(now())) USING TTL {EMAIL_CHANGE_EXPIRES_IN}"),
+            (&tenant_id, &user_id, old_email, &new_email, &payload.email),
+        )
+        .await
+    {
+        event!(Level::ERROR, error = format!("{e}"));
+        return CommonError::InternalServerError {
+            request_id,
+            tenant_id: Some(tenant_id),
+        }
+        .into_response();
+    }
+
+    let mut deliveries = vec![
+        notifications::send_email(
+            &state,
+            &tenant_id,
+            Some(&user_id),
+            &payload.email,
+            Event::EmailChangeVerification,
+            Priority::High,
+            "Verify your new email",
+            "Use this code to confirm your new email address.",
+            HashMap::from([("code".to_string(), code)]),
+        )
+        .await,
+    ];
+
+    if let Some(current_email) = &current_email {
+        deliveries.push(
+            notifications::send_email(
+                &state,
+                &tenant_id,
+                Some(&user_id),
+                &current_email.address,
+                Event::EmailChangeRequested,
+                Priority::High,
+                "Email change requested",
+                "A change of your account's email was requested. If it wasn't you, secure your account.",
+                HashMap::from([("new_email".to_string(), emails::mask(&payload.email))]),
+            )
+            .await,
+        );
+    }
+
+    if deliveries.iter().any(|r| r.is_err()) {
+        for result in deliveries {
+            if let Err(e) = result {
+                event!(Level::ERROR, error = format!("{e}"));
+            }
+        }
+
+        return CommonError::InternalServerError {
+            request_id,
+            tenant_id: Some(tenant_id),
+        }
+        .into_response();
+    }
+
+    let flow_token = FlowToken {
+        token_type: TokenType::Flow,
+        flow: Flow::EmailChange,
+        tenant_id: tenant_id.clone(),
+        user_id,
+        expires_at: (Utc::now() + Duration::seconds(EMAIL_CHANGE_EXPIRES_IN as i64)).timestamp(),
+    }
+    .sign(&state.hmac);
+
+    let flow_token = match flow_token {
+        Ok(t) => t,
+        Err(e) => {
+            event!(Level::ERROR, error = format!("{e}"));
+            return CommonError::InternalServerError {
+                request_id,
+                tenant_id: Some(tenant_id),
+            }
+            .into_response();
+        }
+    };
+
+    (
+        StatusCode::ACCEPTED,
+        Response::new(
+            Some(HashMap::from([
+                ("flow_token", json!(flow_token)),
+                ("flow", json!(Flow::EmailChange)),
+                ("expires_in", json!(EMAIL_CHANGE_EXPIRES_IN)),
+            ])),
+            None,
+            Some(response_meta),
+            Some(HashMap::from([("verify", "/users/@me/email/verify")])),
+        ),
+    )
+        .into_response()
+}
+
+/// Confirms a main email change with the code sent to the new address. The previous main email
+/// is kept as a secondary email for the tenant's rollback window, and a rollback token is sent
+/// to it.
+pub async fn confirm_email_change(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(response_meta): Extension<ResponseMeta<'_>>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    payload: Result<Json<Request<VerifyCodePayload>>, JsonRejection>,
+) -> response::Response<Body> {
+    let Json(Request {
+        data: payload,
+        flow_token,
+    }) = match payload {
+        Ok(p) => p,
+        Err(err) => {
+            return CommonError::JsonRejection {
+                err,
+                request_id,
+                tenant_id: Some(tenant_id),
+            }
+            .into_response()
+        }
+    };
+
+    let Some(user_id) = auth.user_id else {
+        return CommonError::Unauthorized {
+            request_id,
+            tenant_id: Some(tenant_id),
+        }
+        .into_response();
+    };
+
+    let state = state.read().await;
+
+    let invalid_flow_response = error_response(
+        StatusCode::BAD_REQUEST,
+        "Invalid Flow Token",
+        "The flow token is invalid. Check that it hasn't expired and that it belongs to this flow.",
+        Some("body.flow_token"),
+        HashMap::new(),
+        request_id.clone(),
+        Some(tenant_id.clone()),
+    )
+    .into_response();
+
+    match flow_token
+        .as_deref()
+        .and_then(|t| FlowToken::verify(t, &state.hmac, Flow::EmailChange, &tenant_id))
+    {
+        Some(claims) if claims.user_id == user_id => {}
+        _ => return invalid_flow_response,
+    }
+
+    let change = match state
+        .db
+        .query_unpaged(
+            "SELECT old_email, new_email, new_address FROM email_changes WHERE tenant_id = ? AND user_id = ?",
+            (&tenant_id, &user_id),
+        )
+        .await
+    {
+        Ok(r) => r.maybe_first_row_typed::<(Option<String>, String, Option<String>)>(),
+        Err(e) => {
+            event!(Level::ERROR, error = format!("{e}"));
+            return CommonError::InternalServerError {
+                request_id,
+                tenant_id: Some(tenant_id),
+            }
+            .into_response();
+        }
+    };
+
+    let (old_email, new_address, new_email) = match change {
+        Ok(Some((old_email, new_email, new_address))) => (
+            old_email,
+            new_address.unwrap_or_else(|| new_email.clone()),
+            new_email,
+        ),
+        _ => return invalid_flow_response,
+    };
+
+    let mut redis_connection = match state.redis.get_multiplexed_async_connection().await {
+        Ok(conn) => conn,
+        Err(e) => {
+            event!(Level::ERROR, error = format!("{e}"));
+            return CommonError::InternalServerError {
+                request_id,
+                tenant_id: Some(tenant_id),
+            }
+            .into_response();
+        }
+    };
+
+    match codes::check(
+        &mut redis_connection,
+        &tenant_id,
+        "email_change",
+        &user_id,
+        &payload.code,
+    )
+    .await
+    {
+        Err(e) => {
+            event!(Level::ERROR, error = format!("{e}"));
+            return CommonError::InternalServerError {
+                request_id,
+                tenant_id: Some(tenant_id),
+            }
+            .into_response();
+        }
+        Ok(false) => {
+            return error_response(
+                StatusCode::BAD_REQUEST,
+                "Invalid Code",
+                "The verification code is invalid or has expired.",
+                Some("body.data.code"),
+                HashMap::from([("input", json!(trim(&payload.code, 20)))]),
+                request_id,
+                Some(tenant_id),
+            )
+            .into_response();
+        }
+        Ok(true) => {}
+    }
+
+    // The address could have been claimed by someone else while the code was pending, or be
+    // confirmed by someone else right now.
+    let in_use = match emails::claim(&state.db, &tenant_id, &new_email, &user_id).await {
+        Ok(true) => emails::in_use(&state.db, &tenant_id, &new_email).await,
+        Ok(false) => Ok(true),
+        Err(e) => Err(e),
+    };
+
+    match in_use {
+        Err(e) => {
+            event!(Level::ERROR, error = format!("{e}"));
+            return CommonError::InternalServerError {
+                request_id,
+                tenant_id: Some(tenant_id),
+            }
+            .into_response();
+        }
+        Ok(true) => {
+            return error_response(
+                StatusCode::CONFLICT,
+                "Email Already In Use",
+                "There's already a user with this email.",
+                Some("body.data"),
+                HashMap::from([("email", json!(trim(&new_email, 20)))]),
+                request_id,
+                Some(tenant_id),
+            )
+            .into_response();
+        }
+        Ok(false) => {}
+    }
+
+    let rollback_window =
+        match settings::read(&state, &tenant_id, settings::EMAIL_CHANGE_ROLLBACK_WINDOW).await {
+            Ok(w) => w,
+            Err(e) => {
+                event!(Level::ERROR, error = format!("{e}"));
+                return CommonError::InternalServerError {
+                    request_id,
+                    tenant_id: Some(tenant_id),
+                }
+                .into_response();
+            }
+        };
+
+    let old_row = match &old_email {
+        None => None,
+        Some(old_email) => match emails::list(&state.db, &tenant_id, &user_id).await {
+            Ok(rows) => rows.into_iter().find(|row| &row.email == old_email),
+            Err(e) => {
+                event!(Level::ERROR, error = format!("{e}"));
+                return CommonError::InternalServerError {
+                    request_id,
+                    tenant_id: Some(tenant_id),
+                }
+                .into_response();
+            }
+        },
+    };
+
+    let now = CqlTimestamp(Utc::now().timestamp_millis());
+    let rollback_token = token(None);
+
+    let mut batch = Batch::default();
+
+    batch.append_statement(emails::insert_statement(0).as_str());
+    batch.append_statement("DELETE FROM email_changes WHERE tenant_id = ? AND user_id = ?");
+
+    // A TTL of 0 would keep the old email and its rollback token forever, so a window of 0
+    // drops the old email right away instead.
+    let batch_result = match &old_row {
+        Some(old_row) if rollback_window == 0 => {
+            batch.append_statement(
+                "DELETE FROM emails WHERE tenant_id = ? AND user_id = ? AND email = ?",
+            );
+
+            state
+                .db
+                .batch(
+                    &batch,
+                    (
+                        (
+                            &tenant_id,
+                            &user_id,
+                            &new_email,
+                            &new_address,
+                            true,
+                            false,
+                            true,
+                            now,
+                            now,
+                        ),
+                        (&tenant_id, &user_id),
+                        (&tenant_id, &user_id, &old_row.email),
+                    ),
+                )
+                .await
+        }
+        Some(old_row) => {
+            batch.append_statement(emails::insert_statement(rollback_window).as_str());
+            batch.append_statement(format!("INSERT INTO email_rollbacks (tenant_id, rollback_token, user_id, old_email, new_email, created_at) VALUES (?, ?, ?, ?, ?, toTimestamp(now())) USING TTL {rollback_window}").as_str());
+
+            state
+                .db
+                .batch(
+                    &batch,
+                    (
+                        (
+                            &tenant_id,
+                            &user_id,
+                            &new_email,
+                            &new_address,
+                            true,
+                            false,
+                            true,
+                            now,
+                            now,
+                        ),
+                        (&tenant_id, &user_id),
+                        (
+                            &tenant_id,
+                            &user_id,
+                            &old_row.email,
+                            &old_row.address,
+                            false,
+                            old_row.is_work,
+                            old_row.is_verified,
+                            old_row.created_at,
+                            old_row.verified_at,
+                        ),
+                        (
+                            &tenant_id,
+                            &rollback_token,
+                            &user_id,
+                            &old_row.email,
+                            &new_email,
+                        ),
+                    ),
+                )
+                .await
+        }
+        None => {
+            state
+                .db
+                .batch(
+                    &batch,
+                    (
+                        (
+                            &tenant_id,
+                            &user_id,
+                            &new_email,
+                            &new_address,
+                            true,
+                            false,
+                            true,
+                            now,
+                            now,
+                        ),
+                        (&tenant_id, &user_id),
+                    ),
+                )
+                .await
+        }
+    };
+
+    if let Err(e) = batch_result {
+        event!(Level::ERROR, error = format!("{e}"));
+        return CommonError::InternalServerError {
+            request_id,
+            tenant_id: Some(tenant_id),
+        }
+        .into_response();
+    }
+
+    // The membership is secondary to the email change, which already happened.
+    if let Err(e) = organizations::auto_join(&state, &tenant_id, &user_id, &new_email).await {
+        event!(Level::ERROR, error = format!("{e}"));
+    }
+
+    if let Some(old_row) = &old_row {
+        let mut data = HashMap::from([("new_email".to_string(), emails::mask(&new_email))]);
+
+        let body = if rollback_window == 0 {
+            "Your account's email was changed. If it wasn't you, contact support right away."
+        } else {
+            data.insert(
+                "rollback_token".to_string(),
+                URL_SAFE_NO_PAD.encode(&rollback_token),
+            );
+            data.insert(
+                "rollback_expires_in".to_string(),
+                rollback_window.to_string(),
+            );
+
+            "Your account's email was changed. If it wasn't you, use the rollback token to restore this address."
+        };
+
+        if let Err(e) = notifications::send_email(
+            &state,
+            &tenant_id,
+            Some(&user_id),
+            &old_row.address,
+            Event::EmailChanged,
+            Priority::High,
+            "Your email was changed",
+            body,
+            data,
+        )
+        .await
+        {
+            event!(Level::ERROR, error = format!("{e}"));
+        }
+    }
+
+    (
+        StatusCode::OK,
+        Response::new(
+            Some(EmailChangeResponse {
+                email: new_address,
+                previous_email: old_row.as_ref().map(|row| row.address.clone()),
+                rollback_expires_in: if old_row.is_some() {
+                    rollback_window
+                } else {
+                    0
+                },
+            }),
+            None,
+            Some(response_meta),
+            None,
+        ),
+    )
+        .into_response()
+}
+
+/// Changes the user's username. The previous username is recorded in the user's username history
+/// and put on hold for the tenant's hold period.
+pub async fn change_username(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(response_meta): Extension<ResponseMeta<'_>>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    payload: Result<Json<Request<ChangeUsernamePayload>>, JsonRejection>,
+) -> response::Response<Body> {
+    let Json(Request { data: payload, .. }) = match payload {
+        Ok(p) => p,
+        Err(err) => {
+            return CommonError::JsonRejection {
+                err,
+                request_id,
+                tenant_id: Some(tenant_id),
+            }
+            .into_response()
+        }
+    };
+
+    let Some(user_id) = auth.user_id else {
+        return CommonError::Unauthorized {
+            request_id,
+            tenant_id: Some(tenant_id),
+        }
+        .into_response();
+    };
+
+    let state = state.read().await;
+
+    let username_policy = match usernames::Policy::load(&state, &tenant_id).await {
+        Ok(p) => p,
+        Err(e) => {
+            event!(Level::ERROR, error = format!("{e}"));
+            return CommonError::InternalServerError {
+                request_id,
+                tenant_id: Some(tenant_id),
+            }
+            .into_response();
+        }
+    };
+
+    let violations = username_policy.validate(&payload.username);
+
+    if !violations.is_empty() {
+        let errors = violations
+            .iter()
+            .map(|violation| {
+                let (message, detail, mut meta) =
+                    usernames::violation_details(&username_policy, violation);
+
+                meta.insert("input", json!(trim(&payload.username, 20)));
+
+                Error::new(
+                    StatusCode::UNPROCESSABLE_ENTITY.as_u16(),
+                    message,
+                    &detail,
+                    Some("body.data.username"),
+                    meta,
+                )
+            })
+            .collect();
+
+        return (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Response::<Value>::new(None, Some(errors), Some(response_meta), None),
+        )
+            .into_response();
+    }
+
+    let current_username = match state
+        .db
+        .query_unpaged(
+            "SELECT username FROM users WHERE tenant_id = ? AND user_id = ?",
+            (&tenant_id, &user_id),
+        )
+        .await
+    {
+        Ok(r) => match r.maybe_first_row_typed::<(Option<String>,)>() {
+            Ok(Some((username,))) => username,
+            _ => {
+                return CommonError::Unauthorized {
+                    request_id,
+                    tenant_id: Some(tenant_id),
+                }
+                .into_response()
+            }
+        },
+        Err(e) => {
+            event!(Level::ERROR, error = format!("{e}"));
+            return CommonError::InternalServerError {
+                request_id,
+                tenant_id: Some(tenant_id),
+            }
+            .into_response();
+        }
+    };
+
+    if current_username.as_deref() == Some(payload.username.as_str()) {
+        return error_response(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "Same Username",
+            "The username provided is already your username.",
+            Some("body.data.username"),
+            HashMap::from([("input", json!(trim(&payload.username, 20)))]),
+            request_id,
+            Some(tenant_id),
+        )
+        .into_response();
+    }
+
+    let (cooldown, hold_period) = match tokio::try_join!(
+        settings::read(&state, &tenant_id, settings::USERNAME_CHANGE_COOLDOWN),
+        settings::read(&state, &tenant_id, settings::USERNAME_HOLD_PERIOD),
+    ) {
+        Ok(s) => s,
+        Err(e) => {
+            event!(Level::ERROR, error = format!("{e}"));
+            return CommonError::InternalServerError {
+                request_id,
+                tenant_id: Some(tenant_id),
+            }
+            .into_response();
+        }
+    };
+
+    let last_change = match state
+        .db
+        .query_unpaged(
+            "SELECT changed_at FROM username_history WHERE tenant_id = ? AND user_id = ? LIMIT 1",
+            (&tenant_id, &user_id),
+        )
+        .await
+    {
+        Ok(r) => r.maybe_first_row_typed::<(CqlTimestamp,)>(),
+        Err(e) => {
+            event!(Level::ERROR, error = format!("{e}"));
+            return CommonError::InternalServerError {
+                request_id,
+                tenant_id: Some(tenant_id),
+            }
+            .into_response();
+        }
+    };
+
+    if let Ok(Some((CqlTimestamp(changed_at),))) = last_change {
+        let retry_after = changed_at / 1000 + cooldown - Utc::now().timestamp();
+
+        if retry_after > 0 {
+            return error_response(
+                StatusCode::TOO_MANY_REQUESTS,
+                "Username Change Cooldown",
+                "The username was changed recently. Wait for the cooldown to end before changing it again.",
+                Some("body.data.username"),
+                HashMap::from([("retry_after", json!(retry_after))]),
+                request_id,
+                Some(tenant_id),
+            )
+            .into_response();
+        }
+    }
+
+    match usernames::availability(
+        &state.db,
+        &tenant_id,
+        &username_policy,
+        &payload.username,
+        Some(&user_id),
+    )
+    .await
+    {
+        Err(e) => {
+            event!(Level::ERROR, error = format!("{e}"));
+            return CommonError::InternalServerError {
+                request_id,
+                tenant_id: Some(tenant_id),
+            }
+            .into_response();
+        }
+        Ok(Availability::Taken) | Ok(Availability::Reserved) => {
+            return error_response(
+                StatusCode::CONFLICT,
+                "Username Already In Use",
+                "There's already a user with this username.",
+                Some("body.data.username"),
+                HashMap::from([("input", json!(trim(&payload.username, 20)))]),
+                request_id,
+                Some(tenant_id),
+            )
+            .into_response();
+        }
+        Ok(Availability::Available) => {}
+    }
+
+    let new_key = username_policy.key(&payload.username);
+    let old_key = current_username
+        .as_deref()
+        .map(|username| username_policy.key(username))
+        .filter(|key| key != &new_key);
+
+    // Availability was only read, so the new key is claimed before anything is written.
+    match usernames::claim(
+        &state.db,
+        &tenant_id,
+        &new_key,
+        &user_id,
+        &payload.username,
+        0,
+    )
+    .await
+    {
+        Ok(true) => {}
+        Ok(false) => {
+            return error_response(
+                StatusCode::CONFLICT,
+                "Username Already In Use",
+                "There's already a user with this username.",
+                Some("body.data.username"),
+                HashMap::from([("input", json!(trim(&payload.username, 20)))]),
+                request_id,
+                Some(tenant_id),
+            )
+            .into_response();
+        }
+        Err(e) => {
+            event!(Level::ERROR, error = format!("{e}"));
+            return CommonError::InternalServerError {
+                request_id,
+                tenant_id: Some(tenant_id),
+            }
+            .into_response();
+        }
+    }
+
+    let mut batch = Batch::default();
+
+    batch.append_statement("UPDATE users SET username = ?, updated_at = toTimestamp(now()) WHERE tenant_id = ? AND user_id = ?");
+    batch.append_statement("INSERT INTO username_history (tenant_id, user_id, changed_at, old_username, new_username) VALUES (?, ?, toTimestamp(now()), ?, ?)");
+    batch
+        .append_statement("DELETE FROM username_reservations WHERE tenant_id = ? AND username = ?");
+
+    // A TTL of 0 would hold the old username forever, so a hold period of 0 skips the hold.
+    let batch_result = match old_key.as_ref().filter(|_| hold_period > 0) {
+        Some(old_key) => {
+            batch.append_statement(format!("INSERT INTO username_reservations (tenant_id, username, user_id, created_at) VALUES (?, ?, ?, toTimestamp(now())) USING TTL {hold_period}").as_str());
+
+            state
+                .db
+                .batch(
+                    &batch,
+                    (
+                        (&payload.username, &tenant_id, &user_id),
+                        (&tenant_id, &user_id, &current_username, &payload.username),
+                        (&tenant_id, &new_key),
+                        (&tenant_id, old_key, &user_id),
+                    ),
+                )
+                .await
+        }
+        None => {
+            state
+                .db
+                .batch(
+                    &batch,
+                    (
+                        (&payload.username, &tenant_id, &user_id),
+                        (&tenant_id, &user_id, &current_username, &payload.username),
+                        (&tenant_id, &new_key),
+                    ),
+                )
+                .await
+        }
+    };
+
+    if let Err(e) = batch_result {
+        event!(Level::ERROR, error = format!("{e}"));
+
+        // The new key is given back, unless it was already held under the old username.
+        if old_key.is_some() || current_username.is_none() {
+            if let Err(e) = usernames::release(&state.db, &tenant_id, &new_key, &user_id).await {
+                event!(Level::ERROR, error = format!("{e}"));
+            }
+        }
+
+        return CommonError::InternalServerError {
+            request_id,
+            tenant_id: Some(tenant_id),
+        }
+        .into_response();
+    }
+
+    // The old key is only released if it changed, since renames like "Alice" to "alice" keep it.
+    if let Some(old_key) = &old_key {
+        if let Err(e) = usernames::release(&state.db, &tenant_id, old_key, &user_id).await {
+            event!(Level::ERROR, error = format!("{e}"));
+        }
+    }
+
+    (
+        StatusCode::OK,
+        Response::new(
+            Some(HashMap::from([
+                ("username", json!(payload.username)),
+                ("previous_username", json!(current_username)),
+                ("next_change_in", json!(cooldown)),
+            ])),
+            None,
+            Some(response_meta),
+            Some(HashMap::from([("history", "/users/@me/usernames")])),
+        ),
+    )
+        .into_response()
+}
+
+/// Returns the user's username history, most recent change first.
+pub async fn own_username_history(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(response_meta): Extension<ResponseMeta<'_>>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+) -> response::Response<Body> {
+    let Some(user_id) = auth.user_id else {
+        return CommonError::Unauthorized {
+            request_id,
+            tenant_id: Some(tenant_id),
+        }
+        .into_response();
+    };
+
+    let state = state.read().await;
+
+    match query_username_history(&state.db, &tenant_id, &user_id).await {
+        Ok(history) => {
+            Response::new(Some(history), None, Some(response_meta), None).into_response()
+        }
+        Err(e) => {
+            event!(Level::ERROR, error = format!("{e}"));
+            CommonError::InternalServerError {
+                request_id,
+                tenant_id: Some(tenant_id),
+            }
+            .into_response()
+        }
+    }
+}
+
+/// Returns the username history of any user in the tenant. Requires the `users.usernames.read`
+/// permission.
+pub async fn username_history(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(response_meta): Extension<ResponseMeta<'_>>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    Path(user_id): Path<String>,
+) -> response::Response<Body> {
+    let Some(auth_user_id) = auth.user_id else {
+        return CommonError::Unauthorized {
+            request_id,
+            tenant_id: Some(tenant_id),
+        }
+        .into_response();
+    };
+
+    let state = state.read().await;
+
+    match permissions::has_scoped_permission(
+        &state,
+        &tenant_id,
+        &auth_user_id,
+        &auth.scopes,
+        "users.usernames.read",
+    )
+    .await
+    {
+        Ok(true) => {}
+        Ok(false) => {
+            return CommonError::Forbidden {
+                request_id,
+                tenant_id: Some(tenant_id),
+            }
+            .into_response()
+        }
+        Err(e) => {
+            event!(Level::ERROR, error = format!("{e}"));
+            return CommonError::InternalServerError {
+                request_id,
+                tenant_id: Some(tenant_id),
+            }
+            .into_response();
+        }
+    }
+
+    match query_username_history(&state.db, &tenant_id, &user_id).await {
+        Ok(history) => {
+            Response::new(Some(history), None, Some(response_meta), None).into_response()
+        }
+        Err(e) => {
+            event!(Level::ERROR, error = format!("{e}"));
+            CommonError::InternalServerError {
+                request_id,
+                tenant_id: Some(tenant_id),
+            }
+            .into_response()
+        }
+    }
+}
+
+async fn query_username_history(
+    db: &Session,
+    tenant_id: &str,
+    user_id: &str,
+) -> Result<Vec<UsernameHistoryEntry>, QueryError> {
+    let result = db
+        .query_unpaged(
+            "SELECT old_username, new_username, changed_at FROM username_history WHERE tenant_id = ? AND user_id = ?",
+            (tenant_id, user_id),
+        )
+        .await?;
+
+    Ok(result
+        .rows_typed_or_empty::<(Option<String>, String, CqlTimestamp)>()
+        .filter_map(|row| row.ok())
+        .map(
+            |(old_username, new_username, CqlTimestamp(changed_at))| UsernameHistoryEntry {
+                old_username,
+                new_username,
+                changed_at,
+            },
+        )
+        .collect())
+}
+
+/// Returns the organizations the user is a member of, with their role in each.
+pub async fn own_organizations(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(response_meta): Extension<ResponseMeta<'_>>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+) -> response::Response<Body> {
+    let Some(user_id) = auth.user_id else {
+        return CommonError::Unauthorized {
+            request_id,
+            tenant_id: Some(tenant_id),
+        }
+        .into_response();
+    };
+
+    let state = state.read().await;
+
+    match organizations::memberships(&state.db, &tenant_id, &user_id).await {
+        Ok(memberships) => {
+            Response::new(Some(memberships), None, Some(response_meta), None).into_response()
+        }
+        Err(e) => internal_error(e, request_id, tenant_id),
+    }
+}
+
+/// Returns the organizations any user in the tenant is a member of. Requires the
+/// `organizations.manage` permission.
+pub async fn organizations(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(response_meta): Extension<ResponseMeta<'_>>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    Path(user_id): Path<String>,
+) -> response::Response<Body> {
+    let Some(auth_user_id) = auth.user_id else {
+        return CommonError::Unauthorized {
+            request_id,
+            tenant_id: Some(tenant_id),
+        }
+        .into_response();
+    };
+
+    let state = state.read().await;
+
+    match permissions::has_scoped_permission(
+        &state,
+        &tenant_id,
+        &auth_user_id,
+        &auth.scopes,
+        organizations::MANAGE_PERMISSION,
+    )
+    .await
+    {
+        Ok(true) => {}
+        Ok(false) => {
+            return CommonError::Forbidden {
+                request_id,
+                tenant_id: Some(tenant_id),
+            }
+            .into_response()
+        }
+        Err(e) => return internal_error(e, request_id, tenant_id),
+    }
+
+    match organizations::memberships(&state.db, &tenant_id, &user_id).await {
+        Ok(memberships) => {
+            Response::new(Some(memberships), None, Some(response_meta), None).into_response()
+        }
+        Err(e) => internal_error(e, request_id, tenant_id),
+    }
+}
+
+/// Returns the user's effective permissions, resolved from their own, their roles' and their
+/// groups'.
+pub async fn own_permissions(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(response_meta): Extension<ResponseMeta<'_>>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+) -> response::Response<Body> {
+    let Some(user_id) = auth.user_id else {
+        return CommonError::Unauthorized {
+            request_id,
+            tenant_id: Some(tenant_id),
+        }
+        .into_response();
+    };
+
+    let state = state.read().await;
+
+    match permissions::effective_permissions(&state, &tenant_id, &user_id).await {
+        Ok(effective) => {
+            Response::new(Some(effective), None, Some(response_meta), None).into_response()
+        }
+        Err(e) => internal_error(e, request_id, tenant_id),
+    }
+}
+
+/// Lists the feature flags that are on for the user.
+pub async fn own_flags(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(response_meta): Extension<ResponseMeta<'_>>,
+    Extension(auth): Extension<Auth>,
+    Extension(flags): Extension<Flags>,
+) -> response::Response<Body> {
+    if auth.user_id.is_none() {
+        return CommonError::Unauthorized {
+            request_id,
+            tenant_id: Some(tenant_id),
+        }
+        .into_response();
+    }
+
+    match flags.on().await {
+        Ok(on) => Response::new(Some(on), None, Some(response_meta), None).into_response(),
+        Err(e) => internal_error(e, request_id, tenant_id),
+    }
+}
+
+/// Checks whether a feature flag is on for the user. Flags that aren't set are off.
+pub async fn own_flag(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(response_meta): Extension<ResponseMeta<'_>>,
+    Extension(auth): Extension<Auth>,
+    Extension(flags): Extension<Flags>,
+    Path(flag): Path<String>,
+) -> response::Response<Body> {
+    if auth.user_id.is_none() {
+        return CommonError::Unauthorized {
+            request_id,
+            tenant_id: Some(tenant_id),
+        }
+        .into_response();
+    }
+
+    if !flags::is_valid_name(&flag) {
+        return error_response(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "Invalid Flag",
+            "Flag names must be from 1 to 64 lowercase letters, digits, dots, hyphens or underscores.",
+            Some("path.flag"),
+            HashMap::from([("input", json!(trim(&flag, 64)))]),
+            request_id,
+            Some(tenant_id),
+        )
+        .into_response();
+    }
+
+    match flags.is_on(&flag).await {
+        Ok(is_on) => Response::new(
+            Some(json!({ "flag": flag, "is_on": is_on })),
+            None,
+            Some(response_meta),
+            None,
+        )
+        .into_response(),
+        Err(e) => internal_error(e, request_id, tenant_id),
+    }
+}
+
+/// Returns the effective permissions of any user in the tenant. Requires the
+/// `users.permissions.read` permission.
+pub async fn effective_permissions(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(response_meta): Extension<ResponseMeta<'_>>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    Path(user_id): Path<String>,
+) -> response::Response<Body> {
+    let Some(auth_user_id) = auth.user_id else {
+        return CommonError::Unauthorized {
+            request_id,
+            tenant_id: Some(tenant_id),
+        }
+        .into_response();
+    };
+
+    let state = state.read().await;
+
+    match permissions::has_scoped_permission(
+        &state,
+        &tenant_id,
+        &auth_user_id,
+        &auth.scopes,
+        permissions::READ_PERMISSION,
+    )
+    .await
+    {
+        Ok(true) => {}
+        Ok(false) => {
+            return CommonError::Forbidden {
+                request_id,
+                tenant_id: Some(tenant_id),
+            }
+            .into_response()
+        }
+        Err(e) => return internal_error(e, request_id, tenant_id),
+    }
+
+    match permissions::effective_permissions(&state, &tenant_id, &user_id).await {
+        Ok(effective) => {
+            Response::new(Some(effective), None, Some(response_meta), None).into_response()
+        }
+        Err(e) => internal_error(e, request_id, tenant_id),
+    }
+}
+
+/// Returns every role that applies to the user, where each comes from and its permissions.
+pub async fn own_roles(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(response_meta): Extension<ResponseMeta<'_>>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+) -> response::Response<Body> {
+    let Some(user_id) = auth.user_id else {
+        return CommonError::Unauthorized {
+            request_id,
+            tenant_id: Some(tenant_id),
+        }
+        .into_response();
+    };
+
+    let state = state.read().await;
+
+    match permissions::role_grants(&state, &tenant_id, &user_id).await {
+        Ok(grants) => Response::new(Some(grants), None, Some(response_meta), None).into_response(),
+        Err(e) => internal_error(e, request_id, tenant_id),
+    }
+}
+
+/// Returns the roles that apply to any user in the tenant. Requires the
+/// `users.permissions.read` permission.
+pub async fn role_grants(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(response_meta): Extension<ResponseMeta<'_>>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    Path(user_id): Path<String>,
+) -> response::Response<Body> {
+    let Some(auth_user_id) = auth.user_id else {
+        return CommonError::Unauthorized {
+            request_id,
+            tenant_id: Some(tenant_id),
+        }
+        .into_response();
+    };
+
+    let state = state.read().await;
+
+    match permissions::has_scoped_permission(
+        &state,
+        &tenant_id,
+        &auth_user_id,
+        &auth.scopes,
+        permissions::READ_PERMISSION,
+    )
+    .await
+    {
+        Ok(true) => {}
+        Ok(false) => {
+            return CommonError::Forbidden {
+                request_id,
+                tenant_id: Some(tenant_id),
+            }
+            .into_response()
+        }
+        Err(e) => return internal_error(e, request_id, tenant_id),
+    }
+
+    match permissions::role_grants(&state, &tenant_id, &user_id).await {
+        Ok(grants) => Response::new(Some(grants), None, Some(response_meta), None).into_response(),
+        Err(e) => internal_error(e, request_id, tenant_id),
+    }
+}
+
+/// Returns the groups the user belongs to, including the ones containing their groups.
+pub async fn own_groups(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(response_meta): Extension<ResponseMeta<'_>>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+) -> response::Response<Body> {
+    let Some(user_id) = auth.user_id else {
+        return CommonError::Unauthorized {
+            request_id,
+            tenant_id: Some(tenant_id),
+        }
+        .into_response();
+    };
+
+    let state = state.read().await;
+
+    match groups::expanded_membership(&state, &tenant_id, &user_id).await {
+        Ok(memberships) => {
+            Response::new(Some(memberships), None, Some(response_meta), None).into_response()
+        }
+        Err(e) => internal_error(e, request_id, tenant_id),
+    }
+}
+
+/// Returns the expanded group membership of any user in the tenant. Requires the
+/// `groups.manage` permission.
+pub async fn expanded_groups(
+    Extension(TenantID(tenant_id)): Extension<TenantID>,
+    Extension(RequestID(request_id)): Extension<RequestID>,
+    Extension(response_meta): Extension<ResponseMeta<'_>>,
+    Extension(auth): Extension<Auth>,
+    State(state): State<AppState>,
+    Path(user_id): Path<String>,
+) -> response::Response<Body> {
+    let Some(auth_user_id) = auth.user_id else {
+        return CommonError::Unauthorized {
+            request_id,
+            tenant_id: Some(tenant_id),
+        }
+        .into_response();
+    };
+
+    let state = state.read().await;
+
+    match permissions::has_scoped_permission(
+        &state,
+        &tenant_id,
+        &auth_user_id,
+        &auth.scopes,
+        groups::MANAGE_PERMISSION,
+    )
+    .await
+    {
+        Ok(true) => {}
+        Ok(false) => {
+            return CommonError::Forbidden {
+                request_id,
+                tenant_id: Some(tenant_id),
+            }
+            .into_response()
+        }
+        Err(e) => return internal_error(e, request_id, tenant_id),
+    }
+
+    match groups::expanded_membership(&state, &tenant_id, &user_id).await {
+        Ok(memberships) => {
+            Response::new(Some(memberships), None, Some(response_meta), None).into_response()
+        }
+        Err(e) => internal_error(e, request_id, tenant_id),
+    }
+}
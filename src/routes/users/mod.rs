@@ -1,7 +1,32 @@
-use axum::Router;
+mod handlers;
+mod requests;
+mod responses;
+
+use axum::{
+    routing::{get, patch, post},
+    Router,
+};
 
 use crate::state::AppState;
 
 pub fn router() -> Router<AppState> {
     Router::new()
+        .route("/@me/email", post(handlers::request_email_change))
+        .route("/@me/email/verify", post(handlers::confirm_email_change))
+        .route("/@me/username", patch(handlers::change_username))
+        .route("/@me/usernames", get(handlers::own_username_history))
+        .route("/@me/organizations", get(handlers::own_organizations))
+        .route("/@me/permissions", get(handlers::own_permissions))
+        .route("/@me/roles", get(handlers::own_roles))
+        .route("/@me/groups", get(handlers::own_groups))
+        .route("/@me/flags", get(handlers::own_flags))
+        .route("/@me/flags/:flag", get(handlers::own_flag))
+        .route("/:user_id/usernames", get(handlers::username_history))
+        .route("/:user_id/organizations", get(handlers::organizations))
+        .route("/:user_id/roles", get(handlers::role_grants))
+        .route("/:user_id/groups", get(handlers::expanded_groups))
+        .route(
+            "/:user_id/permissions",
+            get(handlers::effective_permissions),
+        )
 }
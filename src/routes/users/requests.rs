@@ -0,0 +1,16 @@
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize)]
+pub struct ChangeEmailPayload {
+    pub email: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct VerifyCodePayload {
+    pub code: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChangeUsernamePayload {
+    pub username: String,
+}
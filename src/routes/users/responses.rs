@@ -0,0 +1,15 @@
+use serde::Serialize;
+
+#[derive(Serialize)]
+pub struct EmailChangeResponse {
+    pub email: String,
+    pub previous_email: Option<String>,
+    pub rollback_expires_in: u64,
+}
+
+#[derive(Serialize)]
+pub struct UsernameHistoryEntry {
+    pub old_username: Option<String>,
+    pub new_username: String,
+    pub changed_at: i64,
+}
@@ -0,0 +1,143 @@
+use std::collections::HashMap;
+
+use scylla::{frame::value::CqlTimestamp, transport::errors::QueryError, Session};
+use serde::Serialize;
+
+use crate::utils::id::gen_id;
+
+/// The code issued by sandbox tenants for every verification, so tests can type it without
+/// reading messages.
+pub const CODE: &str = "000000";
+
+/// Permission a user's token must allow to read the messages of a sandbox tenant.
+pub const READ_PERMISSION: &str = "sandbox.messages.read";
+
+/// Most messages listed at once, newest first.
+pub const MAX_MESSAGES: i32 = 100;
+
+/// How much cheaper requests to sandbox tenants are for the rate limiter.
+pub const RATELIMIT_DISCOUNT: i64 = 10;
+
+/// An email that a sandbox tenant would have sent.
+#[derive(Debug, Serialize)]
+pub struct Message {
+    pub message_id: String,
+    pub to: String,
+    pub user_id: Option<String>,
+    pub event_type: i16,
+    pub title: String,
+    pub message: String,
+
+    /// The data the message would have been rendered with, such as its code.
+    pub data: HashMap<String, String>,
+    pub created_at: i64,
+}
+
+/// Keeps an email of a sandbox tenant for tests to read, instead of queuing it for delivery.
+#[allow(clippy::too_many_arguments)]
+pub async fn record(
+    db: &Session,
+    tenant_id: &str,
+    user_id: Option<&str>,
+    to: &str,
+    event_type: i16,
+    title: &str,
+    message: &str,
+    data: &HashMap<String, String>,
+) -> Result<String, QueryError> {
+    let message_id = gen_id(None);
+
+    db.query_unpaged(
+        "INSERT INTO sandbox_messages (tenant_id, created_at, message_id, recipient, user_id, event_type, title, message, data) VALUES (?, toTimestamp(now()), ?, ?, ?, ?, ?, ?, ?)",
+        (
+            tenant_id,
+            &message_id,
+            to,
+            user_id,
+            event_type,
+            title,
+            message,
+            data,
+        ),
+    )
+    .await?;
+
+    Ok(message_id)
+}
+
+type MessageRow = (
+    CqlTimestamp,
+    String,
+    String,
+    Option<String>,
+    Option<i16>,
+    Option<String>,
+    Option<String>,
+    Option<HashMap<String, String>>,
+);
+
+/// Lists the latest messages of a sandbox tenant, or those sent to an address, newest first.
+pub async fn messages(
+    db: &Session,
+    tenant_id: &str,
+    to: Option<&str>,
+) -> Result<Vec<Message>, QueryError> {
+    const COLUMNS: &str =
+        "created_at, message_id, recipient, user_id, event_type, title, message, data";
+
+    let result = match to {
+        Some(to) => {
+            db.query_unpaged(
+                format!("SELECT {COLUMNS} FROM sandbox_messages WHERE tenant_id = ? AND recipient = ? LIMIT {MAX_MESSAGES} ALLOW FILTERING"),
+                (tenant_id, to),
+            )
+            .await?
+        }
+        None => {
+            db.query_unpaged(
+                format!("SELECT {COLUMNS} FROM sandbox_messages WHERE tenant_id = ? LIMIT {MAX_MESSAGES}"),
+                (tenant_id,),
+            )
+            .await?
+        }
+    };
+
+    Ok(result
+        .rows_typed_or_empty::<MessageRow>()
+        .filter_map(|row| row.ok())
+        .map(
+            |(
+                CqlTimestamp(created_at),
+                message_id,
+                to,
+                user_id,
+                event_type,
+                title,
+                message,
+                data,
+            )| {
+                Message {
+                    message_id,
+                    to,
+                    user_id,
+                    event_type: event_type.unwrap_or_default(),
+                    title: title.unwrap_or_default(),
+                    message: message.unwrap_or_default(),
+                    data: data.unwrap_or_default(),
+                    created_at,
+                }
+            },
+        )
+        .collect())
+}
+
+/// Deletes every message of a sandbox tenant, such as between test runs.
+pub async fn clear(db: &Session, tenant_id: &str) -> Result<(), QueryError> {
+    db.query_unpaged(
+        "DELETE FROM sandbox_messages WHERE tenant_id = ?",
+        (tenant_id,),
+    )
+    .await?;
+
+    Ok(())
+}
@@ -0,0 +1,497 @@
+use std::{collections::HashMap, fmt, marker::PhantomData, str::FromStr};
+
+use num_derive::FromPrimitive;
+use scylla::transport::errors::QueryError;
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::{cache, state::State};
+
+/// Tenant-wide permission required to read and change settings through the API.
+pub const MANAGE_PERMISSION: &str = "settings.manage";
+
+/// A `tenant_settings` category. Stored as `tenant_settings.category`, and mapped as
+/// `TenantSettingCategory` in `db::orm`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, FromPrimitive)]
+#[serde(rename_all = "snake_case")]
+#[repr(i8)]
+pub enum Category {
+    #[default]
+    Security = 0,
+    Users = 1,
+}
+
+impl Category {
+    pub fn parse(category: &str) -> Option<Self> {
+        match category {
+            "security" => Some(Self::Security),
+            "users" => Some(Self::Users),
+            _ => None,
+        }
+    }
+}
+
+/// The type of a setting's value and its bounds.
+#[derive(Clone, Copy, Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Kind {
+    Boolean,
+    Integer { min: i64, max: i64 },
+    Text { max_length: usize },
+}
+
+/// A setting tenants can change.
+#[derive(Debug, Serialize)]
+pub struct Definition {
+    pub category: Category,
+    pub key: &'static str,
+
+    #[serde(flatten)]
+    pub kind: Kind,
+
+    /// The value used while the setting is unset, if it can be written as one.
+    pub default: Option<&'static str>,
+    pub description: &'static str,
+}
+
+impl Definition {
+    /// Checks a value given through the API and returns it as it's stored.
+    pub fn validate(&self, value: &Value) -> Result<String, String> {
+        match (self.kind, value) {
+            (Kind::Boolean, Value::Bool(value)) => Ok(value.to_string()),
+            (Kind::Boolean, _) => Err("The value must be a boolean.".to_string()),
+            (Kind::Integer { min, max }, Value::Number(number)) => match number.as_i64() {
+                Some(value) if (min..=max).contains(&value) => Ok(value.to_string()),
+                _ => Err(format!("The value must be an integer from {min} to {max}.")),
+            },
+            (Kind::Integer { min, max }, _) => {
+                Err(format!("The value must be an integer from {min} to {max}."))
+            }
+            (Kind::Text { max_length }, Value::String(value))
+                if value.chars().count() <= max_length =>
+            {
+                Ok(value.trim().to_string())
+            }
+            (Kind::Text { max_length }, _) => Err(format!(
+                "The value must be a string of at most {max_length} characters."
+            )),
+        }
+    }
+
+    /// Turns a stored value back into JSON, or `null` if it doesn't fit the setting's type.
+    pub fn to_json(&self, value: &str) -> Value {
+        match self.kind {
+            Kind::Boolean => value
+                .trim()
+                .parse::<bool>()
+                .map_or(Value::Null, Value::from),
+            Kind::Integer { .. } => value.trim().parse::<i64>().map_or(Value::Null, Value::from),
+            Kind::Text { .. } => json!(value),
+        }
+    }
+}
+
+/// Every setting tenants can change. Code reading a setting falls back to its default here when
+/// it's unset or its stored value can't be parsed.
+pub const REGISTRY: [Definition; 21] = [
+    Definition {
+        category: Category::Security,
+        key: "access_token_lifetime",
+        kind: Kind::Integer { min: 60, max: 86400 },
+        default: Some("3600"),
+        description: "Seconds access tokens are valid for.",
+    },
+    Definition {
+        category: Category::Security,
+        key: "refresh_token_lifetime",
+        kind: Kind::Integer {
+            min: 3600,
+            max: 31536000,
+        },
+        default: Some("2628288"),
+        description: "Seconds refresh tokens are valid for. Refreshing keeps the expiry of the refresh token used.",
+    },
+    Definition {
+        category: Category::Security,
+        key: "password_min_length",
+        kind: Kind::Integer { min: 1, max: 72 },
+        default: Some("8"),
+        description: "Minimum length of new passwords, in characters.",
+    },
+    Definition {
+        category: Category::Security,
+        key: "password_max_length",
+        kind: Kind::Integer { min: 8, max: 72 },
+        default: Some("32"),
+        description: "Maximum length of new passwords, in characters. Passwords are hashed with bcrypt, which ignores anything past 72 bytes.",
+    },
+    Definition {
+        category: Category::Security,
+        key: "password_min_score",
+        kind: Kind::Integer { min: 0, max: 4 },
+        default: Some("3"),
+        description: "Minimum zxcvbn strength score of new passwords, from 0 to 4.",
+    },
+    Definition {
+        category: Category::Security,
+        key: "mfa_required",
+        kind: Kind::Boolean,
+        default: Some("false"),
+        description: "Whether users must complete a second factor to sign in. Password-only sign-ins are refused while it's on.",
+    },
+    Definition {
+        category: Category::Users,
+        key: "email_change_rollback_window",
+        kind: Kind::Integer { min: 0, max: 2592000 },
+        default: Some("604800"),
+        description: "Seconds a previous main email can roll back a change for.",
+    },
+    Definition {
+        category: Category::Users,
+        key: "username_change_cooldown",
+        kind: Kind::Integer {
+            min: 0,
+            max: 31536000,
+        },
+        default: Some("2592000"),
+        description: "Seconds a user has to wait between username changes.",
+    },
+    Definition {
+        category: Category::Users,
+        key: "username_hold_period",
+        kind: Kind::Integer {
+            min: 0,
+            max: 31536000,
+        },
+        default: Some("7776000"),
+        description: "Seconds a released username is held for its previous owner.",
+    },
+    Definition {
+        category: Category::Users,
+        key: "email_lowercase_local",
+        kind: Kind::Boolean,
+        default: Some("true"),
+        description: "Whether the local part of emails is case-insensitive.",
+    },
+    Definition {
+        category: Category::Users,
+        key: "email_remove_gmail_dots",
+        kind: Kind::Boolean,
+        default: Some("true"),
+        description: "Whether dots are ignored in the local part of Gmail addresses.",
+    },
+    Definition {
+        category: Category::Users,
+        key: "email_strip_plus_tags",
+        kind: Kind::Boolean,
+        default: Some("false"),
+        description: "Whether `+tags` are ignored in the local part of emails.",
+    },
+    Definition {
+        category: Category::Users,
+        key: "email_block_disposable",
+        kind: Kind::Boolean,
+        default: Some("true"),
+        description: "Whether emails from disposable email providers are refused.",
+    },
+    Definition {
+        category: Category::Users,
+        key: "email_blocked_domains",
+        kind: Kind::Text { max_length: 4096 },
+        default: Some(""),
+        description: "Comma-separated email domains that are refused, including their subdomains.",
+    },
+    Definition {
+        category: Category::Users,
+        key: "email_allowed_domains",
+        kind: Kind::Text { max_length: 4096 },
+        default: Some(""),
+        description: "Comma-separated email domains that are the only ones allowed, including their subdomains. Empty allows every domain.",
+    },
+    Definition {
+        category: Category::Users,
+        key: "username_min_length",
+        kind: Kind::Integer { min: 1, max: 64 },
+        default: Some("4"),
+        description: "Minimum length of usernames, in characters.",
+    },
+    Definition {
+        category: Category::Users,
+        key: "username_max_length",
+        kind: Kind::Integer { min: 1, max: 64 },
+        default: Some("32"),
+        description: "Maximum length of usernames, in characters.",
+    },
+    Definition {
+        category: Category::Users,
+        key: "username_allowed_characters",
+        kind: Kind::Text { max_length: 256 },
+        default: Some("letters,digits,underscore,dot,hyphen"),
+        description: "Comma-separated classes of characters allowed in usernames: lowercase, uppercase, letters, digits, underscore, dot and hyphen.",
+    },
+    Definition {
+        category: Category::Users,
+        key: "username_case_insensitive",
+        kind: Kind::Boolean,
+        default: Some("true"),
+        description: "Whether usernames differing only in case are the same. Only affects usernames set afterwards.",
+    },
+    Definition {
+        category: Category::Users,
+        key: "username_confusable_detection",
+        kind: Kind::Boolean,
+        default: Some("true"),
+        description: "Whether usernames that look alike are the same. Only affects usernames set afterwards.",
+    },
+    Definition {
+        category: Category::Users,
+        key: "username_reserved",
+        kind: Kind::Text { max_length: 4096 },
+        default: None,
+        description: "Comma-separated usernames nobody can take. Unset, a built-in list is used.",
+    },
+];
+
+/// Settings bounding the same value from below and from above, as `(category, minimum,
+/// maximum)`. The minimum can't be set above the maximum.
+const RANGES: [(Category, &str, &str); 2] = [
+    (
+        Category::Security,
+        "password_min_length",
+        "password_max_length",
+    ),
+    (
+        Category::Users,
+        "username_min_length",
+        "username_max_length",
+    ),
+];
+
+/// Looks up a setting in the registry.
+pub fn definition(category: Category, key: &str) -> Option<&'static Definition> {
+    REGISTRY
+        .iter()
+        .find(|definition| definition.category == category && definition.key == key)
+}
+
+/// A registered setting read as `T`.
+pub struct Key<T> {
+    pub category: Category,
+    pub key: &'static str,
+    marker: PhantomData<fn() -> T>,
+}
+
+impl<T> Key<T> {
+    const fn new(category: Category, key: &'static str) -> Self {
+        Self {
+            category,
+            key,
+            marker: PhantomData,
+        }
+    }
+}
+
+pub const ACCESS_TOKEN_LIFETIME: Key<u64> = Key::new(Category::Security, "access_token_lifetime");
+pub const REFRESH_TOKEN_LIFETIME: Key<u64> = Key::new(Category::Security, "refresh_token_lifetime");
+pub const PASSWORD_MIN_LENGTH: Key<usize> = Key::new(Category::Security, "password_min_length");
+pub const PASSWORD_MAX_LENGTH: Key<usize> = Key::new(Category::Security, "password_max_length");
+pub const PASSWORD_MIN_SCORE: Key<u8> = Key::new(Category::Security, "password_min_score");
+pub const MFA_REQUIRED: Key<bool> = Key::new(Category::Security, "mfa_required");
+pub const EMAIL_CHANGE_ROLLBACK_WINDOW: Key<u64> =
+    Key::new(Category::Users, "email_change_rollback_window");
+pub const USERNAME_CHANGE_COOLDOWN: Key<i64> =
+    Key::new(Category::Users, "username_change_cooldown");
+pub const USERNAME_HOLD_PERIOD: Key<u64> = Key::new(Category::Users, "username_hold_period");
+
+/// The stored settings of a tenant, by category, then key.
+type Values = HashMap<i8, HashMap<String, String>>;
+
+/// Returns every stored setting of a tenant, through the cache.
+async fn values(state: &State, tenant_id: &str) -> Result<Values, QueryError> {
+    let values = cache::SETTINGS
+        .get(&state.redis, tenant_id, || async {
+            let result = state
+                .db
+                .query_unpaged(
+                    "SELECT category, key, value FROM tenant_settings WHERE tenant_id = ?",
+                    (tenant_id,),
+                )
+                .await?;
+
+            let mut values = Values::new();
+
+            for (category, key, value) in result
+                .rows_typed_or_empty::<(i8, String, Option<String>)>()
+                .filter_map(|row| row.ok())
+            {
+                if let Some(value) = value {
+                    values.entry(category).or_default().insert(key, value);
+                }
+            }
+
+            Ok(Some(values))
+        })
+        .await?;
+
+    Ok(values.unwrap_or_default())
+}
+
+/// Reads a tenant setting, falling back to `default` if it's not set or its value can't be
+/// parsed into `T`.
+pub async fn get<T: FromStr>(
+    state: &State,
+    tenant_id: &str,
+    category: Category,
+    key: &str,
+    default: T,
+) -> Result<T, QueryError> {
+    Ok(values(state, tenant_id)
+        .await?
+        .get(&(category as i8))
+        .and_then(|values| values.get(key))
+        .and_then(|value| value.trim().parse::<T>().ok())
+        .unwrap_or(default))
+}
+
+/// Why a registered setting couldn't be read.
+#[derive(Debug)]
+pub enum ReadError {
+    /// The setting has no default in the registry, or none of the type it's read as.
+    NoDefault(&'static str),
+    Query(QueryError),
+}
+
+impl fmt::Display for ReadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NoDefault(key) => write!(f, "The setting {key} has no default of its type."),
+            Self::Query(e) => write!(f, "The setting couldn't be read: {e}"),
+        }
+    }
+}
+
+impl From<QueryError> for ReadError {
+    fn from(e: QueryError) -> Self {
+        Self::Query(e)
+    }
+}
+
+/// Reads a registered setting, falling back to its default.
+pub async fn read<T: FromStr>(state: &State, tenant_id: &str, key: Key<T>) -> Result<T, ReadError> {
+    let Some(default) = definition(key.category, key.key)
+        .and_then(|definition| definition.default)
+        .and_then(|default| default.parse::<T>().ok())
+    else {
+        return Err(ReadError::NoDefault(key.key));
+    };
+
+    Ok(get(state, tenant_id, key.category, key.key, default).await?)
+}
+
+/// Reads all the tenant settings of a category as raw strings.
+pub async fn get_all(
+    state: &State,
+    tenant_id: &str,
+    category: Category,
+) -> Result<HashMap<String, String>, QueryError> {
+    Ok(values(state, tenant_id)
+        .await?
+        .remove(&(category as i8))
+        .unwrap_or_default())
+}
+
+/// Returns the stored value of a setting, if it's set.
+pub async fn stored(
+    state: &State,
+    tenant_id: &str,
+    category: Category,
+    key: &str,
+) -> Result<Option<String>, QueryError> {
+    Ok(values(state, tenant_id)
+        .await?
+        .remove(&(category as i8))
+        .and_then(|mut values| values.remove(key)))
+}
+
+/// Checks that `value`, stored for a setting bounding a range, keeps the range's minimum at most
+/// its maximum, given the other bound's current value. Returns why it doesn't otherwise.
+pub async fn check_range(
+    state: &State,
+    tenant_id: &str,
+    definition: &Definition,
+    value: &str,
+) -> Result<Option<String>, QueryError> {
+    let Some((category, min, max)) = RANGES.iter().find(|(category, min, max)| {
+        *category == definition.category && [*min, *max].contains(&definition.key)
+    }) else {
+        return Ok(None);
+    };
+
+    let is_min = definition.key == *min;
+    let other_key = if is_min { *max } else { *min };
+
+    let other = match stored(state, tenant_id, *category, other_key).await? {
+        Some(other) => Some(other),
+        None => self::definition(*category, other_key)
+            .and_then(|other| other.default)
+            .map(str::to_string),
+    };
+
+    let (Ok(value), Some(Ok(other))) = (
+        value.trim().parse::<i64>(),
+        other.map(|other| other.trim().parse::<i64>()),
+    ) else {
+        return Ok(None);
+    };
+
+    Ok(match is_min {
+        true if value > other => Some(format!(
+            "The value must be at most {other}, the value of {max}."
+        )),
+        false if value < other => Some(format!(
+            "The value must be at least {other}, the value of {min}."
+        )),
+        _ => None,
+    })
+}
+
+/// Stores the value of a setting, which must have been validated.
+pub async fn set(
+    state: &State,
+    tenant_id: &str,
+    category: Category,
+    key: &str,
+    value: &str,
+) -> Result<(), QueryError> {
+    state
+        .db
+        .query_unpaged(
+            "INSERT INTO tenant_settings (tenant_id, category, key, value) VALUES (?, ?, ?, ?)",
+            (tenant_id, category as i8, key, value),
+        )
+        .await?;
+
+    cache::SETTINGS.invalidate(&state.redis, tenant_id).await;
+
+    Ok(())
+}
+
+/// Unsets a setting, so its default applies again.
+pub async fn reset(
+    state: &State,
+    tenant_id: &str,
+    category: Category,
+    key: &str,
+) -> Result<(), QueryError> {
+    state
+        .db
+        .query_unpaged(
+            "DELETE FROM tenant_settings WHERE tenant_id = ? AND category = ? AND key = ?",
+            (tenant_id, category as i8, key),
+        )
+        .await?;
+
+    cache::SETTINGS.invalidate(&state.redis, tenant_id).await;
+
+    Ok(())
+}
@@ -1,3 +1,4 @@
+use crate::{dns::Resolver, tenants::Resolution};
 use hmac::Hmac;
 use redis_pool::SingleRedisPool;
 use scylla::Session;
@@ -9,6 +10,10 @@ pub struct State {
     pub db: Session,
     pub redis: SingleRedisPool,
     pub hmac: Hmac<Sha384>,
+    pub dns: Resolver,
+
+    /// How requests are matched to tenants, in priority order.
+    pub tenant_resolution: Vec<Resolution>,
 }
 
 pub type AppState = Arc<RwLock<State>>;
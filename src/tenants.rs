@@ -0,0 +1,455 @@
+use std::env;
+
+use chrono::Utc;
+use nanoid::nanoid;
+use scylla::{batch::Batch, frame::value::CqlTimestamp, transport::errors::QueryError, Session};
+use serde::{Deserialize, Serialize};
+
+use crate::{cache, hosts, settings, state::State, tokens::token, utils::id::gen_id};
+
+/// The tenant AccessCore itself runs on. Its users administrate the other tenants, and its
+/// admins are the platform's super-admins.
+pub const PLATFORM_TENANT_ID: &str = "accesscore";
+
+/// Permission a super-admin's token must allow to manage tenants.
+pub const MANAGE_PERMISSION: &str = "platform.tenants.manage";
+
+/// Header naming the tenant of a request, for the `header` resolution strategy.
+pub const TENANT_HEADER: &str = "x-tenant-id";
+
+/// Permission a user and their token must have to name their tenant with the header.
+pub const HEADER_PERMISSION: &str = "tenants.header.use";
+
+/// Prefix of the routes taking no access token, where the header is refused.
+pub const UNAUTHENTICATED_PREFIX: &str = "/auth/";
+
+/// Prefix of paths naming the tenant of a request, for the `path` resolution strategy.
+pub const PATH_PREFIX: &str = "/t/";
+
+/// Algorithm of the signing keys created for new tenants.
+const SIGNING_KEY_ALGORITHM: &str = "HS384";
+
+/// A way of telling which tenant a request is made to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Resolution {
+    /// The `Host` header, matched against the tenant's hosts.
+    Host,
+
+    /// The `X-Tenant-ID` header, only accepted along with an access token of the tenant whose
+    /// user and scopes allow `tenants.header.use`, and never on routes taking no token.
+    Header,
+
+    /// A `/t/{tenant_id}` prefix, stripped from the path before routing.
+    Path,
+}
+
+/// Reads the resolution strategies from `TENANT_RESOLUTION`, a comma-separated list of `host`,
+/// `header` and `path` in priority order. Only the host is used if it's missing.
+pub fn resolution() -> Vec<Resolution> {
+    let Ok(strategies) = env::var("TENANT_RESOLUTION") else {
+        return vec![Resolution::Host];
+    };
+
+    let mut resolution = vec![];
+
+    for strategy in strategies.split(',').map(str::trim) {
+        let strategy = match strategy {
+            "host" => Resolution::Host,
+            "header" => Resolution::Header,
+            "path" => Resolution::Path,
+            _ => panic!("Unknown tenant resolution strategy `{strategy}` in TENANT_RESOLUTION."),
+        };
+
+        if !resolution.contains(&strategy) {
+            resolution.push(strategy);
+        }
+    }
+
+    resolution
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Tenant {
+    pub tenant_id: String,
+    pub name: String,
+    pub is_suspended: bool,
+    pub suspended_at: Option<i64>,
+
+    /// Whether the tenant is for testing, where codes are fixed and messages aren't sent.
+    pub is_sandbox: bool,
+    pub created_at: Option<i64>,
+    pub updated_at: Option<i64>,
+}
+
+/// A signing key of a tenant. The key itself is never serialized.
+#[derive(Debug, Clone, Serialize)]
+pub struct SigningKey {
+    pub key_id: String,
+    pub algorithm: String,
+    pub is_active: bool,
+    pub created_at: Option<i64>,
+}
+
+type TenantRow = (
+    String,
+    Option<String>,
+    Option<bool>,
+    Option<CqlTimestamp>,
+    Option<bool>,
+    Option<CqlTimestamp>,
+    Option<CqlTimestamp>,
+);
+
+const COLUMNS: &str =
+    "tenant_id, name, is_suspended, suspended_at, is_sandbox, created_at, updated_at";
+
+fn from_row(
+    (tenant_id, name, is_suspended, suspended_at, is_sandbox, created_at, updated_at): TenantRow,
+) -> Tenant {
+    Tenant {
+        tenant_id,
+        name: name.unwrap_or_default(),
+        is_suspended: is_suspended.unwrap_or(false),
+        suspended_at: suspended_at.map(|CqlTimestamp(t)| t),
+        is_sandbox: is_sandbox.unwrap_or(false),
+        created_at: created_at.map(|CqlTimestamp(t)| t),
+        updated_at: updated_at.map(|CqlTimestamp(t)| t),
+    }
+}
+
+/// Checks whether `tenant_id` can identify a tenant: 3 to 32 lowercase ASCII letters, digits or
+/// hyphens, not starting or ending with a hyphen.
+pub fn is_valid_id(tenant_id: &str) -> bool {
+    (3..=32).contains(&tenant_id.len())
+        && !tenant_id.starts_with('-')
+        && !tenant_id.ends_with('-')
+        && tenant_id
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+}
+
+/// Generates a tenant ID for tenants created without one.
+pub fn gen_tenant_id() -> String {
+    const ALPHABET: [char; 36] = [
+        'a', 'b', 'c', 'd', 'e', 'f', 'g', 'h', 'i', 'j', 'k', 'l', 'm', 'n', 'o', 'p', 'q', 'r',
+        's', 't', 'u', 'v', 'w', 'x', 'y', 'z', '0', '1', '2', '3', '4', '5', '6', '7', '8', '9',
+    ];
+
+    nanoid!(16, &ALPHABET)
+}
+
+pub async fn get(db: &Session, tenant_id: &str) -> Result<Option<Tenant>, QueryError> {
+    let result = db
+        .query_unpaged(
+            format!("SELECT {COLUMNS} FROM tenants WHERE tenant_id = ?"),
+            (tenant_id,),
+        )
+        .await?;
+
+    Ok(result
+        .maybe_first_row_typed::<TenantRow>()
+        .ok()
+        .flatten()
+        .map(from_row))
+}
+
+/// Lists every tenant. There are few enough of them for a full scan.
+pub async fn all(db: &Session) -> Result<Vec<Tenant>, QueryError> {
+    let result = db
+        .query_unpaged(format!("SELECT {COLUMNS} FROM tenants"), &[])
+        .await?;
+
+    let mut tenants: Vec<Tenant> = result
+        .rows_typed_or_empty::<TenantRow>()
+        .filter_map(|row| row.ok())
+        .map(from_row)
+        .collect();
+
+    tenants.sort_by(|a, b| a.tenant_id.cmp(&b.tenant_id));
+
+    Ok(tenants)
+}
+
+/// What the tenant middleware needs to know about a tenant on every request.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Status {
+    pub is_suspended: bool,
+    pub is_sandbox: bool,
+}
+
+/// Returns the status of a tenant, or `None` if there's no such tenant. Goes through the cache.
+pub async fn status(state: &State, tenant_id: &str) -> Result<Option<Status>, QueryError> {
+    cache::TENANTS
+        .get(&state.redis, tenant_id, || async {
+            let result = state
+                .db
+                .query_unpaged(
+                    "SELECT is_suspended, is_sandbox FROM tenants WHERE tenant_id = ?",
+                    (tenant_id,),
+                )
+                .await?;
+
+            Ok(result
+                .maybe_first_row_typed::<(Option<bool>, Option<bool>)>()
+                .ok()
+                .flatten()
+                .map(|(is_suspended, is_sandbox)| Status {
+                    is_suspended: is_suspended.unwrap_or(false),
+                    is_sandbox: is_sandbox.unwrap_or(false),
+                }))
+        })
+        .await
+}
+
+/// Checks whether a tenant is a sandbox. Missing tenants aren't. Goes through the cache.
+pub async fn is_sandbox(state: &State, tenant_id: &str) -> Result<bool, QueryError> {
+    Ok(status(state, tenant_id)
+        .await?
+        .is_some_and(|status| status.is_sandbox))
+}
+
+/// Creates a tenant, returning `false` if its ID is already taken.
+pub async fn create(
+    state: &State,
+    tenant_id: &str,
+    name: &str,
+    is_sandbox: bool,
+) -> Result<bool, QueryError> {
+    let result = state
+        .db
+        .query_unpaged(
+            "INSERT INTO tenants (tenant_id, name, is_suspended, is_sandbox, created_at, updated_at) VALUES (?, ?, false, ?, toTimestamp(now()), toTimestamp(now())) IF NOT EXISTS",
+            (tenant_id, name, is_sandbox),
+        )
+        .await?;
+
+    // Lookups of the ID may have been cached as missing.
+    cache::TENANTS.invalidate(&state.redis, tenant_id).await;
+
+    Ok(result
+        .first_row()
+        .ok()
+        .and_then(|row| row.columns.into_iter().next().flatten())
+        .and_then(|applied| applied.as_boolean())
+        .unwrap_or(false))
+}
+
+/// Writes the default settings of a new tenant, along with its first signing key. Settings
+/// are only written where missing and the key only if there's none, so it can run again.
+pub async fn bootstrap(db: &Session, tenant_id: &str) -> Result<(), QueryError> {
+    let mut batch = Batch::default();
+    let mut values = vec![];
+
+    // New tenants start with every default written out, so they can be read and changed from
+    // the start.
+    for definition in &settings::REGISTRY {
+        if let Some(value) = definition.default {
+            batch.append_statement(
+                "INSERT INTO tenant_settings (tenant_id, category, key, value) VALUES (?, ?, ?, ?) IF NOT EXISTS",
+            );
+            values.push((tenant_id, definition.category as i8, definition.key, value));
+        }
+    }
+
+    db.batch(&batch, values).await?;
+
+    if signing_keys(db, tenant_id).await?.is_empty() {
+        create_signing_key(db, tenant_id).await?;
+    }
+
+    Ok(())
+}
+
+/// Creates a new active signing key for a tenant.
+pub async fn create_signing_key(db: &Session, tenant_id: &str) -> Result<SigningKey, QueryError> {
+    let key = SigningKey {
+        key_id: gen_id(None),
+        algorithm: SIGNING_KEY_ALGORITHM.to_string(),
+        is_active: true,
+        created_at: Some(Utc::now().timestamp_millis()),
+    };
+
+    db.query_unpaged(
+        "INSERT INTO tenant_signing_keys (tenant_id, key_id, algorithm, secret, is_active, created_at) VALUES (?, ?, ?, ?, true, ?)",
+        (
+            tenant_id,
+            &key.key_id,
+            &key.algorithm,
+            token(Some(48)),
+            key.created_at.map(CqlTimestamp),
+        ),
+    )
+    .await?;
+
+    Ok(key)
+}
+
+pub async fn signing_keys(db: &Session, tenant_id: &str) -> Result<Vec<SigningKey>, QueryError> {
+    let result = db
+        .query_unpaged(
+            "SELECT key_id, algorithm, is_active, created_at FROM tenant_signing_keys WHERE tenant_id = ?",
+            (tenant_id,),
+        )
+        .await?;
+
+    Ok(result
+        .rows_typed_or_empty::<(String, Option<String>, Option<bool>, Option<CqlTimestamp>)>()
+        .filter_map(|row| row.ok())
+        .map(|(key_id, algorithm, is_active, created_at)| SigningKey {
+            key_id,
+            algorithm: algorithm.unwrap_or_else(|| SIGNING_KEY_ALGORITHM.to_string()),
+            is_active: is_active.unwrap_or(false),
+            created_at: created_at.map(|CqlTimestamp(t)| t),
+        })
+        .collect())
+}
+
+pub async fn rename(db: &Session, tenant_id: &str, name: &str) -> Result<(), QueryError> {
+    db.query_unpaged(
+        "UPDATE tenants SET name = ?, updated_at = toTimestamp(now()) WHERE tenant_id = ?",
+        (name, tenant_id),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Turns a tenant into a sandbox, or back into a regular tenant.
+pub async fn set_sandbox(
+    state: &State,
+    tenant_id: &str,
+    is_sandbox: bool,
+) -> Result<(), QueryError> {
+    state
+        .db
+        .query_unpaged(
+            "UPDATE tenants SET is_sandbox = ?, updated_at = toTimestamp(now()) WHERE tenant_id = ?",
+            (is_sandbox, tenant_id),
+        )
+        .await?;
+
+    cache::TENANTS.invalidate(&state.redis, tenant_id).await;
+
+    Ok(())
+}
+
+/// Suspends or resumes a tenant. Requests to suspended tenants are refused by the tenant
+/// middleware, but their data is kept.
+pub async fn set_suspended(
+    state: &State,
+    tenant_id: &str,
+    is_suspended: bool,
+) -> Result<(), QueryError> {
+    let suspended_at = is_suspended.then(|| CqlTimestamp(Utc::now().timestamp_millis()));
+
+    state.db.query_unpaged(
+        "UPDATE tenants SET is_suspended = ?, suspended_at = ?, updated_at = toTimestamp(now()) WHERE tenant_id = ?",
+        (is_suspended, suspended_at, tenant_id),
+    )
+    .await?;
+
+    cache::TENANTS.invalidate(&state.redis, tenant_id).await;
+
+    Ok(())
+}
+
+/// Deletes a tenant along with its hosts, admins, settings and signing keys. The rest of its
+/// data is left in place.
+pub async fn delete(state: &State, tenant_id: &str) -> Result<(), QueryError> {
+    // Hosts are released first, as they're only deleted if the tenant still holds them.
+    for host in hosts::list(&state.db, tenant_id).await? {
+        hosts::remove(state, &host).await?;
+    }
+
+    let admins = admins(&state.db, tenant_id).await?;
+
+    let mut batch = Batch::default();
+    let mut values: Vec<Vec<&str>> = vec![];
+
+    for user_id in &admins {
+        batch.append_statement(
+            "DELETE FROM tenants_by_admin_users WHERE user_id = ? AND tenant_id = ?",
+        );
+        values.push(vec![user_id, tenant_id]);
+    }
+
+    batch.append_statement("DELETE FROM tenant_settings WHERE tenant_id = ?");
+    values.push(vec![tenant_id]);
+    batch.append_statement("DELETE FROM tenant_signing_keys WHERE tenant_id = ?");
+    values.push(vec![tenant_id]);
+    batch.append_statement("DELETE FROM tenants WHERE tenant_id = ?");
+    values.push(vec![tenant_id]);
+
+    state.db.batch(&batch, values).await?;
+
+    cache::TENANTS.invalidate(&state.redis, tenant_id).await;
+    cache::SETTINGS.invalidate(&state.redis, tenant_id).await;
+
+    Ok(())
+}
+
+/// Lists the platform users administrating a tenant.
+pub async fn admins(db: &Session, tenant_id: &str) -> Result<Vec<String>, QueryError> {
+    let result = db
+        .query_unpaged(
+            "SELECT user_id FROM admin_users_by_tenant WHERE tenant_id = ?",
+            (tenant_id,),
+        )
+        .await?;
+
+    Ok(result
+        .rows_typed_or_empty::<(String,)>()
+        .filter_map(|row| row.ok())
+        .map(|(user_id,)| user_id)
+        .collect())
+}
+
+/// Checks whether a platform user administrates a tenant.
+pub async fn is_admin(db: &Session, tenant_id: &str, user_id: &str) -> Result<bool, QueryError> {
+    let result = db
+        .query_unpaged(
+            "SELECT tenant_id FROM tenants_by_admin_users WHERE user_id = ? AND tenant_id = ?",
+            (user_id, tenant_id),
+        )
+        .await?;
+
+    Ok(matches!(
+        result.maybe_first_row_typed::<(String,)>(),
+        Ok(Some(_))
+    ))
+}
+
+pub async fn add_admin(db: &Session, tenant_id: &str, user_id: &str) -> Result<(), QueryError> {
+    db.query_unpaged(
+        "INSERT INTO tenants_by_admin_users (user_id, tenant_id) VALUES (?, ?)",
+        (user_id, tenant_id),
+    )
+    .await?;
+
+    Ok(())
+}
+
+pub async fn remove_admin(db: &Session, tenant_id: &str, user_id: &str) -> Result<(), QueryError> {
+    db.query_unpaged(
+        "DELETE FROM tenants_by_admin_users WHERE user_id = ? AND tenant_id = ?",
+        (user_id, tenant_id),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Checks whether a user of the platform tenant exists, as only they can administrate tenants.
+pub async fn is_platform_user(db: &Session, user_id: &str) -> Result<bool, QueryError> {
+    let result = db
+        .query_unpaged(
+            "SELECT user_id FROM users WHERE tenant_id = ? AND user_id = ?",
+            (PLATFORM_TENANT_ID, user_id),
+        )
+        .await?;
+
+    Ok(matches!(
+        result.maybe_first_row_typed::<(String,)>(),
+        Ok(Some(_))
+    ))
+}
@@ -1,16 +1,26 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::Utc;
+use hmac::Hmac;
+use jwt::{Header, SignWithKey, Token, VerifyWithKey};
 use rand::{rngs::OsRng, RngCore};
+use scylla::transport::errors::QueryError;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256, Sha384};
 
-#[derive(Serialize, Deserialize)]
+use crate::{cache, state::State};
+
+#[derive(Serialize, Deserialize, PartialEq)]
 #[serde(rename_all = "snake_case")]
 pub enum TokenType {
     Flow,
+    Invitation,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, PartialEq)]
 #[serde(rename_all = "snake_case")]
 pub enum Flow {
     SignUpEmailVerification,
+    EmailChange,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -22,6 +32,81 @@ pub struct FlowToken {
     pub expires_at: i64,
 }
 
+impl FlowToken {
+    /// Signs the flow token with HS384.
+    pub fn sign(self, key: &Hmac<Sha384>) -> Result<String, jwt::Error> {
+        let header = Header {
+            algorithm: jwt::AlgorithmType::Hs384,
+            ..Header::default()
+        };
+
+        Ok(Token::new(header, self)
+            .sign_with_key(key)?
+            .as_str()
+            .to_string())
+    }
+
+    /// Verifies a signed flow token, returning its claims only if it was issued for `flow` in
+    /// `tenant_id` and hasn't expired yet.
+    pub fn verify(token: &str, key: &Hmac<Sha384>, flow: Flow, tenant_id: &str) -> Option<Self> {
+        let claims: Self = token.verify_with_key(key).ok()?;
+
+        if claims.token_type != TokenType::Flow
+            || claims.flow != flow
+            || claims.tenant_id != tenant_id
+            || claims.expires_at < Utc::now().timestamp()
+        {
+            return None;
+        }
+
+        Some(claims)
+    }
+}
+
+/// An organization invitation, emailed to the invitee. The invitation row must still exist for
+/// the token to be accepted, which is how invitations are revoked.
+#[derive(Serialize, Deserialize)]
+pub struct InvitationToken {
+    pub token_type: TokenType,
+    pub tenant_id: String,
+    pub organization_id: String,
+    pub invitation_id: String,
+
+    /// The canonical email the invitation was sent to.
+    pub email: String,
+    pub expires_at: i64,
+}
+
+impl InvitationToken {
+    /// Signs the invitation token with HS384.
+    pub fn sign(self, key: &Hmac<Sha384>) -> Result<String, jwt::Error> {
+        let header = Header {
+            algorithm: jwt::AlgorithmType::Hs384,
+            ..Header::default()
+        };
+
+        Ok(Token::new(header, self)
+            .sign_with_key(key)?
+            .as_str()
+            .to_string())
+    }
+
+    /// Verifies a signed invitation token, returning its claims only if it was issued in
+    /// `tenant_id` and hasn't expired yet.
+    pub fn verify(token: &str, key: &Hmac<Sha384>, tenant_id: &str) -> Option<Self> {
+        let claims: Self = token.verify_with_key(key).ok()?;
+
+        if claims.token_type != TokenType::Invitation
+            || claims.tenant_id != tenant_id
+            || claims.expires_at < Utc::now().timestamp()
+        {
+            return None;
+        }
+
+        Some(claims)
+    }
+}
+
 /// Generates a cryptographically secure random token of `size` bytes long, which defaults to 64
 /// for a 64 character long token.
 pub fn token(size: Option<usize>) -> Vec<u8> {
@@ -29,3 +114,64 @@ pub fn token(size: Option<usize>) -> Vec<u8> {
     OsRng.fill_bytes(&mut data);
     data
 }
+
+/// An access token, as read by the authentication middleware.
+#[derive(Serialize, Deserialize)]
+pub struct AccessToken {
+    pub user_id: String,
+    pub scopes: Vec<String>,
+    pub device_id: Option<String>,
+
+    /// When the token expires, in seconds since the epoch. Checked on every use, since a cached
+    /// token can outlive its row.
+    pub expires_at: i64,
+}
+
+/// The cache key of a token, which hashes it so tokens never end up in Redis keys.
+fn cache_key(tenant_id: &str, api_token: &[u8]) -> String {
+    format!(
+        "{tenant_id}:{}",
+        URL_SAFE_NO_PAD.encode(Sha256::digest(api_token))
+    )
+}
+
+/// Returns an unexpired access token of a tenant, going through the cache.
+pub async fn access_token(
+    state: &State,
+    tenant_id: &str,
+    api_token: &[u8],
+) -> Result<Option<AccessToken>, QueryError> {
+    let token = cache::TOKENS
+        .get(&state.redis, &cache_key(tenant_id, api_token), || async {
+            let result = state
+                .db
+                .query_unpaged(
+                    "SELECT user_id, scopes, device_id, TTL(created_at) FROM api_tokens WHERE tenant_id = ? AND api_token = ? AND is_refresh = false LIMIT 1",
+                    (tenant_id, api_token),
+                )
+                .await?;
+
+            Ok(result
+                .maybe_first_row_typed::<(String, Option<Vec<String>>, Option<String>, Option<i32>)>()
+                .ok()
+                .flatten()
+                .map(|(user_id, scopes, device_id, ttl)| AccessToken {
+                    user_id,
+                    scopes: scopes.unwrap_or_default(),
+                    device_id,
+                    expires_at: ttl.map_or(i64::MAX, |ttl| {
+                        Utc::now().timestamp() + i64::from(ttl)
+                    }),
+                }))
+        })
+        .await?;
+
+    Ok(token.filter(|token| token.expires_at > Utc::now().timestamp()))
+}
+
+/// Drops a revoked access token from the cache. Call it once it's deleted from Scylla.
+pub async fn invalidate(state: &State, tenant_id: &str, api_token: &[u8]) {
+    cache::TOKENS
+        .invalidate(&state.redis, &cache_key(tenant_id, api_token))
+        .await;
+}
@@ -3,3 +3,12 @@ pub struct RequestID(pub String);
 
 #[derive(Clone)]
 pub struct TenantID(pub String);
+
+/// Whether the tenant of the request is a sandbox, as found by the tenant middleware. Also set
+/// on responses, for the rate limiter.
+#[derive(Clone, Copy)]
+pub struct Sandbox(pub bool);
+
+/// The tenant named by a `/t/{tenant_id}` path prefix, which was stripped before routing.
+#[derive(Clone)]
+pub struct PathTenantID(pub String);
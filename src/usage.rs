@@ -0,0 +1,408 @@
+use std::{
+    collections::HashMap,
+    sync::{LazyLock, Mutex},
+    time::Duration,
+};
+
+use chrono::{Datelike, NaiveDate, Utc};
+use scylla::{transport::errors::QueryError, Session};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use tracing::{event, Level};
+
+use crate::state::{AppState, State};
+
+/// How often usage counted in-process is written to Scylla. Limits are enforced against what's
+/// written, so they can be overrun by this much usage across instances.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How long Redis remembers that a user was active in a month, so most activity doesn't reach
+/// Scylla. Longer than any month.
+const ACTIVE_USER_TTL: u64 = 32 * 24 * 60 * 60;
+
+/// Longest range of days a usage report covers.
+pub const MAX_REPORT_DAYS: i64 = 366;
+
+/// Something tenants are billed or limited by. Usage is counted per day, and limits apply per
+/// calendar month in UTC, except for users.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Metric {
+    /// Users issued a token during the month. Each day counts the users active for the first
+    /// time that month.
+    ActiveUsers,
+    SignUps,
+    TokenIssuances,
+    Notifications,
+    ApiRequests,
+
+    /// Users of the tenant. Each day counts the users added, and limits apply to the total.
+    /// Users are only counted once verified, as unverified ones expire without being removed
+    /// from the count.
+    Users,
+}
+
+impl Metric {
+    pub const ALL: [Self; 6] = [
+        Self::ActiveUsers,
+        Self::SignUps,
+        Self::TokenIssuances,
+        Self::Notifications,
+        Self::ApiRequests,
+        Self::Users,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::ActiveUsers => "active_users",
+            Self::SignUps => "sign_ups",
+            Self::TokenIssuances => "token_issuances",
+            Self::Notifications => "notifications",
+            Self::ApiRequests => "api_requests",
+            Self::Users => "users",
+        }
+    }
+
+    pub fn parse(metric: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|candidate| candidate.as_str() == metric)
+    }
+}
+
+/// Usage counted by this instance and not written yet, by tenant and metric.
+static PENDING: LazyLock<Mutex<HashMap<(String, Metric), i64>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Counts usage of a tenant. It's written to Scylla in the background.
+pub fn record(tenant_id: &str, metric: Metric, count: i64) {
+    *PENDING
+        .lock()
+        .unwrap()
+        .entry((tenant_id.to_string(), metric))
+        .or_default() += count;
+}
+
+/// Counts a sign-up, and the new user if they're already verified.
+pub fn record_sign_up(tenant_id: &str, is_verified: bool) {
+    record(tenant_id, Metric::SignUps, 1);
+
+    if is_verified {
+        record(tenant_id, Metric::Users, 1);
+    }
+}
+
+fn pending(tenant_id: &str, metric: Metric) -> i64 {
+    PENDING
+        .lock()
+        .unwrap()
+        .get(&(tenant_id.to_string(), metric))
+        .copied()
+        .unwrap_or(0)
+}
+
+/// Writes the usage counted so far to today's counters. Counts that fail to be written are
+/// kept for the next flush. It's also called on shutdown, so no usage is lost.
+pub async fn flush(db: &Session) {
+    let pending = std::mem::take(&mut *PENDING.lock().unwrap());
+    let today = Utc::now().date_naive();
+
+    for ((tenant_id, metric), count) in pending {
+        if let Err(e) = db
+            .query_unpaged(
+                "UPDATE tenant_usage SET count = count + ? WHERE tenant_id = ? AND metric = ? AND day = ?",
+                (count, &tenant_id, metric.as_str(), today),
+            )
+            .await
+        {
+            event!(Level::WARN, error = format!("{e}"), tenant_id);
+            record(&tenant_id, metric, count);
+        }
+    }
+}
+
+/// Writes usage counted by this instance in the background.
+pub async fn run(state: AppState) {
+    let mut interval = tokio::time::interval(FLUSH_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        flush(&state.read().await.db).await;
+    }
+}
+
+fn month_of(day: NaiveDate) -> NaiveDate {
+    day.with_day(1).unwrap()
+}
+
+/// Counts a user as active this month, the first time they are.
+pub async fn record_active(
+    state: &State,
+    tenant_id: &str,
+    user_id: &str,
+) -> Result<(), QueryError> {
+    let month = month_of(Utc::now().date_naive());
+
+    // Redis remembers users already counted, but Scylla decides, should Redis forget.
+    match state.redis.get_multiplexed_async_connection().await {
+        Ok(mut connection) => {
+            let is_new = redis::cmd("SET")
+                .arg(format!("usage:active:{tenant_id}:{month}:{user_id}"))
+                .arg(1)
+                .arg("NX")
+                .arg("EX")
+                .arg(ACTIVE_USER_TTL)
+                .query_async::<Option<String>>(&mut connection)
+                .await;
+
+            match is_new {
+                Ok(None) => return Ok(()),
+                Ok(Some(_)) => {}
+                Err(e) => event!(Level::WARN, error = format!("{e}")),
+            }
+        }
+        Err(e) => event!(Level::WARN, error = format!("{e}")),
+    }
+
+    let result = state
+        .db
+        .query_unpaged(
+            "INSERT INTO tenant_active_users (tenant_id, month, user_id, first_seen_at) VALUES (?, ?, ?, toTimestamp(now())) IF NOT EXISTS",
+            (tenant_id, month, user_id),
+        )
+        .await?;
+
+    let applied = result
+        .first_row()
+        .ok()
+        .and_then(|row| row.columns.into_iter().next().flatten())
+        .and_then(|applied| applied.as_boolean())
+        .unwrap_or(false);
+
+    if applied {
+        record(tenant_id, Metric::ActiveUsers, 1);
+    }
+
+    Ok(())
+}
+
+/// Counts tokens issued to a user, who's then active this month. Failures are only logged, as
+/// the tokens are already issued.
+pub async fn record_issuance(state: &State, tenant_id: &str, user_id: &str) {
+    record(tenant_id, Metric::TokenIssuances, 1);
+
+    if let Err(e) = record_active(state, tenant_id, user_id).await {
+        event!(Level::WARN, error = format!("{e}"), tenant_id);
+    }
+}
+
+/// Returns the usage of a metric on each day it was used in a range.
+async fn daily(
+    db: &Session,
+    tenant_id: &str,
+    metric: Metric,
+    from: NaiveDate,
+    to: NaiveDate,
+) -> Result<HashMap<NaiveDate, i64>, QueryError> {
+    let result = db
+        .query_unpaged(
+            "SELECT day, count FROM tenant_usage WHERE tenant_id = ? AND metric = ? AND day >= ? AND day <= ?",
+            (tenant_id, metric.as_str(), from, to),
+        )
+        .await?;
+
+    Ok(result
+        .rows_typed_or_empty::<(NaiveDate, i64)>()
+        .filter_map(|row| row.ok())
+        .collect())
+}
+
+/// Returns the usage of a metric this month, including what this instance hasn't written yet.
+pub async fn this_month(db: &Session, tenant_id: &str, metric: Metric) -> Result<i64, QueryError> {
+    let today = Utc::now().date_naive();
+    let usage = daily(db, tenant_id, metric, month_of(today), today).await?;
+
+    Ok(usage.values().sum::<i64>() + pending(tenant_id, metric))
+}
+
+/// Returns the usage of a metric its limits apply to, including what this instance hasn't
+/// written yet: the total for users, and this month's for other metrics.
+pub async fn current(db: &Session, tenant_id: &str, metric: Metric) -> Result<i64, QueryError> {
+    if metric != Metric::Users {
+        return this_month(db, tenant_id, metric).await;
+    }
+
+    let result = db
+        .query_unpaged(
+            "SELECT count FROM tenant_usage WHERE tenant_id = ? AND metric = ?",
+            (tenant_id, metric.as_str()),
+        )
+        .await?;
+
+    let usage = result
+        .rows_typed_or_empty::<(i64,)>()
+        .filter_map(|row| row.ok())
+        .map(|(count,)| count)
+        .sum::<i64>();
+
+    Ok(usage + pending(tenant_id, metric))
+}
+
+/// The usage of a tenant on a day.
+#[derive(Debug, Serialize)]
+pub struct Day {
+    pub date: String,
+
+    /// The count of each metric.
+    pub usage: Map<String, Value>,
+}
+
+/// Returns the usage of a tenant on each day of a range, oldest first.
+pub async fn report(
+    db: &Session,
+    tenant_id: &str,
+    from: NaiveDate,
+    to: NaiveDate,
+) -> Result<Vec<Day>, QueryError> {
+    let mut usage = HashMap::new();
+
+    for metric in Metric::ALL {
+        usage.insert(metric, daily(db, tenant_id, metric, from, to).await?);
+    }
+
+    Ok(from
+        .iter_days()
+        .take_while(|day| *day <= to)
+        .map(|day| Day {
+            date: day.to_string(),
+            usage: Metric::ALL
+                .iter()
+                .map(|metric| {
+                    let count = usage[metric].get(&day).copied().unwrap_or(0);
+                    (metric.as_str().to_string(), json!(count))
+                })
+                .collect(),
+        })
+        .collect())
+}
+
+/// Limits on a metric of a tenant, per month or in total for users. Going over the soft limit is only logged, while
+/// actions going over the hard limit are refused where it's enforced.
+#[derive(Clone, Debug, Serialize)]
+pub struct Quota {
+    pub metric: Metric,
+    pub soft_limit: Option<i64>,
+    pub hard_limit: Option<i64>,
+}
+
+pub async fn quotas(db: &Session, tenant_id: &str) -> Result<Vec<Quota>, QueryError> {
+    let result = db
+        .query_unpaged(
+            "SELECT metric, soft_limit, hard_limit FROM tenant_quotas WHERE tenant_id = ?",
+            (tenant_id,),
+        )
+        .await?;
+
+    Ok(result
+        .rows_typed_or_empty::<(String, Option<i64>, Option<i64>)>()
+        .filter_map(|row| row.ok())
+        .filter_map(|(metric, soft_limit, hard_limit)| {
+            Some(Quota {
+                metric: Metric::parse(&metric)?,
+                soft_limit,
+                hard_limit,
+            })
+        })
+        .collect())
+}
+
+pub async fn set_quota(db: &Session, tenant_id: &str, quota: &Quota) -> Result<(), QueryError> {
+    db.query_unpaged(
+        "INSERT INTO tenant_quotas (tenant_id, metric, soft_limit, hard_limit) VALUES (?, ?, ?, ?)",
+        (
+            tenant_id,
+            quota.metric.as_str(),
+            quota.soft_limit,
+            quota.hard_limit,
+        ),
+    )
+    .await?;
+
+    Ok(())
+}
+
+pub async fn remove_quota(db: &Session, tenant_id: &str, metric: Metric) -> Result<(), QueryError> {
+    db.query_unpaged(
+        "DELETE FROM tenant_quotas WHERE tenant_id = ? AND metric = ?",
+        (tenant_id, metric.as_str()),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// A limit reached by a tenant.
+#[derive(Debug, Serialize)]
+pub struct Exceeded {
+    pub metric: Metric,
+    pub limit: i64,
+    pub usage: i64,
+    pub is_hard: bool,
+}
+
+/// Checks whether one more use of each metric stays within the tenant's quotas, returning the
+/// limits it would go over, hard ones first.
+pub async fn check(
+    db: &Session,
+    tenant_id: &str,
+    metrics: &[Metric],
+) -> Result<Vec<Exceeded>, QueryError> {
+    let mut exceeded = vec![];
+
+    for quota in quotas(db, tenant_id).await? {
+        if !metrics.contains(&quota.metric) {
+            continue;
+        }
+
+        let usage = current(db, tenant_id, quota.metric).await?;
+
+        for (limit, is_hard) in [(quota.hard_limit, true), (quota.soft_limit, false)] {
+            if let Some(limit) = limit.filter(|limit| usage >= *limit) {
+                exceeded.push(Exceeded {
+                    metric: quota.metric,
+                    limit,
+                    usage,
+                    is_hard,
+                });
+
+                break;
+            }
+        }
+    }
+
+    exceeded.sort_by_key(|exceeded| !exceeded.is_hard);
+
+    Ok(exceeded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unverified_sign_ups_dont_count_as_users() {
+        record_sign_up("unverified", false);
+
+        assert_eq!(pending("unverified", Metric::SignUps), 1);
+        assert_eq!(pending("unverified", Metric::Users), 0);
+    }
+
+    #[test]
+    fn verified_sign_ups_count_as_users() {
+        record_sign_up("verified", true);
+
+        assert_eq!(pending("verified", Metric::SignUps), 1);
+        assert_eq!(pending("verified", Metric::Users), 1);
+    }
+}
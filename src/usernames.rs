@@ -0,0 +1,434 @@
+use std::{collections::HashMap, str::FromStr};
+
+use scylla::{transport::errors::QueryError, QueryResult, Session};
+use serde_json::{json, Value};
+use unicode_security::confusable_detection::skeleton;
+
+use crate::{
+    settings::{self, Category},
+    state::State,
+};
+
+/// Reserved usernames used when the tenant doesn't configure its own list.
+pub const DEFAULT_RESERVED: [&str; 20] = [
+    "accesscore",
+    "admin",
+    "administrator",
+    "api",
+    "billing",
+    "help",
+    "hostmaster",
+    "info",
+    "me",
+    "moderator",
+    "null",
+    "owner",
+    "postmaster",
+    "root",
+    "security",
+    "staff",
+    "support",
+    "system",
+    "webmaster",
+    "www",
+];
+
+#[derive(Debug, PartialEq)]
+pub enum Availability {
+    Available,
+    Taken,
+
+    /// The username was released by another user and is on hold.
+    Reserved,
+}
+
+/// A class of characters a tenant can allow in usernames.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CharacterClass {
+    /// ASCII `a-z`.
+    Lowercase,
+    /// ASCII `A-Z`.
+    Uppercase,
+    /// ASCII `0-9`.
+    Digits,
+    /// Any Unicode letter, including the ASCII ones.
+    Letters,
+    Underscore,
+    Dot,
+    Hyphen,
+}
+
+impl CharacterClass {
+    pub fn from_name(name: &str) -> Option<Self> {
+        Some(match name.trim() {
+            "lowercase" => Self::Lowercase,
+            "uppercase" => Self::Uppercase,
+            "digits" => Self::Digits,
+            "letters" => Self::Letters,
+            "underscore" => Self::Underscore,
+            "dot" => Self::Dot,
+            "hyphen" => Self::Hyphen,
+            _ => return None,
+        })
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Lowercase => "lowercase",
+            Self::Uppercase => "uppercase",
+            Self::Digits => "digits",
+            Self::Letters => "letters",
+            Self::Underscore => "underscore",
+            Self::Dot => "dot",
+            Self::Hyphen => "hyphen",
+        }
+    }
+
+    pub fn matches(&self, c: char) -> bool {
+        match self {
+            Self::Lowercase => c.is_ascii_lowercase(),
+            Self::Uppercase => c.is_ascii_uppercase(),
+            Self::Digits => c.is_ascii_digit(),
+            Self::Letters => c.is_alphabetic(),
+            Self::Underscore => c == '_',
+            Self::Dot => c == '.',
+            Self::Hyphen => c == '-',
+        }
+    }
+}
+
+/// Why a username was rejected by a `Policy`.
+#[derive(Debug, PartialEq)]
+pub enum Violation {
+    Length { min: usize, max: usize },
+    Characters { invalid: Vec<char> },
+    Reserved,
+}
+
+/// A tenant's username policy, read from the `Users` settings category.
+///
+/// | Key                             | Default                                  |
+/// |---------------------------------|------------------------------------------|
+/// | `username_min_length`           | `4`                                      |
+/// | `username_max_length`           | `32`                                     |
+/// | `username_allowed_characters`   | `letters,digits,underscore,dot,hyphen`   |
+/// | `username_case_insensitive`     | `true`                                   |
+/// | `username_confusable_detection` | `true`                                   |
+/// | `username_reserved`             | `DEFAULT_RESERVED`, comma-separated      |
+///
+/// Changing `username_case_insensitive` or `username_confusable_detection` only affects the
+/// usernames set afterwards, since the keys of the existing ones are not recalculated.
+#[derive(Debug)]
+pub struct Policy {
+    pub min_length: usize,
+    pub max_length: usize,
+    pub allowed: Vec<CharacterClass>,
+    pub case_insensitive: bool,
+    pub confusable_detection: bool,
+    pub reserved: Vec<String>,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Self {
+            min_length: 4,
+            max_length: 32,
+            allowed: vec![
+                CharacterClass::Letters,
+                CharacterClass::Digits,
+                CharacterClass::Underscore,
+                CharacterClass::Dot,
+                CharacterClass::Hyphen,
+            ],
+            case_insensitive: true,
+            confusable_detection: true,
+            reserved: DEFAULT_RESERVED.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+}
+
+impl Policy {
+    /// Reads the tenant's username policy.
+    pub async fn load(state: &State, tenant_id: &str) -> Result<Self, QueryError> {
+        Ok(Self::from_settings(
+            &settings::get_all(state, tenant_id, Category::Users).await?,
+        ))
+    }
+
+    /// Builds a policy out of the `Users` settings category, using the defaults for missing or
+    /// unparseable values.
+    pub fn from_settings(settings: &HashMap<String, String>) -> Self {
+        let default = Self::default();
+
+        fn parse<T: FromStr>(settings: &HashMap<String, String>, key: &str) -> Option<T> {
+            settings.get(key).and_then(|v| v.trim().parse().ok())
+        }
+
+        Self {
+            min_length: parse(settings, "username_min_length").unwrap_or(default.min_length),
+            max_length: parse(settings, "username_max_length").unwrap_or(default.max_length),
+            allowed: settings
+                .get("username_allowed_characters")
+                .map(|v| v.split(',').filter_map(CharacterClass::from_name).collect())
+                .unwrap_or(default.allowed),
+            case_insensitive: parse(settings, "username_case_insensitive")
+                .unwrap_or(default.case_insensitive),
+            confusable_detection: parse(settings, "username_confusable_detection")
+                .unwrap_or(default.confusable_detection),
+            reserved: settings
+                .get("username_reserved")
+                .map(|v| {
+                    v.split(',')
+                        .map(|s| s.trim().to_string())
+                        .filter(|s| !s.is_empty())
+                        .collect()
+                })
+                .unwrap_or(default.reserved),
+        }
+    }
+
+    /// Returns the key used to check a username's uniqueness. Usernames with the same key are
+    /// considered the same, e.g. `Admin` and `аdmin` (with a Cyrillic `а`) both have the key
+    /// `admin` if the policy is case-insensitive and detects confusables.
+    pub fn key(&self, username: &str) -> String {
+        let mut key = if self.case_insensitive {
+            username.to_lowercase()
+        } else {
+            username.to_string()
+        };
+
+        if self.confusable_detection {
+            key = skeleton(&key).collect();
+
+            // Skeletons map some characters to uppercase ones, e.g. `0` to `O`.
+            if self.case_insensitive {
+                key = key.to_lowercase();
+            }
+        }
+
+        key
+    }
+
+    /// Validates a username against the policy, returning every rule it breaks.
+    pub fn validate(&self, username: &str) -> Vec<Violation> {
+        let mut violations = vec![];
+
+        let length = username.chars().count();
+
+        if length < self.min_length || length > self.max_length {
+            violations.push(Violation::Length {
+                min: self.min_length,
+                max: self.max_length,
+            });
+        }
+
+        let mut invalid: Vec<char> = username
+            .chars()
+            .filter(|c| !self.allowed.iter().any(|class| class.matches(*c)))
+            .collect();
+
+        if !invalid.is_empty() {
+            invalid.sort_unstable();
+            invalid.dedup();
+            violations.push(Violation::Characters { invalid });
+        }
+
+        let key = self.key(username);
+
+        if self
+            .reserved
+            .iter()
+            .any(|reserved| self.key(reserved) == key)
+        {
+            violations.push(Violation::Reserved);
+        }
+
+        violations
+    }
+}
+
+/// Checks whether `username` can be taken by `user_id`, or by a new user if `None`. Usernames on
+/// hold can only be taken back by the user who released them.
+pub async fn availability(
+    db: &Session,
+    tenant_id: &str,
+    policy: &Policy,
+    username: &str,
+    user_id: Option<&str>,
+) -> Result<Availability, QueryError> {
+    let key = policy.key(username);
+
+    let taken = db
+        .query_unpaged(
+            "SELECT user_id FROM username_keys WHERE tenant_id = ? AND key = ?",
+            (tenant_id, &key),
+        )
+        .await?;
+
+    if let Ok(Some((holder,))) = taken.maybe_first_row_typed::<(String,)>() {
+        if Some(holder.as_str()) != user_id {
+            return Ok(Availability::Taken);
+        }
+    }
+
+    // Usernames set before keys were introduced only exist in `users_by_username`.
+    let taken = db
+        .query_unpaged(
+            "SELECT user_id FROM users_by_username WHERE tenant_id = ? AND username = ? LIMIT 1",
+            (tenant_id, username),
+        )
+        .await?;
+
+    if let Ok(Some((holder,))) = taken.maybe_first_row_typed::<(String,)>() {
+        if Some(holder.as_str()) != user_id {
+            return Ok(Availability::Taken);
+        }
+    }
+
+    let reservation = db
+        .query_unpaged(
+            "SELECT user_id FROM username_reservations WHERE tenant_id = ? AND username = ?",
+            (tenant_id, &key),
+        )
+        .await?;
+
+    Ok(match reservation.maybe_first_row_typed::<(String,)>() {
+        Ok(Some((holder,))) if Some(holder.as_str()) != user_id => Availability::Reserved,
+        _ => Availability::Available,
+    })
+}
+
+/// Claims the key of a username for a user, returning `false` if another user holds it. Keys are
+/// only written with LWTs, so two users can't take usernames with the same key at once.
+pub async fn claim(
+    db: &Session,
+    tenant_id: &str,
+    key: &str,
+    user_id: &str,
+    username: &str,
+    ttl: u64,
+) -> Result<bool, QueryError> {
+    let result = db
+        .query_unpaged(
+            format!("INSERT INTO username_keys (tenant_id, key, user_id, username) VALUES (?, ?, ?, ?) USING TTL {ttl} IF NOT EXISTS"),
+            (tenant_id, key, user_id, username),
+        )
+        .await?;
+
+    if is_applied(result) {
+        return Ok(true);
+    }
+
+    // The user may already hold the key, such as when only the case of their username changes.
+    let result = db
+        .query_unpaged(
+            format!("UPDATE username_keys USING TTL {ttl} SET username = ? WHERE tenant_id = ? AND key = ? IF user_id = ?"),
+            (username, tenant_id, key, user_id),
+        )
+        .await?;
+
+    Ok(is_applied(result))
+}
+
+/// Releases the key of a username, unless another user took it since.
+pub async fn release(
+    db: &Session,
+    tenant_id: &str,
+    key: &str,
+    user_id: &str,
+) -> Result<(), QueryError> {
+    db.query_unpaged(
+        "DELETE FROM username_keys WHERE tenant_id = ? AND key = ? IF user_id = ?",
+        (tenant_id, key, user_id),
+    )
+    .await?;
+
+    Ok(())
+}
+
+fn is_applied(result: QueryResult) -> bool {
+    result
+        .first_row()
+        .ok()
+        .and_then(|row| row.columns.into_iter().next().flatten())
+        .and_then(|applied| applied.as_boolean())
+        .unwrap_or(false)
+}
+
+/// Returns the error message, detail and meta for a policy violation.
+pub fn violation_details(
+    policy: &Policy,
+    violation: &Violation,
+) -> (&'static str, String, HashMap<&'static str, Value>) {
+    match violation {
+        Violation::Length { min, max } => (
+            "Invalid Username",
+            format!("The username must be from {min} to {max} characters long."),
+            HashMap::from([("min", json!(min)), ("max", json!(max))]),
+        ),
+        Violation::Characters { invalid } => (
+            "Invalid Username Characters",
+            "The username contains characters that are not allowed.".to_string(),
+            HashMap::from([
+                ("invalid", json!(invalid)),
+                (
+                    "allowed",
+                    json!(policy
+                        .allowed
+                        .iter()
+                        .map(|class| class.name())
+                        .collect::<Vec<&str>>()),
+                ),
+            ]),
+        ),
+        Violation::Reserved => (
+            "Reserved Username",
+            "The username is reserved and can't be used.".to_string(),
+            HashMap::new(),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn valid_usernames_pass() {
+        assert!(Policy::default().validate("alice_01").is_empty());
+    }
+
+    #[test]
+    fn invalid_characters_are_listed_once() {
+        assert_eq!(
+            Policy::default().validate("a b!c d!"),
+            [Violation::Characters {
+                invalid: vec![' ', '!']
+            }]
+        );
+    }
+
+    #[test]
+    fn reserved_usernames_are_rejected_regardless_of_case() {
+        assert_eq!(Policy::default().validate("Admin"), [Violation::Reserved]);
+    }
+
+    #[test]
+    fn confusables_of_reserved_usernames_are_rejected() {
+        // The first letter is the Cyrillic "а".
+        assert_eq!(
+            Policy::default().validate("\u{430}dmin"),
+            [Violation::Reserved]
+        );
+    }
+
+    #[test]
+    fn confusables_are_allowed_without_detection() {
+        let policy = Policy {
+            confusable_detection: false,
+            ..Policy::default()
+        };
+
+        assert!(policy.validate("\u{430}dmin").is_empty());
+    }
+}
@@ -0,0 +1,13 @@
+use scylla::{transport::errors::QueryError, Session};
+
+/// Checks whether a user exists in the tenant.
+pub async fn exists(db: &Session, tenant_id: &str, user_id: &str) -> Result<bool, QueryError> {
+    let result = db
+        .query_unpaged(
+            "SELECT user_id FROM users WHERE tenant_id = ? AND user_id = ?",
+            (tenant_id, user_id),
+        )
+        .await?;
+
+    Ok(result.rows_num().unwrap_or(0) != 0)
+}
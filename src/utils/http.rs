@@ -0,0 +1,27 @@
+use reqwest::Response;
+
+/// Reads the body of a response chunk by chunk, giving up as soon as it's larger than `limit`
+/// bytes, so a large body is never held in memory. Returns `None` if it's too large.
+pub async fn read_limited(
+    mut response: Response,
+    limit: usize,
+) -> Result<Option<Vec<u8>>, reqwest::Error> {
+    if response
+        .content_length()
+        .is_some_and(|length| length > limit as u64)
+    {
+        return Ok(None);
+    }
+
+    let mut body = Vec::new();
+
+    while let Some(chunk) = response.chunk().await? {
+        if body.len() + chunk.len() > limit {
+            return Ok(None);
+        }
+
+        body.extend_from_slice(&chunk);
+    }
+
+    Ok(Some(body))
+}